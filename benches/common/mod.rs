@@ -0,0 +1,83 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Timing shared by the benchmarks
+//
+// Each benchmark is a plain program run by `cargo bench`, which times a closure by calling it over and over for a
+// fixed length of time, and prints the average time per call.  Benchmarks that need a chain build one with `grow`
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#![allow(dead_code)]
+
+use blockchain_demo::{
+    block::{calculate_hash, Block, BlockHeader, BLOCK_VERSION},
+    chain::expected_target,
+    config::Config,
+    hash::BlockHash,
+    merkle,
+    mining::{stated_difficulty, DEV_FAST_MINE},
+};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+// Long enough for the average to settle, short enough for `cargo bench` to get through every case in a few seconds
+const RUN_TIME: Duration = Duration::from_millis(500);
+
+// Calls `f` for RUN_TIME, after a warm up call, and returns the average time per call
+pub fn bench<T>(name: &str, mut f: impl FnMut() -> T) -> Duration {
+    black_box(f());
+
+    let started = Instant::now();
+    let mut calls: u32 = 0;
+
+    while started.elapsed() < RUN_TIME {
+        black_box(f());
+        calls += 1;
+    }
+
+    let per_call = started.elapsed() / calls;
+    println!("{:<56} {:>12?} per call", name, per_call);
+    per_call
+}
+
+// How many times faster `new` is than `old`
+pub fn speedup(old: Duration, new: Duration) -> f64 {
+    old.as_secs_f64() / new.as_secs_f64().max(f64::EPSILON)
+}
+
+// `chain` followed by `count` more valid blocks, each dated --block-spacing after the one before
+pub fn grow(chain: &[Block], config: &Config, count: usize, label: &str) -> Vec<Block> {
+    let mut chain = chain.to_vec();
+
+    for _ in 0..count {
+        let parent = &chain[chain.len() - 1].header;
+        let target = expected_target(&chain, chain.len() as u64, config);
+        let data = vec![format!("{} {}", label, chain.len())];
+        let mut header = BlockHeader {
+            version: BLOCK_VERSION,
+            chain_id: config.chain_id.clone(),
+            id: parent.id + 1,
+            hash: BlockHash::ZERO,
+            previous_hash: parent.hash,
+            timestamp: parent.timestamp + config.block_spacing,
+            merkle_root: merkle::merkle_root(&data),
+            nonce: 0,
+            difficulty: stated_difficulty(target),
+            miner: String::from("bench-miner"),
+            reward: config.block_reward,
+        };
+
+        loop {
+            header.hash = calculate_hash(&header, &config.hashing);
+
+            if DEV_FAST_MINE || target.is_met_by(&header.hash) {
+                break;
+            }
+
+            header.nonce += 1;
+        }
+
+        chain.push(Block { header, data });
+    }
+
+    chain
+}
@@ -0,0 +1,56 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Checking a hash against the difficulty
+//
+// Compares Target::is_met_by, which reads the hash as a number, with writing the hash out as a string of binary digits
+// and looking for a prefix of zeros, as the difficulty check used to
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use blockchain_demo::{hash::BlockHash, target::Target};
+use sha2::{Digest, Sha256};
+use std::hint::black_box;
+
+const DIFFICULTY: u32 = 16;
+
+fn binary_string_check(hash: &BlockHash, bits: usize) -> bool {
+    let binary: String = hash
+        .as_bytes()
+        .iter()
+        .map(|byte| format!("{:08b}", byte))
+        .collect();
+
+    binary.starts_with(&"0".repeat(bits))
+}
+
+fn main() {
+    let hashes: Vec<BlockHash> = (0u32..1000)
+        .map(|i| BlockHash::from(<[u8; 32]>::from(Sha256::digest(i.to_be_bytes()))))
+        .collect();
+    let target = Target::from_leading_zero_bits(DIFFICULTY);
+
+    // Both checks have to agree before their speed means anything
+    for hash in &hashes {
+        assert_eq!(
+            target.is_met_by(hash),
+            binary_string_check(hash, DIFFICULTY as usize)
+        );
+    }
+
+    let old = common::bench("binary string check, 1000 hashes", || {
+        hashes
+            .iter()
+            .filter(|hash| binary_string_check(black_box(hash), DIFFICULTY as usize))
+            .count()
+    });
+    let new = common::bench("Target::is_met_by, 1000 hashes", || {
+        hashes
+            .iter()
+            .filter(|hash| target.is_met_by(black_box(hash)))
+            .count()
+    });
+
+    println!(
+        "Target::is_met_by is {:.0}x faster",
+        common::speedup(old, new)
+    );
+}
@@ -0,0 +1,52 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Finding blocks by hash on a 10,000 block chain
+//
+// Compares the hash index kept by the App with scanning the chain for the block, as every lookup used to
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use blockchain_demo::{chain::App, config::Config, hash::BlockHash};
+
+const CHAIN_LENGTH: usize = 10_000;
+
+fn main() {
+    let config = Config {
+        db_path: None,
+        wallet_path: None,
+        key_file: None,
+        ..Config::default()
+    };
+    let mut app = App::new(config.clone()).expect("an app without any storage always starts");
+    let chain = common::grow(app.chain(), &config, CHAIN_LENGTH, "entry");
+
+    for block in &chain[1..] {
+        app.try_add_block(block.clone())
+            .expect("the chain is valid");
+    }
+
+    // Every 100th block, so that lookups are spread over the whole chain
+    let hashes: Vec<BlockHash> = chain
+        .iter()
+        .step_by(100)
+        .map(|block| block.header.hash)
+        .collect();
+    let hex_hashes: Vec<String> = hashes.iter().map(BlockHash::to_string).collect();
+
+    let scan = common::bench("linear scan, 100 lookups", || {
+        hashes
+            .iter()
+            .filter_map(|hash| app.iter().find(|block| block.header.hash == *hash))
+            .count()
+    });
+    let index = common::bench("hash index, 100 lookups", || {
+        hex_hashes
+            .iter()
+            .filter_map(|hash| app.get_block_by_hash(hash))
+            .count()
+    });
+
+    println!(
+        "the hash index is {:.1}x faster",
+        common::speedup(scan, index)
+    );
+}
@@ -0,0 +1,61 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// One mining attempt
+//
+// Compares building the whole hash preimage again for every nonce, both as the JSON object blocks used to be hashed
+// as and as the binary layout of calculate_hash, with reusing one preimage and only overwriting its nonce, as the
+// miner and bench_hashrate do
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use blockchain_demo::{
+    block::{calculate_hash, create_genesis, GenesisSpec},
+    config::DEFAULT_CHAIN_ID,
+    hasher::BlockHashing,
+    mining::bench_hashrate,
+};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// Hashes per call of each closure, so that the time of the loop itself doesn't count for much
+const HASHES: u64 = 1000;
+
+fn main() {
+    let hashing = BlockHashing::default();
+    let mut header = create_genesis(DEFAULT_CHAIN_ID, &GenesisSpec::default(), &hashing).header;
+    let previous_hash = header.previous_hash.to_string();
+
+    let json = common::bench("JSON preimage built for every nonce, 1000 hashes", || {
+        for nonce in 0..HASHES {
+            let preimage = serde_json::json!({
+                "id": header.id,
+                "previous_hash": previous_hash,
+                "data": "genesis!",
+                "timestamp": header.timestamp,
+                "nonce": nonce
+            })
+            .to_string();
+
+            std::hint::black_box(Sha256::digest(preimage.as_bytes()));
+        }
+    });
+    let binary = common::bench("binary preimage built for every nonce, 1000 hashes", || {
+        for nonce in 0..HASHES {
+            header.nonce = nonce;
+            std::hint::black_box(calculate_hash(&header, &hashing));
+        }
+    });
+
+    // bench_hashrate hashes with the same reused preimage as the miner, on a single worker here
+    let stats = bench_hashrate(Duration::from_secs(1), 1, hashing);
+    let reused = Duration::from_secs_f64(HASHES as f64 / stats.hashrate());
+    println!(
+        "{:<56} {:>12?} per call",
+        "preimage reused, 1000 hashes", reused
+    );
+
+    println!(
+        "reusing the preimage is {:.1}x faster than building it as binary, and {:.1}x faster than as JSON",
+        common::speedup(binary, reused),
+        common::speedup(json, reused)
+    );
+}
@@ -0,0 +1,47 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Validating a remote chain that extends the local one
+//
+// Compares validating the whole remote chain, as every chain response used to be, with validating only the blocks
+// after the common ancestor, as chain sync does with each chunk that arrives.  The cost of the first grows with the
+// length of the chain, and the cost of the second with how far the chains have diverged
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use blockchain_demo::{chain::App, config::Config};
+
+fn main() {
+    let config = Config {
+        db_path: None,
+        wallet_path: None,
+        key_file: None,
+        ..Config::default()
+    };
+
+    for (length, divergence) in [(1000, 10), (4000, 10), (4000, 100)] {
+        let mut app = App::new(config.clone()).expect("an app without any storage always starts");
+        let local = common::grow(app.chain(), &config, length, "local");
+
+        for block in &local[1..] {
+            app.try_add_block(block.clone())
+                .expect("the local chain is valid");
+        }
+
+        let remote = common::grow(&local, &config, divergence, "remote");
+        let whole = common::bench(
+            &format!("whole chain, {} + {} blocks", length, divergence),
+            || app.validate_chain(&remote),
+        );
+        let divergent = common::bench(
+            &format!(
+                "blocks after the ancestor, {} + {} blocks",
+                length, divergence
+            ),
+            || app.validate_chain_part(&remote, local.len()),
+        );
+
+        println!(
+            "validating only the blocks after the ancestor is {:.1}x faster",
+            common::speedup(whole, divergent)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::{
+    chain::{AddBlockOutcome, ChainError},
+    hash::BlockHash,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+// Entries the `history` command can show.  Older ones are forgotten, though not from the --audit-log file
+pub const AUDIT_LOG_SIZE: usize = 1000;
+
+// What became of a block.  Serialized as `"outcome"`, along with a `"reason"` for a rejection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", content = "reason", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Accepted,
+    // Kept on a competing branch
+    SideChain,
+    Duplicate,
+    // Kept until its parent arrives
+    Orphaned,
+    Rejected(String),
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "accepted"),
+            Self::SideChain => write!(f, "kept on a competing branch"),
+            Self::Duplicate => write!(f, "duplicate"),
+            Self::Orphaned => write!(f, "orphaned"),
+            Self::Rejected(reason) => write!(f, "rejected - {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    // Unix time the block was dealt with
+    pub timestamp: i64,
+    // `local` for a block mined by this node, otherwise the peer that sent it
+    pub source: String,
+    // `mined` for a block mined by this node, `block` for one sent on its own by a peer, and `chain` for one that came
+    // as part of a chain
+    pub via: &'static str,
+    pub id: u64,
+    pub hash: BlockHash,
+    #[serde(flatten)]
+    pub outcome: AuditOutcome,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Record of every block this node has dealt with, and what became of it
+//
+// The last AUDIT_LOG_SIZE entries are kept in memory.  With --audit-log, every entry is also appended to that file as
+// a line of JSON, so that what the node saw can be pieced together after the event
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    file: Option<File>,
+}
+
+impl AuditLog {
+    // Kept in memory only
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            file: None,
+        }
+    }
+
+    // Entries are appended to whatever the file already holds
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            entries: VecDeque::new(),
+            file: Some(OpenOptions::new().create(true).append(true).open(path)?),
+        })
+    }
+
+    // A file that can't be written to is only logged, since losing an audit entry is no reason to stop the node
+    pub fn record(&mut self, entry: AuditEntry) {
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_vec(&entry).expect("can convert audit entry to JSON");
+            line.push(b'\n');
+
+            if let Err(e) = file.write_all(&line) {
+                log::error!("could not write to the audit log - {}", e);
+            }
+        }
+
+        if self.entries.len() == AUDIT_LOG_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    // Oldest first
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &AuditEntry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+}
+
+impl From<&Result<AddBlockOutcome, ChainError>> for AuditOutcome {
+    fn from(outcome: &Result<AddBlockOutcome, ChainError>) -> Self {
+        match outcome {
+            Ok(AddBlockOutcome::Added { .. } | AddBlockOutcome::Reorg(_)) => Self::Accepted,
+            Ok(AddBlockOutcome::SideChain) => Self::SideChain,
+            Ok(AddBlockOutcome::Duplicate) => Self::Duplicate,
+            Ok(AddBlockOutcome::Orphan) => Self::Orphaned,
+            Err(e) => Self::Rejected(e.to_string()),
+        }
+    }
+}
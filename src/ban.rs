@@ -0,0 +1,205 @@
+use libp2p::PeerId;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// A peer's strikes are forgotten one at a time, one this long after another
+pub const STRIKE_DECAY: Duration = Duration::from_secs(10 * 60);
+
+struct Strikes {
+    count: u32,
+    // When the last strike was forgotten, or the first one given
+    since: Instant,
+}
+
+impl Strikes {
+    fn decay(&mut self) {
+        let forgiven = (self.since.elapsed().as_secs() / STRIKE_DECAY.as_secs()) as u32;
+
+        if forgiven > 0 {
+            self.count = self.count.saturating_sub(forgiven);
+            self.since += STRIKE_DECAY * forgiven;
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Strikes against peers that send invalid blocks, chains or messages, and the peers banned for it
+//
+// A peer whose strikes reach `threshold` is banned for `duration`, and starts again with no strikes once the ban is
+// over.  Strikes slowly decay, so a peer that sends the odd bad block because of a bug isn't banned for good.  A peer
+// banned by hand stays banned until it is unbanned
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct BanList {
+    threshold: u32,
+    duration: Duration,
+    strikes: HashMap<PeerId, Strikes>,
+    // None for a ban that lasts until the peer is unbanned
+    banned: HashMap<PeerId, Option<Instant>>,
+}
+
+impl BanList {
+    pub fn new(threshold: u32, duration: Duration) -> Self {
+        Self {
+            threshold,
+            duration,
+            strikes: HashMap::new(),
+            banned: HashMap::new(),
+        }
+    }
+
+    // Returns true if the strike got the peer banned
+    pub fn strike(&mut self, peer: PeerId) -> bool {
+        if self.is_banned(&peer) {
+            return false;
+        }
+
+        let strikes = self.strikes.entry(peer).or_insert(Strikes {
+            count: 0,
+            since: Instant::now(),
+        });
+        strikes.decay();
+
+        if strikes.count == 0 {
+            strikes.since = Instant::now();
+        }
+        strikes.count += 1;
+
+        if strikes.count < self.threshold {
+            return false;
+        }
+
+        self.strikes.remove(&peer);
+        self.banned.insert(peer, Some(Instant::now() + self.duration));
+        true
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    // Returns false if the peer was already banned until it is unbanned
+    pub fn ban(&mut self, peer: PeerId) -> bool {
+        self.strikes.remove(&peer);
+        self.banned.insert(peer, None) != Some(None)
+    }
+
+    // Returns false if the peer wasn't banned.  Its strikes are forgotten either way
+    pub fn unban(&mut self, peer: &PeerId) -> bool {
+        self.strikes.remove(peer);
+        self.banned.remove(peer).is_some()
+    }
+
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.banned
+            .get(peer)
+            .is_some_and(|until| until.is_none_or(|until| Instant::now() < until))
+    }
+
+    pub fn strikes(&self, peer: &PeerId) -> u32 {
+        self.strikes.get(peer).map_or(0, |strikes| {
+            let forgiven = strikes.since.elapsed().as_secs() / STRIKE_DECAY.as_secs();
+            strikes.count.saturating_sub(forgiven as u32)
+        })
+    }
+
+    // Every banned peer, along with when its ban runs out
+    pub fn banned(&self) -> impl Iterator<Item = (&PeerId, &Option<Instant>)> {
+        self.banned.iter()
+    }
+
+    // Forgets the bans that have run out, and returns the peers they were on
+    pub fn expire(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let expired: Vec<PeerId> = self
+            .banned
+            .iter()
+            .filter(|(_, until)| until.is_some_and(|until| until <= now))
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in &expired {
+            self.banned.remove(peer);
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
+
+    // Moves everything recorded about the peer `by` into the past
+    fn age(bans: &mut BanList, peer: &PeerId, by: Duration) {
+        let earlier = |instant: Instant| {
+            instant
+                .checked_sub(by)
+                .expect("the clock goes back far enough")
+        };
+
+        if let Some(strikes) = bans.strikes.get_mut(peer) {
+            strikes.since = earlier(strikes.since);
+        }
+        if let Some(Some(until)) = bans.banned.get_mut(peer) {
+            *until = earlier(*until);
+        }
+    }
+
+    #[test]
+    fn a_peer_is_banned_at_the_threshold_until_the_ban_runs_out() {
+        let mut bans = BanList::new(3, ONE_HOUR);
+        let peer = PeerId::random();
+
+        assert!(!bans.strike(peer));
+        assert!(!bans.strike(peer));
+        assert_eq!(bans.strikes(&peer), 2);
+        assert!(bans.strike(peer));
+        assert!(bans.is_banned(&peer));
+        assert!(bans.expire().is_empty());
+
+        age(&mut bans, &peer, ONE_HOUR);
+        assert!(!bans.is_banned(&peer));
+        assert_eq!(bans.expire(), vec![peer]);
+        assert_eq!(bans.banned().count(), 0);
+
+        // It starts again with no strikes
+        assert_eq!(bans.strikes(&peer), 0);
+        assert!(!bans.strike(peer));
+    }
+
+    #[test]
+    fn strikes_are_forgotten_one_at_a_time() {
+        let mut bans = BanList::new(3, ONE_HOUR);
+        let peer = PeerId::random();
+
+        bans.strike(peer);
+        bans.strike(peer);
+        age(&mut bans, &peer, STRIKE_DECAY);
+        assert_eq!(bans.strikes(&peer), 1);
+
+        // Two more strikes are needed after one has been forgotten
+        assert!(!bans.strike(peer));
+        assert!(bans.strike(peer));
+    }
+
+    #[test]
+    fn a_peer_banned_by_hand_stays_banned_until_it_is_unbanned() {
+        let mut bans = BanList::new(3, ONE_HOUR);
+        let peer = PeerId::random();
+
+        bans.strike(peer);
+        assert!(bans.ban(peer));
+        assert!(!bans.ban(peer));
+        assert!(bans.expire().is_empty());
+        assert!(bans.is_banned(&peer));
+
+        assert!(bans.unban(&peer));
+        assert!(!bans.is_banned(&peer));
+        assert_eq!(bans.strikes(&peer), 0);
+        assert!(!bans.unban(&peer));
+    }
+}
@@ -0,0 +1,770 @@
+use crate::{
+    config::DEFAULT_CHAIN_ID,
+    hash::BlockHash,
+    hasher::{BlockHashing, HashAlgorithm, HashMode},
+    ledger::TransactionError,
+    merkle,
+    mining::{
+        mine_block, stated_difficulty, MinedBlock, MiningError, MiningEvent, MiningParams,
+        MiningStats,
+    },
+    target::Target,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, sync::atomic::AtomicBool};
+use tokio::sync::mpsc;
+
+// The only block format this node understands.  Bump this whenever the fields of a block or its hash preimage change
+pub const BLOCK_VERSION: u32 = 3;
+
+// The part of a block covered by its proof of work, along with the resulting hash.  The header only commits to the
+// data through its merkle root, so it can be validated without the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub version: u32,
+    // Network the block was mined for, set by --chain-id
+    pub chain_id: String,
+    pub id: u64,
+    pub hash: BlockHash,
+    pub previous_hash: BlockHash,
+    pub timestamp: i64,
+    // Only the merkle root of the data is hashed, so that an entry can be proved to be in the block without the rest
+    pub merkle_root: BlockHash,
+    pub nonce: u64,
+    // Compact form of the target this block was mined against
+    pub difficulty: u32,
+    // Peer id of the node that mined this block, and the reward it earned for doing so
+    pub miner: String,
+    pub reward: u64,
+}
+
+// A header plus the body it describes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub header: BlockHeader,
+    pub data: Vec<String>,
+}
+
+// On the wire and in `ls c`, blocks keep the flat layout they had before the header was split out, so the JSON sent
+// by older peers still parses
+#[derive(Serialize)]
+struct FlatBlockRef<'a> {
+    version: u32,
+    chain_id: &'a str,
+    id: u64,
+    hash: &'a BlockHash,
+    previous_hash: &'a BlockHash,
+    timestamp: i64,
+    data: &'a [String],
+    merkle_root: &'a BlockHash,
+    nonce: u64,
+    difficulty: u32,
+    miner: &'a str,
+    reward: u64,
+}
+
+#[derive(Deserialize)]
+struct FlatBlock {
+    // Blocks from before the version field was added have version 0
+    #[serde(default)]
+    version: u32,
+    // Blocks from before chain ids were added belong to no network at all
+    #[serde(default)]
+    chain_id: String,
+    id: u64,
+    hash: BlockHash,
+    previous_hash: BlockHash,
+    timestamp: i64,
+    data: Vec<String>,
+    merkle_root: BlockHash,
+    nonce: u64,
+    difficulty: u32,
+    miner: String,
+    reward: u64,
+}
+
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let header = &self.header;
+
+        FlatBlockRef {
+            version: header.version,
+            chain_id: &header.chain_id,
+            id: header.id,
+            hash: &header.hash,
+            previous_hash: &header.previous_hash,
+            timestamp: header.timestamp,
+            data: &self.data,
+            merkle_root: &header.merkle_root,
+            nonce: header.nonce,
+            difficulty: header.difficulty,
+            miner: &header.miner,
+            reward: header.reward,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Block {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let flat = FlatBlock::deserialize(deserializer)?;
+
+        Ok(Self {
+            header: BlockHeader {
+                version: flat.version,
+                chain_id: flat.chain_id,
+                id: flat.id,
+                hash: flat.hash,
+                previous_hash: flat.previous_hash,
+                timestamp: flat.timestamp,
+                merkle_root: flat.merkle_root,
+                nonce: flat.nonce,
+                difficulty: flat.difficulty,
+                miner: flat.miner,
+                reward: flat.reward,
+            },
+            data: flat.data,
+        })
+    }
+}
+
+impl Block {
+    // Passing None for `max_attempts` mines until a solution is found or mining is cancelled
+    pub fn new(
+        params: MiningParams,
+        max_attempts: Option<u64>,
+        cancel: &AtomicBool,
+        events: &mpsc::UnboundedSender<MiningEvent>,
+    ) -> Result<(Self, MiningStats), MiningError> {
+        let MinedBlock {
+            nonce,
+            timestamp,
+            hash,
+            merkle_root,
+            stats,
+        } = mine_block(&params, max_attempts, cancel, events)?;
+        let MiningParams {
+            chain_id,
+            id,
+            previous_hash,
+            data,
+            target,
+            miner,
+            reward,
+            ..
+        } = params;
+
+        // The miner may have refreshed the timestamp, so the block must use the one the hash was calculated from
+        let block = Self {
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                chain_id,
+                id,
+                hash,
+                previous_hash,
+                timestamp,
+                merkle_root,
+                nonce,
+                difficulty: stated_difficulty(target),
+                miner,
+                reward,
+            },
+            data,
+        };
+
+        Ok((block, stats))
+    }
+
+    // Number of bytes of data across all entries, which is what --max-data-size limits
+    pub fn data_size(&self) -> usize {
+        self.data.iter().map(String::len).sum()
+    }
+
+    // Whether --prune-keep has discarded the data.  The header is kept for the proof of work and the merkle root, and
+    // the root of no data at all would be all zeros
+    pub fn is_pruned(&self) -> bool {
+        self.data.is_empty() && self.header.merkle_root != BlockHash::ZERO
+    }
+}
+
+// Clocks on different nodes never quite agree, so a block may be dated up to this many seconds before its parent
+pub const PARENT_TIMESTAMP_TOLERANCE: i64 = 10;
+
+// Why a block was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValidationError {
+    UnsupportedVersion {
+        id: u64,
+        version: u32,
+    },
+    // Mined for another network
+    WrongChainId {
+        id: u64,
+        expected: String,
+        got: String,
+    },
+    WrongPreviousHash {
+        id: u64,
+        expected: BlockHash,
+        got: BlockHash,
+    },
+    // Mined by a node built with the dev-fast-mine feature
+    NoProofOfWork {
+        id: u64,
+    },
+    InvalidDifficulty {
+        id: u64,
+        expected: u32,
+        got: u32,
+    },
+    InsufficientWork {
+        id: u64,
+        target: Target,
+    },
+    WrongReward {
+        id: u64,
+        expected: u64,
+        got: u64,
+    },
+    TimestampTooFarAhead {
+        id: u64,
+        timestamp: i64,
+        now: i64,
+        max_drift: i64,
+    },
+    TimestampBeforeParent {
+        id: u64,
+        timestamp: i64,
+        parent_timestamp: i64,
+    },
+    TimestampNotAfterMedian {
+        id: u64,
+        timestamp: i64,
+        median: i64,
+    },
+    NonSequentialId {
+        expected: u64,
+        got: u64,
+    },
+    // The hash is correct for the other hashing mode
+    HashModeMismatch {
+        id: u64,
+        block_mode: HashMode,
+        local_mode: HashMode,
+    },
+    HashMismatch {
+        id: u64,
+        hashing: BlockHashing,
+    },
+    MerkleRootMismatch {
+        id: u64,
+    },
+    DataTooLarge {
+        id: u64,
+        size: usize,
+        max: usize,
+    },
+    InvalidTransaction {
+        id: u64,
+        error: TransactionError,
+    },
+    // The data was discarded by --prune-keep, either here or on the peer the block came from
+    DataPruned {
+        id: u64,
+    },
+    // The first block of a chain isn't a genesis block at all
+    MalformedGenesis {
+        id: u64,
+        previous_hash: BlockHash,
+    },
+    ForeignGenesis {
+        hash: BlockHash,
+    },
+    EmptyChain,
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion { id, version } if *version > BLOCK_VERSION => write!(
+                f,
+                "block with id: {} has version {} - peer is running a newer block format",
+                id, version
+            ),
+            Self::UnsupportedVersion { id, version } => write!(
+                f,
+                "block with id: {} has version {} - peer is running an older block format",
+                id, version
+            ),
+            Self::WrongChainId { id, expected, got } => write!(
+                f,
+                "block with id: {} belongs to chain {}, not {}",
+                id, got, expected
+            ),
+            Self::WrongPreviousHash { id, expected, got } => write!(
+                f,
+                "block with id: {} has wrong previous hash {}, expected {}",
+                id, got, expected
+            ),
+            Self::NoProofOfWork { id } => write!(
+                f,
+                "block with id: {} was mined without proof of work by a node built with the dev-fast-mine feature",
+                id
+            ),
+            Self::InvalidDifficulty { id, expected, got } => write!(
+                f,
+                "block with id: {} states a difficulty of {:#010x}, but the chain requires {:#010x} - was it mined with a different --difficulty?",
+                id, got, expected
+            ),
+            Self::InsufficientWork { id, target } => write!(
+                f,
+                "block with id: {} does not meet its stated difficulty of {}",
+                id, target
+            ),
+            Self::WrongReward { id, expected, got } => write!(
+                f,
+                "block with id: {} claims a reward of {}, but the chain pays {}",
+                id, got, expected
+            ),
+            Self::TimestampTooFarAhead {
+                id,
+                timestamp,
+                now,
+                max_drift,
+            } => write!(
+                f,
+                "block with id: {} has timestamp {}, more than {}s ahead of the local time {}",
+                id, timestamp, max_drift, now
+            ),
+            Self::TimestampBeforeParent {
+                id,
+                timestamp,
+                parent_timestamp,
+            } => write!(
+                f,
+                "block with id: {} has timestamp {}, more than {}s before its parent's timestamp {}",
+                id, timestamp, PARENT_TIMESTAMP_TOLERANCE, parent_timestamp
+            ),
+            Self::TimestampNotAfterMedian {
+                id,
+                timestamp,
+                median,
+            } => write!(
+                f,
+                "block with id: {} has timestamp {}, which is not after {}, the median timestamp of the blocks before it",
+                id, timestamp, median
+            ),
+            Self::NonSequentialId { expected, got } => write!(
+                f,
+                "block with id: {} is not the next block after the latest: {}",
+                got,
+                expected - 1
+            ),
+            Self::HashModeMismatch {
+                id,
+                block_mode,
+                local_mode,
+            } => write!(
+                f,
+                "block with id: {} has a hash mode mismatch - it was hashed with --hashing {}, but this node uses --hashing {}",
+                id, block_mode, local_mode
+            ),
+            Self::HashMismatch { id, hashing } => write!(
+                f,
+                "block with id: {} has invalid hash (is the peer using a different --hash-algorithm than {}?)",
+                id, hashing.algorithm
+            ),
+            Self::MerkleRootMismatch { id } => write!(
+                f,
+                "block with id: {} has a merkle root that doesn't match its data",
+                id
+            ),
+            Self::DataTooLarge { id, size, max } => write!(
+                f,
+                "block with id: {} carries {} bytes of data, more than the limit of {} bytes",
+                id, size, max
+            ),
+            Self::InvalidTransaction { id, error } => write!(
+                f,
+                "block with id: {} has an invalid transaction - {}",
+                id, error
+            ),
+            Self::DataPruned { id } => write!(
+                f,
+                "block with id: {} has had its data pruned, so it can't be validated",
+                id
+            ),
+            Self::MalformedGenesis { id, previous_hash } => write!(
+                f,
+                "chain starts with block id: {} and previous hash {}, rather than a genesis block",
+                id, previous_hash
+            ),
+            Self::ForeignGenesis { hash } => {
+                write!(f, "chain grown from foreign genesis block {}", hash)
+            }
+            Self::EmptyChain => write!(f, "chain has no blocks"),
+        }
+    }
+}
+
+// What the genesis block is built from.  Nodes that don't use the same spec (and hashing) have different genesis
+// blocks, so they never accept each other's chains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenesisSpec {
+    pub data: Vec<String>,
+    pub timestamp: i64,
+    // Balances every chain starts with.  Each one is committed to by an entry in the genesis block
+    pub allocations: Vec<(String, u64)>,
+}
+
+// 2022-01-01T00:00:00Z
+pub const DEFAULT_GENESIS_TIMESTAMP: i64 = 1_640_995_200;
+
+// Starts the genesis block entries that record an allocation
+const ALLOCATION_PREFIX: &str = "allocation:";
+
+impl Default for GenesisSpec {
+    fn default() -> Self {
+        Self {
+            data: vec![String::from("genesis!")],
+            timestamp: DEFAULT_GENESIS_TIMESTAMP,
+            allocations: vec![],
+        }
+    }
+}
+
+// Every node must start from an identical genesis block, so its nonce is the first one counting up from zero that
+// gives a hash with 16 leading zero bits under the configured hashing.  That means it meets any starting target of 16
+// bits or less.  The nonces for the default spec and --chain-id were mined once in advance, so starting a node normally
+// only needs a single hash.  Any other genesis block is mined again every time the node starts, which takes a moment
+pub fn create_genesis(chain_id: &str, spec: &GenesisSpec, hashing: &BlockHashing) -> Block {
+    let data: Vec<String> = spec
+        .data
+        .iter()
+        .cloned()
+        .chain(
+            spec.allocations
+                .iter()
+                .map(|(account, amount)| format!("{}{}={}", ALLOCATION_PREFIX, account, amount)),
+        )
+        .collect();
+    let target = Target::from_leading_zero_bits(16);
+    let mut header = BlockHeader {
+        version: BLOCK_VERSION,
+        chain_id: chain_id.to_owned(),
+        id: 0,
+        hash: BlockHash::ZERO,
+        previous_hash: BlockHash::ZERO,
+        timestamp: spec.timestamp,
+        merkle_root: merkle::merkle_root(&data),
+        nonce: 0,
+        difficulty: target.to_compact(),
+        miner: String::new(),
+        reward: 0,
+    };
+
+    if chain_id == DEFAULT_CHAIN_ID && *spec == GenesisSpec::default() {
+        header.nonce = genesis_nonce(hashing);
+        header.hash = calculate_hash(&header, hashing);
+    } else {
+        log::info!("mining the genesis block...");
+
+        loop {
+            header.hash = calculate_hash(&header, hashing);
+
+            if target.is_met_by(&header.hash) {
+                break;
+            }
+
+            header.nonce += 1;
+        }
+    }
+
+    debug_assert!(
+        target.is_met_by(&header.hash),
+        "genesis nonce for {} doesn't meet the genesis target",
+        hashing
+    );
+    Block { header, data }
+}
+
+fn genesis_nonce(hashing: &BlockHashing) -> u64 {
+    match (hashing.algorithm, hashing.mode) {
+        (HashAlgorithm::Sha256, HashMode::Single) => 16_154,
+        (HashAlgorithm::Sha256, HashMode::Double) => 119_462,
+        #[cfg(feature = "sha3")]
+        (HashAlgorithm::Sha3_256, HashMode::Single) => 26_260,
+        #[cfg(feature = "sha3")]
+        (HashAlgorithm::Sha3_256, HashMode::Double) => 71_744,
+        #[cfg(feature = "blake3")]
+        (HashAlgorithm::Blake3, HashMode::Single) => 51_548,
+        #[cfg(feature = "blake3")]
+        (HashAlgorithm::Blake3, HashMode::Double) => 166_869,
+    }
+}
+
+// The hash preimage of a block is a fixed byte layout, so that every node calculates the same hash for the same
+// block however it was built:
+//
+// | Field           | Encoding
+// |-----------------|---
+// | `version`       | u32, big-endian
+// | `chain_id`      | u32 big-endian length, then the UTF-8 bytes
+// | `id`            | u64, big-endian
+// | `timestamp`     | i64, big-endian
+// | `previous_hash` | 32 byte digest
+// | `merkle_root`   | 32 byte merkle root of the data entries
+// | `difficulty`    | u32, big-endian
+// | `miner`         | u32 big-endian length, then the UTF-8 bytes
+// | `reward`        | u64, big-endian
+// | `nonce`         | u64, big-endian
+//
+// The nonce comes last because it is the only field that changes from one mining attempt to the next, so each attempt
+// only has to overwrite the last 8 bytes of the preimage
+#[derive(Clone)]
+pub(crate) struct Preimage {
+    bytes: Vec<u8>,
+}
+
+impl Preimage {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        version: u32,
+        chain_id: &str,
+        id: u64,
+        timestamp: i64,
+        previous_hash: &BlockHash,
+        merkle_root: &BlockHash,
+        difficulty: u32,
+        miner: &str,
+        reward: u64,
+    ) -> Self {
+        let mut bytes = vec![];
+
+        bytes.extend(version.to_be_bytes());
+        bytes.extend((chain_id.len() as u32).to_be_bytes());
+        bytes.extend(chain_id.as_bytes());
+        bytes.extend(id.to_be_bytes());
+        bytes.extend(timestamp.to_be_bytes());
+        bytes.extend(previous_hash.as_bytes());
+        bytes.extend(merkle_root.as_bytes());
+        bytes.extend(difficulty.to_be_bytes());
+        bytes.extend((miner.len() as u32).to_be_bytes());
+        bytes.extend(miner.as_bytes());
+        bytes.extend(reward.to_be_bytes());
+        bytes.extend(0u64.to_be_bytes());
+
+        Self { bytes }
+    }
+
+    pub(crate) fn hash(&mut self, hashing: &BlockHashing, nonce: u64) -> BlockHash {
+        let nonce_at = self.bytes.len() - 8;
+
+        self.bytes[nonce_at..].copy_from_slice(&nonce.to_be_bytes());
+        BlockHash::from(hashing.hash(&self.bytes))
+    }
+}
+
+pub fn calculate_hash(header: &BlockHeader, hashing: &BlockHashing) -> BlockHash {
+    Preimage::new(
+        header.version,
+        &header.chain_id,
+        header.id,
+        header.timestamp,
+        &header.previous_hash,
+        &header.merkle_root,
+        header.difficulty,
+        &header.miner,
+        header.reward,
+    )
+    .hash(hashing, header.nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashing(mode: HashMode) -> BlockHashing {
+        BlockHashing {
+            mode,
+            ..BlockHashing::default()
+        }
+    }
+
+    // The hashes every node on the default network starts from.  A change to either means no node running an older
+    // build can accept the chain
+    #[test]
+    fn default_genesis_hashes_are_pinned() {
+        for (mode, nonce, hash) in [
+            (
+                HashMode::Single,
+                16_154,
+                "00009a66982074c13bb0f727f2226ff93505e2ee4bd23082d4a8dcb48bbf6f18",
+            ),
+            (
+                HashMode::Double,
+                119_462,
+                "00009556387a2dc89ea9c01a6161b6a4b9bb7fd299258d0ea9cf792a311fcfca",
+            ),
+        ] {
+            let genesis = create_genesis(DEFAULT_CHAIN_ID, &GenesisSpec::default(), &hashing(mode));
+
+            assert_eq!(genesis.header.nonce, nonce);
+            assert_eq!(genesis.header.hash.to_string(), hash);
+        }
+    }
+
+    // The precomputed nonces must be the ones mining from zero would find, or a node with another spec would disagree
+    #[test]
+    fn default_genesis_nonces_are_the_first_to_meet_16_bits() {
+        let target = Target::from_leading_zero_bits(16);
+
+        for mode in [HashMode::Single, HashMode::Double] {
+            let hashing = hashing(mode);
+            let genesis = create_genesis(DEFAULT_CHAIN_ID, &GenesisSpec::default(), &hashing);
+            let mut header = BlockHeader {
+                nonce: 0,
+                ..genesis.header.clone()
+            };
+
+            while !target.is_met_by(&calculate_hash(&header, &hashing)) {
+                header.nonce += 1;
+            }
+
+            assert_eq!(header.nonce, genesis.header.nonce);
+            assert_eq!(genesis.header.hash.as_bytes()[..2], [0, 0]);
+        }
+    }
+
+    fn fixtures() -> Vec<BlockHeader> {
+        let genesis = create_genesis(
+            DEFAULT_CHAIN_ID,
+            &GenesisSpec::default(),
+            &BlockHashing::default(),
+        )
+        .header;
+
+        vec![
+            genesis.clone(),
+            BlockHeader {
+                id: 1,
+                previous_hash: genesis.hash,
+                timestamp: genesis.timestamp + 10,
+                nonce: 7,
+                difficulty: 0x2100_7000,
+                miner: String::from("12D3KooWQEjDRALyMJEUpLgn15kSniF8Jeiwf5mu6jFcBRNYJeof"),
+                reward: 50,
+                ..genesis.clone()
+            },
+            BlockHeader {
+                chain_id: String::from("a network with a rather longer name than usual"),
+                id: u64::MAX,
+                timestamp: -1,
+                nonce: u64::MAX,
+                ..genesis
+            },
+        ]
+    }
+
+    // Mining reuses one preimage and only overwrites the nonce, which must give the same hash as validation building
+    // the whole preimage again from the header
+    #[test]
+    fn a_reused_preimage_hashes_the_same_as_a_new_one() {
+        for mode in [HashMode::Single, HashMode::Double] {
+            let hashing = hashing(mode);
+
+            for header in fixtures() {
+                let mut preimage = Preimage::new(
+                    header.version,
+                    &header.chain_id,
+                    header.id,
+                    header.timestamp,
+                    &header.previous_hash,
+                    &header.merkle_root,
+                    header.difficulty,
+                    &header.miner,
+                    header.reward,
+                );
+
+                for nonce in [0, 1, header.nonce, u64::MAX - 1, header.nonce] {
+                    let header = BlockHeader {
+                        nonce,
+                        ..header.clone()
+                    };
+
+                    assert_eq!(
+                        preimage.hash(&hashing, nonce),
+                        calculate_hash(&header, &hashing)
+                    );
+                }
+            }
+        }
+    }
+
+    fn golden_header() -> BlockHeader {
+        BlockHeader {
+            version: 3,
+            chain_id: String::from("ab"),
+            id: 1,
+            hash: BlockHash::ZERO,
+            previous_hash: BlockHash::from([0x11; 32]),
+            timestamp: 2,
+            merkle_root: BlockHash::from([0x22; 32]),
+            nonce: 7,
+            difficulty: 0x2000_ffff,
+            miner: String::from("m"),
+            reward: 50,
+        }
+    }
+
+    // Any change to the preimage layout changes every block hash, so the exact bytes are pinned here
+    #[test]
+    fn the_preimage_layout_is_pinned() {
+        let header = golden_header();
+        let mut preimage = Preimage::new(
+            header.version,
+            &header.chain_id,
+            header.id,
+            header.timestamp,
+            &header.previous_hash,
+            &header.merkle_root,
+            header.difficulty,
+            &header.miner,
+            header.reward,
+        );
+        preimage.hash(&BlockHashing::default(), header.nonce);
+
+        let mut expected = vec![];
+        expected.extend([0, 0, 0, 3]);
+        expected.extend([0, 0, 0, 2, b'a', b'b']);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 2]);
+        expected.extend([0x11; 32]);
+        expected.extend([0x22; 32]);
+        expected.extend([0x20, 0x00, 0xff, 0xff]);
+        expected.extend([0, 0, 0, 1, b'm']);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 50]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 7]);
+
+        assert_eq!(preimage.bytes, expected);
+    }
+
+    #[test]
+    fn block_hashes_are_pinned_in_both_modes() {
+        for (mode, hash) in [
+            (
+                HashMode::Single,
+                "567c6c7b4739b5d72004e9ceaf19fabccc554ba0ff5a9c7c8718af16b8fcdea8",
+            ),
+            (
+                HashMode::Double,
+                "7f3fa5d2c57fb562e57af5a766c3adaa1b33d6000df7262a0fcaa6abfaa4b692",
+            ),
+        ] {
+            assert_eq!(
+                calculate_hash(&golden_header(), &hashing(mode)).to_string(),
+                hash
+            );
+        }
+    }
+}
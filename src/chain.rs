@@ -0,0 +1,2562 @@
+use crate::{
+    audit::AuditLog,
+    block::{
+        calculate_hash, create_genesis, Block, BlockHeader, BlockValidationError, BLOCK_VERSION,
+        PARENT_TIMESTAMP_TOLERANCE,
+    },
+    config::Config,
+    hash::BlockHash,
+    hasher::BlockHashing,
+    ledger::{Ledger, Transaction},
+    mempool::Mempool,
+    merkle,
+    sanitize::sanitize,
+    mining::{stated_difficulty, MiningStats, DEV_DIFFICULTY, DEV_FAST_MINE},
+    snapshot::{Snapshot, SnapshotError},
+    store::{with_extension, ChainStore, FileStore},
+    target::{Target, U256},
+    wallet::Wallet,
+};
+use chrono::prelude::*;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::{Bound, Range, RangeBounds},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+// Work out the target required of the block at `height` by replaying the retarget rule over the preceding blocks.
+//
+// Every `retarget_interval` blocks, the time taken to produce the last window of blocks is compared against the
+// desired spacing.  Since halving the target doubles the expected work, the target only moves when blocks arrived
+// more than twice as fast, or more than twice as slowly, as intended
+pub fn expected_target(chain: &[Block], height: u64, config: &Config) -> Target {
+    let height = height.min(chain.len() as u64);
+
+    (1..=height).fold(config.target, |target, height| {
+        retarget(chain, height, target, config)
+    })
+}
+
+// The target required of the block at `height`, given the target required of the block before it.  This only changes
+// at the end of a retarget window, so a chain can be validated block by block without replaying every window from the
+// genesis block each time
+pub fn retarget(chain: &[Block], height: u64, target: Target, config: &Config) -> Target {
+    let interval = config.retarget_interval;
+
+    if height < interval || !height.is_multiple_of(interval) {
+        return target;
+    }
+
+    let target_elapsed = config.block_spacing * (interval as i64 - 1);
+    let first = &chain[(height - interval) as usize];
+    let last = &chain[(height - 1) as usize];
+    let elapsed = last.header.timestamp - first.header.timestamp;
+
+    if elapsed * 2 < target_elapsed {
+        target.harder()
+    } else if elapsed > target_elapsed * 2 {
+        target.easier()
+    } else {
+        target
+    }
+}
+
+// Median timestamp of the last `span` blocks of the chain, which the next block must be dated after.  A single node
+// with a bad clock can't move the median on its own.  A span of zero turns the rule off
+pub fn median_time_past(chain: &[Block], span: usize) -> Option<i64> {
+    let mut timestamps: Vec<i64> = chain
+        .iter()
+        .rev()
+        .take(span)
+        .map(|block| block.header.timestamp)
+        .collect();
+
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied()
+}
+
+// Total proof of work behind a chain.  Blocks that don't state a valid target contribute nothing
+pub fn chain_work(chain: &[Block]) -> U256 {
+    chain
+        .iter()
+        .filter_map(|block| Target::from_compact(block.header.difficulty).ok())
+        .fold(U256::zero(), |work, target| {
+            work.saturating_add(target.work())
+        })
+}
+
+// Why a block or a chain given to the App couldn't be used.  Nothing a peer sends can do worse than this, so the
+// node logs the error and carries on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainError {
+    Invalid(BlockValidationError),
+    // validate_block was given a block without the blocks before it
+    NoParent {
+        id: u64,
+    },
+    // Neither chain offered to choose_chain could be used
+    BothInvalid {
+        local: Box<ChainError>,
+        remote: Box<ChainError>,
+    },
+}
+
+impl From<BlockValidationError> for ChainError {
+    fn from(e: BlockValidationError) -> Self {
+        Self::Invalid(e)
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "{}", e),
+            Self::NoParent { id } => write!(
+                f,
+                "block with id: {} can't be validated without the blocks before it",
+                id
+            ),
+            Self::BothInvalid { local, remote } => write!(
+                f,
+                "local and remote chains are both invalid: local {}, remote {}",
+                local, remote
+            ),
+        }
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Blockchain App
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Orphans are forgotten after this long, and at most this many are kept at a time
+const ORPHAN_EXPIRY: Duration = Duration::from_secs(60);
+const MAX_ORPHANS: usize = 32;
+
+// Bytes of `haystack` taken up by the first occurrence of `text`.  Ignoring case compares the lower case forms of
+// both, which can differ in length from the original
+fn find_text(haystack: &str, text: &str, ignore_case: bool) -> Option<Range<usize>> {
+    if !ignore_case {
+        return haystack.find(text).map(|start| start..start + text.len());
+    }
+
+    let wanted: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut lowered = vec![];
+
+        for (i, c) in haystack[start..].char_indices() {
+            lowered.extend(c.to_lowercase());
+
+            if !wanted.starts_with(&lowered) {
+                return None;
+            }
+
+            if lowered.len() == wanted.len() {
+                return Some(start..start + i + c.len_utf8());
+            }
+        }
+
+        None
+    })
+}
+
+// A block that arrived before its parent
+pub struct Orphan {
+    pub block: Block,
+    pub received: Instant,
+}
+
+// The ledger as of a block on the local chain, which is either the last block whose data has been pruned, or the tip
+// of the stored chain when the node started from a snapshot.  A chain that still holds that block is replayed from
+// here rather than from the start, which is the only way to replay it past blocks that have been pruned
+struct Checkpoint {
+    height: usize,
+    hash: BlockHash,
+    ledger: Ledger,
+}
+
+// Seconds between consecutive blocks
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BlockIntervals {
+    pub average: f64,
+    pub min: i64,
+    pub max: i64,
+}
+
+// Summary of the local chain
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainStats {
+    pub chain_id: String,
+    pub height: u64,
+    pub tip_hash: BlockHash,
+    pub genesis_hash: BlockHash,
+    // In decimal, since it can be too large for a JSON number
+    pub chain_work: String,
+    // Required of the next block on the chain
+    pub target: Target,
+    // Over the most recent blocks, or None while the chain holds only the genesis block
+    pub intervals: Option<BlockIntervals>,
+}
+
+// The chain was switched to a competing branch.  The blocks above `fork_height` were rolled back, and replaced by the
+// blocks of the branch
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reorg {
+    pub fork_height: u64,
+    pub rolled_back: Vec<BlockHash>,
+    pub applied: Vec<BlockHash>,
+}
+
+// Sent to every reorg subscriber whenever blocks leave the local chain, whether for a competing branch or for a chain
+// from a peer.  The blocks above `fork_height` were rolled back, and replaced by the applied blocks.  Switching to a
+// chain that only extends the local one rolls nothing back, so it isn't a reorg
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReorgEvent {
+    pub fork_height: u64,
+    pub rolled_back: Vec<Block>,
+    pub applied: Vec<Block>,
+}
+
+// A data entry on the local chain that contains the text searched for, which takes up bytes `range` of the entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataMatch<'a> {
+    pub block: &'a Block,
+    pub entry: usize,
+    pub range: Range<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddBlockOutcome {
+    // The block extends the chain, followed by this many orphans that were waiting for it
+    Added { orphans_attached: usize },
+    // The block extends a competing branch that doesn't have more work behind it than the chain
+    SideChain,
+    // The block, or an orphan that was waiting for it, gave a competing branch more work than the chain
+    Reorg(Reorg),
+    // The block is already on the chain or a competing branch, or already waiting for its parent
+    Duplicate,
+    // The block's parent isn't on the chain, so it has been kept until the parent arrives
+    Orphan,
+}
+
+pub struct App {
+    // Read the chain through tip(), height(), iter(), range() and the other accessors rather than directly.  The field
+    // is only still public while callers move over to them, and will become private
+    pub blocks: Vec<Block>,
+    // Height of every block on the local chain, keyed by block hash
+    block_index: HashMap<BlockHash, usize>,
+    pub config: Config,
+    // Stats for the blocks mined by this node, keyed by block hash.  Entries are never removed, so the stats of any
+    // locally mined block that survives a reorg are still available
+    pub mining_stats: HashMap<BlockHash, MiningStats>,
+    pub mempool: Mempool,
+    // Account balances as of the tip of the local chain
+    pub ledger: Ledger,
+    pub wallet: Wallet,
+    // Every chain this node accepts must start from this block
+    pub genesis_block: Block,
+    // Blocks waiting for their parent to arrive, keyed by the hash of that parent
+    pub orphans: HashMap<BlockHash, Vec<Orphan>>,
+    // Valid blocks that aren't on the chain, but descend from a block that is.  Each one is the tip of, or part of, a
+    // branch that competes with the chain from the block where they meet
+    pub side_blocks: HashMap<BlockHash, Block>,
+    // The last remote chain that would have rolled back more than --max-reorg-depth blocks, kept for accept-reorg
+    pub refused_chain: Option<Vec<Block>>,
+    // Anything that wants to know when blocks leave the chain (the command loop, for instance) can add a sender here
+    pub reorg_subscribers: Vec<mpsc::UnboundedSender<ReorgEvent>>,
+    // Every block received or mined, and what became of it
+    pub audit_log: AuditLog,
+    // Where the chain is kept between runs, if anywhere
+    store: Option<Box<dyn ChainStore>>,
+    // Length and tip hash of the local chain when it was last known to be valid.  Every block on the chain is covered
+    // by the tip hash, so a chain that still matches never needs validating again
+    validated: Option<(usize, BlockHash)>,
+    // Every block that has been pruned is at or below the checkpoint
+    checkpoint: Option<Checkpoint>,
+}
+
+// The App as shared between tasks.  The command loop is the only writer, so blocks are still validated and added in
+// one place, while anything else (an HTTP server or a metrics task, say) can take a read lock to look at the chain
+pub type SharedApp = Arc<RwLock<App>>;
+
+impl App {
+    // The chain starts out holding just the genesis block, or the stored chain if there is one, so it is never empty,
+    // even before the chain has been asked for from a peer
+    pub fn new(config: Config) -> Result<Self, String> {
+        let store = match &config.db_path {
+            Some(path) => Some(Box::new(
+                FileStore::open(path).map_err(|e| format!("can't open {}: {}", path.display(), e))?,
+            ) as Box<dyn ChainStore>),
+            None => None,
+        };
+        let wallet = match &config.wallet_path {
+            Some(path) => Wallet::open(path)
+                .map_err(|e| format!("can't open the wallet {}: {}", path.display(), e))?,
+            None => Wallet::generate(),
+        };
+        let audit_log = match &config.audit_log {
+            Some(path) => AuditLog::open(path)
+                .map_err(|e| format!("can't open the audit log {}: {}", path.display(), e))?,
+            None => AuditLog::new(),
+        };
+        let genesis_block = create_genesis(&config.chain_id, &config.genesis, &config.hashing);
+        let mempool = Mempool::new(config.mempool_size);
+        let ledger = Ledger::new(&config.allocations);
+        let mut app = Self {
+            blocks: vec![genesis_block.clone()],
+            block_index: HashMap::new(),
+            genesis_block,
+            config,
+            mining_stats: HashMap::new(),
+            mempool,
+            ledger,
+            wallet,
+            orphans: HashMap::new(),
+            side_blocks: HashMap::new(),
+            refused_chain: None,
+            reorg_subscribers: vec![],
+            audit_log,
+            store,
+            validated: None,
+            checkpoint: None,
+        };
+
+        app.load_chain()?;
+        app.index_chain();
+        app.mark_validated();
+        app.prune();
+        Ok(app)
+    }
+
+    // Picks up where the stored chain left off.  If nothing has been stored yet, the genesis block is stored, and any
+    // stored blocks after the first one that isn't valid are removed
+    fn load_chain(&mut self) -> Result<(), String> {
+        let Some(store) = &mut self.store else {
+            return Ok(());
+        };
+
+        let stored = store
+            .load()
+            .map_err(|e| format!("can't read the stored chain: {}", e))?;
+
+        let Some(genesis) = stored.first() else {
+            self.save_chain(0);
+            return Ok(());
+        };
+
+        self.validate_genesis(genesis).map_err(|e| {
+            format!(
+                "the stored chain can't be used - {} - give this node a different --db-path",
+                e
+            )
+        })?;
+
+        // The stored blocks are on the local chain while they are validated, so that limits such as --max-data-size
+        // that have since been lowered don't apply to them
+        let stored_len = stored.len();
+        self.blocks = stored;
+
+        if self.trust_snapshot() {
+            log::info!(
+                "loaded {} blocks from the stored chain, which matches its snapshot",
+                self.blocks.len()
+            );
+            return Ok(());
+        }
+
+        if let Some((valid_len, e)) = self.first_invalid_block() {
+            log::warn!(
+                "removing {} stored blocks from block {} onwards - {}",
+                stored_len - valid_len,
+                valid_len,
+                e
+            );
+            self.blocks.truncate(valid_len);
+            self.save_chain(valid_len);
+        }
+
+        log::info!("loaded {} blocks from the stored chain", self.blocks.len());
+        Ok(())
+    }
+
+    // A snapshot that matches the stored chain stands in for validating it, and its balances for replaying its
+    // transactions.  Returns false if there is no such snapshot
+    fn trust_snapshot(&mut self) -> bool {
+        let Some(path) = self.snapshot_path() else {
+            return false;
+        };
+
+        let snapshot = match Snapshot::read(&path) {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return false,
+            Err(e) => {
+                log::warn!("ignoring the snapshot in {} - {}", path.display(), e);
+                return false;
+            }
+        };
+
+        match snapshot.verify(&self.blocks) {
+            Ok(()) => {}
+            Err(e @ SnapshotError::WrongTip { .. }) => {
+                log::info!("not using the snapshot in {} - {}", path.display(), e);
+                return false;
+            }
+            Err(e) => {
+                log::warn!("ignoring the snapshot in {} - {}", path.display(), e);
+                return false;
+            }
+        }
+
+        self.checkpoint = Some(Checkpoint {
+            height: self.blocks.len() - 1,
+            hash: self.tip().header.hash,
+            ledger: snapshot.ledger(),
+        });
+        true
+    }
+
+    fn snapshot_path(&self) -> Option<PathBuf> {
+        self.config
+            .db_path
+            .as_deref()
+            .map(|path| with_extension(path, "snapshot"))
+    }
+
+    // Snapshots are taken of the chain as stored, which still has the data of any blocks that have been pruned.
+    // Returns where the snapshot was written
+    pub fn write_snapshot(&mut self) -> Result<PathBuf, String> {
+        let (Some(path), Some(store)) = (self.snapshot_path(), &mut self.store) else {
+            return Err(String::from(
+                "there is no stored chain to take a snapshot of, as this node is running with --ephemeral",
+            ));
+        };
+
+        let stored = store
+            .load()
+            .map_err(|e| format!("can't read the stored chain: {}", e))?;
+
+        if stored.len() != self.blocks.len()
+            || stored.last().map(|block| block.header.hash) != Some(self.tip().header.hash)
+        {
+            return Err(String::from(
+                "the stored chain is out of step with the local chain",
+            ));
+        }
+
+        Snapshot::take(&stored, &self.ledger)
+            .write(&path)
+            .map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    // The height and error of the first block on the local chain, after the genesis block, that isn't valid
+    fn first_invalid_block(&self) -> Option<(usize, ChainError)> {
+        self.first_invalid_from(&self.blocks, 1)
+    }
+
+    fn save_tip(&mut self) {
+        if let (Some(store), Some(tip)) = (&mut self.store, self.blocks.last()) {
+            if let Err(e) = store.append(tip) {
+                log::error!("could not store block {} - {}", tip.header.id, e);
+            }
+        }
+    }
+
+    // Stores the chain from `height` onwards, where it no longer matches what was stored
+    fn save_chain(&mut self, height: usize) {
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.replace_from(height, &self.blocks[height..]) {
+                log::error!("could not store the chain - {}", e);
+            }
+        }
+    }
+
+    // The latest block on the chain
+    // There is no need for an Option here, because the chain always holds at least the genesis block
+    pub fn tip(&self) -> &Block {
+        match self.blocks.last() {
+            Some(tip) => tip,
+            None => unreachable!("the chain always holds at least the genesis block"),
+        }
+    }
+
+    // Id of the tip, which is 0 while the chain holds only the genesis block
+    pub fn height(&self) -> u64 {
+        self.tip().header.id
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
+    pub fn chain(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    // The blocks whose ids fall in `ids`, cut short at the tip.  A range that starts after the tip gives no blocks
+    pub fn range(&self, ids: impl RangeBounds<u64>) -> &[Block] {
+        let len = self.blocks.len() as u64;
+        let start = match ids.start_bound() {
+            Bound::Included(&id) => id,
+            Bound::Excluded(&id) => id.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match ids.end_bound() {
+            Bound::Included(&id) => id.saturating_add(1),
+            Bound::Excluded(&id) => id,
+            Bound::Unbounded => len,
+        }
+        .min(len);
+
+        &self.blocks[start.min(end) as usize..end as usize]
+    }
+
+    // Blocks dated at or after `timestamp`.  Timestamps only have to be later than the median of the blocks before
+    // them, so they aren't strictly in order, and every block has to be checked
+    pub fn blocks_since(&self, timestamp: i64) -> impl Iterator<Item = &Block> {
+        self.blocks
+            .iter()
+            .filter(move |block| block.header.timestamp >= timestamp)
+    }
+
+    // Every data entry on the chain that contains `text`, oldest first, along with where the first occurrence of `text`
+    // is.  Pruned blocks have no data left to search
+    pub fn find_data(&self, text: &str, ignore_case: bool) -> Vec<DataMatch<'_>> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.data.iter().enumerate().map(move |entry| (block, entry)))
+            .filter_map(|(block, (entry, data))| {
+                find_text(data, text, ignore_case).map(|range| DataMatch { block, entry, range })
+            })
+            .collect()
+    }
+
+    // Starts again from just the genesis block, forgetting everything else that was received or queued, and the
+    // stored chain along with it
+    pub fn reset(&mut self) {
+        self.blocks = vec![self.genesis_block.clone()];
+        self.mempool = Mempool::new(self.config.mempool_size);
+        self.orphans.clear();
+        self.side_blocks.clear();
+        self.refused_chain = None;
+        self.checkpoint = None;
+        self.index_chain();
+        self.mark_validated();
+        self.save_chain(0);
+    }
+
+    // Replaces the local chain with one that choose_chain has already validated.  Competing branches may no longer
+    // meet the new chain, so they are forgotten
+    fn set_chain(&mut self, blocks: Vec<Block>) {
+        let shared = self.find_common_ancestor(&blocks).map_or(0, |ancestor| ancestor + 1);
+
+        self.remove_mined(&blocks[shared..]);
+        let mut old_chain = std::mem::replace(&mut self.blocks, blocks);
+        self.side_blocks.clear();
+        self.index_chain();
+        self.mark_validated();
+        self.save_chain(shared);
+        self.prune();
+
+        // Without a common ancestor, even the old genesis block is rolled back
+        self.emit_reorg(ReorgEvent {
+            fork_height: shared.saturating_sub(1) as u64,
+            rolled_back: old_chain.split_off(shared),
+            applied: self.blocks[shared..].to_vec(),
+        });
+    }
+
+    // Logs the reorg in a single line, and sends it to every subscriber that is still listening
+    fn emit_reorg(&mut self, event: ReorgEvent) {
+        if event.rolled_back.is_empty() {
+            return;
+        }
+
+        let hashes = |blocks: &[Block]| {
+            blocks
+                .iter()
+                .map(|block| block.header.hash.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        log::warn!(
+            "reorg fork_height={} rolled_back=[{}] applied=[{}]",
+            event.fork_height,
+            hashes(&event.rolled_back),
+            hashes(&event.applied)
+        );
+        self.reorg_subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    // Number of blocks of the local chain that switching to `chain` would roll back
+    pub fn reorg_depth(&self, chain: &[Block]) -> u64 {
+        let shared = self
+            .find_common_ancestor(chain)
+            .map_or(0, |height| height + 1);
+        (self.blocks.len() - shared) as u64
+    }
+
+    // Switches to a chain picked by choose_chain, unless that would roll back more than --max-reorg-depth blocks.
+    // Returns true if the chain was switched.  Only chains validated by choose_chain can be passed in, so this isn't
+    // public outside the crate
+    pub(crate) fn switch_chain(&mut self, blocks: Vec<Block>) -> bool {
+        let Some(remote) = blocks.last().map(|block| block.header.clone()) else {
+            unreachable!("choose_chain never picks a chain without a genesis block");
+        };
+        let depth = self.reorg_depth(&blocks);
+
+        if depth <= self.config.max_reorg_depth {
+            self.set_chain(blocks);
+            return true;
+        }
+
+        let local = &self.tip().header;
+
+        log::warn!(
+            "REFUSING REORG - the remote chain would roll back {} blocks, more than --max-reorg-depth {}",
+            depth,
+            self.config.max_reorg_depth
+        );
+        log::warn!("local tip:  block {} {}", local.id, local.hash);
+        log::warn!("remote tip: block {} {}", remote.id, remote.hash);
+        log::warn!("use `accept-reorg {}` to switch to it anyway", remote.hash);
+        self.refused_chain = Some(blocks);
+        false
+    }
+
+    // Switches to the refused chain with the given tip, whatever the depth.  Returns false if there is no such chain
+    pub fn accept_refused_chain(&mut self, tip: &BlockHash) -> bool {
+        match self
+            .refused_chain
+            .take_if(|chain| chain.last().is_some_and(|block| block.header.hash == *tip))
+        {
+            Some(chain) => {
+                self.set_chain(chain);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn mark_validated(&mut self) {
+        self.validated = Some((self.blocks.len(), self.tip().header.hash));
+    }
+
+    fn is_validated(&self) -> bool {
+        self.validated == Some((self.blocks.len(), self.tip().header.hash))
+    }
+
+    // Height of the last block that `chain` shares with the local chain, or None if they don't even share a genesis
+    // block
+    pub fn find_common_ancestor(&self, chain: &[Block]) -> Option<usize> {
+        self.blocks
+            .iter()
+            .zip(chain)
+            .take_while(|(local, remote)| local.header.hash == remote.header.hash)
+            .count()
+            .checked_sub(1)
+    }
+
+    pub fn chain_work(&self) -> U256 {
+        chain_work(&self.blocks)
+    }
+
+    // Earliest timestamp the next block on the local chain may have
+    pub fn min_next_timestamp(&self) -> i64 {
+        median_time_past(&self.blocks, self.config.median_time_span)
+            .map_or(i64::MIN, |median| median + 1)
+    }
+
+    // Confirmed balance as of the tip of the local chain.  An account that has never been used has a balance of 0
+    pub fn balance_of(&self, address: &str) -> u64 {
+        self.ledger.account(address).balance
+    }
+
+    // How much the transactions waiting to be mined would change the balance by
+    pub fn pending_delta(&self, address: &str) -> i128 {
+        self.mempool
+            .iter()
+            .filter_map(|entry| Transaction::decode(entry)?.ok())
+            .map(|transaction| {
+                let amount = i128::from(transaction.amount);
+                let received = if transaction.to == address { amount } else { 0 };
+                let sent = if transaction.from == address { amount } else { 0 };
+
+                received - sent
+            })
+            .sum()
+    }
+
+    // Nonce of the next transaction `address` sends, counting the ones still waiting to be mined
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        let waiting = self
+            .mempool
+            .iter()
+            .filter_map(|entry| Transaction::decode(entry)?.ok())
+            .filter(|transaction| transaction.from == address)
+            .count() as u64;
+
+        self.ledger.account(address).nonce + waiting + 1
+    }
+
+    // Total reward earned by each miner on the chain.  The genesis block has no miner
+    pub fn rewards(&self) -> BTreeMap<&str, u64> {
+        let mut rewards = BTreeMap::new();
+
+        for block in self.blocks.iter().skip(1) {
+            *rewards.entry(block.header.miner.as_str()).or_insert(0) += block.header.reward;
+        }
+
+        rewards
+    }
+
+    // Timing of the last `count` blocks, measured from the block before each one
+    pub fn block_intervals(&self, count: usize) -> Option<BlockIntervals> {
+        let recent = &self.blocks[self.blocks.len().saturating_sub(count + 1)..];
+        let intervals: Vec<i64> = recent
+            .windows(2)
+            .map(|pair| pair[1].header.timestamp - pair[0].header.timestamp)
+            .collect();
+
+        Some(BlockIntervals {
+            average: intervals.iter().sum::<i64>() as f64 / intervals.len() as f64,
+            min: *intervals.iter().min()?,
+            max: *intervals.iter().max()?,
+        })
+    }
+
+    pub fn stats(&self, interval_blocks: usize) -> ChainStats {
+        ChainStats {
+            chain_id: self.config.chain_id.clone(),
+            height: self.height(),
+            tip_hash: self.tip().header.hash,
+            genesis_hash: self.genesis_block.header.hash,
+            chain_work: self.chain_work().to_string(),
+            target: self.expected_target_at(self.height() + 1),
+            intervals: self.block_intervals(interval_blocks),
+        }
+    }
+
+    // Target required of the block at the given height on the local chain
+    pub fn expected_target_at(&self, height: u64) -> Target {
+        expected_target(&self.blocks, height, &self.config)
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Option<&Block> {
+        let height = self.block_index.get(&hash.parse().ok()?)?;
+
+        self.blocks.get(*height)
+    }
+
+    pub fn get_block_by_id(&self, id: u64) -> Option<&Block> {
+        self.blocks.get(usize::try_from(id).ok()?)
+    }
+
+    // Finds a block by its 64 character hash, or otherwise by its id.  The error says why there is no such block
+    pub fn lookup_block(&self, block_ref: &str) -> Result<&Block, String> {
+        if block_ref.len() == 64 {
+            self.get_block_by_hash(block_ref).ok_or_else(|| {
+                format!(
+                    "there is no block with hash {} on the chain",
+                    sanitize(block_ref)
+                )
+            })
+        } else if let Ok(id) = block_ref.parse::<u64>() {
+            self.get_block_by_id(id).ok_or_else(|| {
+                format!(
+                    "there is no block {} - the chain only goes up to block {}",
+                    id,
+                    self.height()
+                )
+            })
+        } else {
+            Err(format!(
+                "{} is neither a block id nor a block hash",
+                sanitize(block_ref)
+            ))
+        }
+    }
+
+    // Rebuilds the hash index and the ledger from scratch whenever the local chain is replaced, so that blocks no
+    // longer on it can't be found through the index, and their transactions no longer count
+    fn index_chain(&mut self) {
+        self.block_index = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(height, block)| (block.header.hash, height))
+            .collect();
+        self.ledger = match self.replay_ledger(&self.blocks) {
+            Ok(ledger) => ledger,
+            Err(e) => unreachable!("the local chain has been validated, but {}", e),
+        };
+
+        let blocks = &self.blocks;
+        self.checkpoint = self.checkpoint.take().filter(|checkpoint| {
+            blocks
+                .get(checkpoint.height)
+                .is_some_and(|block| block.header.hash == checkpoint.hash)
+        });
+    }
+
+    // The ledger as of the last block of `chain`, which must already have been validated
+    fn ledger_at(&self, chain: &[Block]) -> Result<Ledger, BlockValidationError> {
+        let tip = chain.last().map(|block| block.header.hash);
+
+        if chain.len() == self.blocks.len() && tip == Some(self.tip().header.hash) {
+            return Ok(self.ledger.clone());
+        }
+
+        self.replay_ledger(chain)
+    }
+
+    // Replays `chain` from the checkpoint if the chain still holds the block it was taken at, and from the
+    // --allocations otherwise.  Either way, none of the blocks replayed can have been pruned
+    fn replay_ledger(&self, chain: &[Block]) -> Result<Ledger, BlockValidationError> {
+        let (start, ledger) = match &self.checkpoint {
+            Some(checkpoint)
+                if chain
+                    .get(checkpoint.height)
+                    .is_some_and(|block| block.header.hash == checkpoint.hash) =>
+            {
+                (checkpoint.height + 1, checkpoint.ledger.clone())
+            }
+            _ => (0, Ledger::new(&self.config.allocations)),
+        };
+
+        if let Some(block) = chain[start..].iter().find(|block| block.is_pruned()) {
+            return Err(BlockValidationError::DataPruned {
+                id: block.header.id,
+            });
+        }
+
+        ledger
+            .replay(&chain[start..])
+            .map_err(|(id, error)| BlockValidationError::InvalidTransaction { id, error })
+    }
+
+    // Discards the data of the blocks more than --prune-keep blocks below the tip, after moving the checkpoint up to
+    // the last of them.  The genesis block keeps its data, so that chains can still be checked against it.  Only the
+    // copy in memory is pruned, so this must come after the blocks have been stored.
+    //
+    // A checkpoint taken from a snapshot can be above the blocks that are due to be pruned.  It can't be moved back
+    // down, so pruning waits until the chain has grown past it
+    fn prune(&mut self) {
+        let Some(keep) = self.config.prune_keep else {
+            return;
+        };
+        let end = self.blocks.len().saturating_sub(keep);
+
+        if end <= 1 {
+            return;
+        }
+
+        match &self.checkpoint {
+            Some(checkpoint) if checkpoint.height + 1 > end => return,
+            Some(checkpoint) if checkpoint.height + 1 == end => {}
+            _ => {
+                let ledger = match self.replay_ledger(&self.blocks[..end]) {
+                    Ok(ledger) => ledger,
+                    Err(e) => unreachable!("the local chain has been validated, but {}", e),
+                };
+
+                self.checkpoint = Some(Checkpoint {
+                    height: end - 1,
+                    hash: self.blocks[end - 1].header.hash,
+                    ledger,
+                });
+            }
+        }
+
+        // Everything below the first block found already pruned was pruned along with it
+        for block in self.blocks[1..end]
+            .iter_mut()
+            .rev()
+            .take_while(|block| !block.is_pruned())
+        {
+            block.data = vec![];
+        }
+    }
+
+    fn height_of(&self, hash: &BlockHash) -> Option<usize> {
+        self.block_index.get(hash).copied()
+    }
+
+    // True only if the block is on the chain itself
+    pub fn on_chain(&self, hash: &BlockHash) -> bool {
+        self.height_of(hash).is_some()
+    }
+
+    // Looks on the chain and then on the competing branches
+    pub fn find_block(&self, hash: &BlockHash) -> Option<&Block> {
+        self.height_of(hash)
+            .map(|height| &self.blocks[height])
+            .or_else(|| self.side_blocks.get(hash))
+    }
+
+    // True if the block is on the chain or a competing branch, or waiting for its parent
+    pub fn has_block(&self, hash: &BlockHash) -> bool {
+        self.find_block(hash).is_some()
+            || self
+                .orphans
+                .values()
+                .flatten()
+                .any(|orphan| orphan.block.header.hash == *hash)
+    }
+
+    // A block whose parent isn't known is kept until the parent arrives.  Adding a block attaches any orphans that
+    // were waiting for it, and then any that were waiting for them
+    pub fn try_add_block(&mut self, block: Block) -> Result<AddBlockOutcome, ChainError> {
+        self.expire_orphans();
+        self.prune_side_blocks();
+
+        if self.has_block(&block.header.hash) {
+            return Ok(AddBlockOutcome::Duplicate);
+        }
+
+        if self.fork_point(&block.header.previous_hash).is_none() {
+            self.add_orphan(block);
+            return Ok(AddBlockOutcome::Orphan);
+        }
+
+        let mut old_chain = None;
+        let mut parents = vec![block.header.hash];
+        let mut extended = self.connect_block(block, &mut old_chain)?;
+        let mut orphans_attached = 0;
+
+        while let Some(parent) = parents.pop() {
+            for orphan in self.orphans.remove(&parent).unwrap_or_default() {
+                let hash = orphan.block.header.hash;
+
+                match self.connect_block(orphan.block, &mut old_chain) {
+                    Ok(on_chain) => {
+                        extended |= on_chain;
+                        orphans_attached += 1;
+                        parents.push(hash);
+                    }
+                    Err(e) => log::warn!("dropping orphan - {}", e),
+                }
+            }
+        }
+
+        Ok(match old_chain {
+            Some(old_chain) => {
+                let reorg = self.describe_reorg(&old_chain);
+                let fork_height = reorg.fork_height as usize;
+
+                // The rolled back blocks have just become a competing branch
+                self.emit_reorg(ReorgEvent {
+                    fork_height: reorg.fork_height,
+                    rolled_back: reorg
+                        .rolled_back
+                        .iter()
+                        .filter_map(|hash| self.side_blocks.get(hash).cloned())
+                        .collect(),
+                    applied: self.blocks[fork_height + 1..].to_vec(),
+                });
+                AddBlockOutcome::Reorg(reorg)
+            }
+            None if extended => AddBlockOutcome::Added { orphans_attached },
+            None => AddBlockOutcome::SideChain,
+        })
+    }
+
+    // Adds a block whose parent is known, either to the tip of the chain or to a competing branch.  A branch that ends
+    // up with more work behind it than the chain replaces it, and the blocks it replaces become a competing branch in
+    // turn.  The hashes of the chain as it was before the first such switch are saved in `old_chain`.
+    //
+    // Returns true if the block ended up on the chain
+    fn connect_block(
+        &mut self,
+        block: Block,
+        old_chain: &mut Option<Vec<BlockHash>>,
+    ) -> Result<bool, ChainError> {
+        if block.header.previous_hash == self.tip().header.hash {
+            self.extend_chain(block)?;
+            return Ok(true);
+        }
+
+        // try_add_block keeps blocks whose parent isn't known as orphans, and only connects an orphan once its parent
+        // has been connected
+        let Some(fork) = self.fork_point(&block.header.previous_hash) else {
+            unreachable!("only blocks whose parent is known are connected");
+        };
+        let mut branch = self.blocks[..=fork].to_vec();
+        branch.extend(self.branch_from(fork, &block.header.previous_hash));
+
+        let target = expected_target(&branch, branch.len() as u64, &self.config);
+        self.validate_block(&block, &branch, target)?;
+        self.ledger_at(&branch)?
+            .apply(&block)
+            .map_err(|error| BlockValidationError::InvalidTransaction {
+                id: block.header.id,
+                error,
+            })?;
+
+        let branch_work =
+            chain_work(&branch).saturating_add(chain_work(std::slice::from_ref(&block)));
+        let outweighs = (branch_work, branch.len() + 1) > (self.chain_work(), self.blocks.len());
+        let depth = (self.blocks.len() - 1 - fork) as u64;
+
+        if outweighs && depth > self.config.max_reorg_depth {
+            log::warn!(
+                "not switching to the branch forking off at block {} - it would roll back {} blocks, more than --max-reorg-depth {}",
+                fork,
+                depth,
+                self.config.max_reorg_depth
+            );
+        }
+
+        if !outweighs || depth > self.config.max_reorg_depth {
+            self.side_blocks.insert(block.header.hash, block);
+            return Ok(false);
+        }
+
+        branch.push(block);
+
+        old_chain.get_or_insert_with(|| self.blocks.iter().map(|b| b.header.hash).collect());
+
+        for rolled_back in self.blocks.split_off(fork + 1) {
+            self.side_blocks
+                .insert(rolled_back.header.hash, rolled_back);
+        }
+
+        for applied in &branch[fork + 1..] {
+            self.side_blocks.remove(&applied.header.hash);
+        }
+
+        self.remove_mined(&branch[fork + 1..]);
+
+        self.blocks = branch;
+        self.index_chain();
+        self.mark_validated();
+        self.save_chain(fork + 1);
+        self.prune();
+        Ok(true)
+    }
+
+    // Height of the block on the chain that the given block descends from, following competing branches back to it
+    fn fork_point(&self, hash: &BlockHash) -> Option<usize> {
+        let mut hash = *hash;
+
+        loop {
+            if let Some(height) = self.height_of(&hash) {
+                return Some(height);
+            }
+
+            hash = self.side_blocks.get(&hash)?.header.previous_hash;
+        }
+    }
+
+    // The competing blocks from just after the fork point up to and including the one with the given hash
+    fn branch_from(&self, fork: usize, hash: &BlockHash) -> Vec<Block> {
+        let mut branch = vec![];
+        let mut hash = *hash;
+
+        while hash != self.blocks[fork].header.hash {
+            let Some(block) = self.side_blocks.get(&hash) else {
+                unreachable!("a fork point is only reached through the side blocks before it");
+            };
+            branch.push(block.clone());
+            hash = block.header.previous_hash;
+        }
+
+        branch.reverse();
+        branch
+    }
+
+    fn describe_reorg(&self, old_chain: &[BlockHash]) -> Reorg {
+        let fork_height = self
+            .blocks
+            .iter()
+            .zip(old_chain)
+            .take_while(|(block, hash)| block.header.hash == **hash)
+            .count()
+            - 1;
+
+        Reorg {
+            fork_height: fork_height as u64,
+            rolled_back: old_chain[fork_height + 1..].to_vec(),
+            applied: self.blocks[fork_height + 1..]
+                .iter()
+                .map(|b| b.header.hash)
+                .collect(),
+        }
+    }
+
+    fn prune_side_blocks(&mut self) {
+        let tip_id = self.tip().header.id;
+        let max_depth = self.config.max_reorg_depth;
+
+        // Switching to a branch that forks off any lower than this would roll back too many blocks
+        self.side_blocks
+            .retain(|_, block| tip_id.saturating_sub(max_depth) < block.header.id);
+    }
+
+    // Whether this exact block is already on the local chain
+    fn holds(&self, block: &Block) -> bool {
+        self.blocks
+            .get(block.header.id as usize)
+            .is_some_and(|held| held.header.hash == block.header.hash)
+    }
+
+    // Data that has made it onto the chain doesn't need mining again
+    fn remove_mined(&mut self, blocks: &[Block]) {
+        for entry in blocks.iter().flat_map(|block| &block.data) {
+            self.mempool.remove(entry);
+        }
+    }
+
+    fn extend_chain(&mut self, block: Block) -> Result<(), ChainError> {
+        let target = self.expected_target_at(self.blocks.len() as u64);
+
+        self.validate_block(&block, &self.blocks, target)?;
+        self.ledger
+            .apply(&block)
+            .map_err(|error| BlockValidationError::InvalidTransaction {
+                id: block.header.id,
+                error,
+            })?;
+        self.remove_mined(std::slice::from_ref(&block));
+        self.block_index
+            .insert(block.header.hash, self.blocks.len());
+        self.blocks.push(block);
+        self.mark_validated();
+        self.save_tip();
+        self.prune();
+        Ok(())
+    }
+
+    // When the buffer is full, the oldest orphan makes way for the new one
+    fn add_orphan(&mut self, block: Block) {
+        if self.orphans.values().map(Vec::len).sum::<usize>() >= MAX_ORPHANS {
+            let oldest = self
+                .orphans
+                .iter()
+                .flat_map(|(parent, orphans)| {
+                    orphans.iter().map(move |orphan| (orphan.received, *parent))
+                })
+                .min()
+                .map(|(_, parent)| parent);
+
+            if let Some(parent) = oldest {
+                if let Some(orphans) = self.orphans.get_mut(&parent) {
+                    orphans.remove(0);
+
+                    if orphans.is_empty() {
+                        self.orphans.remove(&parent);
+                    }
+                }
+            }
+        }
+
+        self.orphans
+            .entry(block.header.previous_hash)
+            .or_default()
+            .push(Orphan {
+                block,
+                received: Instant::now(),
+            });
+    }
+
+    fn expire_orphans(&mut self) {
+        self.orphans.retain(|_, orphans| {
+            orphans.retain(|orphan| orphan.received.elapsed() < ORPHAN_EXPIRY);
+            !orphans.is_empty()
+        });
+    }
+
+    // `previous_blocks` are the blocks the block follows, ending with its parent
+    pub fn validate_block(
+        &self,
+        block: &Block,
+        previous_blocks: &[Block],
+        target: Target,
+    ) -> Result<(), ChainError> {
+        let previous_block = previous_blocks.last().ok_or(ChainError::NoParent {
+            id: block.header.id,
+        })?;
+        let median = median_time_past(previous_blocks, self.config.median_time_span);
+
+        self.validate_header(&block.header, &previous_block.header, median, target)?;
+
+        // Blocks already on the local chain are grandfathered, so lowering --max-data-size never invalidates it
+        let size = block.data_size();
+
+        if size > self.config.max_data_size && !self.holds(block) {
+            return Err(BlockValidationError::DataTooLarge {
+                id: block.header.id,
+                size,
+                max: self.config.max_data_size,
+            }
+            .into());
+        }
+
+        if block.is_pruned() {
+            return Err(BlockValidationError::DataPruned {
+                id: block.header.id,
+            }
+            .into());
+        }
+
+        // Checked last, as this is the only check whose cost grows with the size of the data
+        if merkle::merkle_root(&block.data) != block.header.merkle_root {
+            return Err(BlockValidationError::MerkleRootMismatch {
+                id: block.header.id,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    // Checks everything about a header except its data, which it only commits to through the merkle root
+    fn validate_header(
+        &self,
+        header: &BlockHeader,
+        previous: &BlockHeader,
+        median_time_past: Option<i64>,
+        target: Target,
+    ) -> Result<(), BlockValidationError> {
+        let id = header.id;
+        let required_difficulty = stated_difficulty(target);
+        let now = Utc::now().timestamp();
+
+        if header.version != BLOCK_VERSION {
+            Err(BlockValidationError::UnsupportedVersion {
+                id,
+                version: header.version,
+            })
+        } else if header.chain_id != self.config.chain_id {
+            Err(BlockValidationError::WrongChainId {
+                id,
+                expected: self.config.chain_id.clone(),
+                got: header.chain_id.clone(),
+            })
+        } else if header.id != previous.id + 1 {
+            Err(BlockValidationError::NonSequentialId {
+                expected: previous.id + 1,
+                got: header.id,
+            })
+        } else if header.previous_hash != previous.hash {
+            Err(BlockValidationError::WrongPreviousHash {
+                id,
+                expected: previous.hash,
+                got: header.previous_hash,
+            })
+        } else if header.difficulty != required_difficulty && header.difficulty == DEV_DIFFICULTY {
+            Err(BlockValidationError::NoProofOfWork { id })
+        } else if header.difficulty != required_difficulty {
+            Err(BlockValidationError::InvalidDifficulty {
+                id,
+                expected: required_difficulty,
+                got: header.difficulty,
+            })
+        } else if !DEV_FAST_MINE && !target.is_met_by(&header.hash) {
+            Err(BlockValidationError::InsufficientWork { id, target })
+        } else if header.reward != self.config.block_reward {
+            Err(BlockValidationError::WrongReward {
+                id,
+                expected: self.config.block_reward,
+                got: header.reward,
+            })
+        } else if header.timestamp > now + self.config.max_timestamp_drift {
+            Err(BlockValidationError::TimestampTooFarAhead {
+                id,
+                timestamp: header.timestamp,
+                now,
+                max_drift: self.config.max_timestamp_drift,
+            })
+        } else if header.timestamp < previous.timestamp - PARENT_TIMESTAMP_TOLERANCE {
+            Err(BlockValidationError::TimestampBeforeParent {
+                id,
+                timestamp: header.timestamp,
+                parent_timestamp: previous.timestamp,
+            })
+        } else if let Some(median) = median_time_past.filter(|median| header.timestamp <= *median) {
+            Err(BlockValidationError::TimestampNotAfterMedian {
+                id,
+                timestamp: header.timestamp,
+                median,
+            })
+        } else {
+            self.validate_hash(header)
+        }
+    }
+
+    fn validate_hash(&self, header: &BlockHeader) -> Result<(), BlockValidationError> {
+        let id = header.id;
+        let hashing = self.config.hashing;
+
+        if calculate_hash(header, &hashing) == header.hash {
+            return Ok(());
+        }
+
+        let other_mode = BlockHashing {
+            mode: hashing.mode.other(),
+            ..hashing
+        };
+
+        if calculate_hash(header, &other_mode) == header.hash {
+            Err(BlockValidationError::HashModeMismatch {
+                id,
+                block_mode: other_mode.mode,
+                local_mode: hashing.mode,
+            })
+        } else {
+            Err(BlockValidationError::HashMismatch { id, hashing })
+        }
+    }
+
+    // The first block of a chain must be this node's genesis block.  Checking that it is well formed first gives a more
+    // useful reason for rejecting a forged genesis block than just saying it doesn't match
+    fn validate_genesis(&self, genesis: &Block) -> Result<(), BlockValidationError> {
+        let header = &genesis.header;
+
+        if header.id != 0 || header.previous_hash != BlockHash::ZERO {
+            return Err(BlockValidationError::MalformedGenesis {
+                id: header.id,
+                previous_hash: header.previous_hash,
+            });
+        }
+
+        if header.chain_id != self.config.chain_id {
+            return Err(BlockValidationError::WrongChainId {
+                id: 0,
+                expected: self.config.chain_id.clone(),
+                got: header.chain_id.clone(),
+            });
+        }
+
+        self.validate_hash(header)?;
+
+        if merkle::merkle_root(&genesis.data) != header.merkle_root {
+            Err(BlockValidationError::MerkleRootMismatch { id: 0 })
+        } else if *genesis != self.genesis_block {
+            Err(BlockValidationError::ForeignGenesis { hash: header.hash })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn validate_chain(&self, chain: &[Block]) -> Result<(), ChainError> {
+        let genesis = chain.first().ok_or(BlockValidationError::EmptyChain)?;
+
+        self.validate_genesis(genesis)?;
+        self.validate_chain_from(chain, 1)
+    }
+
+    // Validates the blocks of `chain` from `start` onwards, trusting the ones before it
+    fn validate_chain_from(
+        &self,
+        chain: &[Block],
+        start: usize,
+    ) -> Result<(), ChainError> {
+        match self.first_invalid_from(chain, start) {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    // The height and error of the first block of `chain` from `start` onwards that isn't valid.  Transactions are
+    // checked against the balances left by the blocks before it
+    fn first_invalid_from(
+        &self,
+        chain: &[Block],
+        start: usize,
+    ) -> Option<(usize, ChainError)> {
+        let start = start.max(1);
+        let mut ledger = match self.ledger_at(&chain[..start]) {
+            Ok(ledger) => ledger,
+            Err(e) => return Some((start, e.into())),
+        };
+
+        let mut target = expected_target(chain, start as u64 - 1, &self.config);
+
+        (start..chain.len()).find_map(|i| {
+            let block = &chain[i];
+            target = retarget(chain, i as u64, target, &self.config);
+
+            self.validate_block(block, &chain[..i], target)
+                .and_then(|()| {
+                    ledger.apply(block).map_err(|error| {
+                        BlockValidationError::InvalidTransaction {
+                            id: block.header.id,
+                            error,
+                        }
+                        .into()
+                    })
+                })
+                .err()
+                .map(|e| (i, e))
+        })
+    }
+
+    // For a chain that arrives a piece at a time, each piece is validated as soon as the blocks before it are there.
+    // The blocks of `chain` before `start` are trusted, so must be the local chain or pieces already validated
+    pub fn validate_chain_part(&self, chain: &[Block], start: usize) -> Result<(), ChainError> {
+        self.validate_chain_from(chain, start)
+    }
+
+    // The same choice as choose_chain, for a remote chain that has already been validated a piece at a time
+    pub fn choose_validated_chain(&self, remote: Vec<Block>) -> Option<Vec<Block>> {
+        let local_weight = (self.chain_work(), self.blocks.len());
+        let remote_weight = (chain_work(&remote), remote.len());
+
+        if local_weight >= remote_weight {
+            None
+        } else {
+            Some(remote)
+        }
+    }
+
+    // Only the part of the remote chain after its common ancestor with the local chain is validated.  The blocks up
+    // to the ancestor are taken from the local chain, so a peer can't slip different headers in behind a matching
+    // hash
+    fn validate_remote_chain(
+        &self,
+        remote: Vec<Block>,
+    ) -> Result<Vec<Block>, ChainError> {
+        match self.find_common_ancestor(&remote) {
+            Some(ancestor) => {
+                let chain: Vec<Block> = self.blocks[..=ancestor]
+                    .iter()
+                    .cloned()
+                    .chain(remote.into_iter().skip(ancestor + 1))
+                    .collect();
+
+                self.validate_chain_from(&chain, ancestor + 1)?;
+                Ok(chain)
+            }
+            None => {
+                self.validate_chain(&remote)?;
+                Ok(remote)
+            }
+        }
+    }
+
+    // We always choose the valid chain with the most proof of work behind it, since a longer chain of easier blocks
+    // can be cheaper to produce than a shorter chain of harder ones.  Only when both have done the same work does the
+    // longer chain win, and the local chain is kept on a complete tie.  The local chain should never be invalid, so
+    // replacing it is logged as an error.
+    //
+    // Returns the chain to switch to, None if the local chain has at least as much work behind it, or why the remote
+    // chain can't be used
+    pub fn choose_chain(&self, remote: Vec<Block>) -> Result<Option<Vec<Block>>, ChainError> {
+        let local = if self.is_validated() {
+            Ok(())
+        } else {
+            self.validate_chain(&self.blocks)
+        };
+
+        // An invalid local chain can't be trusted to supply the common ancestor
+        let remote = match local {
+            Ok(()) => self.validate_remote_chain(remote),
+            Err(_) => self.validate_chain(&remote).map(|()| remote),
+        };
+
+        match (local, remote) {
+            (Ok(()), Ok(remote)) => Ok(self.choose_validated_chain(remote)),
+            (Ok(()), Err(e)) => Err(e),
+            (Err(e), Ok(remote)) => {
+                log::error!(
+                    "local chain is invalid - {} - replacing it with the remote chain",
+                    e
+                );
+                Ok(Some(remote))
+            }
+            (Err(local), Err(remote)) => Err(ChainError::BothInvalid {
+                local: Box::new(local),
+                remote: Box::new(remote),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    use crate::{
+        block::{GenesisSpec, DEFAULT_GENESIS_TIMESTAMP},
+        hasher::HashMode,
+        ledger::TransactionError,
+        mining::MiningParams,
+        test_util::{
+            self, app_with, block_at, config, genesis, grow, grow_with_spacing, next_block,
+        },
+    };
+
+    #[test]
+    fn target_gets_harder_when_blocks_come_too_fast() {
+        let config = config();
+        let chain = grow_with_spacing(&[genesis(&config)], &config, 19, 1);
+
+        assert_eq!(expected_target(&chain, 9, &config), config.target);
+        assert_eq!(expected_target(&chain, 10, &config), config.target.harder());
+        assert_eq!(
+            expected_target(&chain, 20, &config),
+            config.target.harder().harder()
+        );
+    }
+
+    #[test]
+    fn target_gets_easier_when_blocks_come_too_slowly() {
+        let config = Config {
+            target: Target::from_leading_zero_bits(4),
+            ..config()
+        };
+        let chain = grow_with_spacing(&[genesis(&config)], &config, 19, 30);
+
+        assert_eq!(expected_target(&chain, 9, &config), config.target);
+        assert_eq!(expected_target(&chain, 10, &config), config.target.easier());
+        assert_eq!(
+            expected_target(&chain, 20, &config),
+            config.target.easier().easier()
+        );
+    }
+
+    #[test]
+    fn target_stays_put_within_a_factor_of_two_of_the_spacing() {
+        let config = config();
+
+        for spacing in [5, 10, 20] {
+            let chain = grow_with_spacing(&[genesis(&config)], &config, 19, spacing);
+
+            assert_eq!(expected_target(&chain, 20, &config), config.target);
+        }
+    }
+
+    #[test]
+    fn the_target_carried_forward_matches_a_replay_from_the_genesis_block() {
+        let config = config();
+        let chain = grow_with_spacing(&[genesis(&config)], &config, 34, 1);
+        let mut target = config.target;
+
+        for height in 1..chain.len() as u64 {
+            target = retarget(&chain, height, target, &config);
+            assert_eq!(target, expected_target(&chain, height, &config));
+        }
+        assert_eq!(target, config.target.harder().harder().harder());
+    }
+
+    #[test]
+    fn target_never_gets_easier_than_the_maximum() {
+        let config = Config {
+            target: Target::max(),
+            ..config()
+        };
+        let chain = grow_with_spacing(&[genesis(&config)], &config, 9, 30);
+
+        assert_eq!(expected_target(&chain, 10, &config), Target::max());
+    }
+
+    // 0x70 followed by zeros, which only some hashes with a single leading zero bit meet
+    #[test]
+    #[cfg(not(feature = "dev-fast-mine"))]
+    fn blocks_are_checked_against_a_target_between_two_prefix_lengths() {
+        let target = Target::from_compact(0x2100_7000).expect("valid compact target");
+        let mut app = app_with(Config { target, ..config() });
+        let block = next_block(app.chain(), &app.config, &["entry"]);
+
+        // Same leading zero bits as the target, but not below it
+        let mut short = block.clone();
+        while !(0x70..=0x7f).contains(&short.header.hash.as_bytes()[0]) {
+            short.header.nonce += 1;
+            short.header.hash = calculate_hash(&short.header, &app.config.hashing);
+        }
+
+        assert_eq!(
+            app.try_add_block(short),
+            Err(ChainError::Invalid(
+                BlockValidationError::InsufficientWork { id: 1, target }
+            ))
+        );
+        assert!(target.is_met_by(&block.header.hash));
+        assert_eq!(
+            app.try_add_block(block),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 0
+            })
+        );
+    }
+
+    // A node built with dev-fast-mine, whose blocks state no proof of work, can't join a network doing real work
+    #[test]
+    #[cfg(not(feature = "dev-fast-mine"))]
+    fn a_block_without_proof_of_work_is_rejected() {
+        let mut app = app_with(config());
+        let mut block = next_block(app.chain(), &app.config, &["dev mode"]);
+        block.header.difficulty = DEV_DIFFICULTY;
+        block.header.hash = calculate_hash(&block.header, &app.config.hashing);
+
+        assert_eq!(
+            app.try_add_block(block),
+            Err(ChainError::Invalid(BlockValidationError::NoProofOfWork {
+                id: 1
+            }))
+        );
+    }
+
+    // Nor can a node built with dev-fast-mine accept blocks from a network doing real work
+    #[test]
+    #[cfg(feature = "dev-fast-mine")]
+    fn a_block_with_proof_of_work_is_rejected_in_dev_mode() {
+        let mut app = app_with(config());
+        let mut block = next_block(app.chain(), &app.config, &["real work"]);
+        let difficulty = app.config.target.to_compact();
+        block.header.difficulty = difficulty;
+
+        while !app.config.target.is_met_by(&block.header.hash) {
+            block.header.nonce += 1;
+            block.header.hash = calculate_hash(&block.header, &app.config.hashing);
+        }
+
+        assert_eq!(
+            app.try_add_block(block),
+            Err(ChainError::Invalid(
+                BlockValidationError::InvalidDifficulty {
+                    id: 1,
+                    expected: DEV_DIFFICULTY,
+                    got: difficulty,
+                }
+            ))
+        );
+    }
+
+    // The data is only hashed for its merkle root, which is checked after everything else about the block
+    #[test]
+    fn a_10_mb_payload_is_validated_by_its_merkle_root() {
+        let payload = "x".repeat(10 * 1024 * 1024);
+        let mut app = app_with(Config {
+            max_data_size: payload.len(),
+            ..config()
+        });
+        let block = next_block(app.chain(), &app.config, &[&payload]);
+
+        let mut tampered = block.clone();
+        tampered.data[0].replace_range(..1, "y");
+
+        assert_eq!(
+            app.try_add_block(tampered),
+            Err(ChainError::Invalid(
+                BlockValidationError::MerkleRootMismatch { id: 1 }
+            ))
+        );
+        assert_eq!(
+            app.try_add_block(block),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 0
+            })
+        );
+    }
+
+    // A block hashed the other way is reported as such, rather than just as having the wrong hash
+    #[test]
+    fn a_block_hashed_in_the_other_mode_is_reported_as_a_mode_mismatch() {
+        let mut app = app_with(config());
+        let mut block = next_block(app.chain(), &app.config, &["hashed twice"]);
+        let double = Config {
+            hashing: BlockHashing {
+                mode: HashMode::Double,
+                ..app.config.hashing
+            },
+            ..config()
+        };
+        test_util::seal(&mut block.header, app.config.target, &double);
+
+        assert_eq!(
+            app.try_add_block(block.clone()),
+            Err(ChainError::Invalid(
+                BlockValidationError::HashModeMismatch {
+                    id: 1,
+                    block_mode: HashMode::Double,
+                    local_mode: HashMode::Single,
+                }
+            ))
+        );
+
+        // A zero hash meets any target, so it can only be rejected for not being the block's hash
+        block.header.hash = BlockHash::ZERO;
+        assert_eq!(
+            app.try_add_block(block),
+            Err(ChainError::Invalid(BlockValidationError::HashMismatch {
+                id: 1,
+                hashing: app.config.hashing,
+            }))
+        );
+    }
+
+    // Block 1 with `change` made to it, and sealed again so that nothing else about it is wrong
+    fn rejection(app: &App, change: impl FnOnce(&mut Block)) -> BlockValidationError {
+        let mut block = next_block(app.chain(), &app.config, &["entry"]);
+        change(&mut block);
+        test_util::seal(&mut block.header, app.config.target, &app.config);
+
+        match app.validate_block(&block, app.chain(), app.config.target) {
+            Err(ChainError::Invalid(e)) => e,
+            other => panic!("expected the block to be invalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn each_way_a_block_can_be_invalid_is_reported() {
+        let app = app_with(Config {
+            max_data_size: 8,
+            ..config()
+        });
+        let genesis = app.tip().header.clone();
+        let target = app.config.target;
+
+        assert_eq!(
+            rejection(&app, |block| block.header.version = 2),
+            BlockValidationError::UnsupportedVersion { id: 1, version: 2 }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.chain_id = String::from("other")),
+            BlockValidationError::WrongChainId {
+                id: 1,
+                expected: genesis.chain_id.clone(),
+                got: String::from("other"),
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.id = 2),
+            BlockValidationError::NonSequentialId {
+                expected: 1,
+                got: 2
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.previous_hash = BlockHash::ZERO),
+            BlockValidationError::WrongPreviousHash {
+                id: 1,
+                expected: genesis.hash,
+                got: BlockHash::ZERO,
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.difficulty =
+                target.harder().to_compact()),
+            BlockValidationError::InvalidDifficulty {
+                id: 1,
+                expected: stated_difficulty(target),
+                got: target.harder().to_compact(),
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.reward += 1),
+            BlockValidationError::WrongReward {
+                id: 1,
+                expected: app.config.block_reward,
+                got: app.config.block_reward + 1,
+            }
+        );
+        assert!(matches!(
+            rejection(&app, |block| block.header.timestamp =
+                Utc::now().timestamp() + 3600),
+            BlockValidationError::TimestampTooFarAhead { id: 1, .. }
+        ));
+        assert_eq!(
+            rejection(&app, |block| block.header.timestamp =
+                genesis.timestamp - 11),
+            BlockValidationError::TimestampBeforeParent {
+                id: 1,
+                timestamp: genesis.timestamp - 11,
+                parent_timestamp: genesis.timestamp,
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.header.timestamp = genesis.timestamp),
+            BlockValidationError::TimestampNotAfterMedian {
+                id: 1,
+                timestamp: genesis.timestamp,
+                median: genesis.timestamp,
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.data.push(String::from("too much"))),
+            BlockValidationError::DataTooLarge {
+                id: 1,
+                size: 13,
+                max: 8
+            }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.data.clear()),
+            BlockValidationError::DataPruned { id: 1 }
+        );
+        assert_eq!(
+            rejection(&app, |block| block.data[0].push('!')),
+            BlockValidationError::MerkleRootMismatch { id: 1 }
+        );
+
+        let block = next_block(app.chain(), &app.config, &["entry"]);
+        assert_eq!(app.validate_block(&block, app.chain(), target), Ok(()));
+        assert_eq!(
+            app.validate_block(&block, &[], target),
+            Err(ChainError::NoParent { id: 1 })
+        );
+    }
+
+    // Two networks that only differ in their genesis data, so that each one's blocks are otherwise valid on the other
+    #[test]
+    fn a_heavier_chain_from_another_genesis_is_never_adopted() {
+        let app = app_with(config());
+        let other = Config {
+            genesis: GenesisSpec {
+                data: test_util::entries(&["another network"]),
+                ..GenesisSpec::default()
+            },
+            ..config()
+        };
+        let foreign_genesis = genesis(&other);
+        let foreign = grow(std::slice::from_ref(&foreign_genesis), &other, 3, "foreign");
+
+        assert_ne!(foreign_genesis.header.hash, app.tip().header.hash);
+        assert_eq!(
+            app.choose_chain(foreign),
+            Err(ChainError::Invalid(BlockValidationError::ForeignGenesis {
+                hash: foreign_genesis.header.hash
+            }))
+        );
+
+        let own = grow(app.chain(), &app.config, 3, "own");
+        assert_eq!(app.choose_chain(own.clone()), Ok(Some(own)));
+    }
+
+    // The chain after the genesis block is valid each time, so only the genesis block can be to blame
+    #[test]
+    fn a_chain_with_a_forged_genesis_is_invalid() {
+        let app = app_with(config());
+        let chain = grow(app.chain(), &app.config, 2, "entry");
+        let forged = |change: fn(&mut Block)| {
+            let mut forged = chain.clone();
+            change(&mut forged[0]);
+            app.validate_chain(&forged)
+        };
+        let invalid = |e| Err(ChainError::Invalid(e));
+
+        assert_eq!(app.validate_chain(&chain), Ok(()));
+        assert_eq!(
+            app.validate_chain(&[]),
+            invalid(BlockValidationError::EmptyChain)
+        );
+        assert_eq!(
+            forged(|genesis| genesis.header.id = 7),
+            invalid(BlockValidationError::MalformedGenesis {
+                id: 7,
+                previous_hash: BlockHash::ZERO
+            })
+        );
+        assert_eq!(
+            forged(|genesis| genesis.header.previous_hash = BlockHash::from([0xff; 32])),
+            invalid(BlockValidationError::MalformedGenesis {
+                id: 0,
+                previous_hash: BlockHash::from([0xff; 32])
+            })
+        );
+        assert_eq!(
+            forged(|genesis| genesis.header.chain_id = String::from("other")),
+            invalid(BlockValidationError::WrongChainId {
+                id: 0,
+                expected: app.config.chain_id.clone(),
+                got: String::from("other"),
+            })
+        );
+        assert_eq!(
+            forged(|genesis| genesis.header.hash = BlockHash::ZERO),
+            invalid(BlockValidationError::HashMismatch {
+                id: 0,
+                hashing: app.config.hashing
+            })
+        );
+        assert_eq!(
+            forged(|genesis| genesis.data = test_util::entries(&["forged!"])),
+            invalid(BlockValidationError::MerkleRootMismatch { id: 0 })
+        );
+
+        // Internally consistent, but not this network's genesis block
+        let other = Config {
+            genesis: GenesisSpec {
+                timestamp: DEFAULT_GENESIS_TIMESTAMP + 1,
+                ..GenesisSpec::default()
+            },
+            ..config()
+        };
+        let foreign = genesis(&other);
+        let mut forged = chain.clone();
+        forged[0] = foreign.clone();
+        assert_eq!(
+            app.validate_chain(&forged),
+            invalid(BlockValidationError::ForeignGenesis {
+                hash: foreign.header.hash
+            })
+        );
+    }
+
+    // Block 2 of `chain` no longer holds the data its header commits to
+    fn tampered(chain: &[Block]) -> Vec<Block> {
+        let mut chain = chain.to_vec();
+        chain[2].data = test_util::entries(&["tampered"]);
+        chain
+    }
+
+    #[test]
+    fn choosing_between_invalid_chains_is_an_error_rather_than_a_panic() {
+        let mut app = app_with(config());
+        let local = grow(app.chain(), &app.config, 2, "local");
+        let remote = grow(app.chain(), &app.config, 3, "remote");
+        let tampered_error =
+            || ChainError::Invalid(BlockValidationError::MerkleRootMismatch { id: 2 });
+
+        // Valid local chain, invalid remote chain
+        for block in &local[1..] {
+            app.try_add_block(block.clone())
+                .expect("the local chain is valid");
+        }
+        assert_eq!(app.choose_chain(tampered(&remote)), Err(tampered_error()));
+
+        // Invalid local chain, valid remote chain, on a node that has never validated its chain
+        let mut app = app_with(config());
+        app.blocks = tampered(&local);
+        assert_eq!(app.choose_chain(remote.clone()), Ok(Some(remote.clone())));
+
+        // Both invalid
+        assert_eq!(
+            app.choose_chain(tampered(&remote)),
+            Err(ChainError::BothInvalid {
+                local: Box::new(tampered_error()),
+                remote: Box::new(tampered_error()),
+            })
+        );
+    }
+
+    // Blocks 10 seconds apart keep the starting target, while blocks a second apart make it harder from block 10 on, so
+    // each of those does twice the work of a block on the longer chain
+    #[test]
+    #[cfg(not(feature = "dev-fast-mine"))]
+    fn a_shorter_chain_with_more_work_wins() {
+        let config = config();
+        let light = grow_with_spacing(&[genesis(&config)], &config, 25, 10);
+        let heavy = grow_with_spacing(&[genesis(&config)], &config, 19, 1);
+
+        assert!(chain_work(&heavy) > chain_work(&light));
+
+        let mut app = app_with(config.clone());
+        for block in &light[1..] {
+            app.try_add_block(block.clone())
+                .expect("the longer chain is valid");
+        }
+        assert_eq!(app.choose_chain(heavy.clone()), Ok(Some(heavy.clone())));
+
+        let mut app = app_with(config);
+        for block in &heavy[1..] {
+            app.try_add_block(block.clone())
+                .expect("the heavier chain is valid");
+        }
+        assert_eq!(app.choose_chain(light), Ok(None));
+        assert_eq!(app.chain_work(), chain_work(&heavy));
+    }
+
+    #[test]
+    fn blocks_that_arrive_in_reverse_order_end_up_linked() {
+        let mut app = app_with(config());
+        let chain = grow(app.chain(), &app.config, 3, "entry");
+
+        assert_eq!(
+            app.try_add_block(chain[3].clone()),
+            Ok(AddBlockOutcome::Orphan)
+        );
+        assert_eq!(
+            app.try_add_block(chain[2].clone()),
+            Ok(AddBlockOutcome::Orphan)
+        );
+        assert_eq!(
+            app.try_add_block(chain[3].clone()),
+            Ok(AddBlockOutcome::Duplicate)
+        );
+        assert_eq!(
+            app.try_add_block(chain[1].clone()),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 2
+            })
+        );
+        assert_eq!(app.chain(), &chain[..]);
+        assert!(app.orphans.is_empty());
+        assert_eq!(
+            app.try_add_block(chain[1].clone()),
+            Ok(AddBlockOutcome::Duplicate)
+        );
+    }
+
+    fn orphan_count(app: &App) -> usize {
+        app.orphans.values().map(Vec::len).sum()
+    }
+
+    #[test]
+    fn the_orphan_buffer_is_capped_and_expires() {
+        let mut app = app_with(config());
+        let genesis = app.chain().to_vec();
+
+        // Each orphan is the second block of a different branch, whose first block never arrives
+        let branches: Vec<Vec<Block>> = (0..=MAX_ORPHANS)
+            .map(|i| grow(&genesis, &app.config, 2, &format!("branch {}", i)))
+            .collect();
+        for branch in &branches {
+            assert_eq!(
+                app.try_add_block(branch[2].clone()),
+                Ok(AddBlockOutcome::Orphan)
+            );
+        }
+
+        // An older orphan made way for the last one
+        assert_eq!(orphan_count(&app), MAX_ORPHANS);
+        assert!(app.has_block(&branches[MAX_ORPHANS][2].header.hash));
+
+        for orphans in app.orphans.values_mut() {
+            for orphan in orphans {
+                orphan.received -= ORPHAN_EXPIRY;
+            }
+        }
+        let block = next_block(&genesis, &app.config, &["entry"]);
+        app.try_add_block(block).expect("the block is valid");
+        assert_eq!(orphan_count(&app), 0);
+    }
+
+    fn hashes(blocks: &[Block]) -> Vec<BlockHash> {
+        blocks.iter().map(|block| block.header.hash).collect()
+    }
+
+    // Two miners each find blocks 1 and 2.  The branch that arrives second only has as much work as the chain, so it
+    // is kept to one side until its third block makes it the heavier one
+    #[test]
+    fn a_competing_branch_that_overtakes_the_chain_replaces_it() {
+        let mut app = app_with(config());
+        let (events, mut received) = mpsc::unbounded_channel();
+        app.reorg_subscribers.push(events);
+        let first = grow(app.chain(), &app.config, 2, "first");
+        let second = grow(app.chain(), &app.config, 3, "second");
+
+        for block in &first[1..] {
+            assert!(matches!(
+                app.try_add_block(block.clone()),
+                Ok(AddBlockOutcome::Added { .. })
+            ));
+        }
+        for block in &second[1..3] {
+            assert_eq!(
+                app.try_add_block(block.clone()),
+                Ok(AddBlockOutcome::SideChain)
+            );
+        }
+        assert_eq!(app.chain(), &first[..]);
+
+        assert_eq!(
+            app.try_add_block(second[3].clone()),
+            Ok(AddBlockOutcome::Reorg(Reorg {
+                fork_height: 0,
+                rolled_back: hashes(&first[1..]),
+                applied: hashes(&second[1..]),
+            }))
+        );
+        assert_eq!(app.chain(), &second[..]);
+        assert_eq!(
+            received.try_recv(),
+            Ok(ReorgEvent {
+                fork_height: 0,
+                rolled_back: first[1..].to_vec(),
+                applied: second[1..].to_vec(),
+            })
+        );
+
+        // The blocks rolled back are now the competing branch
+        assert!(first[1..]
+            .iter()
+            .all(|block| app.side_blocks.contains_key(&block.header.hash)));
+    }
+
+    // However deep a reorg is allowed, a competing block some way below the tip is kept
+    #[test]
+    fn side_blocks_are_kept_with_an_unlimited_reorg_depth() {
+        let mut app = app_with(Config {
+            max_reorg_depth: u64::MAX,
+            ..config()
+        });
+        let chain = grow(app.chain(), &app.config, 3, "chain");
+        let side = grow(&chain[..1], &app.config, 1, "side");
+
+        for block in &chain[1..] {
+            app.try_add_block(block.clone())
+                .expect("the chain is valid");
+        }
+        assert_eq!(
+            app.try_add_block(side[1].clone()),
+            Ok(AddBlockOutcome::SideChain)
+        );
+
+        app.try_add_block(next_block(app.chain(), &app.config, &["entry"]))
+            .expect("the block is valid");
+        assert!(app.side_blocks.contains_key(&side[1].header.hash));
+    }
+
+    // The local chain is four blocks long, and each remote chain forks off it `depth` blocks below its tip
+    #[test]
+    fn reorgs_deeper_than_the_maximum_are_refused() {
+        let config = Config {
+            max_reorg_depth: 2,
+            ..config()
+        };
+        let local = grow(&[genesis(&config)], &config, 4, "local");
+
+        for (depth, switched) in [(1, true), (2, true), (3, false)] {
+            let mut app = app_with(config.clone());
+            for block in &local[1..] {
+                app.try_add_block(block.clone())
+                    .expect("the local chain is valid");
+            }
+
+            let remote = grow(&local[..local.len() - depth], &config, depth + 1, "remote");
+            let chosen = app
+                .choose_chain(remote.clone())
+                .expect("the remote chain is valid")
+                .expect("the remote chain has more work");
+
+            assert_eq!(app.reorg_depth(&chosen), depth as u64);
+            assert_eq!(app.switch_chain(chosen), switched, "depth {}", depth);
+
+            if switched {
+                assert_eq!(app.chain(), &remote[..]);
+                continue;
+            }
+
+            // Kept until an operator accepts it
+            let tip = remote[remote.len() - 1].header.hash;
+            assert_eq!(app.chain(), &local[..]);
+            assert!(!app.accept_refused_chain(&local[1].header.hash));
+            assert!(app.accept_refused_chain(&tip));
+            assert_eq!(app.chain(), &remote[..]);
+        }
+    }
+
+    // Block 4 is dated a little before block 3, which PARENT_TIMESTAMP_TOLERANCE allows.  The next block is dated late
+    // enough for its parent too, but not after the median of the last five blocks
+    #[test]
+    fn a_block_not_dated_after_the_median_time_past_is_rejected() {
+        let config = config();
+        let mut chain = vec![genesis(&config)];
+        let start = chain[0].header.timestamp;
+
+        for offset in [10, 20, 30, 25] {
+            let block = block_at(&chain, &config, &[], start + offset);
+            chain.push(block);
+        }
+        assert_eq!(
+            median_time_past(&chain, config.median_time_span),
+            Some(start + 20)
+        );
+
+        let mut app = app_with(config.clone());
+        for block in &chain[1..] {
+            app.try_add_block(block.clone())
+                .expect("every block is dated after the median");
+        }
+
+        let skewed = block_at(&chain, &config, &[], start + 20);
+        assert!(skewed.header.timestamp >= chain[4].header.timestamp - PARENT_TIMESTAMP_TOLERANCE);
+        assert_eq!(
+            app.try_add_block(skewed.clone()),
+            Err(ChainError::Invalid(
+                BlockValidationError::TimestampNotAfterMedian {
+                    id: 5,
+                    timestamp: start + 20,
+                    median: start + 20,
+                }
+            ))
+        );
+        assert_eq!(app.min_next_timestamp(), start + 21);
+
+        // With the rule turned off, only the parent's timestamp counts
+        let app = app_with(Config {
+            median_time_span: 0,
+            ..config
+        });
+        assert_eq!(
+            app.validate_block(&skewed, &chain, app.config.target),
+            Ok(())
+        );
+    }
+
+    // There used to be a window after startup where the chain was empty, and any block received in it was a panic
+    #[test]
+    fn a_new_app_takes_blocks_straight_away() {
+        let config = config();
+        let mut app = app_with(config.clone());
+        assert_eq!(app.chain(), &[genesis(&config)]);
+        assert_eq!(app.height(), 0);
+
+        let chain = grow(app.chain(), &config, 2, "entry");
+        assert_eq!(
+            app.try_add_block(chain[2].clone()),
+            Ok(AddBlockOutcome::Orphan)
+        );
+        assert_eq!(
+            app.try_add_block(chain[1].clone()),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 1
+            })
+        );
+
+        // Nor is the chain ever empty after a reset
+        app.reset();
+        assert_eq!(app.chain(), &[genesis(&config)]);
+        assert_eq!(
+            app.try_add_block(chain[1].clone()),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 0
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_are_looked_up_on_the_chain_as_it_is_now() {
+        let config = config();
+        let local = grow(&[genesis(&config)], &config, 2, "local");
+        let remote = grow(&local[..1], &config, 3, "remote");
+        let by_hash = |app: &App, block: &Block| {
+            app.get_block_by_hash(&block.header.hash.to_string())
+                .cloned()
+        };
+
+        for switch_by_block in [false, true] {
+            let mut app = app_with(config.clone());
+            for block in &local[1..] {
+                app.try_add_block(block.clone())
+                    .expect("the local chain is valid");
+            }
+
+            assert_eq!(by_hash(&app, &local[1]).as_ref(), Some(&local[1]));
+            assert_eq!(app.get_block_by_id(2), Some(&local[2]));
+            assert_eq!(by_hash(&app, &remote[1]), None);
+            assert_eq!(app.get_block_by_id(3), None);
+            assert_eq!(app.get_block_by_hash("not a hash"), None);
+
+            // Replaced one block at a time as a competing branch, or all at once as a chain from a peer
+            if switch_by_block {
+                for block in &remote[1..] {
+                    app.try_add_block(block.clone())
+                        .expect("the remote chain is valid");
+                }
+            } else {
+                assert!(app.switch_chain(remote.clone()));
+            }
+
+            assert_eq!(app.chain(), &remote[..]);
+            for block in &local[1..] {
+                assert_eq!(by_hash(&app, block), None);
+                assert!(!app.on_chain(&block.header.hash));
+            }
+            for block in &remote {
+                assert_eq!(by_hash(&app, block).as_ref(), Some(block));
+                assert_eq!(app.get_block_by_id(block.header.id), Some(block));
+            }
+        }
+    }
+
+    fn ids<'a>(blocks: impl IntoIterator<Item = &'a Block>) -> Vec<u64> {
+        blocks.into_iter().map(|block| block.header.id).collect()
+    }
+
+    #[test]
+    fn ranges_are_cut_short_at_the_tip() {
+        let mut app = app_with(config());
+        assert_eq!(ids(app.range(1..)), Vec::<u64>::new());
+        assert_eq!(ids(app.range(..)), [0]);
+
+        for block in &grow(app.chain(), &app.config, 3, "entry")[1..] {
+            app.try_add_block(block.clone())
+                .expect("the chain is valid");
+        }
+
+        assert_eq!(app.height(), 3);
+        assert_eq!(app.tip().header.id, 3);
+        assert_eq!(ids(app.iter().rev()), [3, 2, 1, 0]);
+        assert_eq!(ids(app.range(1..3)), [1, 2]);
+        assert_eq!(ids(app.range(2..=3)), [2, 3]);
+        assert_eq!(ids(app.range(2..)), [2, 3]);
+        assert_eq!(ids(app.range(..=1)), [0, 1]);
+        assert_eq!(ids(app.range(..=u64::MAX)), [0, 1, 2, 3]);
+        assert_eq!(ids(app.range(3..=3)), [3]);
+        assert_eq!(ids(app.range(4..)), Vec::<u64>::new());
+        assert_eq!(ids(app.range(10..20)), Vec::<u64>::new());
+        assert_eq!(ids(app.range(2..2)), Vec::<u64>::new());
+
+        // A range that ends before it starts, as a user might type it
+        let (from, to) = (3, 1);
+        assert_eq!(ids(app.range(from..to)), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn blocks_since_includes_blocks_dated_exactly_then() {
+        let mut app = app_with(config());
+        let start = app.tip().header.timestamp;
+
+        for block in &grow(app.chain(), &app.config, 3, "entry")[1..] {
+            app.try_add_block(block.clone())
+                .expect("the chain is valid");
+        }
+
+        assert_eq!(ids(app.blocks_since(i64::MIN)), [0, 1, 2, 3]);
+        assert_eq!(ids(app.blocks_since(start + 20)), [2, 3]);
+        assert_eq!(ids(app.blocks_since(start + 21)), [3]);
+        assert_eq!(ids(app.blocks_since(start + 31)), Vec::<u64>::new());
+    }
+
+    // Data queued here and then mined by a peer doesn't wait to be mined again
+    #[test]
+    fn entries_in_a_block_from_a_peer_leave_the_mempool() {
+        let mut app = app_with(config());
+        for entry in ["mined by a peer", "still waiting"] {
+            app.mempool.push_back(entry.to_owned());
+        }
+
+        let block = next_block(app.chain(), &app.config, &["mined by a peer"]);
+        app.try_add_block(block).expect("the block is valid");
+
+        assert_eq!(app.mempool.iter().collect::<Vec<_>>(), ["still waiting"]);
+    }
+
+    // A node whose chain starts with 100 in the account of `alice`
+    fn app_with_allocation(alice: &Wallet) -> App {
+        app_with(Config {
+            allocations: vec![(alice.address(), 100)],
+            ..config()
+        })
+    }
+
+    #[test]
+    fn transfers_move_balances_and_overdrafts_are_rejected() {
+        let (alice, bob) = (Wallet::generate(), Wallet::generate().address());
+        let mut app = app_with_allocation(&alice);
+
+        let block = next_block(
+            app.chain(),
+            &app.config,
+            &[&test_util::transfer(&alice, &bob, 30, 1)],
+        );
+        app.try_add_block(block)
+            .expect("alice can afford the transfer");
+        assert_eq!(app.balance_of(&alice.address()), 70);
+        assert_eq!(app.balance_of(&bob), 30);
+        assert_eq!(app.balance_of(test_util::MINER), app.config.block_reward);
+        assert_eq!(app.next_nonce(&alice.address()), 2);
+
+        // Each transfer is affordable on its own, but not both of them
+        let overdraft = next_block(
+            app.chain(),
+            &app.config,
+            &[
+                &test_util::transfer(&alice, &bob, 50, 2),
+                &test_util::transfer(&alice, &bob, 50, 3),
+            ],
+        );
+        assert_eq!(
+            app.try_add_block(overdraft),
+            Err(ChainError::Invalid(
+                BlockValidationError::InvalidTransaction {
+                    id: 2,
+                    error: TransactionError::Overdraft {
+                        account: alice.address(),
+                        balance: 20,
+                        amount: 50,
+                    },
+                }
+            ))
+        );
+        assert_eq!(app.balance_of(&alice.address()), 70);
+        assert_eq!(app.balance_of(&bob), 30);
+    }
+
+    #[test]
+    fn a_reorg_reverts_a_spend() {
+        let (alice, bob) = (Wallet::generate(), Wallet::generate().address());
+        let mut app = app_with_allocation(&alice);
+        let genesis = app.chain().to_vec();
+
+        let spend = next_block(
+            &genesis,
+            &app.config,
+            &[&test_util::transfer(&alice, &bob, 30, 1)],
+        );
+        app.try_add_block(spend)
+            .expect("alice can afford the transfer");
+        assert_eq!(app.balance_of(&bob), 30);
+
+        for block in &grow(&genesis, &app.config, 2, "without the spend")[1..] {
+            app.try_add_block(block.clone())
+                .expect("the branch is valid");
+        }
+        assert_eq!(app.height(), 2);
+        assert_eq!(app.balance_of(&alice.address()), 100);
+        assert_eq!(app.balance_of(&bob), 0);
+        assert_eq!(app.next_nonce(&alice.address()), 1);
+    }
+
+    // A single badly signed transaction is enough for the whole block to be rejected
+    #[test]
+    fn a_block_with_a_tampered_transaction_is_rejected() {
+        let (alice, bob) = (Wallet::generate(), Wallet::generate().address());
+        let mut app = app_with_allocation(&alice);
+        let genuine = test_util::transfer(&alice, &bob, 10, 1);
+        let tampered = genuine.replace("\"amount\":10", "\"amount\":90");
+        assert_ne!(genuine, tampered);
+
+        let block = next_block(app.chain(), &app.config, &["free text", &tampered]);
+        assert_eq!(
+            app.try_add_block(block),
+            Err(ChainError::Invalid(
+                BlockValidationError::InvalidTransaction {
+                    id: 1,
+                    error: TransactionError::BadSignature {
+                        account: alice.address()
+                    },
+                }
+            ))
+        );
+        assert_eq!(app.balance_of(&bob), 0);
+        assert_eq!(app.height(), 0);
+    }
+
+    // Mining and validation have to agree on everything about a block, including its timestamp, which the miner takes
+    // from the clock.  Blocks mined within the same second are still dated after the median time past
+    #[test]
+    fn blocks_mined_on_the_tip_are_valid() {
+        let mut app = app_with(config());
+        let (events, _received) = mpsc::unbounded_channel();
+
+        for i in 1..=3 {
+            let id = app.height() + 1;
+            let target = app.expected_target_at(id);
+            let params = MiningParams {
+                chain_id: app.config.chain_id.clone(),
+                id,
+                previous_hash: app.tip().header.hash,
+                data: test_util::entries(&[&format!("block {}", i)]),
+                target,
+                threads: 2,
+                timestamp_refresh: app.config.timestamp_refresh,
+                hashing: app.config.hashing,
+                miner: String::from(test_util::MINER),
+                reward: app.config.block_reward,
+                min_timestamp: app.min_next_timestamp(),
+            };
+            let (block, _) = Block::new(params, None, &AtomicBool::new(false), &events)
+                .expect("mining without a budget always finds a block");
+
+            assert_eq!(
+                app.try_add_block(block),
+                Ok(AddBlockOutcome::Added {
+                    orphans_attached: 0
+                })
+            );
+        }
+        assert_eq!(app.height(), 3);
+    }
+
+    // Each of these used to take the node down with a panic, when it could just as easily come from a peer
+    #[test]
+    fn inputs_that_used_to_panic_are_errors() {
+        let mut app = app_with(config());
+        let invalid = |e| Err(ChainError::Invalid(e));
+
+        assert_eq!(
+            app.choose_chain(Vec::new()),
+            invalid(BlockValidationError::EmptyChain)
+        );
+
+        // A chain with a block missing from the middle
+        let mut remote = grow(app.chain(), &app.config, 3, "remote");
+        remote.remove(2);
+        assert_eq!(
+            app.choose_chain(remote),
+            invalid(BlockValidationError::NonSequentialId {
+                expected: 2,
+                got: 3
+            })
+        );
+
+        let block = next_block(app.chain(), &app.config, &[]);
+        assert_eq!(
+            app.validate_block(&block, &[], app.config.target),
+            Err(ChainError::NoParent { id: 1 })
+        );
+
+        // A hash that isn't hex at all is refused when the block is decoded
+        let json = serde_json::to_string(&block)
+            .expect("blocks can be serialized")
+            .replace(&block.header.hash.to_string(), "not a hash");
+        assert!(serde_json::from_str::<Block>(&json).is_err());
+
+        assert_eq!(app.height(), 0);
+        assert_eq!(
+            app.try_add_block(block),
+            Ok(AddBlockOutcome::Added {
+                orphans_attached: 0
+            })
+        );
+    }
+
+    // The local and remote chains share blocks 1 and 2, and each has its own blocks after that
+    #[test]
+    fn switching_to_a_remote_chain_reports_the_blocks_that_were_replaced() {
+        let mut app = app_with(config());
+        let (events, mut received) = mpsc::unbounded_channel();
+        app.reorg_subscribers.push(events);
+        let shared = grow(app.chain(), &app.config, 2, "shared");
+        let local = grow(&shared, &app.config, 3, "local");
+        let remote = grow(&shared, &app.config, 4, "remote");
+
+        for block in &local[1..] {
+            app.try_add_block(block.clone())
+                .expect("the local chain is valid");
+        }
+        assert_eq!(received.try_recv(), Err(mpsc::error::TryRecvError::Empty));
+
+        // A chain with less work behind it is ignored
+        let shorter = grow(&shared, &app.config, 2, "shorter");
+        assert_eq!(app.choose_chain(shorter), Ok(None));
+        assert_eq!(received.try_recv(), Err(mpsc::error::TryRecvError::Empty));
+
+        let chosen = app
+            .choose_chain(remote.clone())
+            .expect("the remote chain is valid")
+            .expect("the remote chain is longer");
+        assert!(app.switch_chain(chosen));
+        assert_eq!(
+            received.try_recv(),
+            Ok(ReorgEvent {
+                fork_height: 2,
+                rolled_back: local[3..].to_vec(),
+                applied: remote[3..].to_vec(),
+            })
+        );
+
+        // Only extending the chain rolls nothing back
+        let longer = grow(&remote, &app.config, 2, "longer");
+        let chosen = app
+            .choose_chain(longer.clone())
+            .expect("the longer chain is valid")
+            .expect("the longer chain has more work");
+        assert!(app.switch_chain(chosen));
+        assert_eq!(app.chain(), &longer[..]);
+        assert_eq!(received.try_recv(), Err(mpsc::error::TryRecvError::Empty));
+    }
+}
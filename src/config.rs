@@ -0,0 +1,737 @@
+use crate::{
+    block::GenesisSpec,
+    config_file::{self, Value},
+    hasher::BlockHashing,
+    target::Target,
+};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+// Number of leading zero bits initially required in a block hash
+pub const DEFAULT_DIFFICULTY: u32 = 2;
+
+// The difficulty is recalculated every this many blocks
+pub const DEFAULT_RETARGET_INTERVAL: u64 = 10;
+
+// Desired number of seconds between blocks
+pub const DEFAULT_BLOCK_SPACING: i64 = 10;
+
+// If mining a block takes longer than this, its timestamp is refreshed
+pub const DEFAULT_TIMESTAMP_REFRESH: u64 = 60;
+
+// Reward paid to the miner of every block
+pub const DEFAULT_BLOCK_REWARD: u64 = 50;
+
+// Blocks dated more than this many seconds ahead of the local clock are rejected
+pub const DEFAULT_MAX_TIMESTAMP_DRIFT: i64 = 120;
+
+// A block must be dated later than the median timestamp of this many blocks before it
+pub const DEFAULT_MEDIAN_TIME_SPAN: usize = 5;
+
+// Number of recently seen block hashes remembered, so that copies of the same block can be dropped
+pub const DEFAULT_SEEN_CACHE_SIZE: usize = 4096;
+
+// Largest number of bytes of data a new block may carry, counting all of its entries together
+pub const DEFAULT_MAX_DATA_SIZE: usize = 64 * 1024;
+
+// Chains received from peers with more blocks than this, or taking up more bytes than this on the wire, are dropped
+// without being read or validated
+pub const DEFAULT_MAX_CHAIN_LENGTH: usize = 100_000;
+pub const DEFAULT_MAX_CHAIN_BYTES: usize = 256 * 1024 * 1024;
+
+// Chains are fetched from peers this many blocks at a time
+pub const DEFAULT_SYNC_CHUNK_SIZE: u64 = 500;
+
+// Switching to another chain never rolls back more than this many blocks of the local chain without the operator's
+// say so
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 100;
+
+// Most data entries waiting to be mined that are held at once
+pub const DEFAULT_MEMPOOL_SIZE: usize = 1000;
+
+// Auto-mining puts at most this many waiting entries into each block
+pub const DEFAULT_BLOCK_ENTRIES: usize = 10;
+
+// A peer is banned for --ban-duration seconds once it has this many strikes for sending invalid blocks, chains or
+// messages
+pub const DEFAULT_BAN_THRESHOLD: u32 = 5;
+pub const DEFAULT_BAN_DURATION: u64 = 60 * 60;
+
+// A random peer is asked about its tip about this often, so that a block broadcast the node missed is still fetched
+pub const DEFAULT_ANTI_ENTROPY_INTERVAL: u64 = 30;
+
+// A node that starts up waits this long for a peer to send it a chain before it mines on its own chain
+pub const DEFAULT_INITIAL_SYNC_TIMEOUT: u64 = 10;
+
+// Peers asked for their chain at startup, so that one peer that is itself behind doesn't leave the node behind too
+pub const DEFAULT_INITIAL_SYNC_PEERS: usize = 3;
+
+// Each peer is sent a chain about this often at most, and no more than this many chains are being sent at once, so a
+// peer asking for chains over and over can't keep the node busy sending them
+pub const DEFAULT_CHAIN_RESPONSE_INTERVAL: u64 = 10;
+pub const DEFAULT_MAX_CHAIN_RESPONSES: usize = 4;
+
+// Each --peer and --bootstrap address is dialled at most this many times when the node starts, until one connects
+pub const DEFAULT_DIAL_ATTEMPTS: u32 = 8;
+
+// Most connections the node keeps that peers opened, and that it opened itself.  Peers connected by mDNS usually
+// hold one of each
+pub const DEFAULT_MAX_INBOUND: u32 = 25;
+pub const DEFAULT_MAX_OUTBOUND: u32 = 25;
+
+// Most matches the `find` command lists
+pub const DEFAULT_MAX_FIND_RESULTS: usize = 20;
+
+// Where the chain, the key that signs this node's transactions, and the key its peer id comes from are kept between
+// runs, unless --ephemeral is given
+pub const DEFAULT_DB_PATH: &str = "blockchain.db";
+
+// Listening on port 0 takes whatever port is free
+pub const DEFAULT_LISTEN_ADDR: &str = "/ip4/0.0.0.0/tcp/0";
+
+// Name of the network a node belongs to.  Nodes with different chain ids ignore each other's blocks and chains
+pub const DEFAULT_CHAIN_ID: &str = "minimal-blockchain";
+
+// Longest --chain-id allowed, which keeps it short enough to prefix every topic and every block
+pub const MAX_CHAIN_ID_LENGTH: usize = 64;
+pub const DEFAULT_WALLET_PATH: &str = "wallet.key";
+pub const DEFAULT_KEY_FILE: &str = "node.key";
+
+// Unless --max-attempts is given, mining gives up after this many times the expected number of attempts.  The chance
+// of a block legitimately needing that many is about e^-20
+pub const DEFAULT_ATTEMPT_BUDGET_FACTOR: f64 = 20.0;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Node configuration
+//
+// Values are taken from the --config file first, if there is one, then from the environment, and finally from any
+// command line flags, each overriding the last.  Keys outside a section of the file are flags without their leading
+// `--`, so `difficulty = 4` is the same as `--difficulty 4`, and `no-mine = true` the same as `--no-mine`.  The
+// genesis block can only be set in the `[genesis]` section:
+//
+// | Key           | Value                                                         | Default
+// |---------------|---------------------------------------------------------------|---
+// | `data`        | A string, or an array of strings, one per entry               | `"genesis!"`
+// | `timestamp`   | Seconds since the Unix epoch                                  | `DEFAULT_GENESIS_TIMESTAMP`
+// | `allocations` | An array of `"<account>=<amount>"`, or an `--allocations` list | No balances
+//
+// | Flag                       | Environment variable | Default
+// |----------------------------|----------------------|---
+// | `--chain-id <id>`          | `CHAIN_ID`           | `DEFAULT_CHAIN_ID`
+// | `--difficulty <bits>`      | `DIFFICULTY`         | `DEFAULT_DIFFICULTY`
+// | `--target <compact>`       | `TARGET`             | Derived from the difficulty
+// | `--retarget-interval <n>`  | `RETARGET_INTERVAL`  | `DEFAULT_RETARGET_INTERVAL`
+// | `--block-spacing <secs>`   | `BLOCK_SPACING`      | `DEFAULT_BLOCK_SPACING`
+// | `--reward <n>`             | `BLOCK_REWARD`       | `DEFAULT_BLOCK_REWARD`
+// | `--hash-algorithm <name>`  | `HASH_ALGORITHM`     | `sha256`
+// | `--hashing <mode>`         | `HASHING`            | `single`
+// | `--threads <n>`            | `MINING_THREADS`     | One per CPU
+// | `--timestamp-refresh <s>`  | `TIMESTAMP_REFRESH`  | `DEFAULT_TIMESTAMP_REFRESH`
+// | `--max-attempts <n>`       | `MAX_ATTEMPTS`       | `DEFAULT_ATTEMPT_BUDGET_FACTOR` times the expected attempts
+// | `--max-drift <secs>`       | `MAX_TIMESTAMP_DRIFT`| `DEFAULT_MAX_TIMESTAMP_DRIFT`
+// | `--median-time-span <n>`   | `MEDIAN_TIME_SPAN`   | `DEFAULT_MEDIAN_TIME_SPAN`
+// | `--seen-cache <n>`         | `SEEN_CACHE_SIZE`    | `DEFAULT_SEEN_CACHE_SIZE`
+// | `--max-data-size <bytes>`  | `MAX_DATA_SIZE`      | `DEFAULT_MAX_DATA_SIZE`
+// | `--max-reorg-depth <n>`    | `MAX_REORG_DEPTH`    | `DEFAULT_MAX_REORG_DEPTH`
+// | `--max-chain-length <n>`   | `MAX_CHAIN_LENGTH`   | `DEFAULT_MAX_CHAIN_LENGTH`
+// | `--max-chain-bytes <n>`    | `MAX_CHAIN_BYTES`    | `DEFAULT_MAX_CHAIN_BYTES`
+// | `--sync-chunk-size <n>`    | `SYNC_CHUNK_SIZE`    | `DEFAULT_SYNC_CHUNK_SIZE`
+// | `--allocations <list>`     | `ALLOCATIONS`        | No balances
+// | `--mempool-size <n>`       | `MEMPOOL_SIZE`       | `DEFAULT_MEMPOOL_SIZE`
+// | `--block-entries <n>`      | `BLOCK_ENTRIES`      | `DEFAULT_BLOCK_ENTRIES`
+// | `--max-find-results <n>`   | `MAX_FIND_RESULTS`   | `DEFAULT_MAX_FIND_RESULTS`
+// | `--ban-threshold <n>`      | `BAN_THRESHOLD`      | `DEFAULT_BAN_THRESHOLD`
+// | `--ban-duration <secs>`    | `BAN_DURATION`       | `DEFAULT_BAN_DURATION`
+// | `--max-inbound <n>`        | `MAX_INBOUND`        | `DEFAULT_MAX_INBOUND`
+// | `--max-outbound <n>`       | `MAX_OUTBOUND`       | `DEFAULT_MAX_OUTBOUND`
+// | `--db-path <path>`         | `DB_PATH`            | `DEFAULT_DB_PATH`
+// | `--wallet <path>`          | `WALLET_PATH`        | `DEFAULT_WALLET_PATH`
+// | `--key-file <path>`        | `KEY_FILE`           | `DEFAULT_KEY_FILE`
+// | `--prune-keep <n>`         | `PRUNE_KEEP`         | Every block keeps its data
+// | `--audit-log <path>`       | `AUDIT_LOG`          | The audit log is kept in memory only
+// | `--bootstrap <addrs>`      | `BOOTSTRAP`          | No bootstrap nodes
+// | `--peer <addrs>`           | `PEERS`              | No peers dialled at startup
+// | `--dial-attempts <n>`      | `DIAL_ATTEMPTS`      | `DEFAULT_DIAL_ATTEMPTS`
+// | `--anti-entropy <secs>`    | `ANTI_ENTROPY`       | `DEFAULT_ANTI_ENTROPY_INTERVAL`
+// | `--initial-sync <secs>`    | `INITIAL_SYNC`       | `DEFAULT_INITIAL_SYNC_TIMEOUT`
+// | `--initial-sync-peers <n>` | `INITIAL_SYNC_PEERS` | `DEFAULT_INITIAL_SYNC_PEERS`
+// | `--chain-response-interval <secs>` | `CHAIN_RESPONSE_INTERVAL` | `DEFAULT_CHAIN_RESPONSE_INTERVAL`
+// | `--max-chain-responses <n>` | `MAX_CHAIN_RESPONSES` | `DEFAULT_MAX_CHAIN_RESPONSES`
+// | `--listen <addrs>`         | `LISTEN`             | `DEFAULT_LISTEN_ADDR`
+// | `--ephemeral`              |                      | The chain, wallet and node key are stored at --db-path, --wallet and --key-file
+// | `--ephemeral-identity`     |                      | The node key is stored at --key-file
+// | `--no-mine`                |                      | Mining enabled
+// | `--dht`                    |                      | Only mDNS, unless --bootstrap is given
+// | `--no-mdns`                |                      | Peers on the local network are found with mDNS
+// | `--listen-fallback`        |                      | The node stops if it can't listen on a --listen address
+// | `--evict-peers`            |                      | Connections past --max-inbound are refused
+// | `--drop-unresponsive`      |                      | Peers that stop answering pings are only flagged
+// | `--drop-incompatible`      |                      | Peers on another protocol version are only warned about
+// | `--relay`                  |                      | Only if a --listen or --peer address goes through a relay
+// | `--relay-server`           |                      | Not a relay server
+// | `--config <path>`          | `CONFIG`             | No config file
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone)]
+pub struct Config {
+    // All nodes on the network must agree on these values
+    pub chain_id: String,
+    pub target: Target,
+    pub retarget_interval: u64,
+    pub block_spacing: i64,
+    pub block_reward: u64,
+    pub hashing: BlockHashing,
+    pub mining_threads: usize,
+    pub timestamp_refresh: Duration,
+    pub max_attempts: Option<u64>,
+    pub max_timestamp_drift: i64,
+    pub median_time_span: usize,
+    pub seen_cache_size: usize,
+    pub max_data_size: usize,
+    pub max_reorg_depth: u64,
+    pub max_chain_length: usize,
+    pub max_chain_bytes: usize,
+    pub sync_chunk_size: u64,
+    // Balances every chain starts with, including the allocations of the genesis spec
+    pub allocations: Vec<(String, u64)>,
+    pub mempool_size: usize,
+    pub block_entries: usize,
+    pub max_find_results: usize,
+    pub ban_threshold: u32,
+    pub ban_duration: Duration,
+    pub max_inbound: u32,
+    pub max_outbound: u32,
+    // Once --max-inbound is reached, a new inbound connection evicts the peer that has been of use least recently,
+    // rather than being refused
+    pub evict_peers: bool,
+    // Disconnect a peer once it has failed UNRESPONSIVE_PINGS pings in a row, rather than only flagging it
+    pub drop_unresponsive: bool,
+    // Disconnect a peer whose identify protocol version isn't this node's, rather than only warning about it
+    pub drop_incompatible: bool,
+    // None keeps the chain in memory only
+    pub db_path: Option<PathBuf>,
+    // None uses a new key for every run
+    pub wallet_path: Option<PathBuf>,
+    // None gives the node a new peer id for every run
+    pub key_file: Option<PathBuf>,
+    // Only this many blocks at the tip of the chain keep their data in memory, if set
+    pub prune_keep: Option<usize>,
+    // Every block dealt with is also recorded in this file, if set
+    pub audit_log: Option<PathBuf>,
+    // Peers to join the Kademlia DHT through, each given by an address ending in /p2p/<peer id>
+    pub bootstrap: Vec<(PeerId, Multiaddr)>,
+    // Dialled as soon as the node is listening, along with the bootstrap nodes
+    pub peers: Vec<Multiaddr>,
+    pub dial_attempts: u32,
+    // Zero never checks
+    pub anti_entropy_interval: Duration,
+    // Zero doesn't wait for a peer's chain at all
+    pub initial_sync_timeout: Duration,
+    pub initial_sync_peers: usize,
+    // Zero answers every chain request a peer makes
+    pub chain_response_interval: Duration,
+    pub max_chain_responses: usize,
+    // Peers are found through the DHT as well as mDNS.  Always on if there are bootstrap nodes
+    pub dht_enabled: bool,
+    // Peers on the local network are found with mDNS.  Off, peers are only found through --peer, --bootstrap, the DHT
+    // and `dial`
+    pub mdns_enabled: bool,
+    // Empty listens on DEFAULT_LISTEN_ADDR
+    pub listen: Vec<Multiaddr>,
+    // An address that can't be listened on is tried again with whatever port is free
+    pub listen_fallback: bool,
+    // Addresses through a relay, ending in /p2p-circuit, can be listened on and dialled
+    pub relay_enabled: bool,
+    // Relay circuits for peers behind NAT, keeping their connections open for longer
+    pub relay_server: bool,
+    // Observer nodes validate and relay blocks, but never mine them
+    pub mining_enabled: bool,
+    pub genesis: GenesisSpec,
+}
+
+// Environment variables and the command line flags they correspond to
+const ENV_VARS: &[(&str, &str)] = &[
+    ("CHAIN_ID", "--chain-id"),
+    ("DIFFICULTY", "--difficulty"),
+    ("TARGET", "--target"),
+    ("RETARGET_INTERVAL", "--retarget-interval"),
+    ("BLOCK_SPACING", "--block-spacing"),
+    ("BLOCK_REWARD", "--reward"),
+    ("HASH_ALGORITHM", "--hash-algorithm"),
+    ("HASHING", "--hashing"),
+    ("MINING_THREADS", "--threads"),
+    ("TIMESTAMP_REFRESH", "--timestamp-refresh"),
+    ("MAX_ATTEMPTS", "--max-attempts"),
+    ("MAX_TIMESTAMP_DRIFT", "--max-drift"),
+    ("MEDIAN_TIME_SPAN", "--median-time-span"),
+    ("SEEN_CACHE_SIZE", "--seen-cache"),
+    ("MAX_DATA_SIZE", "--max-data-size"),
+    ("MAX_REORG_DEPTH", "--max-reorg-depth"),
+    ("MAX_CHAIN_LENGTH", "--max-chain-length"),
+    ("MAX_CHAIN_BYTES", "--max-chain-bytes"),
+    ("SYNC_CHUNK_SIZE", "--sync-chunk-size"),
+    ("ALLOCATIONS", "--allocations"),
+    ("MEMPOOL_SIZE", "--mempool-size"),
+    ("BLOCK_ENTRIES", "--block-entries"),
+    ("MAX_FIND_RESULTS", "--max-find-results"),
+    ("BAN_THRESHOLD", "--ban-threshold"),
+    ("BAN_DURATION", "--ban-duration"),
+    ("MAX_INBOUND", "--max-inbound"),
+    ("MAX_OUTBOUND", "--max-outbound"),
+    ("DB_PATH", "--db-path"),
+    ("WALLET_PATH", "--wallet"),
+    ("KEY_FILE", "--key-file"),
+    ("PRUNE_KEEP", "--prune-keep"),
+    ("AUDIT_LOG", "--audit-log"),
+    ("BOOTSTRAP", "--bootstrap"),
+    ("PEERS", "--peer"),
+    ("DIAL_ATTEMPTS", "--dial-attempts"),
+    ("ANTI_ENTROPY", "--anti-entropy"),
+    ("INITIAL_SYNC", "--initial-sync"),
+    ("INITIAL_SYNC_PEERS", "--initial-sync-peers"),
+    ("CHAIN_RESPONSE_INTERVAL", "--chain-response-interval"),
+    ("MAX_CHAIN_RESPONSES", "--max-chain-responses"),
+    ("LISTEN", "--listen"),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            chain_id: String::from(DEFAULT_CHAIN_ID),
+            target: Target::from_leading_zero_bits(DEFAULT_DIFFICULTY),
+            retarget_interval: DEFAULT_RETARGET_INTERVAL,
+            block_spacing: DEFAULT_BLOCK_SPACING,
+            block_reward: DEFAULT_BLOCK_REWARD,
+            hashing: BlockHashing::default(),
+            mining_threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            timestamp_refresh: Duration::from_secs(DEFAULT_TIMESTAMP_REFRESH),
+            max_attempts: None,
+            max_timestamp_drift: DEFAULT_MAX_TIMESTAMP_DRIFT,
+            median_time_span: DEFAULT_MEDIAN_TIME_SPAN,
+            seen_cache_size: DEFAULT_SEEN_CACHE_SIZE,
+            max_data_size: DEFAULT_MAX_DATA_SIZE,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            max_chain_length: DEFAULT_MAX_CHAIN_LENGTH,
+            max_chain_bytes: DEFAULT_MAX_CHAIN_BYTES,
+            sync_chunk_size: DEFAULT_SYNC_CHUNK_SIZE,
+            allocations: vec![],
+            mempool_size: DEFAULT_MEMPOOL_SIZE,
+            block_entries: DEFAULT_BLOCK_ENTRIES,
+            max_find_results: DEFAULT_MAX_FIND_RESULTS,
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: Duration::from_secs(DEFAULT_BAN_DURATION),
+            max_inbound: DEFAULT_MAX_INBOUND,
+            max_outbound: DEFAULT_MAX_OUTBOUND,
+            evict_peers: false,
+            drop_unresponsive: false,
+            drop_incompatible: false,
+            db_path: Some(PathBuf::from(DEFAULT_DB_PATH)),
+            wallet_path: Some(PathBuf::from(DEFAULT_WALLET_PATH)),
+            key_file: Some(PathBuf::from(DEFAULT_KEY_FILE)),
+            prune_keep: None,
+            audit_log: None,
+            bootstrap: vec![],
+            peers: vec![],
+            dial_attempts: DEFAULT_DIAL_ATTEMPTS,
+            anti_entropy_interval: Duration::from_secs(DEFAULT_ANTI_ENTROPY_INTERVAL),
+            initial_sync_timeout: Duration::from_secs(DEFAULT_INITIAL_SYNC_TIMEOUT),
+            initial_sync_peers: DEFAULT_INITIAL_SYNC_PEERS,
+            chain_response_interval: Duration::from_secs(DEFAULT_CHAIN_RESPONSE_INTERVAL),
+            max_chain_responses: DEFAULT_MAX_CHAIN_RESPONSES,
+            dht_enabled: false,
+            mdns_enabled: true,
+            listen: vec![],
+            listen_fallback: false,
+            relay_enabled: false,
+            relay_server: false,
+            mining_enabled: true,
+            genesis: GenesisSpec::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env_and_args() -> Result<Self, String> {
+        let mut config = Self::default();
+        let args: Vec<String> = env::args().skip(1).collect();
+        let config_path = args
+            .iter()
+            .rposition(|arg| arg == "--config")
+            .and_then(|i| args.get(i + 1).cloned())
+            .or_else(|| env::var("CONFIG").ok());
+
+        if let Some(path) = config_path {
+            config.apply_file(Path::new(&path))?;
+        }
+
+        for (var, flag) in ENV_VARS {
+            if let Ok(value) = env::var(var) {
+                config.set(flag, &value)?;
+            }
+        }
+
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if config.set_switch(&arg) {
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} requires a value", arg))?;
+            config.set(&arg, &value)?;
+        }
+
+        if config.retarget_interval < 2 {
+            return Err(String::from("--retarget-interval must be at least 2"));
+        }
+
+        if config.ban_threshold == 0 {
+            return Err(String::from("--ban-threshold must be at least 1"));
+        }
+
+        if config.dial_attempts == 0 {
+            return Err(String::from("--dial-attempts must be at least 1"));
+        }
+
+        if config.initial_sync_peers == 0 {
+            return Err(String::from("--initial-sync-peers must be at least 1"));
+        }
+
+        if config.max_chain_responses == 0 {
+            return Err(String::from("--max-chain-responses must be at least 1"));
+        }
+
+        if config.sync_chunk_size == 0 {
+            return Err(String::from("--sync-chunk-size must be at least 1"));
+        }
+
+        // A reorg has to replay the transactions of the blocks it keeps from the fork point onwards
+        if config
+            .prune_keep
+            .is_some_and(|keep| keep as u64 <= config.max_reorg_depth)
+        {
+            return Err(String::from(
+                "--prune-keep must be more than --max-reorg-depth",
+            ));
+        }
+
+        if config.genesis.timestamp > chrono::Utc::now().timestamp() {
+            return Err(String::from(
+                "the genesis timestamp is in the future, so no block could follow it",
+            ));
+        }
+
+        config
+            .allocations
+            .extend(config.genesis.allocations.iter().cloned());
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<(), String> {
+        for entry in config_file::read(path)? {
+            let result = match entry.section.as_str() {
+                "" => self.apply_setting(&entry.key, &entry.value),
+                "genesis" => self.apply_genesis(&entry.key, &entry.value),
+                section => Err(format!("unknown section [{}]", section)),
+            };
+
+            result.map_err(|e| format!("{} line {}: {}", path.display(), entry.line, e))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_setting(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        let flag = format!("--{}", key.replace('_', "-"));
+
+        match value {
+            _ if flag == "--config" => Err(String::from(
+                "a config file can't name another config file",
+            )),
+            Value::Boolean(on) => {
+                // A switch that is off leaves the setting as it was
+                let mut config = self.clone();
+
+                if !config.set_switch(&flag) {
+                    return Err(format!("{} can't be true or false", key));
+                }
+
+                if *on {
+                    *self = config;
+                }
+                Ok(())
+            }
+            value => self.set(&flag, &value.to_string()),
+        }
+    }
+
+    fn apply_genesis(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        match (key, value) {
+            ("data", Value::String(entry)) => self.genesis.data = vec![entry.clone()],
+            ("data", Value::Array(entries)) => {
+                self.genesis.data = entries
+                    .iter()
+                    .map(|entry| match entry {
+                        Value::String(entry) => Ok(entry.clone()),
+                        _ => Err(String::from("genesis data entries must be strings")),
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            ("timestamp", Value::Integer(timestamp)) => self.genesis.timestamp = *timestamp,
+            ("allocations", Value::String(_) | Value::Array(_)) => {
+                self.genesis.allocations = parse_allocations(key, &value.to_string())?
+            }
+            ("data" | "timestamp" | "allocations", value) => {
+                return Err(format!("invalid value for {}: {}", key, value))
+            }
+            _ => return Err(format!("unknown genesis setting: {}", key)),
+        }
+
+        Ok(())
+    }
+
+    // Flags that don't take a value.  Returns false if `flag` isn't one of them
+    fn set_switch(&mut self, flag: &str) -> bool {
+        match flag {
+            "--no-mine" => self.mining_enabled = false,
+            "--dht" => self.dht_enabled = true,
+            "--no-mdns" => self.mdns_enabled = false,
+            "--listen-fallback" => self.listen_fallback = true,
+            "--relay" => self.relay_enabled = true,
+            "--relay-server" => self.relay_server = true,
+            "--evict-peers" => self.evict_peers = true,
+            "--drop-unresponsive" => self.drop_unresponsive = true,
+            "--drop-incompatible" => self.drop_incompatible = true,
+            "--ephemeral-identity" => self.key_file = None,
+            "--ephemeral" => {
+                self.db_path = None;
+                self.wallet_path = None;
+                self.key_file = None;
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "--chain-id" => self.chain_id = parse_chain_id(flag, value)?,
+            "--difficulty" => {
+                self.target = Target::from_leading_zero_bits(parse_value(flag, value)?)
+            }
+            "--target" => self.target = Target::from_compact(parse_compact(flag, value)?)?,
+            "--retarget-interval" => self.retarget_interval = parse_value(flag, value)?,
+            "--block-spacing" => self.block_spacing = parse_value(flag, value)?,
+            "--reward" => self.block_reward = parse_value(flag, value)?,
+            "--hash-algorithm" => self.hashing.algorithm = value.parse()?,
+            "--hashing" => self.hashing.mode = value.parse()?,
+            "--threads" => self.mining_threads = parse_value(flag, value)?,
+            "--timestamp-refresh" => {
+                self.timestamp_refresh = Duration::from_secs(parse_value(flag, value)?)
+            }
+            "--max-attempts" => self.max_attempts = Some(parse_value(flag, value)?),
+            "--max-drift" => self.max_timestamp_drift = parse_value(flag, value)?,
+            "--median-time-span" => self.median_time_span = parse_value(flag, value)?,
+            "--seen-cache" => self.seen_cache_size = parse_value(flag, value)?,
+            "--max-data-size" => self.max_data_size = parse_value(flag, value)?,
+            "--max-reorg-depth" => self.max_reorg_depth = parse_value(flag, value)?,
+            "--max-chain-length" => self.max_chain_length = parse_value(flag, value)?,
+            "--max-chain-bytes" => self.max_chain_bytes = parse_value(flag, value)?,
+            "--sync-chunk-size" => self.sync_chunk_size = parse_value(flag, value)?,
+            "--allocations" => self.allocations = parse_allocations(flag, value)?,
+            "--mempool-size" => self.mempool_size = parse_value(flag, value)?,
+            "--block-entries" => self.block_entries = parse_value(flag, value)?,
+            "--max-find-results" => self.max_find_results = parse_value(flag, value)?,
+            "--ban-threshold" => self.ban_threshold = parse_value(flag, value)?,
+            "--ban-duration" => self.ban_duration = Duration::from_secs(parse_value(flag, value)?),
+            "--max-inbound" => self.max_inbound = parse_value(flag, value)?,
+            "--max-outbound" => self.max_outbound = parse_value(flag, value)?,
+            "--db-path" => self.db_path = Some(PathBuf::from(value)),
+            "--wallet" => self.wallet_path = Some(PathBuf::from(value)),
+            "--key-file" => self.key_file = Some(PathBuf::from(value)),
+            "--prune-keep" => self.prune_keep = Some(parse_value(flag, value)?),
+            "--audit-log" => self.audit_log = Some(PathBuf::from(value)),
+            // Given more than once, every list is kept
+            "--bootstrap" => {
+                self.bootstrap.extend(parse_bootstrap(flag, value)?);
+                self.dht_enabled = true;
+            }
+            "--listen" => self.listen.extend(parse_multiaddrs(flag, value)?),
+            "--peer" => self.peers.extend(parse_multiaddrs(flag, value)?),
+            "--dial-attempts" => self.dial_attempts = parse_value(flag, value)?,
+            "--anti-entropy" => {
+                self.anti_entropy_interval = Duration::from_secs(parse_value(flag, value)?)
+            }
+            "--initial-sync" => {
+                self.initial_sync_timeout = Duration::from_secs(parse_value(flag, value)?)
+            }
+            "--initial-sync-peers" => self.initial_sync_peers = parse_value(flag, value)?,
+            "--chain-response-interval" => {
+                self.chain_response_interval = Duration::from_secs(parse_value(flag, value)?)
+            }
+            "--max-chain-responses" => self.max_chain_responses = parse_value(flag, value)?,
+            // Already read before the environment and the other flags
+            "--config" => {}
+            _ => return Err(format!("unknown argument: {}", flag)),
+        }
+
+        Ok(())
+    }
+
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if self.listen.is_empty() {
+            vec![DEFAULT_LISTEN_ADDR.parse().expect("valid default listen address")]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    // A relay server relays for others, and a node listening through a relay or dialling a peer through one is a client
+    pub fn uses_relay(&self) -> bool {
+        let through_relay = |addr: &Multiaddr| addr.iter().any(|protocol| protocol == Protocol::P2pCircuit);
+
+        self.relay_enabled
+            || self.relay_server
+            || self.listen.iter().any(through_relay)
+            || self.peers.iter().any(through_relay)
+    }
+
+    // The --peer addresses followed by those of the bootstrap nodes, each only once
+    pub fn startup_peers(&self) -> Vec<Multiaddr> {
+        let mut addrs: Vec<Multiaddr> = vec![];
+
+        for addr in self.peers.iter().chain(self.bootstrap.iter().map(|(_, addr)| addr)) {
+            if !addrs.contains(addr) {
+                addrs.push(addr.clone());
+            }
+        }
+
+        addrs
+    }
+
+    // Number of hashes to try before giving up on a block mined against `target`
+    pub fn attempt_budget(&self, target: Target) -> u64 {
+        self.max_attempts
+            .unwrap_or_else(|| (target.expected_attempts() * DEFAULT_ATTEMPT_BUDGET_FACTOR) as u64)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+// A comma separated list of <account>=<amount>, for example alice=100,bob=50
+fn parse_allocations(name: &str, value: &str) -> Result<Vec<(String, u64)>, String> {
+    value
+        .split(',')
+        .filter(|allocation| !allocation.trim().is_empty())
+        .map(|allocation| match allocation.split_once('=') {
+            Some((account, amount)) if !account.trim().is_empty() => {
+                Ok((account.trim().to_owned(), parse_value(name, amount.trim())?))
+            }
+            _ => Err(format!("invalid value for {}: {}", name, allocation)),
+        })
+        .collect()
+}
+
+// A comma separated list of multiaddrs, for example /ip4/0.0.0.0/tcp/4001,/ip6/::/tcp/4001
+fn parse_multiaddrs(name: &str, value: &str) -> Result<Vec<Multiaddr>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            if is_quic(addr) {
+                Err(format!("{} address {} - {}", name, addr, QUIC_UNSUPPORTED))
+            } else {
+                parse_value(name, addr)
+            }
+        })
+        .collect()
+}
+
+// libp2p 0.39 has no QUIC transport, so a QUIC address is refused up front with the reason, rather than failing to
+// parse (`/quic-v1`) or parsing and then failing to listen or dial (`/quic`)
+pub const QUIC_UNSUPPORTED: &str = "QUIC isn't supported by this node - use a /tcp or /ws address";
+
+pub fn is_quic(addr: &str) -> bool {
+    addr.split('/')
+        .any(|protocol| protocol == "quic" || protocol == "quic-v1")
+}
+
+// Multiaddrs as for parse_multiaddrs, each of which has to name the peer it reaches, for example
+// /ip4/192.168.1.10/tcp/4001/p2p/12D3KooW...
+fn parse_bootstrap(name: &str, value: &str) -> Result<Vec<(PeerId, Multiaddr)>, String> {
+    parse_multiaddrs(name, value)?
+        .into_iter()
+        .map(|addr| match addr.iter().last() {
+            Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash)
+                .map(|peer| (peer, addr.clone()))
+                .map_err(|_| format!("invalid peer id in {} address: {}", name, addr)),
+            _ => Err(format!("{} address must end in /p2p/<peer id>: {}", name, addr)),
+        })
+        .collect()
+}
+
+// Letters, digits, `-`, `_` and `.` only, so that a chain id reads the same in a log line as in a topic name
+fn parse_chain_id(name: &str, value: &str) -> Result<String, String> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_CHAIN_ID_LENGTH
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if valid {
+        Ok(value.to_owned())
+    } else {
+        Err(format!(
+            "invalid value for {}: {} - use up to {} letters, digits, '-', '_' or '.'",
+            name, value, MAX_CHAIN_ID_LENGTH
+        ))
+    }
+}
+
+// Compact targets are normally written in hex, for example 0x1f00ffff
+fn parse_compact(name: &str, value: &str) -> Result<u32, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16)
+            .map_err(|_| format!("invalid value for {}: {}", name, value)),
+        None => parse_value(name, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quic_addresses_are_refused_with_the_reason() {
+        let mut config = Config::default();
+
+        for addr in [
+            "/ip4/0.0.0.0/udp/4001/quic-v1",
+            "/ip4/0.0.0.0/udp/4001/quic",
+        ] {
+            let refused = config
+                .set("--listen", addr)
+                .expect_err("QUIC isn't supported");
+            assert!(refused.contains(QUIC_UNSUPPORTED), "{}", refused);
+            assert!(config.set("--peer", addr).is_err());
+        }
+        assert!(config.listen.is_empty() && config.peers.is_empty());
+
+        config
+            .set("--listen", "/ip4/0.0.0.0/tcp/4001,/ip4/0.0.0.0/tcp/4002/ws")
+            .expect("TCP and WebSocket are supported");
+        assert_eq!(config.listen.len(), 2);
+    }
+}
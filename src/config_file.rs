@@ -0,0 +1,202 @@
+use std::{fmt, fs, path::Path};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Settings read from a --config file
+//
+// The file is written in the small part of TOML that the node needs: `[section]` headers, and `key = value` lines
+// whose value is a double quoted string, an integer, `true` or `false`, or an array of these on a single line.
+// Anything after a `#` outside a string is a comment
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", s),
+            Self::Integer(n) => write!(f, "{}", n),
+            Self::Boolean(b) => write!(f, "{}", b),
+            Self::Array(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+// A key and its value, along with the section it was found in.  Keys before the first section header have an empty
+// section name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub section: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+pub fn read(path: &Path) -> Result<Vec<Entry>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn parse(text: &str) -> Result<Vec<Entry>, String> {
+    let mut section = String::new();
+    let mut entries = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let fail = |e: &str| format!("line {}: {}", i + 1, e);
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            section = name
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| is_bare_key(name))
+                .ok_or_else(|| fail("invalid section header"))?
+                .to_owned();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| fail("expected `key = value`"))?;
+        let key = key.trim();
+
+        if !is_bare_key(key) {
+            return Err(fail("invalid key"));
+        }
+
+        let mut rest = value.trim();
+        let value = parse_value(&mut rest).map_err(|e| fail(&e))?;
+
+        if !rest.trim().is_empty() {
+            return Err(fail("unexpected text after the value"));
+        }
+
+        entries.push(Entry {
+            section: section.clone(),
+            key: key.to_owned(),
+            value,
+            line: i + 1,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// A `#` inside a string doesn't start a comment
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+// Parses the value at the start of `text`, leaving `text` holding whatever follows it
+fn parse_value(text: &mut &str) -> Result<Value, String> {
+    *text = text.trim_start();
+
+    if let Some(rest) = text.strip_prefix('"') {
+        return parse_string(rest, text);
+    }
+
+    if let Some(rest) = text.strip_prefix('[') {
+        *text = rest;
+        return parse_array(text);
+    }
+
+    let end = text
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    *text = rest;
+
+    match word {
+        "true" => Ok(Value::Boolean(true)),
+        "false" => Ok(Value::Boolean(false)),
+        _ => word
+            .replace('_', "")
+            .parse()
+            .map(Value::Integer)
+            .map_err(|_| format!("invalid value: {}", word)),
+    }
+}
+
+// `rest` follows the opening quote
+fn parse_string<'a>(rest: &'a str, text: &mut &'a str) -> Result<Value, String> {
+    let mut value = String::new();
+    let mut chars = rest.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                *text = &rest[i + 1..];
+                return Ok(Value::String(value));
+            }
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                _ => return Err(String::from("unsupported escape in string")),
+            },
+            _ => value.push(c),
+        }
+    }
+
+    Err(String::from("unterminated string"))
+}
+
+// `text` follows the opening bracket.  A trailing comma is allowed
+fn parse_array(text: &mut &str) -> Result<Value, String> {
+    let mut values = vec![];
+
+    loop {
+        *text = text.trim_start();
+
+        if let Some(rest) = text.strip_prefix(']') {
+            *text = rest;
+            return Ok(Value::Array(values));
+        }
+
+        if text.is_empty() {
+            return Err(String::from("unterminated array"));
+        }
+
+        values.push(parse_value(text)?);
+        *text = text.trim_start();
+
+        if let Some(rest) = text.strip_prefix(',') {
+            *text = rest;
+        } else if !text.starts_with(']') {
+            return Err(String::from("expected `,` or `]` in array"));
+        }
+    }
+}
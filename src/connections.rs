@@ -0,0 +1,208 @@
+use libp2p::{core::connection::ConnectionLimits, PeerId};
+use serde::Serialize;
+use std::{collections::HashMap, time::Instant};
+
+// Connections to one peer in each direction, and when the first of them was made
+struct PeerConnections {
+    inbound: u32,
+    outbound: u32,
+    since: Instant,
+}
+
+// Shown by `ls p` and `stats`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ConnectionCounts {
+    pub inbound: u32,
+    pub max_inbound: u32,
+    pub outbound: u32,
+    pub max_outbound: u32,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The connections the node holds, and how useful each peer has been
+//
+// The swarm refuses connections past its limits by itself.  With `evict`, it lets one more inbound connection through
+// than `max_inbound`, and the peer that has least recently sent a block or a chain is disconnected to make room.  A
+// peer that has never sent either goes first, oldest connection first, so a busy network can't crowd out the peers the
+// node actually syncs from
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct Connections {
+    max_inbound: u32,
+    max_outbound: u32,
+    evict: bool,
+    peers: HashMap<PeerId, PeerConnections>,
+    // When each peer last sent a block that was added, or a chunk of a chain that checked out
+    last_useful: HashMap<PeerId, Instant>,
+}
+
+impl Connections {
+    pub fn new(max_inbound: u32, max_outbound: u32, evict: bool) -> Self {
+        Self {
+            max_inbound,
+            max_outbound,
+            evict,
+            peers: HashMap::new(),
+            last_useful: HashMap::new(),
+        }
+    }
+
+    // The limits the swarm enforces
+    pub fn limits(&self) -> ConnectionLimits {
+        let max_inbound = if self.evict {
+            self.max_inbound + 1
+        } else {
+            self.max_inbound
+        };
+
+        ConnectionLimits::default()
+            .with_max_established_incoming(Some(max_inbound))
+            .with_max_established_outgoing(Some(self.max_outbound))
+    }
+
+    // Returns true if that is the only connection to the peer
+    pub fn established(&mut self, peer: PeerId, inbound: bool) -> bool {
+        let connections = self.peers.entry(peer).or_insert(PeerConnections {
+            inbound: 0,
+            outbound: 0,
+            since: Instant::now(),
+        });
+
+        if inbound {
+            connections.inbound += 1;
+        } else {
+            connections.outbound += 1;
+        }
+
+        connections.inbound + connections.outbound == 1
+    }
+
+    // Returns true if that was the last connection to the peer
+    pub fn closed(&mut self, peer: &PeerId, inbound: bool) -> bool {
+        let Some(connections) = self.peers.get_mut(peer) else {
+            return false;
+        };
+
+        if inbound {
+            connections.inbound = connections.inbound.saturating_sub(1);
+        } else {
+            connections.outbound = connections.outbound.saturating_sub(1);
+        }
+
+        if connections.inbound > 0 || connections.outbound > 0 {
+            return false;
+        }
+
+        self.peers.remove(peer);
+        self.last_useful.remove(peer);
+        true
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerId> {
+        self.peers.keys()
+    }
+
+    // Connections to the peer that it made, and that this node made
+    pub fn directions(&self, peer: &PeerId) -> (u32, u32) {
+        self.peers
+            .get(peer)
+            .map_or((0, 0), |connections| (connections.inbound, connections.outbound))
+    }
+
+    pub fn connected_since(&self, peer: &PeerId) -> Option<Instant> {
+        self.peers.get(peer).map(|connections| connections.since)
+    }
+
+    pub fn useful(&mut self, peer: &PeerId) {
+        if self.peers.contains_key(peer) {
+            self.last_useful.insert(*peer, Instant::now());
+        }
+    }
+
+    pub fn counts(&self) -> ConnectionCounts {
+        ConnectionCounts {
+            inbound: self.peers.values().map(|c| c.inbound).sum(),
+            max_inbound: self.max_inbound,
+            outbound: self.peers.values().map(|c| c.outbound).sum(),
+            max_outbound: self.max_outbound,
+        }
+    }
+
+    // The peer to disconnect now that `newcomer` has taken the node past --max-inbound, if it is to evict peers at
+    // all.  Only peers with an inbound connection are evicted, and never the newcomer unless it is the only one
+    pub fn to_evict(&self, newcomer: &PeerId) -> Option<PeerId> {
+        if !self.evict || self.counts().inbound <= self.max_inbound {
+            return None;
+        }
+
+        self.peers
+            .iter()
+            .filter(|(_, connections)| connections.inbound > 0)
+            .min_by_key(|(peer, connections)| {
+                (*peer == newcomer, self.last_useful.get(peer), connections.since)
+            })
+            .map(|(peer, _)| *peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_are_counted_in_each_direction() {
+        let mut connections = Connections::new(2, 1, false);
+        let peer = PeerId::random();
+
+        assert!(connections.established(peer, true));
+        assert!(!connections.established(peer, false));
+        assert_eq!(connections.directions(&peer), (1, 1));
+
+        assert!(!connections.closed(&peer, true));
+        assert!(connections.closed(&peer, false));
+        assert_eq!(connections.peers().count(), 0);
+        assert_eq!(connections.counts().inbound, 0);
+        assert_eq!(connections.counts().outbound, 0);
+    }
+
+    // Two peers connected in, one of which has sent a block, and one this node connected out to
+    fn past_the_limit(evict: bool) -> (Connections, PeerId, PeerId) {
+        let mut connections = Connections::new(2, 8, evict);
+        let (useful, idle, outbound) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        connections.established(outbound, false);
+        connections.established(useful, true);
+        connections.established(idle, true);
+        connections.useful(&useful);
+        (connections, idle, useful)
+    }
+
+    #[test]
+    fn the_least_useful_inbound_peer_makes_way_for_a_newcomer() {
+        let (mut connections, idle, _) = past_the_limit(true);
+        let newcomer = PeerId::random();
+
+        assert_eq!(connections.to_evict(&newcomer), None);
+        connections.established(newcomer, true);
+        assert_eq!(connections.to_evict(&newcomer), Some(idle));
+    }
+
+    #[test]
+    fn a_newcomer_is_kept_even_over_peers_that_have_been_useful() {
+        let (mut connections, idle, useful) = past_the_limit(true);
+        let newcomer = PeerId::random();
+
+        connections.useful(&idle);
+        connections.established(newcomer, true);
+        let evicted = connections.to_evict(&newcomer);
+        assert!(evicted == Some(idle) || evicted == Some(useful));
+    }
+
+    #[test]
+    fn nobody_is_evicted_without_evict() {
+        let (mut connections, _, _) = past_the_limit(false);
+        let newcomer = PeerId::random();
+
+        connections.established(newcomer, true);
+        assert_eq!(connections.to_evict(&newcomer), None);
+    }
+}
@@ -0,0 +1,131 @@
+use libp2p::Multiaddr;
+use std::time::{Duration, Instant};
+
+// The first retry waits this long, and each one after it twice as long as the one before, up to MAX_DIAL_BACKOFF
+pub const FIRST_DIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_DIAL_BACKOFF: Duration = Duration::from_secs(64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialState {
+    // Waiting until `next_attempt` to be dialled
+    Waiting,
+    Dialling,
+    Connected,
+    // Every attempt failed
+    GaveUp,
+}
+
+pub struct StartupDial {
+    pub addr: Multiaddr,
+    pub state: DialState,
+    pub attempts: u32,
+    next_attempt: Instant,
+    pub last_error: Option<String>,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The peers given with --peer or --bootstrap, which are dialled as soon as the node is listening
+//
+// An address that can't be reached is tried again after an exponential backoff, up to `max_attempts` times in all.
+// Only the outcome of each address is logged, not every failed attempt
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct StartupDials {
+    max_attempts: u32,
+    dials: Vec<StartupDial>,
+    summarized: bool,
+}
+
+impl StartupDials {
+    pub fn new(addrs: Vec<Multiaddr>, max_attempts: u32) -> Self {
+        let now = Instant::now();
+
+        Self {
+            max_attempts,
+            dials: addrs
+                .into_iter()
+                .map(|addr| StartupDial {
+                    addr,
+                    state: DialState::Waiting,
+                    attempts: 0,
+                    next_attempt: now,
+                    last_error: None,
+                })
+                .collect(),
+            summarized: false,
+        }
+    }
+
+    // The addresses due to be dialled now, which are taken to be dialling from here on
+    pub fn due(&mut self) -> Vec<Multiaddr> {
+        let now = Instant::now();
+
+        self.dials
+            .iter_mut()
+            .filter(|dial| dial.state == DialState::Waiting && dial.next_attempt <= now)
+            .map(|dial| {
+                dial.state = DialState::Dialling;
+                dial.attempts += 1;
+                dial.addr.clone()
+            })
+            .collect()
+    }
+
+    pub fn is_dialling(&self, addr: &Multiaddr) -> bool {
+        self.dials
+            .iter()
+            .any(|dial| dial.state == DialState::Dialling && dial.addr == *addr)
+    }
+
+    pub fn connected(&mut self, addr: &Multiaddr) {
+        if let Some(dial) = self.dialling(addr) {
+            dial.state = DialState::Connected;
+        }
+    }
+
+    // Returns the dial if that was its last attempt
+    pub fn failed(&mut self, addr: &Multiaddr, error: String) -> Option<&StartupDial> {
+        let max_attempts = self.max_attempts;
+        let dial = self.dialling(addr)?;
+
+        dial.last_error = Some(error);
+
+        if dial.attempts >= max_attempts {
+            dial.state = DialState::GaveUp;
+            return Some(dial);
+        }
+
+        let backoff = FIRST_DIAL_BACKOFF
+            .saturating_mul(1 << (dial.attempts - 1).min(16))
+            .min(MAX_DIAL_BACKOFF);
+        dial.state = DialState::Waiting;
+        dial.next_attempt = Instant::now() + backoff;
+        None
+    }
+
+    fn dialling(&mut self, addr: &Multiaddr) -> Option<&mut StartupDial> {
+        self.dials
+            .iter_mut()
+            .find(|dial| dial.state == DialState::Dialling && dial.addr == *addr)
+    }
+
+    // Returns the number connected to and given up on, the first time every dial has done one or the other
+    pub fn summary(&mut self) -> Option<(usize, usize)> {
+        let finished = self
+            .dials
+            .iter()
+            .all(|dial| matches!(dial.state, DialState::Connected | DialState::GaveUp));
+
+        if self.summarized || self.dials.is_empty() || !finished {
+            return None;
+        }
+
+        self.summarized = true;
+        let connected = self
+            .dials
+            .iter()
+            .filter(|dial| dial.state == DialState::Connected)
+            .count();
+
+        Some((connected, self.dials.len() - connected))
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// SHA-256 block hash
+//
+// Hashes are held as raw digests, and only converted to hex for display and on the wire.  Parsing accepts either
+// case, but hashes are always written in lower case
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockHash([u8; 32]);
+
+impl BlockHash {
+    // Stands in for the hash of the block before the genesis block, which doesn't exist
+    pub const ZERO: Self = Self([0; 32]);
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for BlockHash {
+    fn from(digest: [u8; 32]) -> Self {
+        Self(digest)
+    }
+}
+
+impl PartialEq<[u8; 32]> for BlockHash {
+    fn eq(&self, digest: &[u8; 32]) -> bool {
+        &self.0 == digest
+    }
+}
+
+impl PartialEq<[u8]> for BlockHash {
+    fn eq(&self, digest: &[u8]) -> bool {
+        self.0[..] == *digest
+    }
+}
+
+impl FromStr for BlockHash {
+    type Err = String;
+
+    fn from_str(hex_hash: &str) -> Result<Self, Self::Err> {
+        let mut digest = [0; 32];
+
+        hex::decode_to_slice(hex_hash, &mut digest)
+            .map_err(|e| format!("invalid block hash {:?}: {}", hex_hash, e))?;
+
+        Ok(Self(digest))
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BlockHash({})", self)
+    }
+}
+
+// Human readable formats such as JSON get a hex string, so blocks look the same as they did when hashes were Strings.
+// Binary formats get the raw digest
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer)?
+                .parse()
+                .map_err(serde::de::Error::custom)
+        } else {
+            <[u8; 32]>::deserialize(deserializer).map(Self)
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fmt, str::FromStr};
+
+// Calculates block hashes from their preimage.  Every node on a network must use the same algorithm
+pub trait BlockHasher: Send + Sync {
+    fn hash_preimage(&self, preimage: &[u8]) -> [u8; 32];
+}
+
+struct Sha256Hasher;
+
+impl BlockHasher for Sha256Hasher {
+    fn hash_preimage(&self, preimage: &[u8]) -> [u8; 32] {
+        use sha2::Digest;
+
+        sha2::Sha256::digest(preimage).into()
+    }
+}
+
+#[cfg(feature = "sha3")]
+struct Sha3Hasher;
+
+#[cfg(feature = "sha3")]
+impl BlockHasher for Sha3Hasher {
+    fn hash_preimage(&self, preimage: &[u8]) -> [u8; 32] {
+        use sha3::Digest;
+
+        sha3::Sha3_256::digest(preimage).into()
+    }
+}
+
+#[cfg(feature = "blake3")]
+struct Blake3Hasher;
+
+#[cfg(feature = "blake3")]
+impl BlockHasher for Blake3Hasher {
+    fn hash_preimage(&self, preimage: &[u8]) -> [u8; 32] {
+        blake3::hash(preimage).into()
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Hashing algorithm selected by --hash-algorithm
+//
+// SHA-256 is always available.  SHA3-256 and BLAKE3 are only available when built with the `sha3` or `blake3`
+// features respectively
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    #[cfg(feature = "sha3")]
+    Sha3_256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(self) -> &'static dyn BlockHasher {
+        match self {
+            Self::Sha256 => &Sha256Hasher,
+            #[cfg(feature = "sha3")]
+            Self::Sha3_256 => &Sha3Hasher,
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => &Blake3Hasher,
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "sha256" => Ok(Self::Sha256),
+            #[cfg(feature = "sha3")]
+            "sha3-256" => Ok(Self::Sha3_256),
+            #[cfg(not(feature = "sha3"))]
+            "sha3-256" => Err(String::from("sha3-256 needs a build with --features sha3")),
+            #[cfg(feature = "blake3")]
+            "blake3" => Ok(Self::Blake3),
+            #[cfg(not(feature = "blake3"))]
+            "blake3" => Err(String::from("blake3 needs a build with --features blake3")),
+            _ => Err(format!(
+                "unknown hash algorithm: {} (expected sha256, sha3-256 or blake3)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Sha256 => "sha256",
+            #[cfg(feature = "sha3")]
+            Self::Sha3_256 => "sha3-256",
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => "blake3",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Whether the hashing algorithm is applied once, or twice as Bitcoin does with SHA-256, selected by --hashing
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashMode {
+    Single,
+    Double,
+}
+
+impl HashMode {
+    pub fn other(self) -> Self {
+        match self {
+            Self::Single => Self::Double,
+            Self::Double => Self::Single,
+        }
+    }
+}
+
+impl FromStr for HashMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "single" => Ok(Self::Single),
+            "double" => Ok(Self::Double),
+            _ => Err(format!(
+                "unknown hashing mode: {} (expected single or double)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HashMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Single => write!(f, "single"),
+            Self::Double => write!(f, "double"),
+        }
+    }
+}
+
+// Everything needed to calculate a block hash from its preimage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHashing {
+    pub algorithm: HashAlgorithm,
+    pub mode: HashMode,
+}
+
+impl BlockHashing {
+    pub fn hash(&self, preimage: &[u8]) -> [u8; 32] {
+        let hasher = self.algorithm.hasher();
+        let digest = hasher.hash_preimage(preimage);
+
+        match self.mode {
+            HashMode::Single => digest,
+            HashMode::Double => hasher.hash_preimage(&digest),
+        }
+    }
+}
+
+impl Default for BlockHashing {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::Sha256,
+            mode: HashMode::Single,
+        }
+    }
+}
+
+impl fmt::Display for BlockHashing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            HashMode::Single => write!(f, "{}", self.algorithm),
+            HashMode::Double => write!(f, "double {}", self.algorithm),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_digest(hashing: BlockHashing, preimage: &[u8]) -> String {
+        hex::encode(hashing.hash(preimage))
+    }
+
+    #[test]
+    fn sha256_digests_of_known_inputs_are_pinned() {
+        let single = BlockHashing::default();
+        let double = BlockHashing {
+            mode: HashMode::Double,
+            ..single
+        };
+
+        assert_eq!(
+            hex_digest(single, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex_digest(double, b"abc"),
+            "4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"
+        );
+        assert_eq!(
+            hex_digest(single, b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex_digest(double, b""),
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use libp2p::PeerId;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+// Pings the average round trip time is taken over
+pub const PING_SAMPLES: usize = 10;
+
+// A peer is flagged as unresponsive once this many pings to it have failed in a row
+pub const UNRESPONSIVE_PINGS: u32 = 3;
+
+#[derive(Default)]
+pub struct PeerLatency {
+    // Most recent last
+    samples: VecDeque<Duration>,
+    pub failures_in_a_row: u32,
+}
+
+impl PeerLatency {
+    pub fn last(&self) -> Option<Duration> {
+        self.samples.back().copied()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+
+    pub fn last_ping_failed(&self) -> bool {
+        self.failures_in_a_row > 0
+    }
+
+    pub fn is_unresponsive(&self) -> bool {
+        self.failures_in_a_row >= UNRESPONSIVE_PINGS
+    }
+}
+
+// One peer's figures, as `stats --json` shows them
+#[derive(Debug, Serialize)]
+pub struct PeerLatencyStats {
+    pub peer: String,
+    pub last_ms: Option<f64>,
+    pub average_ms: Option<f64>,
+    pub failures_in_a_row: u32,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Round trip times to each connected peer, from the ping protocol
+//
+// Each peer keeps its last PING_SAMPLES round trip times, and a count of the pings to it that have failed since the
+// last one that didn't.  A peer is forgotten once the node has no connection to it
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Default)]
+pub struct Latencies {
+    peers: HashMap<PeerId, PeerLatency>,
+}
+
+impl Latencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn success(&mut self, peer: PeerId, rtt: Duration) {
+        let latency = self.peers.entry(peer).or_default();
+
+        if latency.samples.len() == PING_SAMPLES {
+            latency.samples.pop_front();
+        }
+
+        latency.samples.push_back(rtt);
+        latency.failures_in_a_row = 0;
+    }
+
+    // Returns true if the failure has just made the peer unresponsive
+    pub fn failure(&mut self, peer: PeerId) -> bool {
+        let latency = self.peers.entry(peer).or_default();
+        latency.failures_in_a_row += 1;
+        latency.failures_in_a_row == UNRESPONSIVE_PINGS
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&PeerLatency> {
+        self.peers.get(peer)
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    pub fn peer_stats(&self, peer: &PeerId) -> Option<PeerLatencyStats> {
+        self.peers.get(peer).map(|latency| latency_stats(peer, latency))
+    }
+
+    // Sorted by peer id, so that the output is stable
+    pub fn stats(&self) -> Vec<PeerLatencyStats> {
+        let mut stats: Vec<PeerLatencyStats> = self
+            .peers
+            .iter()
+            .map(|(peer, latency)| latency_stats(peer, latency))
+            .collect();
+
+        stats.sort_by(|a, b| a.peer.cmp(&b.peer));
+        stats
+    }
+}
+
+fn latency_stats(peer: &PeerId, latency: &PeerLatency) -> PeerLatencyStats {
+    let millis = |d: Duration| d.as_secs_f64() * 1000.0;
+
+    PeerLatencyStats {
+        peer: peer.to_string(),
+        last_ms: latency.last().map(millis),
+        average_ms: latency.average().map(millis),
+        failures_in_a_row: latency.failures_in_a_row,
+    }
+}
@@ -0,0 +1,418 @@
+use crate::{wallet, Block};
+use libp2p::identity::ed25519;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+// Data entries that start with this are transactions rather than free text
+const TRANSACTION_PREFIX: &str = "tx:";
+
+// Starts the bytes a transaction signature covers, so that they can't be mistaken for anything else that gets signed
+const SIGNING_PREFIX: &[u8] = b"transaction";
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Transfer of `amount` from one account to another
+//
+// An account is named after the ed25519 key that controls it, and every transaction carries the sender's public key
+// and its signature over the other fields.  The nonce stops the same signed transaction being applied twice: each
+// account numbers the transactions it sends from 1, and they have to reach the chain in that order
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+    pub nonce: u64,
+    // Both in hex, and left empty by older versions
+    #[serde(default)]
+    pub public_key: String,
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl Transaction {
+    // The data entry that carries the transaction in a block
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_string(self).expect("can convert transaction to JSON");
+
+        format!("{}{}", TRANSACTION_PREFIX, json)
+    }
+
+    // None if the entry isn't a transaction at all
+    pub fn decode(entry: &str) -> Option<Result<Self, TransactionError>> {
+        let json = entry.strip_prefix(TRANSACTION_PREFIX)?;
+
+        Some(serde_json::from_str(json).map_err(|e| TransactionError::Malformed(e.to_string())))
+    }
+
+    // The fields the signature covers, each in a fixed layout so that no two transactions have the same bytes
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = SIGNING_PREFIX.to_vec();
+
+        for field in [&self.from, &self.to] {
+            bytes.extend((field.len() as u32).to_be_bytes());
+            bytes.extend(field.as_bytes());
+        }
+
+        bytes.extend(self.amount.to_be_bytes());
+        bytes.extend(self.nonce.to_be_bytes());
+        bytes
+    }
+
+    // The public key has to be the one the sender's account is named after, and the signature has to be its
+    // signature over this transaction
+    pub fn verify(&self) -> Result<(), TransactionError> {
+        if self.public_key.is_empty() || self.signature.is_empty() {
+            return Err(TransactionError::Unsigned {
+                account: self.from.clone(),
+            });
+        }
+
+        let bad_signature = || TransactionError::BadSignature {
+            account: self.from.clone(),
+        };
+        let key = hex::decode(&self.public_key)
+            .ok()
+            .and_then(|bytes| ed25519::PublicKey::decode(&bytes).ok())
+            .ok_or_else(bad_signature)?;
+
+        if wallet::address(&key) != self.from {
+            return Err(TransactionError::SenderMismatch {
+                account: self.from.clone(),
+            });
+        }
+
+        let signature = hex::decode(&self.signature).map_err(|_| bad_signature())?;
+
+        if !key.verify(&self.signing_bytes(), &signature) {
+            return Err(bad_signature());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    Malformed(String),
+    Unsigned {
+        account: String,
+    },
+    // The public key doesn't control the sending account
+    SenderMismatch {
+        account: String,
+    },
+    BadSignature {
+        account: String,
+    },
+    WrongNonce {
+        account: String,
+        expected: u64,
+        got: u64,
+    },
+    Overdraft {
+        account: String,
+        balance: u64,
+        amount: u64,
+    },
+    Overflow {
+        account: String,
+    },
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed transaction: {}", e),
+            Self::Unsigned { account } => {
+                write!(f, "a transaction from {} is not signed", account)
+            }
+            Self::SenderMismatch { account } => write!(
+                f,
+                "a transaction from {} is signed with a key that doesn't control that account",
+                account
+            ),
+            Self::BadSignature { account } => write!(
+                f,
+                "a transaction from {} has a signature that doesn't match it",
+                account
+            ),
+            Self::WrongNonce {
+                account,
+                expected,
+                got,
+            } => write!(
+                f,
+                "{} sent transaction number {}, but its next one is number {}",
+                account, got, expected
+            ),
+            Self::Overdraft {
+                account,
+                balance,
+                amount,
+            } => write!(
+                f,
+                "{} has a balance of {}, too little to send {}",
+                account, balance, amount
+            ),
+            Self::Overflow { account } => write!(f, "the balance of {} would overflow", account),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    // Number of transactions sent so far
+    pub nonce: u64,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Account balances as of some block
+//
+// Every chain starts with the --allocations balances.  Each block then pays its reward to its miner, after applying
+// its transactions in order, so a miner can't spend the reward of a block in the block itself
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone)]
+pub struct Ledger {
+    accounts: HashMap<String, Account>,
+}
+
+impl Ledger {
+    pub fn new(allocations: &[(String, u64)]) -> Self {
+        let mut accounts: HashMap<String, Account> = HashMap::new();
+
+        for (account, amount) in allocations {
+            let balance = &mut accounts.entry(account.clone()).or_default().balance;
+            *balance = balance.saturating_add(*amount);
+        }
+
+        Self { accounts }
+    }
+
+    // Picks up from accounts saved earlier
+    pub fn from_accounts(accounts: impl IntoIterator<Item = (String, Account)>) -> Self {
+        Self {
+            accounts: accounts.into_iter().collect(),
+        }
+    }
+
+    // The ledger after every block of `chain` in turn, or the id of the first block that can't be applied and why
+    pub fn replay(mut self, chain: &[Block]) -> Result<Self, (u64, TransactionError)> {
+        for block in chain {
+            self.apply(block).map_err(|e| (block.header.id, e))?;
+        }
+
+        Ok(self)
+    }
+
+    pub fn account(&self, name: &str) -> Account {
+        self.accounts.get(name).copied().unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&String, &Account)> {
+        self.accounts.iter()
+    }
+
+    // Either the whole block is applied, or none of it is
+    pub fn apply(&mut self, block: &Block) -> Result<(), TransactionError> {
+        let mut changes = HashMap::new();
+
+        for entry in &block.data {
+            if let Some(transaction) = Transaction::decode(entry) {
+                self.transfer(&mut changes, &transaction?)?;
+            }
+        }
+
+        if block.header.reward > 0 {
+            let miner = &block.header.miner;
+            let mut account = self.current(&changes, miner);
+
+            account.balance = account
+                .balance
+                .checked_add(block.header.reward)
+                .ok_or_else(|| TransactionError::Overflow {
+                    account: miner.clone(),
+                })?;
+            changes.insert(miner.clone(), account);
+        }
+
+        self.accounts.extend(changes);
+        Ok(())
+    }
+
+    // Splits `data` into the entries that could go into the next block in that order, and the transactions that
+    // couldn't, along with the reason why
+    pub fn admissible(&self, data: Vec<String>) -> (Vec<String>, Vec<(String, TransactionError)>) {
+        let mut changes = HashMap::new();
+        let mut admitted = vec![];
+        let mut refused = vec![];
+
+        for entry in data {
+            let result = match Transaction::decode(&entry) {
+                Some(transaction) => {
+                    transaction.and_then(|transaction| self.transfer(&mut changes, &transaction))
+                }
+                None => Ok(()),
+            };
+
+            match result {
+                Ok(()) => admitted.push(entry),
+                Err(e) => refused.push((entry, e)),
+            }
+        }
+
+        (admitted, refused)
+    }
+
+    // Leaves `changes` as it was if the transfer fails
+    fn transfer(
+        &self,
+        changes: &mut HashMap<String, Account>,
+        transaction: &Transaction,
+    ) -> Result<(), TransactionError> {
+        let Transaction {
+            from,
+            to,
+            amount,
+            nonce,
+            ..
+        } = transaction;
+
+        transaction.verify()?;
+
+        let mut sender = self.current(changes, from);
+
+        if *nonce != sender.nonce + 1 {
+            return Err(TransactionError::WrongNonce {
+                account: from.clone(),
+                expected: sender.nonce + 1,
+                got: *nonce,
+            });
+        }
+
+        if sender.balance < *amount {
+            return Err(TransactionError::Overdraft {
+                account: from.clone(),
+                balance: sender.balance,
+                amount: *amount,
+            });
+        }
+
+        sender.balance -= amount;
+        sender.nonce += 1;
+
+        // Sending to yourself only uses up a nonce
+        let mut receiver = if to == from {
+            sender
+        } else {
+            self.current(changes, to)
+        };
+
+        receiver.balance = receiver
+            .balance
+            .checked_add(*amount)
+            .ok_or_else(|| TransactionError::Overflow {
+                account: to.clone(),
+            })?;
+
+        if to != from {
+            changes.insert(from.clone(), sender);
+        }
+        changes.insert(to.clone(), receiver);
+        Ok(())
+    }
+
+    fn current(&self, changes: &HashMap<String, Account>, name: &str) -> Account {
+        changes
+            .get(name)
+            .copied()
+            .unwrap_or_else(|| self.account(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Wallet;
+
+    fn signed(wallet: &Wallet, amount: u64) -> Transaction {
+        let mut transaction = Transaction {
+            from: wallet.address(),
+            to: Wallet::generate().address(),
+            amount,
+            nonce: 1,
+            public_key: String::new(),
+            signature: String::new(),
+        };
+
+        wallet.sign(&mut transaction);
+        transaction
+    }
+
+    #[test]
+    fn a_signed_transaction_survives_being_encoded() {
+        let transaction = signed(&Wallet::generate(), 10);
+
+        assert_eq!(transaction.verify(), Ok(()));
+        assert_eq!(
+            Transaction::decode(&transaction.encode()),
+            Some(Ok(transaction))
+        );
+        assert_eq!(Transaction::decode("free text"), None);
+    }
+
+    #[test]
+    fn a_tampered_amount_breaks_the_signature() {
+        let alice = Wallet::generate();
+        let transaction = Transaction {
+            amount: 1000,
+            ..signed(&alice, 10)
+        };
+
+        assert_eq!(
+            transaction.verify(),
+            Err(TransactionError::BadSignature {
+                account: alice.address()
+            })
+        );
+    }
+
+    #[test]
+    fn a_transfer_from_someone_else_cannot_be_forged() {
+        let (alice, mallory) = (Wallet::generate(), Wallet::generate());
+        let genuine = signed(&alice, 10);
+
+        // Signed with mallory's key, which doesn't control alice's account
+        let mut forged = genuine.clone();
+        mallory.sign(&mut forged);
+        assert_eq!(
+            forged.verify(),
+            Err(TransactionError::SenderMismatch {
+                account: alice.address()
+            })
+        );
+
+        // Claiming alice's key, with mallory's signature
+        let forged = Transaction {
+            public_key: genuine.public_key.clone(),
+            ..forged
+        };
+        assert_eq!(
+            forged.verify(),
+            Err(TransactionError::BadSignature {
+                account: alice.address()
+            })
+        );
+
+        let unsigned = Transaction {
+            signature: String::new(),
+            ..genuine
+        };
+        assert_eq!(
+            unsigned.verify(),
+            Err(TransactionError::Unsigned {
+                account: alice.address()
+            })
+        );
+    }
+}
@@ -0,0 +1,37 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Blocks, mining and the chain, along with the peer to peer behaviour that shares them
+//
+// The node binary is a thin command loop over this library, so the chain logic can be used from another binary, or
+// exercised without a network
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub mod audit;
+pub mod ban;
+pub mod block;
+pub mod chain;
+pub mod config;
+pub mod config_file;
+pub mod connections;
+pub mod dialer;
+pub mod hash;
+pub mod hasher;
+pub mod latency;
+pub mod ledger;
+pub mod mempool;
+pub mod merkle;
+pub mod mining;
+pub mod node_key;
+pub mod p2p;
+pub mod peers;
+mod sanitize;
+mod seen;
+pub mod snapshot;
+pub mod store;
+pub mod sync;
+pub mod target;
+#[cfg(test)]
+mod test_util;
+pub mod wallet;
+
+pub use block::{Block, BlockHeader, BlockValidationError};
+pub use chain::App;
+pub use hash::BlockHash;
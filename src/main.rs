@@ -1,236 +1,122 @@
-mod p2p;
-
-use chrono::prelude::*;
+use blockchain_demo::{chain::App, config::Config, mining::DEV_FAST_MINE, node_key, p2p};
 use libp2p::{
     core::upgrade,
+    dns::TokioDnsConfig,
     futures::StreamExt,
     mplex,
+    yamux,
+    relay,
     noise::{Keypair, NoiseConfig, X25519Spec},
-    swarm::{Swarm, SwarmBuilder},
+    swarm::SwarmBuilder,
     tcp::TokioTcpConfig,
+    websocket::WsConfig,
     Transport,
 };
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::time::Duration;
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 use tokio::{
     io::{stdin, AsyncBufReadExt, BufReader},
     select, spawn,
     sync::mpsc,
-    time::sleep,
+    time::interval,
 };
 
-// Very simplistic hash prefix
-const DIFFICULTY_PREFIX: &str = "00";
-
-fn hash_to_bin(hash: &[u8]) -> String {
-    let mut res: String = String::default();
-    for c in hash {
-        res.push_str(&format!("{:b}", c));
-    }
-    res
-}
-
-fn mine_block(id: u64, timestamp: i64, previous_hash: &str, data: &str) -> (u64, String) {
-    log::info!("mining block...");
-    let mut nonce = 0;
-
-    loop {
-        if nonce % 100000 == 0 {
-            log::info!("nonce: {}", nonce);
-        }
-
-        let hash = calculate_hash(id, timestamp, previous_hash, data, nonce);
-        let binary_hash = hash_to_bin(&hash);
-
-        if binary_hash.starts_with(DIFFICULTY_PREFIX) {
-            log::info!(
-                "mined! nonce: {}, hash: {}, binary hash: {}",
-                nonce,
-                hex::encode(&hash),
-                binary_hash
-            );
-
-            return (nonce, hex::encode(hash));
-        }
-
-        nonce += 1;
-    }
-}
-
-fn calculate_hash(id: u64, timestamp: i64, previous_hash: &str, data: &str, nonce: u64) -> Vec<u8> {
-    let mut hasher = Sha256::new();
-
-    hasher.update(
-        serde_json::json!({
-            "id": id,
-            "previous_hash": previous_hash,
-            "data": data,
-            "timestamp": timestamp,
-            "nonce": nonce
-        })
-        .to_string()
-        .as_bytes(),
-    );
-    hasher.finalize().as_slice().to_owned()
-}
-
-// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-// Block
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Block {
-    pub id: u64,
-    pub hash: String,
-    pub previous_hash: String,
-    pub timestamp: i64,
-    pub data: String,
-    pub nonce: u64,
-}
-
-impl Block {
-    pub fn new(id: u64, previous_hash: String, data: String) -> Self {
-        let now = Utc::now();
-        let (nonce, hash) = mine_block(id, now.timestamp(), &previous_hash, &data);
-        Self {
-            id,
-            hash,
-            timestamp: now.timestamp(),
-            previous_hash,
-            data,
-            nonce,
-        }
-    }
-}
-
-// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-// Blockchain App
+// Start here
 // - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-pub struct App {
-    pub blocks: Vec<Block>,
-}
-
-impl App {
-    fn new() -> Self {
-        Self { blocks: vec![] }
-    }
-
-    fn genesis(&mut self) {
-        let genesis_block = Block {
-            id: 0,
-            timestamp: Utc::now().timestamp(),
-            previous_hash: String::from("genesis"),
-            data: String::from("genesis!"),
-            nonce: 2836,
-            hash: "0000f816a87f806bb0073dcf026a64fb40c946b5abee2573702828694d5b4c43".to_string(),
-        };
-        self.blocks.push(genesis_block);
-    }
-
-    fn try_add_block(&mut self, block: Block) {
-        let latest_block = self.blocks.last().expect("there is at least one block");
-
-        if self.is_block_valid(&block, latest_block) {
-            self.blocks.push(block);
-        } else {
-            log::error!("could not add block - invalid");
-        }
-    }
-
-    fn is_block_valid(&self, block: &Block, previous_block: &Block) -> bool {
-        if block.previous_hash != previous_block.hash {
-            log::warn!("block with id: {} has wrong previous hash", block.id);
-            false
-        } else if !hash_to_bin(&hex::decode(&block.hash).expect("can decode from hex"))
-            .starts_with(DIFFICULTY_PREFIX)
-        {
-            log::warn!("block with id: {} has invalid difficulty", block.id);
-            false
-        } else if block.id != previous_block.id + 1 {
-            log::warn!(
-                "block with id: {} is not the next block after the latest: {}",
-                block.id,
-                previous_block.id
-            );
-            false
-        } else if hex::encode(calculate_hash(
-            block.id,
-            block.timestamp,
-            &block.previous_hash,
-            &block.data,
-            block.nonce,
-        )) != block.hash
-        {
-            log::warn!("block with id: {} has invalid hash", block.id);
-            false
-        } else {
-            true
-        }
-    }
-
-    fn is_chain_valid(&self, chain: &[Block]) -> bool {
-        for i in 1..chain.len() {
-            let first = chain.get(i - 1).expect("previous block has to exist");
-            let second = chain.get(i).expect("current block has to exist");
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
 
-            if !self.is_block_valid(second, first) {
-                return false;
-            }
-        }
+    let config = Config::from_env_and_args().unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+    log::info!("{:?}", config);
 
-        true
+    if DEV_FAST_MINE {
+        log::warn!("built with the dev-fast-mine feature - blocks are mined without proof of work");
     }
 
-    // We always choose the longest valid chain
-    fn choose_chain(&mut self, local: Vec<Block>, remote: Vec<Block>) -> Vec<Block> {
-        let is_local_valid = self.is_chain_valid(&local);
-        let is_remote_valid = self.is_chain_valid(&remote);
-
-        if is_local_valid {
-            if is_remote_valid {
-                // Both chains are valid so simply choose the longest
-                if local.len() >= remote.len() {
-                    local
-                } else {
-                    remote
-                }
-            } else {
-                local
+    if let Some(path) = &config.key_file {
+        match node_key::open(path) {
+            Ok(keys) => p2p::init_keys(keys),
+            Err(e) => {
+                log::error!(
+                    "can't read the node key {}: {} - delete it to have a new one made, which changes the peer id",
+                    path.display(),
+                    e
+                );
+                std::process::exit(1);
             }
-        } else if is_remote_valid {
-            remote
-        } else {
-            panic!("local and remote chains are both invalid");
         }
     }
-}
-
-// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-// Start here
-// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
-#[tokio::main]
-async fn main() {
-    pretty_env_logger::init();
 
     // Channel initialization
     log::info!("Peer Id: {}", p2p::PEER_ID.clone());
-    let (response_sender, mut response_rcv) = mpsc::unbounded_channel();
-    let (init_sender, mut init_rcv) = mpsc::unbounded_channel();
+    let (mined_sender, mut mined_rcv) = mpsc::unbounded_channel();
+    let (mining_event_sender, mut mining_event_rcv) = mpsc::unbounded_channel();
+    let (reorg_sender, mut reorg_rcv) = mpsc::unbounded_channel();
 
     // Initialize network stack
     let auth_keys = Keypair::<X25519Spec>::new()
         .into_authentic(&p2p::KEYS)
         .expect("can't create auth keys");
 
-    let transp = TokioTcpConfig::new()
+    // Peers are reached over TCP, or over WebSocket for addresses ending in /ws, either of them at a DNS name as well
+    // as an IP address.  Relayed connections are carried over either, and upgraded like any other once they are
+    // through.  Yamux is preferred to mplex, which relayed connections stall on, but mplex is still there for older
+    // nodes
+    let dns_tcp = TokioDnsConfig::system(TokioTcpConfig::new()).unwrap_or_else(|e| {
+        log::error!("can't read the system DNS configuration - {}", e);
+        std::process::exit(1);
+    });
+    let ws_dns_tcp = WsConfig::new(dns_tcp.clone());
+    let (relay_transport, relay) = relay::new_transport_and_behaviour(
+        p2p::relay_config(&config),
+        ws_dns_tcp.or_transport(dns_tcp),
+    );
+
+    let transp = relay_transport
         .upgrade(upgrade::Version::V1)
         .authenticate(NoiseConfig::xx(auth_keys).into_authenticated())
-        .multiplex(mplex::MplexConfig::new())
+        .multiplex(upgrade::SelectUpgrade::new(
+            yamux::YamuxConfig::default(),
+            mplex::MplexConfig::new(),
+        ))
         .boxed();
 
-    let behaviour = p2p::AppBehaviour::new(App::new(), response_sender, init_sender.clone()).await;
+    let (listen_addrs, listen_fallback) = (config.listen_addrs(), config.listen_fallback);
+    let relay = config.uses_relay().then_some(relay);
+
+    if config.relay_server {
+        log::info!("relaying circuits for peers that can't be reached directly");
+    }
+
+    if !config.mdns_enabled && !config.dht_enabled && config.startup_peers().is_empty() {
+        log::warn!("mDNS is off and there are no --peer or --bootstrap addresses - peers can only be connected to with dial");
+    }
+
+    let mut app = App::new(config).unwrap_or_else(|e| {
+        log::error!("{}", e);
+        std::process::exit(1);
+    });
+    app.reorg_subscribers.push(reorg_sender);
+
+    let behaviour = p2p::AppBehaviour::new(
+        Arc::new(RwLock::new(app)),
+        relay,
+        mined_sender,
+        mining_event_sender,
+    )
+    .await;
+
+    let connection_limits = behaviour.connections.limits();
 
     let mut swarm = SwarmBuilder::new(transp, behaviour, *p2p::PEER_ID)
+        .connection_limits(connection_limits)
         .executor(Box::new(|fut| {
             spawn(fut);
         }))
@@ -239,84 +125,115 @@ async fn main() {
     // Initialize buffered reader
     let mut stdin = BufReader::new(stdin()).lines();
 
-    Swarm::listen_on(
-        &mut swarm,
-        "/ip4/0.0.0.0/tcp/0"
-            .parse()
-            .expect("can't get a local TCP socket"),
-    )
-    .expect("swarm cannot be started");
+    if let Err(e) = p2p::start_listening(&mut swarm, listen_addrs, listen_fallback) {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
 
-    // Wait one second, then send out init event
-    spawn(async move {
-        sleep(Duration::from_secs(1)).await;
-        log::info!("sending init event");
-        init_sender.send(true).expect("can't send init event");
-    });
+    p2p::dial_startup_peers(&mut swarm);
+
+    // Block requests that have gone unanswered are retried, stalled chain syncs given up on, bans that have run out
+    // lifted, and the DHT refreshed, --peer addresses retried and a peer's tip checked when they are due, on every
+    // tick.  Until the node has a peer's chain, each tick also asks any connected peer not yet asked for one
+    let mut retry_timer = interval(Duration::from_secs(1));
 
     // Command loop
     loop {
         let evt = {
             select! {
-                line = stdin.next_line() => Some(
-                    p2p::EventType::Input(
-                        line.expect("can't get line").expect("can't read line from stdin")
-                    )
+                line = stdin.next_line() => Some(match line {
+                    Ok(line) => line.map_or(p2p::EventType::Quit, p2p::EventType::Input),
+                    Err(e) => {
+                        log::error!("can't read from stdin - {}", e);
+                        p2p::EventType::Quit
+                    }
+                }),
+
+                mined = mined_rcv.recv() => Some(
+                    p2p::EventType::MinedBlock(mined.expect("mining result already exists"))
                 ),
 
-                response = response_rcv.recv() => Some(
-                    p2p::EventType::LocalChainResponse(response.expect("response already exists"))
+                mining_event = mining_event_rcv.recv() => Some(
+                    p2p::EventType::Mining(mining_event.expect("mining event already exists"))
                 ),
 
-                _init = init_rcv.recv() => Some(p2p::EventType::Init),
+                reorg = reorg_rcv.recv() => Some(
+                    p2p::EventType::Reorg(reorg.expect("reorg event already exists"))
+                ),
 
-                event = swarm.select_next_some() => {
-                    log::info!("Unhandled Swarm Event: {:?}", event);
-                    None
-                },
+                _ = retry_timer.tick() => Some(p2p::EventType::Tick),
+
+                event = swarm.select_next_some() => p2p::swarm_event(event),
             }
         };
 
         if let Some(event) = evt {
             match event {
-                p2p::EventType::Init => {
-                    let peers = p2p::get_list_peers(&swarm);
-
-                    swarm.behaviour_mut().app.genesis();
-                    log::info!("connected nodes: {}", peers.len());
-
-                    if !peers.is_empty() {
-                        let req = p2p::LocalChainRequest {
-                            from_peer_id: peers
-                                .iter()
-                                .last()
-                                .expect("at least one peer needed")
-                                .to_string(),
-                        };
-
-                        let json = serde_json::to_string(&req).expect("not a JSON request");
-                        swarm
-                            .behaviour_mut()
-                            .floodsub
-                            .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
-                    }
+                p2p::EventType::MinedBlock(mined) => p2p::handle_mined_block(mined, &mut swarm),
+
+                p2p::EventType::Network(event) => p2p::handle_network_event(event, &mut swarm),
+
+                p2p::EventType::Tick => {
+                    swarm.behaviour_mut().retry_block_requests();
+                    swarm.behaviour_mut().expire_sync_sessions();
+                    swarm.behaviour_mut().refresh_dht();
+                    swarm.behaviour_mut().expire_bans();
+                    p2p::dial_startup_peers(&mut swarm);
+                    swarm.behaviour_mut().anti_entropy();
+                    swarm.behaviour_mut().initial_sync();
                 }
 
-                p2p::EventType::LocalChainResponse(resp) => {
-                    let json = serde_json::to_string(&resp).expect("not a JSON response");
-                    swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .publish(p2p::CHAIN_TOPIC.clone(), json.as_bytes());
-                }
+                p2p::EventType::Quit => break,
+
+                p2p::EventType::Mining(event) => p2p::handle_mining_event(event, &mut swarm),
+
+                p2p::EventType::Reorg(reorg) => swarm.behaviour_mut().requeue_rolled_back(&reorg),
 
                 p2p::EventType::Input(line) => match line.as_str() {
-                    "ls p" => p2p::handle_print_peers(&swarm),
-                    cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(&swarm),
+                    cmd if cmd == "ls p" || cmd.starts_with("ls p ") => p2p::handle_print_peers(cmd, &swarm),
+                    cmd if cmd == "ban" || cmd.starts_with("ban ") => p2p::handle_ban(cmd, &mut swarm),
+                    cmd if cmd == "unban" || cmd.starts_with("unban ") => p2p::handle_unban(cmd, &mut swarm),
+                    cmd if cmd == "dial" || cmd.starts_with("dial ") => p2p::handle_dial(cmd, &mut swarm),
+                    "quit" => break,
+                    "snapshot" => p2p::handle_snapshot(&mut swarm),
+                    "rewards" => p2p::handle_print_rewards(&swarm),
+                    "mining stats" => p2p::handle_print_mining_stats(&swarm),
+                    cmd if cmd.starts_with("stats") => p2p::handle_print_stats(cmd, &swarm),
+                    cmd if cmd.starts_with("prove") => p2p::handle_prove(cmd, &swarm),
+                    cmd if cmd.starts_with("accept-reorg") => {
+                        p2p::handle_accept_reorg(cmd, &mut swarm)
+                    }
+                    cmd if cmd.starts_with("ls c") => p2p::handle_print_chain(cmd, &swarm),
+                    cmd if cmd.starts_with("save") => p2p::handle_save(cmd, &swarm),
+                    cmd if cmd.starts_with("load") => p2p::handle_load(cmd, &mut swarm),
+                    cmd if cmd == "history" || cmd.starts_with("history ") => {
+                        p2p::handle_print_history(cmd, &swarm)
+                    }
+                    cmd if cmd == "find" || cmd.starts_with("find ") => p2p::handle_find(cmd, &swarm),
+                    cmd if cmd.starts_with("show block") => p2p::handle_show_block(cmd, &swarm),
                     cmd if cmd.starts_with("create b") => p2p::handle_create_block(cmd, &mut swarm),
+                    cmd if cmd.starts_with("mine bench") => p2p::handle_mine_bench(cmd, &swarm),
+                    "mine pause" => p2p::handle_mine_pause(&mut swarm),
+                    "mine resume" => p2p::handle_mine_resume(&mut swarm),
+                    cmd if cmd.starts_with("queue") || cmd.starts_with("add") => {
+                        p2p::handle_queue(cmd, &mut swarm)
+                    }
+                    "pending" => p2p::handle_print_pending(&swarm),
+                    "balances" => p2p::handle_print_balances(&swarm),
+                    cmd if cmd.starts_with("reset") => p2p::handle_reset(cmd, &mut swarm),
+                    cmd if cmd == "balance" || cmd.starts_with("balance ") => {
+                        p2p::handle_print_balance(cmd, &swarm)
+                    }
+                    cmd if cmd.starts_with("tx ") => p2p::handle_transaction(cmd, &mut swarm),
+                    cmd if cmd.starts_with("auto-mine") => p2p::handle_auto_mine(cmd, &mut swarm),
                     _ => log::error!("unknown command"),
                 },
             }
         }
+
+        // Bans made while handling the event are passed on to the swarm
+        p2p::apply_bans(&mut swarm);
     }
+
+    p2p::handle_shutdown(&mut swarm);
 }
@@ -0,0 +1,178 @@
+use crate::{hash::BlockHash, merkle};
+use std::collections::{HashSet, VecDeque};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Data entries waiting to be mined, oldest first
+//
+// Each entry is held only once, however many times it is queued, going by the hash of its content.  Once full, the
+// oldest entry is dropped to make room for a new one
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct Mempool {
+    capacity: usize,
+    entries: VecDeque<String>,
+    hashes: HashSet<BlockHash>,
+}
+
+impl Mempool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+            hashes: HashSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter()
+    }
+
+    // Returns false if the entry is already waiting
+    pub fn push_back(&mut self, entry: String) -> bool {
+        if !self.hashes.insert(merkle::leaf_hash(&entry)) {
+            return false;
+        }
+
+        self.entries.push_back(entry);
+
+        while self.entries.len() > self.capacity {
+            let oldest = self.entries.pop_front().expect("the mempool is not empty");
+            log::warn!("mempool full - dropping the oldest entry");
+            self.hashes.remove(&merkle::leaf_hash(&oldest));
+        }
+
+        true
+    }
+
+    // For entries that were taken to be mined and have to wait again.  They were queued before anything now waiting,
+    // so if there isn't room for them, the newest entry is dropped instead
+    pub fn push_front(&mut self, entry: String) -> bool {
+        if !self.hashes.insert(merkle::leaf_hash(&entry)) {
+            return false;
+        }
+
+        self.entries.push_front(entry);
+
+        while self.entries.len() > self.capacity {
+            let newest = self.entries.pop_back().expect("the mempool is not empty");
+            log::warn!("mempool full - dropping the newest entry");
+            self.hashes.remove(&merkle::leaf_hash(&newest));
+        }
+
+        true
+    }
+
+    pub fn remove(&mut self, entry: &str) -> bool {
+        if !self.hashes.remove(&merkle::leaf_hash(entry)) {
+            return false;
+        }
+
+        self.entries.retain(|waiting| waiting != entry);
+        true
+    }
+
+    // Takes up to `count` entries from the front, stopping before the first one that would bring their total size
+    // over `max_size` bytes
+    pub fn take(&mut self, count: usize, max_size: usize) -> Vec<String> {
+        let mut size = 0;
+        let taken = self
+            .entries
+            .iter()
+            .take(count)
+            .take_while(|entry| {
+                size += entry.len();
+                size <= max_size
+            })
+            .count();
+
+        let entries: Vec<String> = self.entries.drain(..taken).collect();
+
+        for entry in &entries {
+            self.hashes.remove(&merkle::leaf_hash(entry));
+        }
+
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waiting(mempool: &Mempool) -> Vec<&str> {
+        mempool.iter().map(String::as_str).collect()
+    }
+
+    fn mempool(capacity: usize, entries: &[&str]) -> Mempool {
+        let mut mempool = Mempool::new(capacity);
+
+        for entry in entries {
+            mempool.push_back(entry.to_string());
+        }
+
+        mempool
+    }
+
+    #[test]
+    fn entries_are_only_held_once() {
+        let mut mempool = mempool(10, &["a", "b"]);
+
+        assert!(!mempool.push_back(String::from("a")));
+        assert!(!mempool.push_front(String::from("b")));
+        assert_eq!(waiting(&mempool), ["a", "b"]);
+
+        // Once taken, an entry can be queued again
+        assert_eq!(mempool.take(1, usize::MAX), ["a"]);
+        assert!(mempool.push_back(String::from("a")));
+        assert_eq!(waiting(&mempool), ["b", "a"]);
+    }
+
+    #[test]
+    fn the_oldest_entry_makes_way_once_full() {
+        let mut mempool = mempool(2, &["a", "b"]);
+
+        assert!(mempool.push_back(String::from("c")));
+        assert_eq!(waiting(&mempool), ["b", "c"]);
+
+        // The dropped entry is forgotten, so it can be queued again
+        assert!(mempool.push_back(String::from("a")));
+        assert_eq!(waiting(&mempool), ["c", "a"]);
+    }
+
+    #[test]
+    fn an_entry_put_back_pushes_out_the_newest_once_full() {
+        let mut mempool = mempool(2, &["b", "c"]);
+
+        assert!(mempool.push_front(String::from("a")));
+        assert_eq!(waiting(&mempool), ["a", "b"]);
+    }
+
+    #[test]
+    fn entries_are_removed_by_content() {
+        let mut mempool = mempool(10, &["a", "b", "c"]);
+
+        assert!(mempool.remove("b"));
+        assert!(!mempool.remove("b"));
+        assert!(!mempool.remove("d"));
+        assert_eq!(waiting(&mempool), ["a", "c"]);
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn entries_are_taken_up_to_a_count_and_a_size() {
+        let mut mempool = mempool(10, &["aaa", "bb", "c", "dddd"]);
+
+        assert_eq!(mempool.take(2, usize::MAX), ["aaa", "bb"]);
+        assert_eq!(mempool.take(10, 4), ["c"]);
+        assert_eq!(mempool.take(10, 3), Vec::<String>::new());
+        assert_eq!(mempool.take(10, 4), ["dddd"]);
+        assert!(mempool.is_empty());
+    }
+}
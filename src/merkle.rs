@@ -0,0 +1,192 @@
+use crate::hash::BlockHash;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Leaves and interior nodes are hashed with different prefixes, so an interior node can never be passed off as a leaf
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Merkle tree over the data entries of a block
+//
+// Each level is built by hashing adjacent pairs of nodes from the level below.  When a level has an odd number of
+// nodes, the last one is carried up to the next level unchanged.  Pairing it with a copy of itself instead, as Bitcoin
+// does, would give [a, b, c] and [a, b, c, c] the same root.
+//
+// The root of a single entry is the hash of that entry, and the root of no entries at all is all zeros
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub fn merkle_root(entries: &[String]) -> BlockHash {
+    let mut level: Vec<BlockHash> = entries.iter().map(|entry| leaf_hash(entry)).collect();
+
+    if level.is_empty() {
+        return BlockHash::ZERO;
+    }
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level[0]
+}
+
+// Which side of the path the sibling sits on at one level of the tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub side: Side,
+    pub sibling: BlockHash,
+}
+
+// The siblings needed to rebuild the root from a single entry, starting at the leaves.  Levels where the entry's
+// ancestor was carried up unchanged have no sibling, and so no step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub path: Vec<ProofStep>,
+}
+
+// Returns None if there is no entry at `index`
+pub fn prove(entries: &[String], index: usize) -> Option<MerkleProof> {
+    if index >= entries.len() {
+        return None;
+    }
+
+    let mut level: Vec<BlockHash> = entries.iter().map(|entry| leaf_hash(entry)).collect();
+    let mut position = index;
+    let mut path = vec![];
+
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+
+        if sibling < level.len() {
+            path.push(ProofStep {
+                side: if sibling < position {
+                    Side::Left
+                } else {
+                    Side::Right
+                },
+                sibling: level[sibling],
+            });
+        }
+
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof { index, path })
+}
+
+pub fn verify(entry: &str, proof: &MerkleProof, root: &BlockHash) -> bool {
+    let computed = proof
+        .path
+        .iter()
+        .fold(leaf_hash(entry), |node, step| match step.side {
+            Side::Left => node_hash(&step.sibling, &node),
+            Side::Right => node_hash(&node, &step.sibling),
+        });
+
+    computed == *root
+}
+
+fn next_level(level: &[BlockHash]) -> Vec<BlockHash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [odd] => *odd,
+            _ => unreachable!("chunks of two"),
+        })
+        .collect()
+}
+
+pub fn leaf_hash(entry: &str) -> BlockHash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(entry.as_bytes());
+    BlockHash::from(<[u8; 32]>::from(hasher.finalize()))
+}
+
+fn node_hash(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    BlockHash::from(<[u8; 32]>::from(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::entries;
+
+    #[test]
+    fn every_entry_proves_against_the_root() {
+        let all = entries(&["a", "b", "c", "d", "e"]);
+
+        for count in [1, 2, 3, 5] {
+            let entries = &all[..count];
+            let root = merkle_root(entries);
+
+            for (index, entry) in entries.iter().enumerate() {
+                let proof = prove(entries, index).expect("there is an entry at the index");
+                assert!(verify(entry, &proof, &root), "entry {} of {}", index, count);
+            }
+            assert_eq!(prove(entries, count), None);
+        }
+    }
+
+    #[test]
+    fn the_roots_of_small_trees_are_pinned_to_their_construction() {
+        let (a, b, c) = (leaf_hash("a"), leaf_hash("b"), leaf_hash("c"));
+
+        assert_eq!(merkle_root(&[]), BlockHash::ZERO);
+        assert_eq!(prove(&[], 0), None);
+        assert_eq!(merkle_root(&entries(&["a"])), a);
+        assert_eq!(
+            prove(&entries(&["a"]), 0).map(|proof| proof.path),
+            Some(vec![])
+        );
+
+        // The odd entry is carried up unchanged, rather than paired with itself
+        let root = merkle_root(&entries(&["a", "b", "c"]));
+        assert_eq!(root, node_hash(&node_hash(&a, &b), &c));
+        assert_ne!(root, merkle_root(&entries(&["a", "b", "c", "c"])));
+
+        let proof = prove(&entries(&["a", "b", "c"]), 2).expect("there is a third entry");
+        assert_eq!(
+            proof.path,
+            vec![ProofStep {
+                side: Side::Left,
+                sibling: node_hash(&a, &b)
+            }]
+        );
+    }
+
+    #[test]
+    fn a_wrong_entry_or_a_tampered_proof_is_rejected() {
+        let entries = entries(&["a", "b", "c", "d", "e"]);
+        let root = merkle_root(&entries);
+        let proof = prove(&entries, 1).expect("there is a second entry");
+
+        assert!(verify("b", &proof, &root));
+        assert!(!verify("x", &proof, &root));
+        assert!(!verify("a", &proof, &root));
+
+        let mut wrong_sibling = proof.clone();
+        wrong_sibling.path[0].sibling = leaf_hash("x");
+        assert!(!verify("b", &wrong_sibling, &root));
+
+        let mut wrong_side = proof.clone();
+        wrong_side.path[0].side = Side::Right;
+        assert!(!verify("b", &wrong_side, &root));
+
+        let mut short = proof;
+        short.path.pop();
+        assert!(!verify("b", &short, &root));
+    }
+}
@@ -0,0 +1,638 @@
+use crate::{
+    block::{Preimage, BLOCK_VERSION},
+    hash::BlockHash,
+    hasher::BlockHashing,
+    merkle,
+    target::Target,
+};
+use chrono::prelude::*;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+// Number of leading zero bits in the hash
+fn leading_zero_bits(hash: &BlockHash) -> u32 {
+    let mut bits = 0;
+
+    for byte in hash.as_bytes() {
+        bits += byte.leading_zeros();
+
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+// With the dev-fast-mine feature, blocks are accepted without any proof of work.  Such blocks state this difficulty,
+// which is not a valid compact target, so a node doing real proof of work will always reject them and vice versa
+pub const DEV_DIFFICULTY: u32 = 0;
+pub const DEV_FAST_MINE: bool = cfg!(feature = "dev-fast-mine");
+
+// The difficulty a block mined against `target` should state
+pub fn stated_difficulty(target: Target) -> u32 {
+    if DEV_FAST_MINE {
+        DEV_DIFFICULTY
+    } else {
+        target.to_compact()
+    }
+}
+
+// How often to report progress while mining
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+// Everything the miner needs to know about the block it is mining
+#[derive(Debug, Clone)]
+pub struct MiningParams {
+    pub chain_id: String,
+    pub id: u64,
+    pub previous_hash: BlockHash,
+    pub data: Vec<String>,
+    pub target: Target,
+    pub threads: usize,
+    pub timestamp_refresh: Duration,
+    pub hashing: BlockHashing,
+    // Peer id of the node the reward is paid to
+    pub miner: String,
+    pub reward: u64,
+    // The block's timestamp is never earlier than this, even if the local clock is
+    pub min_timestamp: i64,
+}
+
+// Summary of a mining session
+#[derive(Debug, Clone)]
+pub struct MiningStats {
+    pub attempts: u64,
+    pub elapsed: Duration,
+}
+
+// A solution found by the miner.  The timestamp may differ from the one mining started with
+#[derive(Debug, Clone)]
+pub struct MinedBlock {
+    pub nonce: u64,
+    pub timestamp: i64,
+    pub hash: BlockHash,
+    pub merkle_root: BlockHash,
+    pub stats: MiningStats,
+}
+
+#[derive(Debug, Clone)]
+pub enum MiningError {
+    Cancelled { id: u64 },
+    BudgetExhausted { id: u64, attempts: u64 },
+}
+
+impl fmt::Display for MiningError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Cancelled { id } => write!(f, "mining of block {} was cancelled", id),
+            Self::BudgetExhausted { id, attempts } => write!(
+                f,
+                "gave up mining block {} after {} attempts without finding a solution",
+                id, attempts
+            ),
+        }
+    }
+}
+
+impl MiningStats {
+    // Average hashes per second over the whole session
+    pub fn hashrate(&self) -> f64 {
+        self.attempts as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// Progress reports from the miner.  Progress events are sent at most once every PROGRESS_INTERVAL
+#[derive(Debug, Clone)]
+pub enum MiningEvent {
+    Started {
+        id: u64,
+        target: Target,
+        threads: usize,
+    },
+    Progress {
+        id: u64,
+        attempts: u64,
+        hashrate: f64,
+        elapsed: Duration,
+        best_zero_bits: u32,
+    },
+    Found {
+        id: u64,
+        nonce: u64,
+        hash: BlockHash,
+        attempts: u64,
+        elapsed: Duration,
+    },
+    Aborted {
+        id: u64,
+    },
+}
+
+pub fn format_hashrate(hashrate: f64) -> String {
+    match hashrate {
+        r if r >= 1e9 => format!("{:.1} GH/s", r / 1e9),
+        r if r >= 1e6 => format!("{:.1} MH/s", r / 1e6),
+        r if r >= 1e3 => format!("{:.1} kH/s", r / 1e3),
+        r => format!("{:.0} H/s", r),
+    }
+}
+
+pub fn log_mining_event(event: &MiningEvent) {
+    match event {
+        MiningEvent::Started {
+            id,
+            target,
+            threads,
+        } => log::info!(
+            "mining block {} at target {} using {} worker threads...",
+            id,
+            target,
+            threads
+        ),
+
+        MiningEvent::Progress {
+            id,
+            hashrate,
+            elapsed,
+            best_zero_bits,
+            ..
+        } => log::info!(
+            "mining block {}: {}, elapsed {}s, best leading zeros so far: {}",
+            id,
+            format_hashrate(*hashrate),
+            elapsed.as_secs(),
+            best_zero_bits
+        ),
+
+        MiningEvent::Found {
+            id,
+            nonce,
+            hash,
+            attempts,
+            elapsed,
+        } => {
+            let stats = MiningStats {
+                attempts: *attempts,
+                elapsed: *elapsed,
+            };
+
+            log::info!(
+                "mined block {}! nonce: {}, hash: {}, attempts: {}, elapsed: {:.1}s, average: {}",
+                id,
+                nonce,
+                hash,
+                attempts,
+                elapsed.as_secs_f64(),
+                format_hashrate(stats.hashrate())
+            );
+        }
+
+        MiningEvent::Aborted { id } => log::info!("mining of block {} abandoned", id),
+    }
+}
+
+// Measure how quickly this machine can calculate block hashes by hashing a dummy block for `duration` on each of
+// `threads` workers.  No attempt is made to find a valid hash
+pub fn bench_hashrate(duration: Duration, threads: usize, hashing: BlockHashing) -> MiningStats {
+    let started = Instant::now();
+    let attempts = AtomicU64::new(0);
+
+    thread::scope(|scope| {
+        for worker in 0..threads.max(1) as u64 {
+            let attempts = &attempts;
+
+            scope.spawn(move || {
+                let mut preimage = Preimage::new(
+                    BLOCK_VERSION,
+                    "benchmark",
+                    1,
+                    0,
+                    &BlockHash::ZERO,
+                    &BlockHash::ZERO,
+                    0,
+                    "benchmark",
+                    0,
+                );
+                let mut nonce = worker << 32;
+
+                while started.elapsed() < duration {
+                    for _ in 0..1000 {
+                        preimage.hash(&hashing, nonce);
+                        nonce += 1;
+                    }
+
+                    attempts.fetch_add(1000, Ordering::Relaxed);
+                }
+            });
+        }
+    });
+
+    MiningStats {
+        attempts: attempts.load(Ordering::Relaxed),
+        elapsed: started.elapsed(),
+    }
+}
+
+// The nonces each of `threads` workers searches, as the first nonce and how many more after it.  Each range starts
+// where the one before it ends, wrapping around from u64::MAX to 0, and the last runs up to the nonce just before
+// `first_nonce`, so taken together they cover all 2^64 nonces exactly once
+fn worker_ranges(first_nonce: u64, threads: u64) -> impl Iterator<Item = (u64, u64)> {
+    let range_len = u64::MAX / threads;
+
+    (0..threads).map(move |worker| {
+        let offset = worker * range_len;
+        let more = if worker == threads - 1 {
+            u64::MAX - offset
+        } else {
+            range_len - 1
+        };
+
+        (first_nonce.wrapping_add(offset), more)
+    })
+}
+
+// The nonce space is split into one contiguous range per worker thread.  The first worker to find a valid hash sets
+// the shared flag, which tells all the other workers to stop.  Setting `cancel` abandons the search, and once
+// `max_attempts` hashes have been tried without success the search gives up
+//
+// The search begins at a random nonce so that peers racing to mine the same block explore different parts of the
+// nonce space
+//
+// If no solution has been found after `timestamp_refresh`, or the nonce space is exhausted, the timestamp is
+// refreshed and the search starts again.  This keeps the timestamp of a long mining session current, so the
+// timestamp actually used is returned alongside the nonce and hash
+//
+// A separate reporter thread sends progress events every PROGRESS_INTERVAL, so progress is reported at the same pace
+// irrespective of how quickly each individual hash can be calculated
+pub fn mine_block(
+    params: &MiningParams,
+    max_attempts: Option<u64>,
+    cancel: &AtomicBool,
+    events: &mpsc::UnboundedSender<MiningEvent>,
+) -> Result<MinedBlock, MiningError> {
+    let MiningParams {
+        chain_id,
+        id,
+        previous_hash,
+        data,
+        target,
+        threads,
+        timestamp_refresh,
+        hashing,
+        miner,
+        reward,
+        min_timestamp,
+    } = params;
+    let (id, target, timestamp_refresh, reward) = (*id, *target, *timestamp_refresh, *reward);
+    let difficulty = stated_difficulty(target);
+    let merkle_root = merkle::merkle_root(data);
+    let threads = (*threads).max(1) as u64;
+
+    // Nobody listening for events is not a reason to stop mining
+    let emit = |event| events.send(event).unwrap_or_default();
+
+    emit(MiningEvent::Started {
+        id,
+        target,
+        threads: threads as usize,
+    });
+
+    let started = Instant::now();
+    let finished = AtomicBool::new(false);
+    let attempts = AtomicU64::new(0);
+    let best_zero_bits = AtomicU32::new(0);
+    let max_attempts = max_attempts.unwrap_or(u64::MAX);
+    let exhausted = || attempts.load(Ordering::Relaxed) >= max_attempts;
+
+    // Search for a solution using the current timestamp
+    let mine_round = || -> Option<(u64, i64, BlockHash)> {
+        let timestamp = Utc::now().timestamp().max(*min_timestamp);
+        let round_started = Instant::now();
+        let first_nonce: u64 = rand::random();
+        let found = AtomicBool::new(false);
+        let preimage = &Preimage::new(
+            BLOCK_VERSION,
+            chain_id,
+            id,
+            timestamp,
+            previous_hash,
+            &merkle_root,
+            difficulty,
+            miner,
+            reward,
+        );
+
+        // Search nonces start..=start + more, giving up as soon as another worker signals that it has found a valid
+        // hash, or it is time to refresh the timestamp
+        let mine_range = |start: u64, more: u64| -> Option<(u64, BlockHash)> {
+            let mut preimage = preimage.clone();
+            let mut best = 0;
+
+            for attempt in 0..=more {
+                if attempt % 1000 == 0 {
+                    attempts.fetch_add(attempt.min(1000), Ordering::Relaxed);
+
+                    if found.load(Ordering::Relaxed)
+                        || cancel.load(Ordering::Relaxed)
+                        || exhausted()
+                        || round_started.elapsed() >= timestamp_refresh
+                    {
+                        return None;
+                    }
+                }
+
+                let nonce = start.wrapping_add(attempt);
+                let hash = preimage.hash(hashing, nonce);
+                let zero_bits = leading_zero_bits(&hash);
+
+                if zero_bits > best {
+                    best = zero_bits;
+                    best_zero_bits.fetch_max(best, Ordering::Relaxed);
+                }
+
+                if DEV_FAST_MINE || target.is_met_by(&hash) {
+                    attempts.fetch_add(attempt % 1000 + 1, Ordering::Relaxed);
+                    found.store(true, Ordering::Relaxed);
+                    return Some((nonce, hash));
+                }
+            }
+
+            None
+        };
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = worker_ranges(first_nonce, threads)
+                .map(|(start, more)| scope.spawn(move || mine_range(start, more)))
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining worker panicked"))
+                .next()
+                .map(|(nonce, hash)| (nonce, timestamp, hash))
+        })
+    };
+
+    let report_progress = || {
+        let mut last_report = started;
+
+        while !finished.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+
+            if last_report.elapsed() >= PROGRESS_INTERVAL {
+                let stats = MiningStats {
+                    attempts: attempts.load(Ordering::Relaxed),
+                    elapsed: started.elapsed(),
+                };
+
+                emit(MiningEvent::Progress {
+                    id,
+                    attempts: stats.attempts,
+                    hashrate: stats.hashrate(),
+                    elapsed: stats.elapsed,
+                    best_zero_bits: best_zero_bits.load(Ordering::Relaxed),
+                });
+                last_report = Instant::now();
+            }
+        }
+    };
+
+    let (solution, elapsed) = thread::scope(|scope| {
+        scope.spawn(report_progress);
+
+        let solution = loop {
+            if let Some(solution) = mine_round() {
+                break Some(solution);
+            }
+
+            if cancel.load(Ordering::Relaxed) || exhausted() {
+                break None;
+            }
+
+            log::info!(
+                "no solution for block {} yet - refreshing the timestamp",
+                id
+            );
+        };
+
+        finished.store(true, Ordering::Relaxed);
+        (solution, started.elapsed())
+    });
+
+    let stats = MiningStats {
+        attempts: attempts.load(Ordering::Relaxed),
+        elapsed,
+    };
+
+    let Some((nonce, timestamp, hash)) = solution else {
+        emit(MiningEvent::Aborted { id });
+
+        return Err(if cancel.load(Ordering::Relaxed) {
+            MiningError::Cancelled { id }
+        } else {
+            MiningError::BudgetExhausted {
+                id,
+                attempts: stats.attempts,
+            }
+        });
+    };
+
+    emit(MiningEvent::Found {
+        id,
+        nonce,
+        hash,
+        attempts: stats.attempts,
+        elapsed: stats.elapsed,
+    });
+
+    Ok(MinedBlock {
+        nonce,
+        timestamp,
+        hash,
+        merkle_root,
+        stats,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::{calculate_hash, Block},
+        test_util,
+    };
+
+    fn hash_with_prefix(prefix: &[u8]) -> BlockHash {
+        let mut bytes = [0xff; 32];
+
+        bytes[..prefix.len()].copy_from_slice(prefix);
+        BlockHash::from(bytes)
+    }
+
+    // Every zero bit of a leading zero byte counts, as do the zero bits at the top of the first byte that isn't zero
+    #[test]
+    fn leading_zero_bits_counts_across_byte_boundaries() {
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[])), 0);
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[0x7f])), 1);
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[0x0f])), 4);
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[0x01])), 7);
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[0x00, 0x80])), 8);
+        assert_eq!(leading_zero_bits(&hash_with_prefix(&[0x00, 0x01])), 15);
+        assert_eq!(
+            leading_zero_bits(&hash_with_prefix(&[0x00, 0x00, 0x0f])),
+            20
+        );
+        assert_eq!(leading_zero_bits(&BlockHash::ZERO), 256);
+    }
+
+    #[test]
+    fn worker_ranges_cover_every_nonce_exactly_once() {
+        for first_nonce in [0, 12_345, u64::MAX / 2, u64::MAX] {
+            for threads in [1, 2, 3, 7, 64] {
+                let ranges: Vec<_> = worker_ranges(first_nonce, threads).collect();
+                let total: u128 = ranges.iter().map(|(_, more)| *more as u128 + 1).sum();
+
+                assert_eq!(ranges.len() as u64, threads);
+                assert_eq!(total, 1 << 64);
+                assert_eq!(ranges[0].0, first_nonce);
+
+                // Each range ends just before the next begins, and the last just before the first nonce
+                for (i, (start, more)) in ranges.iter().enumerate() {
+                    let next = ranges.get(i + 1).map_or(first_nonce, |(start, _)| *start);
+
+                    assert_eq!(start.wrapping_add(*more).wrapping_add(1), next);
+                }
+            }
+        }
+    }
+
+    fn params(target: Target, threads: usize) -> MiningParams {
+        MiningParams {
+            chain_id: String::from("test"),
+            id: 1,
+            previous_hash: BlockHash::ZERO,
+            data: test_util::entries(&["some data"]),
+            target,
+            threads,
+            timestamp_refresh: Duration::from_secs(60),
+            hashing: BlockHashing::default(),
+            miner: test_util::MINER.to_owned(),
+            reward: 50,
+            min_timestamp: i64::MIN,
+        }
+    }
+
+    // mine_block only returns once every worker, and the progress reporter, has been joined
+    #[test]
+    fn all_workers_stop_once_a_solution_is_found() {
+        let target = Target::from_leading_zero_bits(12);
+
+        for threads in [1, 2, 8] {
+            let params = params(target, threads);
+            let (events, mut received) = mpsc::unbounded_channel();
+            let (block, stats) = Block::new(params.clone(), None, &AtomicBool::new(false), &events)
+                .expect("a solution at 12 bits is always found");
+
+            assert_eq!(
+                calculate_hash(&block.header, &params.hashing),
+                block.header.hash
+            );
+            assert!(DEV_FAST_MINE || target.is_met_by(&block.header.hash));
+            assert!(stats.attempts >= 1);
+            assert!(
+                matches!(received.try_recv(), Ok(MiningEvent::Started { threads: t, .. }) if t == threads)
+            );
+            assert!(matches!(
+                received.try_recv(),
+                Ok(MiningEvent::Found { nonce, hash, .. }) if nonce == block.header.nonce && hash == block.header.hash
+            ));
+        }
+    }
+
+    #[test]
+    fn all_workers_stop_when_mining_is_cancelled() {
+        let params = params(Target::from_leading_zero_bits(64), 4);
+        let (events, _) = mpsc::unbounded_channel();
+
+        assert!(matches!(
+            mine_block(&params, None, &AtomicBool::new(true), &events),
+            Err(MiningError::Cancelled { id: 1 })
+        ));
+    }
+
+    #[test]
+    fn mining_meets_a_target_between_two_prefix_lengths() {
+        let target = Target::from_compact(0x1f60_0000).expect("valid compact target");
+        let params = params(target, 2);
+        let (events, _) = mpsc::unbounded_channel();
+        let (block, _) = Block::new(params, None, &AtomicBool::new(false), &events)
+            .expect("a solution at 9 to 10 bits is always found");
+
+        assert_eq!(block.header.difficulty, stated_difficulty(target));
+        assert!(DEV_FAST_MINE || target.is_met_by(&block.header.hash));
+    }
+
+    #[test]
+    #[cfg(feature = "dev-fast-mine")]
+    fn dev_mode_takes_the_first_nonce_at_any_difficulty() {
+        let params = params(Target::from_leading_zero_bits(256), 1);
+        let (events, _) = mpsc::unbounded_channel();
+        let mined = mine_block(&params, Some(1), &AtomicBool::new(false), &events)
+            .expect("dev mode never has to search");
+
+        assert_eq!(mined.stats.attempts, 1);
+    }
+
+    #[test]
+    fn mining_within_the_attempt_budget_succeeds() {
+        let params = params(Target::from_leading_zero_bits(8), 2);
+        let (events, _) = mpsc::unbounded_channel();
+        let mined = mine_block(&params, Some(1_000_000), &AtomicBool::new(false), &events)
+            .expect("8 bits takes far fewer attempts than the budget");
+
+        assert!(mined.stats.attempts <= 1_000_000);
+    }
+
+    // Each worker checks the budget every 1000 attempts, so it can be overrun by up to that many per worker
+    #[test]
+    #[cfg(not(feature = "dev-fast-mine"))]
+    fn mining_gives_up_once_the_attempt_budget_is_spent() {
+        let params = params(Target::from_leading_zero_bits(64), 2);
+        let (events, mut received) = mpsc::unbounded_channel();
+        let result = mine_block(&params, Some(5_000), &AtomicBool::new(false), &events);
+
+        assert!(matches!(
+            result,
+            Err(MiningError::BudgetExhausted { id: 1, attempts }) if (5_000..=7_000).contains(&attempts)
+        ));
+
+        let last = std::iter::from_fn(|| received.try_recv().ok()).last();
+        assert!(matches!(last, Some(MiningEvent::Aborted { id: 1 })));
+    }
+
+    // Only the merkle root of the data goes into the preimage, so a 10 MB payload is hashed once however many nonces
+    // are tried.  Hashing all of it for each of these attempts would take minutes
+    #[test]
+    #[cfg(not(feature = "dev-fast-mine"))]
+    fn mining_a_10_mb_payload_only_hashes_it_once() {
+        let params = MiningParams {
+            data: vec!["x".repeat(10 * 1024 * 1024)],
+            ..params(Target::from_leading_zero_bits(64), 1)
+        };
+        let (events, _received) = mpsc::unbounded_channel();
+        let started = Instant::now();
+        let result = mine_block(&params, Some(20_000), &AtomicBool::new(false), &events);
+
+        assert!(matches!(
+            result,
+            Err(MiningError::BudgetExhausted { id: 1, .. })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
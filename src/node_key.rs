@@ -0,0 +1,79 @@
+use crate::wallet::write_private;
+use libp2p::identity::{ed25519, Keypair};
+use std::{fs, io, path::Path};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Key that the node's peer id is derived from, and that signs its messages
+//
+// Kept in hex in the --key-file, in the same way as the wallet key, so that the peer id stays the same from one run to
+// the next.  The file is created with a new key the first time the node starts
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub fn open(path: &Path) -> io::Result<Keypair> {
+    match fs::read_to_string(path) {
+        Ok(text) => {
+            let bytes = hex::decode(text.trim()).map_err(io::Error::other)?;
+            let secret = ed25519::SecretKey::from_bytes(bytes).map_err(io::Error::other)?;
+
+            Ok(Keypair::Ed25519(secret.into()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = ed25519::Keypair::generate();
+            let secret = ed25519::SecretKey::from(keypair.clone());
+
+            write_private(path, &hex::encode(secret))?;
+            log::info!("created a new node key in {}", path.display());
+            Ok(Keypair::Ed25519(keypair))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use libp2p::PeerId;
+
+    fn peer_id(keypair: &Keypair) -> PeerId {
+        PeerId::from(keypair.public())
+    }
+
+    #[test]
+    fn the_key_created_on_the_first_run_is_loaded_on_the_next() {
+        let path = temp_path("node.key");
+        let _ = fs::remove_file(&path);
+
+        let created = open(&path).expect("can create the key");
+        let loaded = open(&path).expect("can load the key");
+        assert_eq!(peer_id(&loaded), peer_id(&created));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path)
+                .expect("the key file exists")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn a_malformed_key_file_is_an_error() {
+        let path = temp_path("malformed.key");
+
+        for contents in ["not hex", "abcd", ""] {
+            fs::write(&path, contents).expect("can write the key file");
+            assert!(open(&path).is_err(), "{:?}", contents);
+        }
+
+        // The file is left for whoever runs the node to look at, rather than replaced with a new key
+        assert_eq!(
+            fs::read_to_string(&path).expect("the key file is still there"),
+            ""
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
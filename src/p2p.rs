@@ -1,161 +1,4798 @@
-use super::{App, Block};
+use crate::{
+    audit::{AuditEntry, AuditOutcome},
+    ban::BanList,
+    block::{Block, BlockHeader},
+    chain::{AddBlockOutcome, App, ChainStats, ReorgEvent, SharedApp},
+    config::{self, Config},
+    connections::{ConnectionCounts, Connections},
+    dialer::StartupDials,
+    hash::BlockHash,
+    latency::{Latencies, PeerLatencyStats, UNRESPONSIVE_PINGS},
+    ledger::Transaction,
+    merkle,
+    peers::{BannedPeer, PeerBook, PeerInfo, PeerList},
+    mining::{
+        bench_hashrate, format_hashrate, log_mining_event, MiningError, MiningEvent, MiningParams,
+        MiningStats,
+    },
+    sanitize::sanitize,
+    seen::SeenBlocks,
+    sync::{
+        BlockCodec, BlockProtocol, BlockRequest, BlockResponse, ChainRequest, ChainResponse,
+        InitialSync, Refusal, ResponseLimiter, SyncCodec, SyncProtocol, SyncSession,
+        SYNC_SESSION_TIMEOUT,
+    },
+    target::Target,
+};
 use libp2p::{
-    floodsub::{Floodsub, FloodsubEvent, Topic},
+    core::{connection::PendingConnectionError, ConnectedPoint},
+    gossipsub::{
+        error::PublishError, Gossipsub, GossipsubConfig, GossipsubConfigBuilder, GossipsubEvent,
+        GossipsubMessage, IdentTopic, MessageAuthenticity, MessageId, TopicHash, ValidationMode,
+    },
+    identify::{Identify, IdentifyConfig, IdentifyEvent},
+    ping::{Ping, PingConfig, PingEvent, PingSuccess},
+    relay::{Relay, RelayConfig},
     identity,
+    kad::{store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent},
     mdns::{Mdns, MdnsEvent},
-    swarm::{NetworkBehaviourEventProcess, Swarm},
+    multiaddr::Protocol,
+    Multiaddr,
+    request_response::{
+        ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+        InboundFailure, RequestResponseMessage,
+    },
+    swarm::{
+        toggle::Toggle, CloseConnection, DialPeerCondition, NetworkBehaviourAction, NetworkBehaviourEventProcess,
+        PollParameters, Swarm, SwarmEvent,
+    },
     NetworkBehaviour, PeerId,
 };
-use once_cell::sync::Lazy;
+use chrono::prelude::*;
+use once_cell::sync::{Lazy, OnceCell};
+use rand::{seq::IteratorRandom, Rng};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use tokio::sync::mpsc;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt, fs,
+    num::NonZeroU32,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLockReadGuard, RwLockWriteGuard,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, task::spawn_blocking};
 
-pub static KEYS: Lazy<identity::Keypair> = Lazy::new(identity::Keypair::generate_ed25519);
+// Set by init_keys, which has to be called before KEYS is first used.  Without it, the node gets a new key
+static NODE_KEYS: OnceCell<identity::Keypair> = OnceCell::new();
+
+pub static KEYS: Lazy<identity::Keypair> = Lazy::new(|| {
+    NODE_KEYS
+        .get()
+        .cloned()
+        .unwrap_or_else(identity::Keypair::generate_ed25519)
+});
 pub static PEER_ID: Lazy<PeerId> = Lazy::new(|| PeerId::from(KEYS.public()));
-pub static CHAIN_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("chains"));
-pub static BLOCK_TOPIC: Lazy<Topic> = Lazy::new(|| Topic::new("blocks"));
 
+// The topics are named after the --chain-id when the behaviour is built, so that nodes on different networks never see
+// each other's messages, even when they are connected to each other
+pub struct Topics {
+    pub chain: IdentTopic,
+    pub block: IdentTopic,
+    pub data: IdentTopic,
+    pub transaction: IdentTopic,
+}
+
+impl Topics {
+    pub fn new(chain_id: &str) -> Self {
+        Self {
+            chain: IdentTopic::new(format!("{}/chains", chain_id)),
+            block: IdentTopic::new(format!("{}/blocks", chain_id)),
+            data: IdentTopic::new(format!("{}/data", chain_id)),
+            transaction: IdentTopic::new(format!("{}/transactions", chain_id)),
+        }
+    }
+
+    // Largest message read from the topic.  The chain topic only carries chain resets, which hold no data
+    pub fn max_message_size(&self, topic: &TopicHash, max_data_size: usize) -> usize {
+        if *topic == self.chain.hash() {
+            MAX_CONTROL_MESSAGE_SIZE
+        } else {
+            max_data_size + MESSAGE_OVERHEAD
+        }
+    }
+
+    // The topic each type of message is published on
+    pub fn for_message(&self, message: &Message) -> &IdentTopic {
+        match message {
+            Message::ChainReset => &self.chain,
+            Message::Block(_) | Message::BlockAnnouncement(_) => &self.block,
+            Message::PendingData(_) => &self.data,
+            Message::Transaction(_) => &self.transaction,
+        }
+    }
+}
+
+// Published in place of a newly mined block.  Peers that want the block ask for it with a BlockRequest
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockAnnouncement {
+    pub id: u64,
+    pub hash: BlockHash,
+    pub previous_hash: BlockHash,
+}
+
+impl BlockAnnouncement {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        Self {
+            id: header.id,
+            hash: header.hash,
+            previous_hash: header.previous_hash,
+        }
+    }
+}
+
+// Data waiting to be mined into a block by whichever node gets to it first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingData {
+    pub data: String,
+}
+
+// Messages are sent as this tag followed by the bincode encoding of a Message.  Older versions sent bare JSON, which
+// always starts with '{', so it can never be mistaken for a tagged message
+const BINARY_FORMAT: u8 = 1;
+
+// Or as this tag followed by the bincode encoding of an Envelope, which is how this version publishes every message
+const SIGNED_FORMAT: u8 = 2;
+
+// A message signed with the key of the node that published it.  Gossipsub already checks its own signature over the
+// whole of what is sent, so this one can only fail to check out if the publisher itself got it wrong, and the message
+// is then held against the publisher.  The signature covers the encoded message, so it can't be moved onto another
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ChainResponse {
-    pub blocks: Vec<Block>,
-    pub receiver: String,
+struct Envelope {
+    // Protobuf encoding of the publisher's public key
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+    // A Message tagged with BINARY_FORMAT
+    payload: Vec<u8>,
+}
+
+// Why a message couldn't be used
+#[derive(Debug)]
+pub enum DecodeError {
+    Unreadable(String),
+    // The signature in its envelope doesn't match the message, or was made with some key other than the publisher's
+    BadSignature,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "{}", e),
+            Self::BadSignature => write!(f, "its signature doesn't check out"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct LocalChainRequest {
-    pub from_peer_id: String,
+pub enum Message {
+    Block(Block),
+    PendingData(PendingData),
+    BlockAnnouncement(BlockAnnouncement),
+    // The sender has wiped its chain, so it can no longer supply any block other than the genesis block
+    ChainReset,
+    // A signed transaction waiting to be mined.  Older versions queue transactions as PendingData, which is still
+    // accepted
+    Transaction(Transaction),
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![BINARY_FORMAT];
+        bincode::serialize_into(&mut bytes, self).expect("can encode message");
+        bytes
+    }
+
+    // Encoded and then signed with `keys`
+    pub fn sign(&self, keys: &identity::Keypair) -> Vec<u8> {
+        let payload = self.encode();
+        let envelope = Envelope {
+            public_key: keys.public().into_protobuf_encoding(),
+            signature: keys.sign(&payload).expect("can sign message"),
+            payload,
+        };
+        let mut bytes = vec![SIGNED_FORMAT];
+
+        bincode::serialize_into(&mut bytes, &envelope).expect("can encode envelope");
+        bytes
+    }
+
+    // Signed messages are only taken from the peer that signed them.  Unsigned ones come from older versions
+    pub fn decode(msg: &GossipsubMessage) -> Result<Self, DecodeError> {
+        match msg.data.split_first() {
+            Some((&SIGNED_FORMAT, encoded)) => {
+                let envelope: Envelope = bincode::deserialize(encoded)
+                    .map_err(|e| DecodeError::Unreadable(e.to_string()))?;
+                let signer = identity::PublicKey::from_protobuf_encoding(&envelope.public_key)
+                    .map_err(|_| DecodeError::BadSignature)?;
+
+                if msg.source != Some(PeerId::from(signer.clone()))
+                    || !signer.verify(&envelope.payload, &envelope.signature)
+                {
+                    return Err(DecodeError::BadSignature);
+                }
+
+                match envelope.payload.split_first() {
+                    Some((&BINARY_FORMAT, encoded)) => bincode::deserialize(encoded)
+                        .map_err(|e| DecodeError::Unreadable(e.to_string())),
+                    _ => Err(DecodeError::Unreadable(String::from(
+                        "unrecognised signed message",
+                    ))),
+                }
+            }
+            Some((&BINARY_FORMAT, encoded)) => {
+                bincode::deserialize(encoded).map_err(|e| DecodeError::Unreadable(e.to_string()))
+            }
+            _ => Self::decode_json(&msg.data).map_err(DecodeError::Unreadable),
+        }
+    }
+
+    // Legacy JSON messages don't say what they are, so each type of message is tried in turn
+    fn decode_json(data: &[u8]) -> Result<Self, String> {
+        if let Ok(block) = serde_json::from_slice(data) {
+            Ok(Self::Block(block))
+        } else if let Ok(pending) = serde_json::from_slice(data) {
+            Ok(Self::PendingData(pending))
+        } else {
+            // Blocks from older versions of this program have no difficulty field, and blocks with a malformed hash
+            // can't be read at all, so they end up here
+            let error = serde_json::from_slice::<Block>(data).err();
+
+            Err(error.map_or_else(|| String::from("unrecognised message"), |e| e.to_string()))
+        }
+    }
+}
+
+// Outcome of a background mining job that wasn't cancelled
+pub type MiningResult = Result<(Block, MiningStats), MiningError>;
+
+// A peer that doesn't answer a block request within this time is given up on, and the block is asked for elsewhere
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// However many blocks arrive from ahead of the local tip, the chain is requested at most once in this time
+pub const SYNC_DEBOUNCE: Duration = Duration::from_secs(10);
+
+// Peers that connect for the first time within this long of each other have their tips checked in one go
+pub const NEW_PEER_DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Room allowed for the header and encoding around a block's data.  Messages on the block and data topics larger than
+// --max-data-size plus this are dropped unread.  Chain responses carry whole chains, so they have no such limit
+const MESSAGE_OVERHEAD: usize = 4 * 1024;
+
+// Messages on the chain topic larger than this are dropped unread
+const MAX_CONTROL_MESSAGE_SIZE: usize = 1024;
+
+// A message identical to one seen within this time is dropped, however many peers pass it on.  Requests sent again
+// after this time (for a chain that is still wanted, say) get through
+const DUPLICATE_CACHE_TIME: Duration = Duration::from_secs(10);
+
+// A peer asked for a chunk of its chain has this long to send it
+pub const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Most chunks of a chain asked for at once from one peer
+const SYNC_CHUNKS_IN_FLIGHT: u64 = 4;
+
+// The block intervals shown by `stats` are taken over this many of the most recent blocks
+const STATS_INTERVAL_BLOCKS: usize = 10;
+
+// Nodes only ever join a DHT of other nodes running this protocol, never the public IPFS one
+const KAD_PROTOCOL: &[u8] = b"/minimalchain/kad/1";
+
+// The DHT is bootstrapped again this often, so that the routing table keeps up with peers joining and leaving
+const DHT_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Sent to every peer by identify, which is what tells the DHT the addresses a peer that connected to this node is
+// listening on.  A peer sending another protocol version can't be expected to speak the same protocols
+const IDENTIFY_PROTOCOL_VERSION: &str = "/minimalchain/1";
+const AGENT_VERSION: &str = concat!("minimal_blockchain/", env!("CARGO_PKG_VERSION"));
+
+// `find` shows up to this many characters of an entry on either side of the match
+const FIND_CONTEXT_CHARS: usize = 30;
+
+// Reverse video, which `find` shows the match in
+const HIGHLIGHT_ON: &str = "\u{1b}[7m";
+const HIGHLIGHT_OFF: &str = "\u{1b}[0m";
+
+// Every message is signed with the node's key, and checked against its source before it is passed on.  Anything
+// larger than `max_message_size` is dropped by gossipsub itself, unread.  A message whose signature doesn't check
+// out is dropped as it is read, and gossipsub doesn't tell the behaviour about it, so there is nothing to count
+fn gossipsub_config(max_message_size: usize) -> GossipsubConfig {
+    GossipsubConfigBuilder::default()
+        .message_id_fn(message_id)
+        .validation_mode(ValidationMode::Strict)
+        .duplicate_cache_time(DUPLICATE_CACHE_TIME)
+        .max_transmit_size(max_message_size)
+        .build()
+        .expect("valid gossipsub config")
+}
+
+fn new_gossipsub(max_message_size: usize) -> Gossipsub {
+    Gossipsub::new(
+        MessageAuthenticity::Signed(KEYS.clone()),
+        gossipsub_config(max_message_size),
+    )
+    .expect("can create gossipsub")
+}
+
+// A hash of the message's content and the peer that published it, so that however many peers pass a message on, each
+// node only handles it once.  Two peers publishing the same content, such as a request for the same block, still
+// send two different messages
+fn message_id(message: &GossipsubMessage) -> MessageId {
+    let mut hasher = Sha256::new();
+
+    if let Some(source) = &message.source {
+        hasher.update(source.to_bytes());
+    }
+    hasher.update(&message.data);
+
+    MessageId::from(hex::encode(hasher.finalize()))
+}
+
+// The bootstrap nodes are the first entries in the routing table.  Peers are only added to it once they have been
+// connected to, so every peer in it is known to be reachable
+fn new_kademlia(bootstrap: &[(PeerId, Multiaddr)]) -> Kademlia<MemoryStore> {
+    let mut config = KademliaConfig::default();
+    config.set_protocol_name(KAD_PROTOCOL);
+
+    let mut kademlia = Kademlia::with_config(*PEER_ID, MemoryStore::new(*PEER_ID), config);
+
+    for (peer, addr) in bootstrap {
+        kademlia.add_address(peer, addr.clone());
+    }
+
+    kademlia
+}
+
+// A block is asked for from one peer at a time, which has BLOCK_REQUEST_TIMEOUT to send it before another peer is asked
+// instead
+fn new_block_fetch(max_block_size: usize) -> RequestResponse<BlockCodec> {
+    let mut config = RequestResponseConfig::default();
+    config.set_request_timeout(BLOCK_REQUEST_TIMEOUT);
+
+    RequestResponse::new(
+        BlockCodec {
+            max_response_size: max_block_size,
+        },
+        [(BlockProtocol, ProtocolSupport::Full)],
+        config,
+    )
+}
+
+// Chains are only ever asked for from one peer at a time, which has this long to send its chain before another peer
+// is asked instead
+fn new_sync(max_chain_bytes: usize) -> RequestResponse<SyncCodec> {
+    let mut config = RequestResponseConfig::default();
+    config.set_request_timeout(SYNC_REQUEST_TIMEOUT);
+
+    RequestResponse::new(
+        SyncCodec {
+            max_response_size: max_chain_bytes,
+        },
+        [(SyncProtocol, ProtocolSupport::Full)],
+        config,
+    )
+}
+
+// A relay closes a circuit that has carried nothing for 5 seconds, so a node that uses relays pings more often than the
+// usual every 15 seconds to keep its circuits open
+const RELAYED_PING_INTERVAL: Duration = Duration::from_secs(4);
+
+// The connection is only closed over failed pings with --drop-unresponsive, and then once UNRESPONSIVE_PINGS have
+// failed in a row
+fn new_ping(drop_unresponsive: bool, uses_relay: bool) -> Ping {
+    let max_failures = if drop_unresponsive {
+        UNRESPONSIVE_PINGS
+    } else {
+        u32::MAX
+    };
+    let mut config = PingConfig::new().with_max_failures(NonZeroU32::new(max_failures).expect("not zero"));
+
+    if uses_relay {
+        config = config.with_interval(RELAYED_PING_INTERVAL);
+    }
+
+    Ping::new(config)
+}
+
+// What a peer said about itself through identify.  Everything but the observed address comes from the peer, so it is
+// sanitized before being displayed
+pub struct PeerIdentity {
+    pub agent_version: String,
+    pub protocol_version: String,
+    // The address the peer sees this node connecting from
+    pub observed_addr: Multiaddr,
+}
+
+// A relay server keeps idle connections open for longer, since a peer listening through it is only reachable while its
+// connection to the relay stays up
+const RELAY_SERVER_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+pub fn relay_config(config: &Config) -> RelayConfig {
+    let mut relay_config = RelayConfig::default();
+
+    if config.relay_server {
+        relay_config.connection_idle_timeout = RELAY_SERVER_IDLE_TIMEOUT;
+    }
+
+    relay_config
+}
+
+// A block that has been announced but not yet received
+pub struct PendingBlock {
+    pub id: u64,
+    // Peers that announced the block, in the order their announcements arrived
+    pub sources: Vec<PeerId>,
+    // Every peer asked for the block so far, including the one currently being waited on
+    pub asked: HashSet<PeerId>,
+    pub requested_at: Instant,
+}
+
+// The block currently being mined in the background
+pub struct MiningJob {
+    pub id: u64,
+    pub previous_hash: BlockHash,
+    pub data: Vec<String>,
+    pub cancel: Arc<AtomicBool>,
+}
+
+// A node doesn't mine until it has the chain its peers have, or has given up waiting for one, so that it doesn't start
+// a fork of its own from the genesis block while the chain is still on its way
+#[derive(Debug, Clone)]
+pub enum NodeState {
+    Syncing(Box<InitialSync>),
+    Ready,
+}
+
+// What the swarm reports about connections and listeners, which swarm_event translates from its SwarmEvent
+#[derive(Debug)]
+pub enum NetworkEvent {
+    Connected {
+        peer: PeerId,
+        endpoint: ConnectedPoint,
+    },
+    Disconnected {
+        peer: PeerId,
+        endpoint: ConnectedPoint,
+        cause: Option<String>,
+    },
+    Listening(Multiaddr),
+    StoppedListening(Multiaddr),
+    // A listener has hit an error, and has stopped listening on `addrs` if there are any
+    ListenerFailed {
+        addrs: Vec<Multiaddr>,
+        error: String,
+    },
+    // An address that was dialled couldn't be reached, at least not by the peer expected there if there is one
+    DialFailed {
+        peer: Option<PeerId>,
+        addr: Multiaddr,
+        error: String,
+    },
+    // Turned away for taking the node past --max-inbound
+    InboundRefused {
+        addr: Multiaddr,
+        inbound: u32,
+    },
 }
 
 pub enum EventType {
-    LocalChainResponse(ChainResponse),
+    MinedBlock(MiningResult),
+    Mining(MiningEvent),
+    Reorg(ReorgEvent),
     Input(String),
-    Init,
+    // Standard input has been closed
+    Quit,
+    Tick,
+    Network(NetworkEvent),
 }
 
 #[derive(NetworkBehaviour)]
+#[behaviour(poll_method = "poll")]
 pub struct AppBehaviour {
-    pub floodsub: Floodsub,
-    pub mdns: Mdns,
+    pub gossipsub: Gossipsub,
+    pub sync: RequestResponse<SyncCodec>,
+    pub block_fetch: RequestResponse<BlockCodec>,
+    // Not there with --no-mdns
+    pub mdns: Toggle<Mdns>,
+    // Only there with --dht or --bootstrap
+    pub kademlia: Toggle<Kademlia<MemoryStore>>,
+    pub identify: Identify,
+    pub ping: Ping,
+    // Only there with --relay or --relay-server, or an address through a relay
+    pub relay: Toggle<Relay>,
+    // Peers found by mDNS or the DHT that haven't been dialled yet.  Gossipsub only talks to peers it is already
+    // connected to
+    #[behaviour(ignore)]
+    pub undialled_peers: VecDeque<PeerId>,
+    // Peers found through the DHT, including the bootstrap nodes
+    #[behaviour(ignore)]
+    pub dht_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pub last_dht_refresh: Instant,
+    // Addresses given to `dial` that have neither connected nor failed yet, and the peers connected to that way
+    #[behaviour(ignore)]
+    pub pending_dials: HashSet<Multiaddr>,
+    #[behaviour(ignore)]
+    pub dialled_peers: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    pub startup_dials: StartupDials,
+    #[behaviour(ignore)]
+    pub bans: BanList,
+    #[behaviour(ignore)]
+    pub connections: Connections,
+    #[behaviour(ignore)]
+    pub latencies: Latencies,
+    #[behaviour(ignore)]
+    pub identities: HashMap<PeerId, PeerIdentity>,
+    // Every address peers have seen this node at, each logged the first time it is seen
+    #[behaviour(ignore)]
+    pub observed_addrs: HashSet<Multiaddr>,
+    // Peers to disconnect the next time the swarm polls the behaviour
+    #[behaviour(ignore)]
+    pub pending_disconnects: VecDeque<PeerId>,
+    // Bans and unbans the swarm has yet to be told about, true for a ban
+    #[behaviour(ignore)]
+    pub pending_bans: Vec<(PeerId, bool)>,
+    #[behaviour(ignore)]
+    pub mined_sender: mpsc::UnboundedSender<MiningResult>,
+    #[behaviour(ignore)]
+    pub mining_event_sender: mpsc::UnboundedSender<MiningEvent>,
+    // Anything that wants to follow mining progress (a UI, for instance) can add a sender here
+    #[behaviour(ignore)]
+    pub mining_subscribers: Vec<mpsc::UnboundedSender<MiningEvent>>,
+    #[behaviour(ignore)]
+    pub mining: Option<MiningJob>,
+    // While paused, the data of any block that was being mined is kept here until mining resumes
     #[behaviour(ignore)]
-    pub response_sender: mpsc::UnboundedSender<ChainResponse>,
+    pub mining_paused: bool,
     #[behaviour(ignore)]
-    pub init_sender: mpsc::UnboundedSender<bool>,
+    pub paused_data: Option<Vec<String>>,
+    // When set, the node keeps mining the next entry in the pending queue until the queue is empty
     #[behaviour(ignore)]
-    pub app: App,
+    pub auto_mine: bool,
+    #[behaviour(ignore)]
+    pub state: NodeState,
+    // Announced blocks that have been requested but not yet received, keyed by block hash
+    #[behaviour(ignore)]
+    pub pending_blocks: HashMap<BlockHash, PendingBlock>,
+    // When the chain was last requested because a block arrived from ahead of the local tip
+    #[behaviour(ignore)]
+    pub last_sync_request: Option<Instant>,
+    // The peer whose chain was last asked for, and the peers whose chains have been dropped for being too big
+    #[behaviour(ignore)]
+    pub chain_requested_from: Option<PeerId>,
+    // The sync session of the last anti-entropy check, whose finding nothing to sync is only logged at debug level
+    #[behaviour(ignore)]
+    pub anti_entropy_session: Option<u64>,
+    #[behaviour(ignore)]
+    pub next_anti_entropy: Instant,
+    // Peers that have connected, or reconnected, since their tips were last checked, which they are once
+    // NEW_PEER_DEBOUNCE has passed since the first of them
+    #[behaviour(ignore)]
+    pub new_peers: Vec<PeerId>,
+    #[behaviour(ignore)]
+    pub new_peer_check: Option<Instant>,
+    #[behaviour(ignore)]
+    pub oversized_chain_peers: HashSet<PeerId>,
+    // Peers that didn't answer a request for their chain, which aren't asked again until some peer has answered one
+    #[behaviour(ignore)]
+    pub failed_sync_peers: HashSet<PeerId>,
+    // Chains being fetched a chunk at a time, keyed by session id.  Each is fetched from a different peer
+    #[behaviour(ignore)]
+    pub sync_sessions: HashMap<u64, SyncSession>,
+    // The peer each session's first chunk was asked of, until it answers.  A first chunk from any other peer, or for a
+    // session that isn't waiting on one, is dropped
+    #[behaviour(ignore)]
+    pub sessions_asked: HashMap<u64, PeerId>,
+    #[behaviour(ignore)]
+    pub next_sync_session: u64,
+    // Copies of blocks that have already been dealt with are dropped, and counted, without being validated again
+    #[behaviour(ignore)]
+    pub seen_blocks: SeenBlocks,
+    #[behaviour(ignore)]
+    pub duplicates_dropped: u64,
+    // Messages dropped unread for being larger than their topic allows
+    #[behaviour(ignore)]
+    pub oversized_dropped: u64,
+    // Signed messages dropped for a signature that doesn't check out
+    #[behaviour(ignore)]
+    pub bad_signatures: u64,
+    #[behaviour(ignore)]
+    pub peer_book: PeerBook,
+    // How often each peer is sent a chain, and the chain requests dropped for it
+    #[behaviour(ignore)]
+    pub chain_responses: ResponseLimiter,
+    // New blocks received from peers since startup, and how many of them were invalid
+    #[behaviour(ignore)]
+    pub blocks_received: u64,
+    #[behaviour(ignore)]
+    pub blocks_rejected: u64,
+    #[behaviour(ignore)]
+    pub topics: Topics,
+    // Shared with any other task that wants to read the chain, but only ever written through the helpers below
+    #[behaviour(ignore)]
+    pub app: SharedApp,
+}
+
+// Summary of the node, shown by the `stats` command
+#[derive(Debug, Serialize)]
+pub struct NodeStats {
+    #[serde(flatten)]
+    pub chain: ChainStats,
+    pub connected_peers: usize,
+    pub connections: ConnectionCounts,
+    pub latencies: Vec<PeerLatencyStats>,
+    pub blocks_received: u64,
+    pub blocks_rejected: u64,
+    pub oversized_dropped: u64,
+    pub bad_signatures: u64,
+    // Chain requests dropped for coming too often from one peer, and for coming while the node was sending too many
+    pub chain_requests_limited: u64,
+    pub chain_requests_busy: u64,
+}
+
+// The key the node's peer id is derived from, loaded from --key-file
+pub fn init_keys(keys: identity::Keypair) {
+    if NODE_KEYS.set(keys).is_err() {
+        log::warn!("the node key has already been set");
+    }
 }
 
 impl AppBehaviour {
     pub async fn new(
-        app: App,
-        response_sender: mpsc::UnboundedSender<ChainResponse>,
-        init_sender: mpsc::UnboundedSender<bool>,
+        app: SharedApp,
+        relay: Option<Relay>,
+        mined_sender: mpsc::UnboundedSender<MiningResult>,
+        mining_event_sender: mpsc::UnboundedSender<MiningEvent>,
     ) -> Self {
+        let (
+            seen_cache_size,
+            topics,
+            max_data_size,
+            max_chain_bytes,
+            kademlia,
+            bootstrap,
+            startup_dials,
+            bans,
+            connections,
+            ping,
+            initial_sync,
+            chain_responses,
+            mdns_enabled,
+        ) = {
+            let app = app.read().unwrap_or_else(PoisonError::into_inner);
+            let config = &app.config;
+
+            (
+                config.seen_cache_size,
+                Topics::new(&config.chain_id),
+                config.max_data_size,
+                config.max_chain_bytes,
+                config.dht_enabled.then(|| new_kademlia(&config.bootstrap)),
+                config.bootstrap.iter().map(|(peer, _)| *peer).collect::<Vec<_>>(),
+                StartupDials::new(config.startup_peers(), config.dial_attempts),
+                BanList::new(config.ban_threshold, config.ban_duration),
+                Connections::new(config.max_inbound, config.max_outbound, config.evict_peers),
+                new_ping(config.drop_unresponsive, config.uses_relay()),
+                InitialSync::new(config.initial_sync_timeout, config.initial_sync_peers),
+                ResponseLimiter::new(config.chain_response_interval, config.max_chain_responses),
+                config.mdns_enabled,
+            )
+        };
+        let mdns = if mdns_enabled {
+            Some(Mdns::new(Default::default()).await.expect("can't create mdns"))
+        } else {
+            None
+        };
         let mut behaviour = Self {
-            floodsub: Floodsub::new(*PEER_ID),
-            mdns: Mdns::new(Default::default())
-                .await
-                .expect("can't create mdns"),
-            response_sender,
-            init_sender,
+            gossipsub: new_gossipsub(max_data_size + MESSAGE_OVERHEAD),
+            sync: new_sync(max_chain_bytes),
+            block_fetch: new_block_fetch(max_data_size + MESSAGE_OVERHEAD),
+            mdns: Toggle::from(mdns),
+            kademlia: Toggle::from(kademlia),
+            identify: Identify::new(
+                IdentifyConfig::new(String::from(IDENTIFY_PROTOCOL_VERSION), KEYS.public())
+                    .with_agent_version(String::from(AGENT_VERSION)),
+            ),
+            ping,
+            relay: Toggle::from(relay),
+            undialled_peers: VecDeque::new(),
+            dht_peers: bootstrap.into_iter().collect(),
+            last_dht_refresh: Instant::now(),
+            pending_dials: HashSet::new(),
+            dialled_peers: HashSet::new(),
+            startup_dials,
+            bans,
+            connections,
+            latencies: Latencies::new(),
+            identities: HashMap::new(),
+            observed_addrs: HashSet::new(),
+            pending_disconnects: VecDeque::new(),
+            pending_bans: vec![],
+            mined_sender,
+            mining_event_sender,
+            mining_subscribers: vec![],
+            mining: None,
+            mining_paused: false,
+            paused_data: None,
+            auto_mine: false,
+            state: if initial_sync.until <= Instant::now() {
+                NodeState::Ready
+            } else {
+                NodeState::Syncing(Box::new(initial_sync))
+            },
+            pending_blocks: HashMap::new(),
+            last_sync_request: None,
+            chain_requested_from: None,
+            anti_entropy_session: None,
+            next_anti_entropy: Instant::now(),
+            new_peers: vec![],
+            new_peer_check: None,
+            oversized_chain_peers: HashSet::new(),
+            failed_sync_peers: HashSet::new(),
+            sync_sessions: HashMap::new(),
+            sessions_asked: HashMap::new(),
+            next_sync_session: 0,
+            seen_blocks: SeenBlocks::new(seen_cache_size),
+            duplicates_dropped: 0,
+            oversized_dropped: 0,
+            bad_signatures: 0,
+            peer_book: PeerBook::new(),
+            chain_responses,
+            blocks_received: 0,
+            blocks_rejected: 0,
+            topics,
             app,
         };
-        behaviour.floodsub.subscribe(CHAIN_TOPIC.clone());
-        behaviour.floodsub.subscribe(BLOCK_TOPIC.clone());
 
+        let topics = &behaviour.topics;
+
+        for topic in [&topics.chain, &topics.block, &topics.data, &topics.transaction] {
+            behaviour
+                .gossipsub
+                .subscribe(topic)
+                .expect("can subscribe to topic");
+        }
+
+        behaviour.bootstrap_dht();
         behaviour
     }
-}
 
-impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
-    fn inject_event(&mut self, event: MdnsEvent) {
-        match event {
-            MdnsEvent::Discovered(discovered_list) => {
-                for (peer, _addr) in discovered_list {
-                    self.floodsub.add_node_to_partial_view(peer);
-                }
-            }
-            MdnsEvent::Expired(expired_list) => {
-                for (peer, _addr) in expired_list {
-                    if !self.mdns.has_node(&peer) {
-                        self.floodsub.remove_node_from_partial_view(&peer);
-                    }
-                }
+    // Looks up the node's own peer id, which fills the routing table with the peers closest to it
+    fn bootstrap_dht(&mut self) {
+        if let Some(kademlia) = self.kademlia.as_mut() {
+            self.last_dht_refresh = Instant::now();
+
+            if kademlia.bootstrap().is_err() {
+                log::debug!("no peers to bootstrap the DHT from yet");
             }
         }
     }
-}
 
-// incoming event handler
-impl NetworkBehaviourEventProcess<FloodsubEvent> for AppBehaviour {
-    fn inject_event(&mut self, event: FloodsubEvent) {
-        if let FloodsubEvent::Message(msg) = event {
-            if let Ok(resp) = serde_json::from_slice::<ChainResponse>(&msg.data) {
-                if resp.receiver == PEER_ID.to_string() {
-                    log::info!("Response from {}:", msg.source);
-                    resp.blocks.iter().for_each(|r| log::info!("{:?}", r));
-
-                    self.app.blocks = self.app.choose_chain(self.app.blocks.clone(), resp.blocks);
-                }
-            } else if let Ok(resp) = serde_json::from_slice::<LocalChainRequest>(&msg.data) {
-                log::info!("sending local chain to {}", msg.source.to_string());
-                let peer_id = resp.from_peer_id;
-                if PEER_ID.to_string() == peer_id {
-                    if let Err(e) = self.response_sender.send(ChainResponse {
-                        blocks: self.app.blocks.clone(),
-                        receiver: msg.source.to_string(),
-                    }) {
-                        log::error!("error sending response via channel, {}", e);
+    pub fn refresh_dht(&mut self) {
+        if self.last_dht_refresh.elapsed() >= DHT_REFRESH_INTERVAL {
+            self.bootstrap_dht();
+        }
+    }
+
+    // Every peer found by mDNS or through the DHT, or connected to with `dial`, that isn't banned
+    pub fn discovered_peers(&self) -> Vec<PeerId> {
+        let mut seen = HashSet::new();
+
+        self.mdns
+            .as_ref()
+            .into_iter()
+            .flat_map(Mdns::discovered_nodes)
+            .chain(&self.dht_peers)
+            .chain(&self.dialled_peers)
+            .filter(|peer| !self.bans.is_banned(peer) && seen.insert(**peer))
+            .copied()
+            .collect()
+    }
+
+    fn dial_later(&mut self, peer: PeerId) {
+        if !self.bans.is_banned(&peer) && !self.undialled_peers.contains(&peer) {
+            self.undialled_peers.push_back(peer);
+        }
+    }
+
+    // The chain is locked only for as long as the guard lives, so a guard should never be kept across a call that
+    // locks it again for writing.  A panic while it was locked leaves the chain as valid as it was before the call
+    // that panicked, since every change to it is validated first, so a poisoned lock is used as it is
+    pub fn app(&self) -> RwLockReadGuard<'_, App> {
+        self.app.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn app_mut(&self) -> RwLockWriteGuard<'_, App> {
+        self.app.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Mining runs on a blocking task so that the command loop stays responsive.  The finished block is delivered back
+    // to the command loop as an EventType::MinedBlock, as is a failure to find one within the attempt budget
+    pub fn start_mining(&mut self, data: Vec<String>) {
+        if self.is_syncing() {
+            log::error!("still syncing the chain from peers - try again once the node is ready");
+            return;
+        }
+
+        let app = self.app();
+
+        if !app.config.mining_enabled {
+            log::error!("mining disabled on this node");
+            return;
+        }
+
+        let latest_block = app.tip();
+        let id = latest_block.header.id + 1;
+        let target = app.expected_target_at(id);
+        let max_attempts = app.config.attempt_budget(target);
+        let params = MiningParams {
+            chain_id: app.config.chain_id.clone(),
+            id,
+            previous_hash: latest_block.header.hash,
+            data: data.clone(),
+            target,
+            threads: app.config.mining_threads,
+            timestamp_refresh: app.config.timestamp_refresh,
+            hashing: app.config.hashing,
+            miner: PEER_ID.to_string(),
+            reward: app.config.block_reward,
+            min_timestamp: app.min_next_timestamp(),
+        };
+        drop(app);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mined_sender = self.mined_sender.clone();
+        let mining_event_sender = self.mining_event_sender.clone();
+
+        self.mining = Some(MiningJob {
+            id,
+            previous_hash: params.previous_hash,
+            data,
+            cancel: cancel.clone(),
+        });
+
+        spawn_blocking(move || {
+            match Block::new(params, Some(max_attempts), &cancel, &mining_event_sender) {
+                // Whoever cancelled the job has already moved on
+                Err(MiningError::Cancelled { .. }) => {}
+                mined => {
+                    if let Err(e) = mined_sender.send(mined) {
+                        log::error!("error sending mined block via channel, {}", e);
                     }
                 }
-            } else if let Ok(block) = serde_json::from_slice::<Block>(&msg.data) {
-                log::info!("received new block from {}", msg.source.to_string());
-                self.app.try_add_block(block);
             }
+        });
+    }
+
+    // Every peer that is connected, found or dialled, sorted by peer id, connected peers first
+    pub fn peer_list(&self) -> PeerList {
+        let mut peers = self.discovered_peers();
+        for peer in self.connections.peers() {
+            if !peers.contains(peer) {
+                peers.push(*peer);
+            }
+        }
+
+        let mut peers: Vec<PeerInfo> = peers.iter().map(|peer| self.peer_info(peer)).collect();
+        peers.sort_by(|a, b| b.connected.cmp(&a.connected).then_with(|| a.peer.cmp(&b.peer)));
+
+        let now = Instant::now();
+        let mut banned: Vec<BannedPeer> = self
+            .bans
+            .banned()
+            .map(|(peer, until)| BannedPeer {
+                peer: peer.to_string(),
+                remaining_secs: until.map(|until| until.saturating_duration_since(now).as_secs()),
+            })
+            .collect();
+        banned.sort_by(|a, b| a.peer.cmp(&b.peer));
+
+        PeerList {
+            connections: self.connections.counts(),
+            peers,
+            banned,
         }
     }
-}
 
-pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<String> {
-    log::info!("Discovered Peers:");
-    let nodes = swarm.behaviour().mdns.discovered_nodes();
-    let mut unique_peers = HashSet::new();
-    for peer in nodes {
-        unique_peers.insert(peer);
+    fn peer_info(&self, peer: &PeerId) -> PeerInfo {
+        let since = self.connections.connected_since(peer);
+        let (inbound, outbound) = self.connections.directions(peer);
+        let identity = self.identities.get(peer);
+
+        PeerInfo {
+            peer: peer.to_string(),
+            connected: since.is_some(),
+            inbound,
+            outbound,
+            connected_secs: since.map(|since| since.elapsed().as_secs()),
+            addrs: self.peer_book.addrs(peer).iter().map(|addr| addr.to_string()).collect(),
+            blocks: self.peer_book.blocks(peer),
+            oversized_messages: self.peer_book.oversized_messages(peer),
+            agent_version: identity.map(|identity| sanitize(&identity.agent_version)),
+            protocol_version: identity.map(|identity| sanitize(&identity.protocol_version)),
+            observed_addr: identity.map(|identity| identity.observed_addr.to_string()),
+            ping: self.latencies.peer_stats(peer),
+            strikes: self.bans.strikes(peer),
+        }
     }
-    unique_peers.iter().map(|p| p.to_string()).collect()
-}
 
-pub fn handle_print_peers(swarm: &Swarm<AppBehaviour>) {
-    let peers = get_list_peers(swarm);
-    peers.iter().for_each(|p| log::info!("{}", p));
-}
+    pub fn stats(&self) -> NodeStats {
+        NodeStats {
+            chain: self.app().stats(STATS_INTERVAL_BLOCKS),
+            connected_peers: self.connections.peers().count(),
+            connections: self.connections.counts(),
+            latencies: self.latencies.stats(),
+            blocks_received: self.blocks_received,
+            blocks_rejected: self.blocks_rejected,
+            oversized_dropped: self.oversized_dropped,
+            bad_signatures: self.bad_signatures,
+            chain_requests_limited: self.chain_responses.too_often,
+            chain_requests_busy: self.chain_responses.busy,
+        }
+    }
 
-pub fn handle_print_chain(swarm: &Swarm<AppBehaviour>) {
-    log::info!("Local Blockchain:");
-    let pretty_json = serde_json::to_string_pretty(&swarm.behaviour().app.blocks)
-        .expect("can't convert blocks to JSON");
-    log::info!("{}", pretty_json);
-}
+    pub fn mining_status(&self) -> String {
+        let status = match (&self.mining, self.mining_paused) {
+            _ if !self.app().config.mining_enabled => String::from("disabled"),
+            _ if self.is_syncing() => String::from("waiting for a peer's chain"),
+            (_, true) if self.paused_data.is_some() => String::from("paused with a block pending"),
+            (_, true) => String::from("paused"),
+            (Some(job), false) => format!("mining block {}", job.id),
+            (None, false) => String::from("idle"),
+        };
 
-pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
-    if let Some(data) = cmd.strip_prefix("create b") {
-        let behaviour = swarm.behaviour_mut();
-        let latest_block = behaviour
-            .app
-            .blocks
-            .last()
-            .expect("there needs to be at least one block");
-        let block = Block::new(
-            latest_block.id + 1,
-            latest_block.hash.clone(),
-            data.to_owned(),
-        );
-        let json = serde_json::to_string(&block).expect("not a JSON request");
-        behaviour.app.blocks.push(block);
-        log::info!("broadcasting new block");
-        behaviour
-            .floodsub
-            .publish(BLOCK_TOPIC.clone(), json.as_bytes());
+        if self.auto_mine {
+            format!(
+                "{} (auto-mining, {} pending)",
+                status,
+                self.app().mempool.len()
+            )
+        } else {
+            status
+        }
+    }
+
+    // Start mining a block of the next pending entries, but only if auto-mining is on and nothing else is being mined
+    fn auto_mine_next(&mut self) {
+        if !self.auto_mine
+            || self.mining_paused
+            || self.mining.is_some()
+            || self.is_syncing()
+            || !self.app().config.mining_enabled
+        {
+            return;
+        }
+
+        // Each entry is within --max-data-size on its own, so a block always takes at least one.  Transactions that
+        // the chain no longer allows are dropped, rather than being mined into a block that would be rejected
+        let (data, refused) = {
+            let mut app = self.app_mut();
+            let (block_entries, max_data_size) = (app.config.block_entries, app.config.max_data_size);
+            let taken = app.mempool.take(block_entries, max_data_size);
+
+            app.ledger.admissible(taken)
+        };
+
+        for (_, e) in refused {
+            log::warn!("dropping queued transaction - {}", e);
+        }
+
+        if !data.is_empty() {
+            self.start_mining(data);
+        }
+    }
+
+    pub fn publish(&mut self, message: &Message) {
+        let topic = self.topics.for_message(message).clone();
+
+        match self.gossipsub.publish(topic, message.sign(&KEYS)) {
+            // As when there was nobody to send to before, a node on its own just carries on
+            Ok(_) | Err(PublishError::InsufficientPeers) => {}
+            Err(e) => log::warn!("could not publish message - {:?}", e),
+        }
+    }
+
+    // Disconnects the peers that are to be dropped, then dials the peers found by mDNS, one each time the swarm polls
+    // the behaviour
+    fn poll<T>(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<T, ()>> {
+        if let Some(peer_id) = self.pending_disconnects.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::CloseConnection {
+                peer_id,
+                connection: CloseConnection::All,
+            });
+        }
+
+        match self.undialled_peers.pop_front() {
+            Some(peer_id) => Poll::Ready(NetworkBehaviourAction::DialPeer {
+                peer_id,
+                condition: DialPeerCondition::Disconnected,
+            }),
+            None => Poll::Pending,
+        }
+    }
+
+    // If the chain has moved on since mining started, the block being mined can never be accepted.  Abandon it and
+    // start again on top of the new tip with the same data
+    fn restart_stale_mining(&mut self) {
+        let tip_hash = self.app().tip().header.hash;
+
+        if let Some(job) = self.mining.take_if(|job| job.previous_hash != tip_hash) {
+            log::info!(
+                "chain advanced while mining block {} - restarting on top of the new tip",
+                job.id
+            );
+            job.cancel.store(true, Ordering::Relaxed);
+            self.start_mining(job.data);
+        }
+    }
+
+    fn audit(&self, source: &str, via: &'static str, id: u64, hash: BlockHash, outcome: AuditOutcome) {
+        self.app_mut().audit_log.record(AuditEntry {
+            timestamp: Utc::now().timestamp(),
+            source: source.to_owned(),
+            via,
+            id,
+            hash,
+            outcome,
+        });
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        matches!(self.state, NodeState::Syncing(_))
+    }
+
+    // While the node is syncing, each peer that connects is asked for its chain, until --initial-sync-peers peers have
+    // been asked.  A peer that has already failed to send its chain, or is already sending one, isn't asked again
+    pub fn initial_sync_from(&mut self, peer: PeerId) {
+        if self.failed_sync_peers.contains(&peer)
+            || self.bans.is_banned(&peer)
+            || self.chain_requested_from == Some(peer)
+            || self.sync_sessions.values().any(|session| session.peer == peer)
+        {
+            return;
+        }
+
+        let NodeState::Syncing(sync) = &mut self.state else {
+            return;
+        };
+
+        if sync.ask(peer) {
+            log::info!("asking {} for its chain", peer);
+            self.request_chain(peer);
+        }
+    }
+
+    // Run on every tick.  A peer found after the others were asked is asked in place of any that have failed, and the
+    // node uses its own chain if no peer has sent one by --initial-sync
+    pub fn initial_sync(&mut self) {
+        let NodeState::Syncing(sync) = &self.state else {
+            return;
+        };
+
+        if sync.timed_out() {
+            let height = self.app().height();
+            log::warn!(
+                "no peer has sent its chain within {} seconds - carrying on from block {} of the local chain",
+                self.app().config.initial_sync_timeout.as_secs(),
+                height
+            );
+            self.ready();
+            return;
+        }
+
+        if sync.is_done() {
+            self.ready();
+            return;
+        }
+
+        let peers: Vec<PeerId> = self.connections.peers().copied().collect();
+        for peer in peers {
+            self.initial_sync_from(peer);
+        }
+    }
+
+    // A peer has answered with its chain, or with nothing because the local chain already has everything it does
+    fn synced_with(&mut self, peer: &PeerId) {
+        let NodeState::Syncing(sync) = &mut self.state else {
+            return;
+        };
+
+        sync.answered(*peer);
+
+        if sync.is_done() {
+            self.ready();
+        }
+    }
+
+    fn initial_sync_failed(&mut self, peer: &PeerId) {
+        if let NodeState::Syncing(sync) = &mut self.state {
+            sync.failed(peer);
+        }
+    }
+
+    fn ready(&mut self) {
+        let NodeState::Syncing(sync) = std::mem::replace(&mut self.state, NodeState::Ready) else {
+            return;
+        };
+        let height = self.app().height();
+
+        match sync.adopted {
+            Some(peer) => log::info!(
+                "the node is ready, with the chain from {} up to block {} - {} of the peers asked answered",
+                peer,
+                height,
+                sync.answers()
+            ),
+            None if sync.answers() > 0 => log::info!(
+                "the node is ready, with the local chain up to block {} - none of the {} peers that answered had more",
+                height,
+                sync.answers()
+            ),
+            None => {}
+        }
+
+        self.auto_mine_next();
+    }
+
+    fn startup_peer_connected(&mut self, peer: PeerId, addr: &Multiaddr) {
+        log::info!("connected to {} at {}", peer, addr);
+        self.startup_dials.connected(addr);
+        self.dialled_peers.insert(peer);
+
+        // Syncing from the first peer reached, rather than waiting for mDNS to find one
+        if self.chain_requested_from.is_none() && self.sync_sessions.is_empty() {
+            self.request_chain(peer);
+        }
+
+        self.summarize_startup_dials();
+    }
+
+    fn startup_dial_failed(&mut self, addr: &Multiaddr, error: String) {
+        if let Some(dial) = self.startup_dials.failed(addr, error) {
+            log::warn!(
+                "could not connect to {} after {} attempts - {}",
+                dial.addr,
+                dial.attempts,
+                dial.last_error.as_deref().unwrap_or("unknown error")
+            );
+        }
+
+        self.summarize_startup_dials();
+    }
+
+    fn summarize_startup_dials(&mut self) {
+        if let Some((connected, failed)) = self.startup_dials.summary() {
+            log::info!(
+                "connected to {} of the {} --peer and --bootstrap addresses",
+                connected,
+                connected + failed
+            );
+        }
+    }
+
+    // A peer that has sent something invalid gets a strike, and is banned once it has too many
+    fn strike(&mut self, peer: &PeerId) {
+        if *peer == *PEER_ID || !self.bans.strike(*peer) {
+            return;
+        }
+
+        log::warn!(
+            "banning {} for {} seconds - too many invalid blocks, chains or messages",
+            peer,
+            self.bans.duration().as_secs()
+        );
+        self.drop_banned_peer(peer);
+    }
+
+    // Nothing more is waited for from a banned peer, and the swarm is told to drop its connections
+    fn drop_banned_peer(&mut self, peer: &PeerId) {
+        self.sync_failed(peer);
+        self.pending_bans.push((*peer, true));
+    }
+
+    pub fn expire_bans(&mut self) {
+        for peer in self.bans.expire() {
+            log::info!("the ban on {} has run out", peer);
+            self.lift_ban(peer);
+        }
+    }
+
+    // A peer that is still around is connected to again once the swarm has been told it is no longer banned
+    fn lift_ban(&mut self, peer: PeerId) {
+        self.pending_bans.push((peer, false));
+
+        if self.discovered_peers().contains(&peer) {
+            self.dial_later(peer);
+        }
+    }
+
+    // Returns true if the block was added to the chain
+    fn receive_block(&mut self, block: Block, source: &PeerId) -> bool {
+        let (id, hash, previous_hash) = (
+            block.header.id,
+            block.header.hash,
+            block.header.previous_hash,
+        );
+
+        if block.header.chain_id != self.app().config.chain_id {
+            log::warn!(
+                "dropping block {} from {} - it belongs to chain {}, but this node is on chain {}",
+                id,
+                source,
+                sanitize(&block.header.chain_id),
+                self.app().config.chain_id
+            );
+            let reason = format!("it belongs to chain {}", block.header.chain_id);
+            self.audit(&source.to_string(), "block", id, hash, AuditOutcome::Rejected(reason));
+            return false;
+        }
+
+        if self.seen_blocks.check(&hash) {
+            self.duplicates_dropped += 1;
+            return false;
+        }
+
+        log::info!("received new block from {}", source);
+        self.blocks_received += 1;
+        self.peer_book.received(*source);
+        let first_new = self.app().height() + 1;
+
+        self.sync_if_behind(id, source);
+
+        let outcome = self.app_mut().try_add_block(block);
+        self.audit(&source.to_string(), "block", id, hash, (&outcome).into());
+
+        // An orphan has to be looked at again once its parent arrives, so only orphans are left out of the cache
+        if outcome != Ok(AddBlockOutcome::Orphan) {
+            self.seen_blocks.insert(hash);
+        }
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                log::error!("could not add block - {}", e);
+                self.blocks_rejected += 1;
+                self.peer_book.rejected(*source);
+                self.strike(source);
+                return false;
+            }
+        };
+
+        if outcome != AddBlockOutcome::Duplicate {
+            self.connections.useful(source);
+        }
+
+        let first_new = match outcome {
+            AddBlockOutcome::Added { orphans_attached } => {
+                if orphans_attached > 0 {
+                    log::info!(
+                        "block {} was followed by {} orphans waiting for it",
+                        id,
+                        orphans_attached
+                    );
+                }
+
+                first_new
+            }
+            AddBlockOutcome::SideChain => {
+                // Peers need to see the competing branch too, in case it overtakes the chain
+                log::info!("block {} extends a competing branch", id);
+                return true;
+            }
+            AddBlockOutcome::Reorg(reorg) => reorg.fork_height + 1,
+            AddBlockOutcome::Duplicate => {
+                log::info!("already have block {}", id);
+                return false;
+            }
+            AddBlockOutcome::Orphan => {
+                // The peer that sent the block will have its parent too
+                log::info!("block {} arrived before its parent - requesting it", id);
+                self.fetch_block(id.saturating_sub(1), previous_hash, *source);
+                return false;
+            }
+        };
+
+        let (data, hashes): (Vec<String>, Vec<BlockHash>) = {
+            let app = self.app();
+            let added = app.range(first_new..);
+
+            (
+                added.iter().flat_map(|block| block.data.iter().cloned()).collect(),
+                added.iter().map(|block| block.header.hash).collect(),
+            )
+        };
+
+        for hash in hashes {
+            self.seen_blocks.insert(hash);
+        }
+
+        // Any entries of an abandoned block that the peer didn't mine go back to the front of the queue
+        if self.auto_mine {
+            if let Some(job) = self
+                .mining
+                .take_if(|job| job.data.iter().any(|entry| data.contains(entry)))
+            {
+                log::info!("block {} was mined by a peer first - abandoning it", job.id);
+                job.cancel.store(true, Ordering::Relaxed);
+
+                let mut app = self.app_mut();
+
+                for entry in job.data.into_iter().rev() {
+                    if !data.contains(&entry) {
+                        app.mempool.push_front(entry);
+                    }
+                }
+            }
+        }
+
+        self.restart_stale_mining();
+        self.auto_mine_next();
+        true
+    }
+
+    // Data in the rolled back blocks that the chain doesn't include goes back on the queue, so it isn't lost.  The
+    // chain may have moved on since the reorg, so anything it now holds above the fork point is left out
+    pub fn requeue_rolled_back(&mut self, reorg: &ReorgEvent) {
+        let mut app = self.app_mut();
+        let on_chain: HashSet<&String> = app
+            .range(reorg.fork_height + 1..)
+            .iter()
+            .flat_map(|block| &block.data)
+            .collect();
+        let requeued: Vec<String> = reorg
+            .rolled_back
+            .iter()
+            .flat_map(|block| &block.data)
+            .filter(|entry| !on_chain.contains(entry))
+            .cloned()
+            .collect();
+
+        if !requeued.is_empty() {
+            log::info!(
+                "returning {} entries from rolled back blocks to the queue",
+                requeued.len()
+            );
+            for entry in requeued {
+                app.mempool.push_back(entry);
+            }
+        }
+    }
+
+    // Announcements of blocks we already have, or are already fetching, are dropped without comment.  A block too far
+    // below the local tip couldn't overtake the chain anyway, so it isn't fetched
+    fn handle_announcement(&mut self, announcement: BlockAnnouncement, source: PeerId) {
+        if self.seen_blocks.check(&announcement.hash) {
+            self.duplicates_dropped += 1;
+            return;
+        }
+
+        if self.app().has_block(&announcement.hash) {
+            return;
+        }
+
+        if self.pending_blocks.contains_key(&announcement.hash) {
+            self.fetch_block(announcement.id, announcement.hash, source);
+            return;
+        }
+
+        let (tip_id, max_reorg_depth) = {
+            let app = self.app();
+            (app.tip().header.id, app.config.max_reorg_depth)
+        };
+
+        if announcement.id.saturating_add(max_reorg_depth) <= tip_id {
+            log::info!(
+                "ignoring block {} announced by {} - the chain is already at block {}",
+                announcement.id,
+                source,
+                tip_id
+            );
+            return;
+        }
+
+        if announcement.id <= tip_id {
+            log::info!(
+                "block {} announced by {} competes with the chain - requesting it",
+                announcement.id,
+                source
+            );
+        } else {
+            log::info!(
+                "block {} announced by {} - requesting it",
+                announcement.id,
+                source
+            );
+        }
+
+        self.sync_if_behind(announcement.id, &source);
+        self.fetch_block(announcement.id, announcement.hash, source);
+    }
+
+    // A block more than one ahead of the local tip means we have missed some.  Fetching its parents one at a time will
+    // get there eventually, but the peer's whole chain is asked for too in case that is quicker
+    fn sync_if_behind(&mut self, id: u64, source: &PeerId) {
+        let tip_id = self.app().tip().header.id;
+
+        if id <= tip_id + 1
+            || self
+                .last_sync_request
+                .is_some_and(|requested| requested.elapsed() < SYNC_DEBOUNCE)
+        {
+            return;
+        }
+
+        log::info!(
+            "{} blocks behind {} - requesting its chain",
+            id - tip_id,
+            source
+        );
+        self.last_sync_request = Some(Instant::now());
+        self.request_chain(*source);
+    }
+
+    // Only the blocks above the local tip are asked for at first
+    pub fn request_chain(&mut self, peer: PeerId) {
+        let height = self.app().height();
+        self.request_chain_from(peer, height);
+    }
+
+    // Starts a new session with the first chunk.  Any session already going with the peer is given up on
+    fn request_chain_from(&mut self, peer: PeerId, height: u64) {
+        self.chain_requested_from = Some(peer);
+        self.drop_sync_sessions(&peer);
+
+        let session = self.next_sync_session;
+        self.next_sync_session += 1;
+        self.sessions_asked.insert(session, peer);
+
+        let request = {
+            let app = self.app();
+            let height = height.min(app.height());
+
+            ChainRequest {
+                chain_id: app.config.chain_id.clone(),
+                session,
+                sequence: 0,
+                chunk_size: app.config.sync_chunk_size,
+                from_height: height,
+                from_hash: app.range(height..=height)[0].header.hash,
+                tip_hash: None,
+            }
+        };
+        self.sync.send_request(&peer, request);
+    }
+
+    // Keeps up to SYNC_CHUNKS_IN_FLIGHT chunks of the session on their way
+    fn request_chunks(&mut self, session_id: u64) {
+        let chain_id = self.app().config.chain_id.clone();
+        let Some(session) = self.sync_sessions.get_mut(&session_id) else {
+            return;
+        };
+        let from_hash = session.chain[session.from_height as usize].header.hash;
+
+        while session.next_request < session.chunk_count() && session.in_flight() < SYNC_CHUNKS_IN_FLIGHT {
+            let request = ChainRequest {
+                chain_id: chain_id.clone(),
+                session: session_id,
+                sequence: session.next_request,
+                chunk_size: session.chunk_size,
+                from_height: session.from_height,
+                from_hash,
+                tip_hash: Some(session.tip_hash),
+            };
+            self.sync.send_request(&session.peer, request);
+            session.next_request += 1;
+        }
+    }
+
+    // Gives up on any chain whose chunks have stopped arriving, and asks another peer instead
+    pub fn expire_sync_sessions(&mut self) {
+        let expired: Vec<u64> = self
+            .sync_sessions
+            .iter()
+            .filter(|(_, session)| session.has_expired())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            if let Some(session) = self.sync_sessions.remove(&id) {
+                log::warn!(
+                    "giving up on the chain from {} - no chunk has arrived for {} seconds",
+                    session.peer,
+                    SYNC_SESSION_TIMEOUT.as_secs()
+                );
+                self.sync_failed(&session.peer);
+            }
+        }
+    }
+
+    // Once a peer has sent its chain, peers that failed to are worth asking again
+    fn sync_finished(&mut self, source: &PeerId) {
+        if self.chain_requested_from == Some(*source) {
+            self.chain_requested_from = None;
+        }
+        self.failed_sync_peers.clear();
+    }
+
+    // A chain that is too big is dropped, and if it was the one asked for, some other peer is asked instead.  Peers
+    // that have sent one chain that is too big aren't asked again
+    fn drop_oversized_chain(&mut self, source: &PeerId) {
+        self.drop_sync_sessions(source);
+        self.oversized_chain_peers.insert(*source);
+        self.ask_another_peer(source);
+    }
+
+    // A request that times out, or fails for any other reason, is sent to some other peer instead.  The rest of the
+    // chain isn't waited for
+    fn sync_failed(&mut self, peer: &PeerId) {
+        self.drop_sync_sessions(peer);
+        self.failed_sync_peers.insert(*peer);
+        self.initial_sync_failed(peer);
+        self.ask_another_peer(peer);
+    }
+
+    fn drop_sync_sessions(&mut self, peer: &PeerId) {
+        self.sync_sessions
+            .retain(|_, session| session.peer != *peer);
+        self.sessions_asked.retain(|_, asked| asked != peer);
+    }
+
+    fn ask_another_peer(&mut self, source: &PeerId) {
+        if self.chain_requested_from != Some(*source) {
+            return;
+        }
+
+        self.chain_requested_from = None;
+
+        let other = self.discovered_peers().into_iter().find(|peer| {
+            !self.oversized_chain_peers.contains(peer) && !self.failed_sync_peers.contains(peer)
+        });
+
+        match other {
+            Some(peer) => {
+                log::info!("asking {} for its chain instead", peer);
+                self.request_chain(peer);
+            }
+            None => log::warn!("no other peer to ask for its chain"),
+        }
+    }
+
+    // Blocks are no longer fetched from a peer that has reset its chain, and its chain is no longer waited on
+    fn forget_reset_peer(&mut self, source: &PeerId) {
+        for pending in self.pending_blocks.values_mut() {
+            pending.sources.retain(|peer| peer != source);
+            pending.asked.insert(*source);
+        }
+
+        if self.chain_requested_from == Some(*source) {
+            self.chain_requested_from = None;
+        }
+        self.drop_sync_sessions(source);
+    }
+
+    // Cancels any mining and any block requests along with the chain, so that nothing from before the reset gets
+    // added to the new chain, and tells peers not to rely on this node for blocks
+    pub fn reset(&mut self) {
+        if let Some(job) = self.mining.take() {
+            job.cancel.store(true, Ordering::Relaxed);
+        }
+
+        self.paused_data = None;
+        self.pending_blocks.clear();
+        self.last_sync_request = None;
+        self.chain_requested_from = None;
+        self.sync_sessions.clear();
+        self.sessions_asked.clear();
+        let seen_cache_size = self.app().config.seen_cache_size;
+        self.seen_blocks = SeenBlocks::new(seen_cache_size);
+        self.app_mut().reset();
+        self.publish(&Message::ChainReset);
+    }
+
+    // A transaction that is malformed or badly signed is a strike against the peer that published it.  One that the
+    // chain doesn't allow after the transactions already queued, because it has already been mined say, or would
+    // overdraw its sender, is only dropped, since the publisher may just not have seen the latest block yet
+    fn receive_transaction(&mut self, entry: String, source: &PeerId) {
+        let verified = Transaction::decode(&entry)
+            .expect("entry is a transaction")
+            .and_then(|transaction| transaction.verify());
+
+        if let Err(e) = verified {
+            log::warn!("rejecting transaction from {} - {}", source, e);
+            self.strike(source);
+            return;
+        }
+
+        let refused = {
+            let app = self.app();
+
+            if app.mempool.iter().any(|queued| *queued == entry) {
+                return;
+            }
+
+            let queued = app.mempool.iter().cloned().chain([entry.clone()]).collect();
+            let (_, refused) = app.ledger.admissible(queued);
+
+            refused.into_iter().find(|(refused, _)| *refused == entry)
+        };
+
+        if let Some((_, e)) = refused {
+            log::info!("dropping transaction from {} - {}", source, e);
+            return;
+        }
+
+        if self.app_mut().mempool.push_back(entry) {
+            log::info!("queued transaction from {}", source);
+            self.auto_mine_next();
+        }
+    }
+
+    // Asks `source` for a block, unless it is already being fetched from someone else
+    fn fetch_block(&mut self, id: u64, hash: BlockHash, source: PeerId) {
+        if let Some(pending) = self.pending_blocks.get_mut(&hash) {
+            if !pending.sources.contains(&source) {
+                pending.sources.push(source);
+            }
+            return;
+        }
+
+        self.pending_blocks.insert(
+            hash,
+            PendingBlock {
+                id,
+                sources: vec![source],
+                asked: HashSet::new(),
+                requested_at: Instant::now(),
+            },
+        );
+        self.request_block(hash, source);
+    }
+
+    fn request_block(&mut self, hash: BlockHash, peer: PeerId) {
+        if let Some(pending) = self.pending_blocks.get_mut(&hash) {
+            pending.asked.insert(peer);
+            pending.requested_at = Instant::now();
+        }
+
+        self.block_fetch.send_request(&peer, BlockRequest { hash });
+    }
+
+    // The block a peer asked for, if we have it with its data.  Without its data the block is no use to the requester,
+    // who will ask someone else once the request fails
+    fn requested_block(&self, hash: &BlockHash, requester: &PeerId) -> Option<Block> {
+        let app = self.app();
+        let Some(block) = app.find_block(hash) else {
+            log::warn!(
+                "{} asked for block {}, which we don't have",
+                requester,
+                hash
+            );
+            return None;
+        };
+
+        if block.is_pruned() {
+            log::warn!(
+                "{} asked for block {}, whose data has been pruned",
+                requester,
+                block.header.id
+            );
+            return None;
+        }
+
+        log::info!("sending block {} to {}", block.header.id, requester);
+        Some(block.clone())
+    }
+
+    // Only blocks still waiting to be fetched are taken.  One that arrives after its request has timed out, and some
+    // other peer has already sent it, is dropped
+    fn receive_requested_block(&mut self, block: Block, source: &PeerId) {
+        if self.pending_blocks.remove(&block.header.hash).is_none() {
+            return;
+        }
+
+        let announcement = BlockAnnouncement::from_header(&block.header);
+
+        if self.receive_block(block, source) {
+            // Peers that can't reach the block's origin can fetch it from here instead
+            self.publish(&Message::BlockAnnouncement(announcement));
+        }
+    }
+
+    // Asks someone else for any block whose request has timed out.  Peers that announced the block are tried first,
+    // then any other peer, before giving up on it
+    pub fn retry_block_requests(&mut self) {
+        let now = Instant::now();
+        let discovered = self.discovered_peers();
+        let mut retries = vec![];
+
+        self.pending_blocks.retain(|hash, pending| {
+            if now.duration_since(pending.requested_at) < BLOCK_REQUEST_TIMEOUT {
+                return true;
+            }
+
+            match pending
+                .sources
+                .iter()
+                .chain(&discovered)
+                .find(|peer| !pending.asked.contains(peer))
+            {
+                Some(peer) => {
+                    log::warn!(
+                        "no response to request for block {} - asking {} instead",
+                        pending.id,
+                        peer
+                    );
+                    retries.push((*hash, *peer));
+                    true
+                }
+                None => {
+                    log::warn!("giving up on block {} - no peer sent it", pending.id);
+                    false
+                }
+            }
+        });
+
+        for (hash, peer) in retries {
+            self.request_block(hash, peer);
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<MdnsEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        match event {
+            MdnsEvent::Discovered(discovered_list) => {
+                for (peer, addr) in discovered_list {
+                    self.peer_book.add_addr(peer, addr);
+                    self.dial_later(peer);
+                }
+            }
+            // Gossipsub forgets a peer once its connection closes
+            MdnsEvent::Expired(_) => {}
+        }
+    }
+}
+
+// DHT peers are dialled as soon as they are found, so that gossipsub can reach them
+impl NetworkBehaviourEventProcess<KademliaEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: KademliaEvent) {
+        if let KademliaEvent::RoutingUpdated {
+            peer,
+            is_new_peer: true,
+            ..
+        } = event
+        {
+            if self.dht_peers.insert(peer) {
+                log::info!("found {} through the DHT", peer);
+            }
+            self.dial_later(peer);
+        }
+    }
+}
+
+// A peer that connected to this node can only be added to the routing table once the addresses it listens on are known
+impl NetworkBehaviourEventProcess<IdentifyEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: IdentifyEvent) {
+        let IdentifyEvent::Received { peer_id, info } = event else {
+            return;
+        };
+
+        if self.observed_addrs.insert(info.observed_addr.clone()) {
+            log::info!("{} sees this node at {}", peer_id, info.observed_addr);
+        }
+
+        if info.protocol_version != IDENTIFY_PROTOCOL_VERSION {
+            let drop = self.app().config.drop_incompatible;
+            log::warn!(
+                "{} runs {} with protocol version {} rather than {}{}",
+                peer_id,
+                sanitize(&info.agent_version),
+                sanitize(&info.protocol_version),
+                IDENTIFY_PROTOCOL_VERSION,
+                if drop { " - disconnecting it" } else { "" }
+            );
+
+            if drop {
+                self.pending_disconnects.push_back(peer_id);
+                return;
+            }
+        }
+
+        for addr in &info.listen_addrs {
+            self.peer_book.add_addr(peer_id, addr.clone());
+        }
+
+        if let Some(kademlia) = self.kademlia.as_mut() {
+            for addr in info.listen_addrs {
+                kademlia.add_address(&peer_id, addr);
+            }
+            self.dht_peers.insert(peer_id);
+        }
+
+        self.identities.insert(
+            peer_id,
+            PeerIdentity {
+                agent_version: info.agent_version,
+                protocol_version: info.protocol_version,
+                observed_addr: info.observed_addr,
+            },
+        );
+    }
+}
+
+// Relay reports nothing, the connections it relays being reported by the swarm like any other
+impl NetworkBehaviourEventProcess<()> for AppBehaviour {
+    fn inject_event(&mut self, _: ()) {}
+}
+
+impl NetworkBehaviourEventProcess<PingEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: PingEvent) {
+        let peer = event.peer;
+
+        match event.result {
+            Ok(PingSuccess::Ping { rtt }) => self.latencies.success(peer, rtt),
+            // The peer pinging this node, which says nothing about the round trip time
+            Ok(PingSuccess::Pong) => {}
+            Err(e) if self.latencies.failure(peer) => {
+                let disconnecting = if self.app().config.drop_unresponsive {
+                    " - disconnecting it"
+                } else {
+                    ""
+                };
+                log::warn!(
+                    "{} hasn't answered the last {} pings - {}{}",
+                    peer,
+                    UNRESPONSIVE_PINGS,
+                    e,
+                    disconnecting
+                );
+            }
+            Err(e) => log::debug!("ping to {} failed - {}", peer, e),
+        }
+    }
+}
+
+// incoming event handler
+impl NetworkBehaviourEventProcess<GossipsubEvent> for AppBehaviour {
+    fn inject_event(&mut self, event: GossipsubEvent) {
+        if let GossipsubEvent::Message {
+            propagation_source,
+            message: msg,
+            ..
+        } = event
+        {
+            // Messages are signed, so the source is always known, and is the peer that published the message rather
+            // than the one that passed it on
+            let source = msg.source.unwrap_or(propagation_source);
+
+            if self.bans.is_banned(&source) || self.bans.is_banned(&propagation_source) {
+                log::debug!("dropping message from banned peer {}", source);
+                return;
+            }
+
+            let limit = self
+                .topics
+                .max_message_size(&msg.topic, self.app().config.max_data_size);
+
+            if msg.data.len() > limit {
+                log::warn!(
+                    "rejecting {} byte message on {} from {} - larger than the limit of {} bytes",
+                    msg.data.len(),
+                    msg.topic,
+                    source,
+                    limit
+                );
+                self.oversized_dropped += 1;
+                self.peer_book.oversized(source);
+                self.strike(&source);
+                return;
+            }
+
+            let message = match Message::decode(&msg) {
+                Ok(message) => message,
+                Err(DecodeError::BadSignature) => {
+                    log::warn!(
+                        "rejecting message from {} - its signature doesn't check out",
+                        source
+                    );
+                    self.bad_signatures += 1;
+                    self.strike(&source);
+                    return;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "rejecting message from {} that cannot be read: {}",
+                        source,
+                        sanitize(&e.to_string())
+                    );
+                    self.strike(&source);
+                    return;
+                }
+            };
+
+            match message {
+                Message::Block(block) => {
+                    // Older peers send whole blocks rather than announcing them
+                    self.receive_block(block, &source);
+                }
+                Message::PendingData(pending)
+                    if pending.data.len() > self.app().config.max_data_size =>
+                {
+                    log::warn!(
+                        "rejecting {} bytes of queued data from {} - larger than --max-data-size {}",
+                        pending.data.len(),
+                        source,
+                        self.app().config.max_data_size
+                    );
+                }
+                Message::PendingData(pending) if Transaction::decode(&pending.data).is_some() => {
+                    self.receive_transaction(pending.data, &source);
+                }
+                Message::PendingData(pending) => {
+                    if self.app_mut().mempool.push_back(pending.data) {
+                        log::info!("queued data from {}", source);
+                        self.auto_mine_next();
+                    }
+                }
+                Message::Transaction(transaction) => {
+                    self.receive_transaction(transaction.encode(), &source);
+                }
+                Message::BlockAnnouncement(announcement) => {
+                    self.handle_announcement(announcement, source);
+                }
+                Message::ChainReset => {
+                    log::info!("{} has reset its chain", source);
+                    self.forget_reset_peer(&source);
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<BlockRequest, BlockResponse>> for AppBehaviour {
+    fn inject_event(&mut self, event: RequestResponseEvent<BlockRequest, BlockResponse>) {
+        match event {
+            RequestResponseEvent::Message { peer, .. } if self.bans.is_banned(&peer) => {
+                log::debug!("ignoring block fetch message from banned peer {}", peer);
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Dropping the channel tells the peer that asked that it isn't getting the block
+                if let Some(block) = self.requested_block(&request.hash, &peer) {
+                    if self
+                        .block_fetch
+                        .send_response(channel, BlockResponse { block })
+                        .is_err()
+                    {
+                        log::warn!(
+                            "could not send block to {} - the connection has closed",
+                            peer
+                        );
+                    }
+                }
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => self.receive_requested_block(response.block, &peer),
+            // The block is asked for elsewhere once its request times out
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                log::warn!("{} did not send the block asked for - {:?}", peer, error);
+            }
+            RequestResponseEvent::InboundFailure {
+                error: InboundFailure::ResponseOmission,
+                ..
+            } => {}
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                log::warn!("could not send block to {} - {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { .. } => {}
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<ChainRequest, ChainResponse>> for AppBehaviour {
+    fn inject_event(&mut self, event: RequestResponseEvent<ChainRequest, ChainResponse>) {
+        match event {
+            // A request from a banned peer goes unanswered, and a response from one is dropped
+            RequestResponseEvent::Message { peer, .. } if self.bans.is_banned(&peer) => {
+                log::debug!("ignoring chain sync message from banned peer {}", peer);
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        request_id,
+                        request,
+                        channel,
+                    },
+            } => {
+                // Dropping the channel tells the peer that asked that it isn't getting an answer
+                match self.chain_responses.allow(peer, &request) {
+                    Ok(()) => {}
+                    Err(Refusal::TooOften) => {
+                        log::debug!("dropping chain request from {} - it has asked too often", peer);
+                        return;
+                    }
+                    Err(Refusal::Busy) => {
+                        log::debug!(
+                            "dropping chain request from {} - already sending as many chains as --max-chain-responses",
+                            peer
+                        );
+                        return;
+                    }
+                }
+
+                let response = self.chain_chunk(&request, &peer);
+                self.chain_responses.answering(peer, &response);
+
+                if self.sync.send_response(channel, response).is_err() {
+                    log::warn!("could not send local chain to {} - the connection has closed", peer);
+                } else {
+                    self.chain_responses.sending(request_id);
+                }
+            }
+            RequestResponseEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => self.receive_chain(response, &peer),
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                log::warn!("{} did not send its chain - {:?}", peer, error);
+                self.sync_failed(&peer);
+            }
+            // A request this node chose not to answer
+            RequestResponseEvent::InboundFailure {
+                error: InboundFailure::ResponseOmission,
+                ..
+            } => {}
+            RequestResponseEvent::InboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                self.chain_responses.sent(&request_id);
+                log::warn!("could not send local chain to {} - {:?}", peer, error);
+            }
+            RequestResponseEvent::ResponseSent { request_id, .. } => self.chain_responses.sent(&request_id),
+        }
+    }
+}
+
+impl AppBehaviour {
+    // The chunk asked for, taken from the chain ending in the requested tip, or the local tip for the first chunk.
+    // Chunks are never bigger than --sync-chunk-size, however big a chunk is asked for
+    fn chain_chunk(&self, request: &ChainRequest, peer: &PeerId) -> ChainResponse {
+        let app = self.app();
+        let chunk_size = request.chunk_size.min(app.config.sync_chunk_size);
+        let tip = match &request.tip_hash {
+            Some(hash) => app.find_block(hash).filter(|_| app.on_chain(hash)),
+            None => Some(app.tip()),
+        };
+        let tip = tip.filter(|tip| {
+            request.chain_id == app.config.chain_id
+                && tip.header.id >= request.from_height
+                && app
+                    .get_block_by_id(request.from_height)
+                    .is_some_and(|block| block.header.hash == request.from_hash)
+        });
+
+        let Some(tip) = tip else {
+            return ChainResponse {
+                chain_id: app.config.chain_id.clone(),
+                session: request.session,
+                sequence: request.sequence,
+                from_height: request.from_height,
+                matched: false,
+                tip_hash: app.tip().header.hash,
+                total: 0,
+                chunk_size,
+                blocks: vec![],
+            };
+        };
+
+        if request.sequence == 0 {
+            log::info!(
+                "sending local chain above block {} to {}",
+                request.from_height,
+                peer
+            );
+        }
+
+        let start = request
+            .from_height
+            .saturating_add(1)
+            .saturating_add(request.sequence.saturating_mul(chunk_size));
+        let end = start
+            .saturating_add(chunk_size.saturating_sub(1))
+            .min(tip.header.id);
+
+        ChainResponse {
+            chain_id: app.config.chain_id.clone(),
+            session: request.session,
+            sequence: request.sequence,
+            from_height: request.from_height,
+            matched: true,
+            tip_hash: tip.header.hash,
+            total: tip.header.id - request.from_height,
+            chunk_size,
+            blocks: if chunk_size == 0 {
+                vec![]
+            } else {
+                app.range(start..=end).to_vec()
+            },
+        }
+    }
+
+    fn receive_chain(&mut self, resp: ChainResponse, source: &PeerId) {
+        if resp.chain_id != self.app().config.chain_id {
+            log::warn!(
+                "dropping chain from {} - it belongs to chain {}, but this node is on chain {}",
+                source,
+                sanitize(&resp.chain_id),
+                self.app().config.chain_id
+            );
+            return;
+        }
+
+        if resp.sequence == 0 {
+            match self.sessions_asked.get(&resp.session) {
+                Some(asked) if asked == source => {
+                    self.sessions_asked.remove(&resp.session);
+                }
+                // A request that has since been given up on, or answered by some other peer
+                None if resp.session < self.next_sync_session => {
+                    log::debug!("dropping a chain from {} that is no longer wanted", source);
+                    return;
+                }
+                _ => {
+                    log::warn!(
+                        "dropping the chain from {} - it is for session {}, which it wasn't asked for",
+                        source,
+                        resp.session
+                    );
+                    self.sync_failed(source);
+                    self.strike(source);
+                    return;
+                }
+            }
+
+            self.start_sync_session(resp, source);
+            return;
+        }
+
+        if self
+            .sync_sessions
+            .get(&resp.session)
+            .is_none_or(|session| session.peer != *source)
+        {
+            log::debug!("dropping chunk {} of a chain from {} that is no longer wanted", resp.sequence, source);
+            return;
+        }
+
+        if !resp.matched {
+            log::warn!(
+                "{} has moved off the chain it was sending - asking for its chain again",
+                source
+            );
+            self.request_chain(*source);
+            return;
+        }
+
+        self.add_chunk(resp.session, resp.sequence, resp.blocks);
+    }
+
+    // The first chunk says how many blocks there are to fetch
+    fn start_sync_session(&mut self, resp: ChainResponse, source: &PeerId) {
+        if !resp.matched {
+            self.sync_mismatched(&resp, source);
+            return;
+        }
+
+        let length = resp.from_height.saturating_add(resp.total).saturating_add(1);
+        let max_chain_length = self.app().config.max_chain_length;
+
+        if length > max_chain_length as u64 {
+            log::warn!(
+                "dropping chain of {} blocks from {} - longer than --max-chain-length {}",
+                length,
+                source,
+                max_chain_length
+            );
+            self.drop_oversized_chain(source);
+            return;
+        }
+
+        if resp.total == 0 {
+            log::log!(
+                self.sync_log_level(&resp),
+                "{} has no blocks above block {}",
+                source,
+                resp.from_height
+            );
+            self.sync_finished(source);
+            self.synced_with(source);
+            return;
+        }
+
+        // The chunks are counted by the size the peer sends them in, which is never more than was asked for
+        let (asked, local) = {
+            let app = self.app();
+            (app.config.sync_chunk_size, app.range(..=resp.from_height).to_vec())
+        };
+
+        if resp.chunk_size == 0 || resp.chunk_size > asked {
+            log::warn!(
+                "dropping the chain from {} - it sends chunks of {} blocks, when chunks of up to {} were asked for",
+                source,
+                resp.chunk_size,
+                asked
+            );
+            self.sync_failed(source);
+            self.strike(source);
+            return;
+        }
+
+        // The chunks follow on from the local block at `from_height`, so together they make up the remote chain
+
+        if local.len() as u64 != resp.from_height + 1 {
+            log::info!("the local chain has changed since {} was asked for its chain - asking again", source);
+            self.request_chain(*source);
+            return;
+        }
+
+        // Peers that have the same chain answer with the same tip, which is only fetched once
+        let duplicate = self
+            .sync_sessions
+            .values()
+            .find(|session| session.tip_hash == resp.tip_hash)
+            .map(|session| session.peer);
+
+        if let Some(other) = duplicate {
+            log::info!("{} has the same chain as {} - not fetching it twice", source, other);
+            if self.chain_requested_from == Some(*source) {
+                self.chain_requested_from = None;
+            }
+            self.synced_with(source);
+            return;
+        }
+
+        let session = SyncSession::new(*source, &resp, local);
+
+        if session.chunk_count() > 1 {
+            log::info!(
+                "fetching {} blocks from {} in {} chunks",
+                resp.total,
+                source,
+                session.chunk_count()
+            );
+        }
+
+        self.sync_sessions.insert(resp.session, session);
+        self.add_chunk(resp.session, resp.sequence, resp.blocks);
+    }
+
+    // Each run of chunks that can be added in order is validated straight away, so a bad chain is given up on without
+    // waiting for the rest of it
+    fn add_chunk(&mut self, id: u64, sequence: u64, blocks: Vec<Block>) {
+        let Some(mut session) = self.sync_sessions.remove(&id) else {
+            return;
+        };
+        let peer = session.peer;
+
+        let start = match session.receive(sequence, blocks) {
+            Ok(start) => start,
+            Err(e) => {
+                log::warn!("dropping the chain from {} - {}", peer, e);
+                self.sync_failed(&peer);
+                self.strike(&peer);
+                return;
+            }
+        };
+
+        if let Some(start) = start {
+            let validated = self.app().validate_chain_part(&session.chain, start);
+
+            if let Err(e) = validated {
+                log::warn!("rejecting the chain from {} - {}", peer, e);
+                self.sync_finished(&peer);
+                self.strike(&peer);
+                self.audit_chain(&session, AuditOutcome::Rejected(e.to_string()));
+                return;
+            }
+
+            self.connections.useful(&peer);
+        }
+
+        if session.is_complete() {
+            let checked = self.anti_entropy_session == Some(id);
+
+            self.sync_finished(&peer);
+            if !self.finish_sync(session) && checked {
+                self.announce_tip(&peer);
+            }
+            self.synced_with(&peer);
+        } else {
+            self.sync_sessions.insert(id, session);
+            self.request_chunks(id);
+        }
+    }
+
+    // Returns false if the chain has no more work behind it than the local chain
+    fn finish_sync(&mut self, session: SyncSession) -> bool {
+        let sent = &session.chain[session.from_height as usize + 1..];
+
+        if let (Some(first), Some(last)) = (sent.first(), sent.last()) {
+            log::info!(
+                "received blocks {} to {} of the chain of {}",
+                first.header.id,
+                last.header.id,
+                session.peer
+            );
+        }
+        sent.iter()
+            .for_each(|r| log::debug!("{:?}", sanitized_block(r)));
+
+        // Worked out before switching, since only the blocks the node didn't already have are audited
+        let new_blocks = self.new_blocks(sent);
+        let chosen = self.app().choose_validated_chain(session.chain);
+        let heavier = chosen.is_some();
+
+        let outcome = match chosen {
+            Some(blocks) => {
+                if self.app_mut().switch_chain(blocks) {
+                    if let NodeState::Syncing(sync) = &mut self.state {
+                        sync.adopted = Some(session.peer);
+                    }
+                    self.restart_stale_mining();
+                    AuditOutcome::Accepted
+                } else {
+                    AuditOutcome::Rejected(String::from(
+                        "the chain would roll back more than --max-reorg-depth blocks",
+                    ))
+                }
+            }
+            None => AuditOutcome::Rejected(String::from(
+                "the chain has no more work behind it than the local chain",
+            )),
+        };
+
+        for (id, hash) in new_blocks {
+            self.audit(&session.peer.to_string(), "chain", id, hash, outcome.clone());
+        }
+        heavier
+    }
+
+    // Records what became of the blocks of a chain that has only partly arrived
+    fn audit_chain(&mut self, session: &SyncSession, outcome: AuditOutcome) {
+        let sent = &session.chain[session.from_height as usize + 1..];
+
+        for (id, hash) in self.new_blocks(sent) {
+            self.audit(&session.peer.to_string(), "chain", id, hash, outcome.clone());
+        }
+    }
+
+    fn new_blocks(&self, blocks: &[Block]) -> Vec<(u64, BlockHash)> {
+        let app = self.app();
+
+        blocks
+            .iter()
+            .filter(|block| !app.has_block(&block.header.hash))
+            .map(|block| (block.header.id, block.header.hash))
+            .collect()
+    }
+
+    // Nothing to sync is the usual outcome of an anti-entropy check, so it isn't worth logging every time
+    fn sync_log_level(&self, resp: &ChainResponse) -> log::Level {
+        if self.anti_entropy_session == Some(resp.session) {
+            log::Level::Debug
+        } else {
+            log::Level::Info
+        }
+    }
+
+    // Asks a random connected peer for whatever it has above the local tip, every --anti-entropy seconds give or take
+    // half of that, so that nodes started together don't all ask at once.  When the peer agrees, the response holds no
+    // blocks.  Skipped while a chain is already being fetched
+    pub fn anti_entropy(&mut self) {
+        if self.chain_requested_from.is_some() || !self.sync_sessions.is_empty() {
+            return;
+        }
+
+        if self.new_peer_check.is_some_and(|at| Instant::now() >= at) {
+            let peers = std::mem::take(&mut self.new_peers);
+            self.new_peer_check = None;
+            self.check_tip(peers.into_iter());
+            return;
+        }
+
+        let interval = self.app().config.anti_entropy_interval;
+
+        if interval.is_zero() || Instant::now() < self.next_anti_entropy {
+            return;
+        }
+
+        self.next_anti_entropy = Instant::now() + interval.mul_f64(rand::thread_rng().gen_range(0.5..1.5));
+        let peers: Vec<PeerId> = self.connections.peers().copied().collect();
+        self.check_tip(peers.into_iter());
+    }
+
+    // A peer that has only just connected, or reconnected after a while away, may have been on a chain of its own, so
+    // its tip is checked as soon as the peers that connect along with it have too.  One of them is asked, and the rest
+    // are left to --anti-entropy, so the node doesn't fetch the same chain from each of them
+    fn new_peer(&mut self, peer: PeerId) {
+        if self.is_syncing() {
+            return;
+        }
+
+        self.new_peers.push(peer);
+        self.new_peer_check
+            .get_or_insert_with(|| Instant::now() + NEW_PEER_DEBOUNCE);
+    }
+
+    // Asks one of the peers, picked at random from those still connected, for whatever it has above the local tip
+    fn check_tip(&mut self, peers: impl Iterator<Item = PeerId>) {
+        let peer = peers
+            .filter(|peer| self.connections.connected_since(peer).is_some() && !self.bans.is_banned(peer))
+            .choose(&mut rand::thread_rng());
+
+        if let Some(peer) = peer {
+            log::debug!("checking the local tip against {}", peer);
+            self.anti_entropy_session = Some(self.next_sync_session);
+            self.request_chain(peer);
+        }
+    }
+
+    // A peer found to be behind, or on a lighter fork, by a tip check is told about the local tip, which it then syncs
+    // up to like any other block announced from ahead of its own tip
+    fn announce_tip(&mut self, peer: &PeerId) {
+        let announcement = BlockAnnouncement::from_header(&self.app().tip().header);
+
+        log::info!(
+            "{} has less than the local chain - announcing block {}",
+            peer,
+            announcement.id
+        );
+        self.publish(&Message::BlockAnnouncement(announcement));
+    }
+
+    // The peer doesn't have the local block at `from_height`.  A peer whose tip is on the local chain is only behind,
+    // so has nothing to send.  Otherwise its chain forks off lower down, and it is asked again from twice as far back
+    fn sync_mismatched(&mut self, resp: &ChainResponse, source: &PeerId) {
+        let (behind, height) = {
+            let app = self.app();
+            (app.on_chain(&resp.tip_hash), app.height())
+        };
+
+        if behind {
+            log::log!(
+                self.sync_log_level(resp),
+                "{} is behind the local chain - nothing to sync",
+                source
+            );
+            self.sync_finished(source);
+            self.synced_with(source);
+
+            if self.anti_entropy_session == Some(resp.session) {
+                self.announce_tip(source);
+            }
+            return;
+        }
+
+        if resp.from_height == 0 {
+            log::warn!(
+                "{} has a different genesis block - its chain can't be synced",
+                source
+            );
+            self.sync_finished(source);
+            self.synced_with(source);
+            return;
+        }
+
+        let depth = height.saturating_sub(resp.from_height).max(1) * 2;
+        let from_height = height.saturating_sub(depth);
+
+        log::info!(
+            "{} forks off below block {} - asking for its chain above block {}",
+            source,
+            resp.from_height,
+            from_height
+        );
+
+        // Still a tip check, so a lighter fork is told about the local tip in the end
+        if self.anti_entropy_session == Some(resp.session) {
+            self.anti_entropy_session = Some(self.next_sync_session);
+        }
+        self.request_chain_from(*source, from_height);
+    }
+}
+
+pub fn get_list_peers(swarm: &Swarm<AppBehaviour>) -> Vec<String> {
+    log::info!("Discovered Peers:");
+    swarm
+        .behaviour()
+        .discovered_peers()
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+// Whether and how the peer is connected, the software it runs, how quickly it answers pings, anything that counts
+// against it, the blocks it has sent and the addresses it is known at
+fn peer_details(info: &PeerInfo) -> Vec<String> {
+    let mut details = vec![];
+
+    match info.connected_secs {
+        Some(secs) => details.push(format!(
+            "connected for {}s ({} inbound, {} outbound)",
+            secs, info.inbound, info.outbound
+        )),
+        None => details.push(String::from("not connected")),
+    }
+
+    if let (Some(agent), Some(protocol), Some(observed)) =
+        (&info.agent_version, &info.protocol_version, &info.observed_addr)
+    {
+        details.push(format!("{} on {}, sees this node at {}", agent, protocol, observed));
+    }
+
+    if let Some(ping) = &info.ping {
+        if let (Some(last), Some(average)) = (ping.last_ms, ping.average_ms) {
+            details.push(format!("ping {:.0} ms, {:.0} ms on average", last, average));
+        }
+
+        if ping.failures_in_a_row >= UNRESPONSIVE_PINGS {
+            details.push(format!(
+                "UNRESPONSIVE - the last {} pings failed",
+                ping.failures_in_a_row
+            ));
+        } else if ping.failures_in_a_row > 0 {
+            details.push(String::from("last ping failed"));
+        }
+    }
+
+    if info.strikes > 0 {
+        details.push(format!("{} strikes", info.strikes));
+    }
+
+    if info.blocks.received > 0 {
+        details.push(format!(
+            "{} blocks received, {} rejected",
+            info.blocks.received, info.blocks.rejected
+        ));
+    }
+
+    if info.oversized_messages > 0 {
+        details.push(format!("{} oversized messages dropped", info.oversized_messages));
+    }
+
+    if !info.addrs.is_empty() {
+        details.push(format!("at {}", info.addrs.join(" ")));
+    }
+
+    details
+}
+
+fn log_connection_counts(counts: &ConnectionCounts) {
+    log::info!(
+        "Connections: {} inbound of at most {}, {} outbound of at most {}",
+        counts.inbound,
+        counts.max_inbound,
+        counts.outbound,
+        counts.max_outbound
+    );
+}
+
+// `ls p --json` prints the same list as a single line of JSON on standard output
+pub fn handle_print_peers(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let list = swarm.behaviour().peer_list();
+
+    match cmd.split_whitespace().nth(2) {
+        None => {}
+        Some("--json") => {
+            let json = serde_json::to_string(&list).expect("can't convert peers to JSON");
+            println!("{}", json);
+            return;
+        }
+        Some(_) => {
+            log::error!("usage: ls p [--json]");
+            return;
+        }
+    }
+
+    log_connection_counts(&list.connections);
+    log::info!("Peers:");
+
+    for info in &list.peers {
+        log::info!("{} - {}", info.peer, peer_details(info).join(", "));
+    }
+
+    if list.banned.is_empty() {
+        return;
+    }
+
+    log::info!("Banned Peers:");
+
+    for banned in &list.banned {
+        match banned.remaining_secs {
+            Some(secs) => log::info!("{} for another {} seconds", banned.peer, secs),
+            None => log::info!("{} until unbanned", banned.peer),
+        }
+    }
+}
+
+// `ban <peer id>` drops any connection to the peer, and ignores it until `unban <peer id>`
+pub fn handle_ban(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let peer = match parse_peer_arg(cmd, "ban") {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    if peer == *PEER_ID {
+        log::error!("can't ban this node");
+        return;
+    }
+
+    let behaviour = swarm.behaviour_mut();
+
+    if !behaviour.bans.ban(peer) {
+        log::info!("{} is already banned", peer);
+        return;
+    }
+
+    log::info!("banned {} until it is unbanned", peer);
+    behaviour.drop_banned_peer(&peer);
+    apply_bans(swarm);
+}
+
+pub fn handle_unban(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let peer = match parse_peer_arg(cmd, "unban") {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let behaviour = swarm.behaviour_mut();
+
+    if !behaviour.bans.unban(&peer) {
+        log::info!("{} isn't banned", peer);
+        return;
+    }
+
+    log::info!("unbanned {}", peer);
+    behaviour.lift_ban(peer);
+    apply_bans(swarm);
+}
+
+// The peer id that follows `command`, with the error logged if there isn't a valid one
+fn parse_peer_arg(cmd: &str, command: &str) -> Option<PeerId> {
+    match cmd.strip_prefix(command).map(str::trim) {
+        Some(peer) if !peer.is_empty() => peer
+            .parse()
+            .map_err(|_| log::error!("invalid peer id {} - `ls p` lists the peers", sanitize(peer)))
+            .ok(),
+        _ => {
+            log::error!("usage: {} <peer id>", command);
+            None
+        }
+    }
+}
+
+// The swarm drops a banned peer's connections, and refuses to dial it or accept a connection from it.  The behaviour
+// can't do this itself, so the bans it makes are queued until this is called from the main loop
+pub fn apply_bans(swarm: &mut Swarm<AppBehaviour>) {
+    for (peer, ban) in std::mem::take(&mut swarm.behaviour_mut().pending_bans) {
+        if ban {
+            swarm.ban_peer_id(peer);
+        } else {
+            swarm.unban_peer_id(peer);
+        }
+    }
+}
+
+// `dial <multiaddr>` connects to a peer at a known address, for when mDNS can't find it.  Whether it worked is only
+// known once the swarm reports the connection or the failure, which handle_network_event logs
+pub fn handle_dial(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let addr = match cmd.strip_prefix("dial").map(str::trim) {
+        Some(addr) if !addr.is_empty() => addr,
+        _ => {
+            log::error!("usage: dial <multiaddr>");
+            return;
+        }
+    };
+
+    if config::is_quic(addr) {
+        log::error!(
+            "can't dial {} - {}",
+            sanitize(addr),
+            config::QUIC_UNSUPPORTED
+        );
+        return;
+    }
+
+    let addr: Multiaddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            log::error!(
+                "invalid address {} - {} - use something like /ip4/192.168.1.10/tcp/4001/p2p/<peer id>",
+                sanitize(addr),
+                e
+            );
+            return;
+        }
+    };
+
+    match swarm.dial_addr(addr.clone()) {
+        Ok(()) => {
+            log::info!("dialling {}", addr);
+            swarm.behaviour_mut().pending_dials.insert(addr);
+        }
+        Err(e) => log::error!("could not dial {} - {}", addr, e),
+    }
+}
+
+// The addresses actually listened on are logged as the swarm reports them.  With `fallback`, an address that can't be
+// listened on, because its port is taken say, is tried again with whatever port is free
+pub fn start_listening(
+    swarm: &mut Swarm<AppBehaviour>,
+    addrs: Vec<Multiaddr>,
+    fallback: bool,
+) -> Result<(), String> {
+    for addr in addrs {
+        let e = match swarm.listen_on(addr.clone()) {
+            Ok(_) => continue,
+            Err(e) => e,
+        };
+
+        if !fallback {
+            return Err(format!("can't listen on {} - {}", addr, e));
+        }
+
+        let any_port: Multiaddr = addr
+            .iter()
+            .map(|protocol| match protocol {
+                Protocol::Tcp(_) => Protocol::Tcp(0),
+                protocol => protocol,
+            })
+            .collect();
+
+        log::warn!("can't listen on {} - {} - trying {} instead", addr, e, any_port);
+        swarm
+            .listen_on(any_port.clone())
+            .map_err(|e| format!("can't listen on {} - {}", any_port, e))?;
+    }
+
+    Ok(())
+}
+
+// Dials whichever --peer and --bootstrap addresses are due, which is all of them once the node is listening, and the
+// ones waiting to be retried after that
+pub fn dial_startup_peers(swarm: &mut Swarm<AppBehaviour>) {
+    for addr in swarm.behaviour_mut().startup_dials.due() {
+        if let Err(e) = swarm.dial_addr(addr.clone()) {
+            swarm.behaviour_mut().startup_dial_failed(&addr, e.to_string());
+        }
+    }
+}
+
+// Translates the swarm events the node acts on.  The rest, such as incoming connections still being upgraded or
+// events that the behaviours have already dealt with, are only logged, at debug level
+pub fn swarm_event<B: fmt::Debug, E: fmt::Debug + fmt::Display>(event: SwarmEvent<B, E>) -> Option<EventType> {
+    let event = match event {
+        SwarmEvent::ConnectionEstablished {
+            peer_id, endpoint, ..
+        } => NetworkEvent::Connected {
+            peer: peer_id,
+            endpoint,
+        },
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            endpoint,
+            cause,
+            ..
+        } => NetworkEvent::Disconnected {
+            peer: peer_id,
+            endpoint,
+            cause: cause.map(|cause| cause.to_string()),
+        },
+        SwarmEvent::NewListenAddr { address, .. } => NetworkEvent::Listening(address),
+        SwarmEvent::ExpiredListenAddr { address, .. } => NetworkEvent::StoppedListening(address),
+        SwarmEvent::ListenerClosed {
+            addresses,
+            reason: Err(e),
+            ..
+        } => NetworkEvent::ListenerFailed {
+            addrs: addresses,
+            error: e.to_string(),
+        },
+        SwarmEvent::ListenerError { error, .. } => NetworkEvent::ListenerFailed {
+            addrs: Vec::new(),
+            error: error.to_string(),
+        },
+        SwarmEvent::UnreachableAddr {
+            peer_id,
+            address,
+            error,
+            ..
+        } => NetworkEvent::DialFailed {
+            peer: Some(peer_id),
+            addr: address,
+            error: error.to_string(),
+        },
+        SwarmEvent::UnknownPeerUnreachableAddr { address, error } => NetworkEvent::DialFailed {
+            peer: None,
+            addr: address,
+            error: error.to_string(),
+        },
+        SwarmEvent::IncomingConnectionError {
+            send_back_addr,
+            error: PendingConnectionError::ConnectionLimit(limit),
+            ..
+        } => NetworkEvent::InboundRefused {
+            addr: send_back_addr,
+            inbound: limit.current,
+        },
+        event => {
+            log::debug!("swarm event: {:?}", event);
+            return None;
+        }
+    };
+
+    Some(EventType::Network(event))
+}
+
+// Reports how the dials started by `dial`, --peer and --bootstrap turned out.  A peer connected to is used like any
+// other, and gossipsub sends it messages as soon as it has said which topics it subscribes to.  Connections are counted
+// as they open and close, and one past --max-inbound evicts a peer when --evict-peers is set.  A peer's first
+// connection, whether it is new or back after a while away, has its tip checked
+pub fn handle_network_event(event: NetworkEvent, swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+    let mut evict = None;
+
+    match event {
+        NetworkEvent::Connected { peer, endpoint } => {
+            let first = behaviour.connections.established(peer, endpoint.is_listener());
+            evict = behaviour.connections.to_evict(&peer);
+
+            if let ConnectedPoint::Dialer { address } = endpoint {
+                behaviour.peer_book.add_addr(peer, address.clone());
+
+                if behaviour.pending_dials.remove(&address) {
+                    log::info!("connected to {} at {}", peer, address);
+                    behaviour.dialled_peers.insert(peer);
+                } else if behaviour.startup_dials.is_dialling(&address) {
+                    behaviour.startup_peer_connected(peer, &address);
+                }
+            }
+
+            behaviour.initial_sync_from(peer);
+
+            if first {
+                behaviour.new_peer(peer);
+            }
+        }
+        NetworkEvent::Disconnected {
+            peer,
+            endpoint,
+            cause,
+        } => {
+            if behaviour.connections.closed(&peer, endpoint.is_listener()) {
+                behaviour.latencies.forget(&peer);
+                behaviour.identities.remove(&peer);
+                behaviour.chain_responses.disconnected(&peer);
+
+                match cause {
+                    Some(cause) => log::debug!("disconnected from {} - {}", peer, cause),
+                    None => log::debug!("disconnected from {}", peer),
+                }
+            }
+        }
+        NetworkEvent::Listening(addr) => log::info!("listening on {}/p2p/{}", addr, *PEER_ID),
+        NetworkEvent::StoppedListening(addr) => log::warn!("no longer listening on {}", addr),
+        NetworkEvent::ListenerFailed { addrs, error } if addrs.is_empty() => {
+            log::warn!("listener error - {}", error)
+        }
+        NetworkEvent::ListenerFailed { addrs, error } => {
+            for addr in addrs {
+                log::error!("stopped listening on {} - {}", addr, error);
+            }
+        }
+        NetworkEvent::DialFailed { addr, error, .. } if behaviour.startup_dials.is_dialling(&addr) => {
+            behaviour.startup_dial_failed(&addr, error)
+        }
+        NetworkEvent::DialFailed { addr, error, .. } if behaviour.pending_dials.remove(&addr) => {
+            log::error!("could not dial {} - {}", addr, error)
+        }
+        NetworkEvent::DialFailed { peer, addr, error } => match peer {
+            Some(peer) => log::debug!("could not reach {} at {} - {}", peer, addr, error),
+            None => log::debug!("could not reach {} - {}", addr, error),
+        },
+        NetworkEvent::InboundRefused { addr, inbound } => log::warn!(
+            "refused a connection from {} - the node already has {} inbound connections",
+            addr,
+            inbound
+        ),
+    }
+
+    if let Some(peer) = evict {
+        log::info!(
+            "disconnecting {} to make room for a new peer - it has been of least use of the peers connected to this node",
+            peer
+        );
+        let _ = swarm.disconnect_peer_id(peer);
+    }
+}
+
+// Entries come from peers as well as this node, so they are sanitized before being displayed
+pub fn handle_print_pending(swarm: &Swarm<AppBehaviour>) {
+    let app = swarm.behaviour().app();
+    let mempool = &app.mempool;
+
+    if mempool.is_empty() {
+        log::info!("no data is waiting to be mined");
+        return;
+    }
+
+    log::info!("Data waiting to be mined, oldest first:");
+    for (i, entry) in mempool.iter().enumerate() {
+        log::info!("{}: {}", i + 1, sanitize(entry));
+    }
+}
+
+// `stats --json` prints the same figures as a single line of JSON on standard output, where the log doesn't get in the
+// way of reading it
+pub fn handle_print_stats(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let stats = swarm.behaviour().stats();
+
+    match cmd.split_whitespace().nth(1) {
+        None => {}
+        Some("--json") => {
+            let json = serde_json::to_string(&stats).expect("can't convert stats to JSON");
+            println!("{}", json);
+            return;
+        }
+        Some(_) => {
+            log::error!("usage: stats [--json]");
+            return;
+        }
+    }
+
+    let chain = &stats.chain;
+
+    log::info!("Chain id: {}", chain.chain_id);
+    log::info!("Height: {}", chain.height);
+    log::info!("Tip: {}", chain.tip_hash);
+    log::info!("Genesis: {}", chain.genesis_hash);
+    log::info!("Chain work: {} hashes", chain.chain_work);
+    log::info!("Target: {:?}", chain.target);
+    match chain.intervals {
+        Some(intervals) => log::info!(
+            "Block interval over the last {} blocks: {:.1}s on average, {}s minimum, {}s maximum",
+            STATS_INTERVAL_BLOCKS.min(chain.height as usize),
+            intervals.average,
+            intervals.min,
+            intervals.max
+        ),
+        None => log::info!("Block interval: no blocks after the genesis block yet"),
+    }
+    log::info!("Connected peers: {}", stats.connected_peers);
+    log_connection_counts(&stats.connections);
+    let averages: Vec<f64> = stats.latencies.iter().filter_map(|l| l.average_ms).collect();
+    if !averages.is_empty() {
+        log::info!(
+            "Ping: {:.1} ms on average across {} peers",
+            averages.iter().sum::<f64>() / averages.len() as f64,
+            averages.len()
+        );
+    }
+    let unresponsive = stats
+        .latencies
+        .iter()
+        .filter(|l| l.failures_in_a_row >= UNRESPONSIVE_PINGS)
+        .count();
+    if unresponsive > 0 {
+        log::info!("Unresponsive peers: {}", unresponsive);
+    }
+    log::info!(
+        "Blocks received: {}, of which {} were rejected",
+        stats.blocks_received,
+        stats.blocks_rejected
+    );
+    if stats.oversized_dropped > 0 {
+        log::info!("Oversized messages dropped: {}", stats.oversized_dropped);
+    }
+    if stats.bad_signatures > 0 {
+        log::info!(
+            "Messages with bad signatures dropped: {}",
+            stats.bad_signatures
+        );
+    }
+    if stats.chain_requests_limited > 0 || stats.chain_requests_busy > 0 {
+        log::info!(
+            "Chain requests dropped: {} for coming too often, {} while busy sending other chains",
+            stats.chain_requests_limited,
+            stats.chain_requests_busy
+        );
+    }
+}
+
+// The data and miner of a block come from whoever mined it, so they are sanitized before being displayed
+fn sanitized_block(block: &Block) -> Block {
+    let mut shown = block.clone();
+    shown.header.miner = sanitize(&block.header.miner);
+    shown.data = block.data.iter().map(|entry| sanitize(entry)).collect();
+    shown
+}
+
+// `ls c <from>..<to>` lists only the blocks with ids in that range, and `ls c since <timestamp>` only those dated at
+// or after that Unix timestamp
+pub fn handle_print_chain(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour();
+    let app = behaviour.app();
+    let args: Vec<&str> = cmd.split_whitespace().skip(2).collect();
+
+    let blocks: Option<Vec<&Block>> = match args.as_slice() {
+        [] => Some(app.iter().collect()),
+        ["since", timestamp] => timestamp
+            .parse()
+            .ok()
+            .map(|timestamp| app.blocks_since(timestamp).collect()),
+        [range] => match range.split_once("..") {
+            Some((from, to)) => match (from.parse::<u64>(), to.parse::<u64>()) {
+                (Ok(from), Ok(to)) => Some(app.range(from..=to).iter().collect()),
+                _ => None,
+            },
+            None => None,
+        },
+        _ => None,
+    };
+
+    let Some(blocks) = blocks else {
+        log::error!("usage: ls c [<from>..<to> | since <timestamp>]");
+        return;
+    };
+
+    log::info!("Local Blockchain:");
+    let shown: Vec<Block> = blocks.into_iter().map(sanitized_block).collect();
+    let pretty_json = serde_json::to_string_pretty(&shown).expect("can't convert blocks to JSON");
+    log::info!("{}", pretty_json);
+    log::info!("Chain work: {} hashes", behaviour.app().chain_work());
+    log::info!("Duplicate blocks dropped: {}", behaviour.duplicates_dropped);
+    log::info!(
+        "Blocks on competing branches: {}",
+        behaviour.app().side_blocks.len()
+    );
+    log::info!("Mining: {}", behaviour.mining_status());
+}
+
+// `history <n>` shows only the last `n` entries of the audit log
+pub fn handle_print_history(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let mut args = cmd.split_whitespace().skip(1);
+
+    let count = match (args.next().map(str::parse::<usize>), args.next()) {
+        (None, None) => usize::MAX,
+        (Some(Ok(count)), None) => count,
+        _ => {
+            log::error!("usage: history [<n>]");
+            return;
+        }
+    };
+
+    let app = swarm.behaviour().app();
+    let entries = app.audit_log.entries();
+    let skip = entries.len().saturating_sub(count);
+
+    log::info!("Audit log:");
+    for entry in entries.skip(skip) {
+        let time = Utc
+            .timestamp_opt(entry.timestamp, 0)
+            .single()
+            .map_or_else(|| String::from("out of range"), |time| time.to_rfc3339());
+
+        log::info!(
+            "{} block {} {} ({} from {}): {}",
+            time,
+            entry.id,
+            entry.hash,
+            entry.via,
+            entry.source,
+            sanitize(&entry.outcome.to_string())
+        );
+    }
+}
+
+// `find -i <text>` ignores case.  The text is everything after the command, spaces included
+pub fn handle_find(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let text = cmd.strip_prefix("find").unwrap_or_default().trim();
+    let (text, ignore_case) = match text.strip_prefix("-i ") {
+        Some(text) => (text.trim(), true),
+        None => (text, false),
+    };
+
+    if text.is_empty() || text == "-i" {
+        log::error!("usage: find [-i] <text>");
+        return;
+    }
+
+    let app = swarm.behaviour().app();
+    let matches = app.find_data(text, ignore_case);
+    let limit = app.config.max_find_results;
+
+    if matches.is_empty() {
+        log::info!("no block data contains \"{}\"", sanitize(text));
+        return;
+    }
+
+    for found in matches.iter().take(limit) {
+        let entry = &found.block.data[found.entry];
+        let timestamp = Utc
+            .timestamp_opt(found.block.header.timestamp, 0)
+            .single()
+            .map_or_else(|| String::from("out of range"), |time| time.to_rfc3339());
+
+        log::info!(
+            "block {} ({}) entry {}: {}",
+            found.block.header.id,
+            timestamp,
+            found.entry,
+            snippet(entry, &found.range)
+        );
+    }
+
+    log::info!("Matches: {}", matches.len());
+
+    if matches.len() > limit {
+        log::info!(
+            "{} more not shown - raise --max-find-results to see them",
+            matches.len() - limit
+        );
+    }
+}
+
+// The text around `range` in `entry`, with `range` itself highlighted
+fn snippet(entry: &str, range: &Range<usize>) -> String {
+    let before = &entry[..range.start];
+    let after = &entry[range.end..];
+    let before_start = before
+        .char_indices()
+        .rev()
+        .nth(FIND_CONTEXT_CHARS - 1)
+        .map_or(0, |(i, _)| i);
+    let after_end = after
+        .char_indices()
+        .nth(FIND_CONTEXT_CHARS)
+        .map_or(after.len(), |(i, _)| i);
+
+    let ellipsis = |cut: bool| if cut { "…" } else { "" };
+
+    format!(
+        "{}{}{}{}{}{}{}",
+        ellipsis(before_start > 0),
+        sanitize(&before[before_start..]),
+        HIGHLIGHT_ON,
+        sanitize(&entry[range.clone()]),
+        HIGHLIGHT_OFF,
+        sanitize(&after[..after_end]),
+        ellipsis(after_end < after.len())
+    )
+}
+
+// A block can be given by its id or by its 64 character hash.  `--raw` shows the block as JSON, exactly as it is held,
+// without sanitizing its data
+pub fn handle_show_block(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let mut args = cmd.split_whitespace().skip(2);
+
+    let (Some(block_ref), raw, None) = (args.next(), args.next(), args.next()) else {
+        log::error!("usage: show block <id|hash> [--raw]");
+        return;
+    };
+
+    if raw.is_some_and(|flag| flag != "--raw") {
+        log::error!("usage: show block <id|hash> [--raw]");
+        return;
+    }
+
+    let app = swarm.behaviour().app();
+    let block = match app.lookup_block(block_ref) {
+        Ok(block) => block,
+        Err(e) => {
+            log::error!("{}", e);
+            return;
+        }
+    };
+
+    if raw.is_some() {
+        let pretty_json = serde_json::to_string_pretty(block).expect("can't convert block to JSON");
+        log::info!("{}", pretty_json);
+        return;
+    }
+
+    let header = &block.header;
+    let timestamp = Utc
+        .timestamp_opt(header.timestamp, 0)
+        .single()
+        .map_or_else(|| String::from("out of range"), |time| time.to_rfc3339());
+    let difficulty = match Target::from_compact(header.difficulty) {
+        Ok(target) => format!("{:?}", target),
+        Err(_) => format!("{:#010x}", header.difficulty),
+    };
+
+    log::info!("Block {}", header.id);
+    log::info!("Timestamp:     {} ({})", timestamp, header.timestamp);
+    log::info!("Hash:          {}", header.hash);
+    log::info!("Previous hash: {}", header.previous_hash);
+    log::info!("Merkle root:   {}", header.merkle_root);
+    log::info!("Nonce:         {}", header.nonce);
+    log::info!("Difficulty:    {}", difficulty);
+    log::info!("Miner:         {}", sanitize(&header.miner));
+    log::info!("Reward:        {}", header.reward);
+    log::info!("Confirmations: {}", app.height() - header.id);
+
+    if block.is_pruned() {
+        log::info!("Data:          pruned - only the last --prune-keep blocks keep their data");
+        return;
+    }
+
+    log::info!(
+        "Data:          {} entries, {} bytes",
+        block.data.len(),
+        block.data_size()
+    );
+    for (i, entry) in block.data.iter().enumerate() {
+        log::info!("  {}: {}", i, sanitize(entry));
+    }
+}
+
+pub fn handle_print_rewards(swarm: &Swarm<AppBehaviour>) {
+    let local_peer = PEER_ID.to_string();
+
+    log::info!("Rewards:");
+    for (miner, reward) in swarm.behaviour().app().rewards() {
+        if miner == local_peer {
+            log::info!("{}: {} (this node)", miner, reward);
+        } else {
+            log::info!("{}: {}", sanitize(miner), reward);
+        }
+    }
+}
+
+// Only blocks on the current chain are shown, since anything else has been lost to a reorg
+pub fn handle_print_mining_stats(swarm: &Swarm<AppBehaviour>) {
+    let app = swarm.behaviour().app();
+    let mut total = MiningStats {
+        attempts: 0,
+        elapsed: Duration::ZERO,
+    };
+    let mut mined = 0;
+
+    log::info!("Locally mined blocks:");
+    for block in app.iter() {
+        if let Some(stats) = app.mining_stats.get(&block.header.hash) {
+            log::info!(
+                "block {}: {:.1}s, {} attempts, {}",
+                block.header.id,
+                stats.elapsed.as_secs_f64(),
+                stats.attempts,
+                format_hashrate(stats.hashrate())
+            );
+            total.attempts += stats.attempts;
+            total.elapsed += stats.elapsed;
+            mined += 1;
+        }
+    }
+
+    if mined == 0 {
+        log::info!("no blocks on the chain were mined by this node");
+    } else {
+        log::info!(
+            "{} of {} blocks mined by this node, {:.1}s per block on average, {} attempts in total, {}",
+            mined,
+            app.height(),
+            total.elapsed.as_secs_f64() / mined as f64,
+            total.attempts,
+            format_hashrate(total.hashrate())
+        );
+    }
+}
+
+// Switches to a chain that was refused for rolling back more than --max-reorg-depth blocks, once the operator has
+// checked that it really should replace the local chain
+pub fn handle_accept_reorg(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let Some(Ok(tip)) = cmd
+        .strip_prefix("accept-reorg")
+        .map(|hash| hash.trim().parse::<BlockHash>())
+    else {
+        log::error!("usage: accept-reorg <tip hash>");
+        return;
+    };
+
+    let behaviour = swarm.behaviour_mut();
+
+    if behaviour.app_mut().accept_refused_chain(&tip) {
+        log::warn!("switched to the refused chain ending in {}", tip);
+        behaviour.restart_stale_mining();
+    } else {
+        log::error!(
+            "no chain ending in {} has been refused - it must be received again first",
+            tip
+        );
+    }
+}
+
+pub fn handle_snapshot(swarm: &mut Swarm<AppBehaviour>) {
+    let mut app = swarm.behaviour().app_mut();
+
+    match app.write_snapshot() {
+        Ok(path) => log::info!(
+            "wrote a snapshot of block {} to {}",
+            app.height(),
+            path.display()
+        ),
+        Err(e) => log::error!("could not write a snapshot - {}", e),
+    }
+}
+
+// Stops mining, and leaves a snapshot behind so that the next start is quick
+pub fn handle_shutdown(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+
+    if let Some(job) = behaviour.mining.take() {
+        job.cancel.store(true, Ordering::Relaxed);
+    }
+
+    if behaviour.app().config.db_path.is_some() {
+        handle_snapshot(swarm);
+    }
+
+    log::info!("shutting down");
+}
+
+pub fn handle_save(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let path = cmd.strip_prefix("save").map(str::trim).unwrap_or_default();
+
+    if path.is_empty() {
+        log::error!("usage: save <path>");
+        return;
+    }
+
+    let json = serde_json::to_string_pretty(swarm.behaviour().app().chain())
+        .expect("can't convert chain to JSON");
+
+    match fs::write(path, json) {
+        Ok(()) => log::info!(
+            "saved {} blocks to {}",
+            swarm.behaviour().app().chain().len(),
+            path
+        ),
+        Err(e) => log::error!("could not save the chain to {} - {}", path, e),
+    }
+}
+
+// The chain in the file is only switched to if it is valid from the genesis block onwards, and would win against the
+// local chain just as a chain received from a peer would
+pub fn handle_load(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let path = cmd.strip_prefix("load").map(str::trim).unwrap_or_default();
+
+    if path.is_empty() {
+        log::error!("usage: load <path>");
+        return;
+    }
+
+    let blocks = match fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<Vec<Block>>(&json).map_err(|e| e.to_string()))
+    {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            log::error!("could not read a chain from {} - {}", path, e);
+            return;
+        }
+    };
+
+    let behaviour = swarm.behaviour_mut();
+
+    if let Err(e) = behaviour.app().validate_chain(&blocks) {
+        log::error!("not loading the chain in {} - {}", path, e);
+        return;
+    }
+
+    let chosen = behaviour.app().choose_chain(blocks);
+
+    match chosen {
+        Ok(Some(blocks)) => {
+            if behaviour.app_mut().switch_chain(blocks) {
+                log::info!("switched to the chain in {}", path);
+                behaviour.restart_stale_mining();
+            }
+        }
+        Ok(None) => log::info!(
+            "keeping the local chain - the chain in {} has no more work behind it",
+            path
+        ),
+        Err(e) => log::error!("keeping the local chain - {}", e),
+    }
+}
+
+// Prints the merkle path proving that one data entry is in a block, then checks the proof against the block's root
+pub fn handle_prove(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let mut args = cmd.split_whitespace().skip(1).map(str::parse::<u64>);
+
+    let (Some(Ok(id)), Some(Ok(index)), None) = (args.next(), args.next(), args.next()) else {
+        log::error!("usage: prove <block> <index>");
+        return;
+    };
+
+    let app = swarm.behaviour().app();
+    let Some(block) = app.get_block_by_id(id) else {
+        log::error!("there is no block with id: {}", id);
+        return;
+    };
+
+    if block.is_pruned() {
+        log::error!("the data of block {} has been pruned", id);
+        return;
+    }
+
+    let Some(proof) = merkle::prove(&block.data, index as usize) else {
+        log::error!("block {} has only {} data entries", id, block.data.len());
+        return;
+    };
+
+    let entry = &block.data[proof.index];
+
+    log::info!("entry {} of block {}: {}", index, id, sanitize(entry));
+    for step in &proof.path {
+        log::info!("{:?} sibling: {}", step.side, step.sibling);
+    }
+
+    if merkle::verify(entry, &proof, &block.header.merkle_root) {
+        log::info!(
+            "proof verified against merkle root {}",
+            block.header.merkle_root
+        );
+    } else {
+        log::error!(
+            "proof does not match merkle root {}",
+            block.header.merkle_root
+        );
+    }
+}
+
+pub fn handle_create_block(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    if let Some(data) = cmd.strip_prefix("create b") {
+        let behaviour = swarm.behaviour_mut();
+
+        if behaviour.mining_paused {
+            log::error!("mining is paused - use `mine resume` first");
+        } else if let Some(job) = &behaviour.mining {
+            log::error!("already mining block {} - wait for it to finish", job.id);
+        } else if data.len() > behaviour.app().config.max_data_size {
+            log::error!(
+                "block data is {} bytes, but blocks may carry at most {} - split it up or raise --max-data-size",
+                data.len(),
+                behaviour.app().config.max_data_size
+            );
+        } else {
+            behaviour.start_mining(vec![data.to_owned()]);
+        }
+    }
+}
+
+// Pausing abandons the block currently being mined, but remembers its data so it can be mined again on resume
+pub fn handle_mine_pause(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+
+    if behaviour.mining_paused {
+        log::info!("mining is already paused");
+        return;
+    }
+
+    behaviour.mining_paused = true;
+
+    if let Some(job) = behaviour.mining.take() {
+        job.cancel.store(true, Ordering::Relaxed);
+        behaviour.paused_data = Some(job.data);
+        log::info!(
+            "mining paused - block {} will be mined again on resume",
+            job.id
+        );
+    } else {
+        log::info!("mining paused");
+    }
+}
+
+pub fn handle_mine_resume(swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+
+    if !behaviour.mining_paused {
+        log::info!("mining is not paused");
+        return;
+    }
+
+    behaviour.mining_paused = false;
+    log::info!("mining resumed");
+
+    if let Some(data) = behaviour.paused_data.take() {
+        behaviour.start_mining(data);
+    } else {
+        behaviour.auto_mine_next();
+    }
+}
+
+// Queued data is shared with every peer, and mined by whichever auto-mining node gets to it first
+// `add` is another name for `queue`
+pub fn handle_queue(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    if let Some(data) = cmd
+        .strip_prefix("queue")
+        .or_else(|| cmd.strip_prefix("add"))
+    {
+        let data = data.trim();
+
+        if data.is_empty() {
+            log::error!("usage: queue <data>");
+            return;
+        }
+
+        queue_entry(swarm.behaviour_mut(), data.to_owned());
+    }
+}
+
+// Queues the entry on this node and all its peers
+fn queue_entry(behaviour: &mut AppBehaviour, data: String) {
+    if data.len() > behaviour.app().config.max_data_size {
+        log::error!(
+            "queued data is {} bytes, but blocks may carry at most {} - split it up or raise --max-data-size",
+            data.len(),
+            behaviour.app().config.max_data_size
+        );
+        return;
+    }
+
+    if !behaviour.app_mut().mempool.push_back(data.clone()) {
+        log::error!("that data is already queued");
+        return;
+    }
+
+    // Transactions have a topic of their own
+    let message = match Transaction::decode(&data) {
+        Some(Ok(transaction)) => Message::Transaction(transaction),
+        _ => Message::PendingData(PendingData { data }),
+    };
+
+    behaviour.publish(&message);
+    log::info!("queued data - {} pending", behaviour.app().mempool.len());
+    behaviour.auto_mine_next();
+}
+
+// Sends from this node's wallet.  The transaction is numbered after any others from the wallet that are still waiting
+// to be mined, signed, and queued like any other data
+pub fn handle_transaction(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+
+    let (Some(to), Some(Ok(amount)), None) = (
+        args.first(),
+        args.get(1).map(|amount| amount.parse::<u64>()),
+        args.get(2),
+    ) else {
+        log::error!("usage: tx <to> <amount>");
+        return;
+    };
+
+    let behaviour = swarm.behaviour_mut();
+    let from = &behaviour.app().wallet.address();
+    let sender = behaviour.app().ledger.account(from);
+
+    if sender.balance < amount {
+        log::error!("{} has a balance of {}, too little to send {}", from, sender.balance, amount);
+        return;
+    }
+
+    let mut transaction = Transaction {
+        from: from.clone(),
+        to: to.to_string(),
+        amount,
+        nonce: behaviour.app().next_nonce(from),
+        public_key: String::new(),
+        signature: String::new(),
+    };
+
+    behaviour.app().wallet.sign(&mut transaction);
+    queue_entry(behaviour, transaction.encode());
+}
+
+pub fn handle_reset(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    match cmd.split_whitespace().nth(1) {
+        Some("--yes") => {
+            let behaviour = swarm.behaviour_mut();
+
+            behaviour.reset();
+            log::warn!(
+                "chain reset - starting again from genesis block {}",
+                behaviour.app().tip().header.hash
+            );
+        }
+        _ => log::warn!(
+            "this wipes the chain, the queue and the stored chain - use `reset --yes` to go ahead"
+        ),
+    }
+}
+
+// Without an address, shows the balance of this node's wallet
+pub fn handle_print_balance(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    let app = swarm.behaviour().app();
+    let mut args = cmd.split_whitespace().skip(1);
+
+    let address = match (args.next(), args.next()) {
+        (Some(address), None) => address.to_owned(),
+        (None, None) => app.wallet.address(),
+        _ => {
+            log::error!("usage: balance [<address>]");
+            return;
+        }
+    };
+
+    let delta = app.pending_delta(&address);
+
+    log::info!(
+        "{}: {} confirmed, {}{} pending",
+        sanitize(&address),
+        app.balance_of(&address),
+        if delta >= 0 { "+" } else { "" },
+        delta
+    );
+}
+
+// Account names come from peers' transactions and miners, so they are sanitized before being displayed
+pub fn handle_print_balances(swarm: &Swarm<AppBehaviour>) {
+    let app = swarm.behaviour().app();
+    let wallet = app.wallet.address();
+    let accounts: BTreeMap<_, _> = app.ledger.accounts().collect();
+
+    log::info!("This node's wallet: {}", wallet);
+
+    if accounts.is_empty() {
+        log::info!("no account has a balance yet");
+        return;
+    }
+
+    log::info!("Balances:");
+    for (name, account) in accounts {
+        log::info!(
+            "{}: {} ({} transactions sent){}",
+            sanitize(name),
+            account.balance,
+            account.nonce,
+            if *name == wallet { " (this node's wallet)" } else { "" }
+        );
+    }
+}
+
+// Turning auto-mining off lets any block currently being mined finish, but nothing more is taken from the queue
+pub fn handle_auto_mine(cmd: &str, swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+
+    match cmd.strip_prefix("auto-mine").map(str::trim) {
+        Some("on") => {
+            behaviour.auto_mine = true;
+            log::info!("auto-mining on - {} pending", behaviour.app().mempool.len());
+            behaviour.auto_mine_next();
+        }
+        Some("off") => {
+            behaviour.auto_mine = false;
+            log::info!("auto-mining off");
+        }
+        _ => log::error!("usage: auto-mine on|off"),
+    }
+}
+
+// Benchmark hashing runs on a blocking task so that the node stays responsive
+pub fn handle_mine_bench(cmd: &str, swarm: &Swarm<AppBehaviour>) {
+    if let Some(seconds) = cmd.strip_prefix("mine bench") {
+        let seconds = match seconds.trim() {
+            "" => 5,
+            seconds => match seconds.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => seconds,
+                _ => {
+                    log::error!("usage: mine bench [seconds]");
+                    return;
+                }
+            },
+        };
+
+        let app = swarm.behaviour().app();
+        let target = app.expected_target_at(app.blocks.len() as u64);
+        let threads = app.config.mining_threads;
+        let hashing = app.config.hashing;
+
+        log::info!(
+            "benchmarking {} hash rate for {}s using {} worker threads...",
+            hashing,
+            seconds,
+            threads
+        );
+
+        spawn_blocking(move || {
+            let stats = bench_hashrate(Duration::from_secs(seconds), threads, hashing);
+            let expected_secs = target.expected_attempts() / stats.hashrate();
+
+            log::info!(
+                "benchmark: {} hashes in {:.1}s = {}",
+                stats.attempts,
+                stats.elapsed.as_secs_f64(),
+                format_hashrate(stats.hashrate())
+            );
+            log::info!(
+                "expected time to mine a block at the current target {} ({} leading zero bits): {:.1}s",
+                target,
+                target.leading_zero_bits(),
+                expected_secs
+            );
+        });
+    }
+}
+
+// Mining events are logged, then passed on to anyone who has subscribed to them
+pub fn handle_mining_event(event: MiningEvent, swarm: &mut Swarm<AppBehaviour>) {
+    log_mining_event(&event);
+    swarm
+        .behaviour_mut()
+        .mining_subscribers
+        .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+}
+
+// The chain may have advanced while we were mining, so the block is only accepted if it still extends the current tip
+pub fn handle_mined_block(mined: MiningResult, swarm: &mut Swarm<AppBehaviour>) {
+    let behaviour = swarm.behaviour_mut();
+
+    finish_mining(behaviour, mined);
+    behaviour.auto_mine_next();
+}
+
+fn finish_mining(behaviour: &mut AppBehaviour, mined: MiningResult) {
+    let (block, stats) = match mined {
+        Ok(mined) => mined,
+        Err(e) => {
+            if let MiningError::BudgetExhausted { id, .. } = e {
+                behaviour
+                    .mining
+                    .take_if(|job| job.id == id && !job.cancel.load(Ordering::Relaxed));
+            }
+
+            log::error!("{} - try again with `create b`, or raise --max-attempts", e);
+            return;
+        }
+    };
+
+    // A cancelled worker can still deliver a block it found just before noticing the cancellation
+    if behaviour
+        .mining
+        .take_if(|job| job.id == block.header.id && job.previous_hash == block.header.previous_hash)
+        .is_none()
+    {
+        log::warn!("discarding stale mined block with id: {}", block.header.id);
+        return;
+    }
+
+    let latest_block = behaviour.app().tip().header.clone();
+
+    if block.header.previous_hash != latest_block.hash {
+        log::warn!(
+            "discarding mined block with id: {} - the chain advanced to block {} while mining",
+            block.header.id,
+            latest_block.id
+        );
+        return;
+    }
+
+    let announcement = BlockAnnouncement::from_header(&block.header);
+
+    let outcome = behaviour.app_mut().try_add_block(block);
+    behaviour.audit(
+        "local",
+        "mined",
+        announcement.id,
+        announcement.hash,
+        (&outcome).into(),
+    );
+
+    match outcome {
+        Ok(AddBlockOutcome::Added { .. }) => {}
+        Err(e) => {
+            log::error!("could not add mined block - {}", e);
+            return;
+        }
+        Ok(outcome) => {
+            log::error!("could not add mined block - {:?}", outcome);
+            return;
+        }
+    }
+
+    behaviour.app_mut().mining_stats.insert(announcement.hash, stats);
+    behaviour.seen_blocks.insert(announcement.hash);
+    log::info!("announcing new block");
+    behaviour.publish(&Message::BlockAnnouncement(announcement));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        block::GenesisSpec,
+        config::DEFAULT_CHAIN_ID,
+        test_util::{self, block_at, grow, next_block},
+    };
+    use libp2p::{
+        core::{InboundUpgrade, UpgradeInfo},
+        futures::{io::Cursor, StreamExt},
+        gossipsub::protocol::ProtocolConfig,
+    };
+    use std::sync::RwLock;
+
+    // A node on its own, ready to mine straight away.  Blocks it mines in the background are delivered to the receiver
+    async fn node(config: Config) -> (AppBehaviour, mpsc::UnboundedReceiver<MiningResult>) {
+        let config = Config {
+            mdns_enabled: false,
+            initial_sync_timeout: Duration::ZERO,
+            ..config
+        };
+        let app = Arc::new(RwLock::new(test_util::app_with(config)));
+        let (mined_sender, mined) = mpsc::unbounded_channel();
+        let (mining_event_sender, _) = mpsc::unbounded_channel();
+
+        (
+            AppBehaviour::new(app, None, mined_sender, mining_event_sender).await,
+            mined,
+        )
+    }
+
+    // Stands in for a mining job that is part way through mining the block after the tip
+    fn mining_job(behaviour: &mut AppBehaviour, data: &str) -> (Block, Arc<AtomicBool>) {
+        let block = {
+            let app = behaviour.app();
+            next_block(app.chain(), &app.config, &[data])
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        behaviour.mining = Some(MiningJob {
+            id: block.header.id,
+            previous_hash: block.header.previous_hash,
+            data: block.data.clone(),
+            cancel: cancel.clone(),
+        });
+        (block, cancel)
+    }
+
+    // A block from a peer that takes the place of the one being mined
+    fn peer_block(behaviour: &AppBehaviour) -> Block {
+        let app = behaviour.app();
+        let chain = app.chain();
+
+        block_at(
+            chain,
+            &app.config,
+            &["mined by a peer"],
+            chain[0].header.timestamp + 5,
+        )
+    }
+
+    fn stats() -> MiningStats {
+        MiningStats {
+            attempts: 1,
+            elapsed: Duration::from_secs(1),
+        }
+    }
+
+    // The block the worker turns up with after a peer's block has taken its place is never announced
+    fn assert_not_announced(behaviour: &mut AppBehaviour, stale: &Block, remote: &Block) {
+        let app = behaviour.app();
+
+        assert_eq!(app.tip(), remote);
+        assert!(!app.has_block(&stale.header.hash));
+        assert!(!app.mining_stats.contains_key(&stale.header.hash));
+        drop(app);
+        assert!(!behaviour.seen_blocks.check(&stale.header.hash));
+    }
+
+    #[tokio::test]
+    async fn a_block_mined_after_a_remote_block_arrives_is_never_announced() {
+        let (mut behaviour, _mined) = node(test_util::config()).await;
+        let (stale, cancel) = mining_job(&mut behaviour, "mined here");
+        let remote = peer_block(&behaviour);
+
+        assert!(behaviour.receive_block(remote.clone(), &PeerId::random()));
+        assert!(cancel.load(Ordering::Relaxed));
+
+        // Mining restarted on top of the remote block, and then the cancelled worker delivered what it had found
+        assert!(behaviour
+            .mining
+            .as_ref()
+            .is_some_and(|job| job.previous_hash == remote.header.hash));
+        finish_mining(&mut behaviour, Ok((stale.clone(), stats())));
+        assert_not_announced(&mut behaviour, &stale, &remote);
+    }
+
+    #[tokio::test]
+    async fn a_block_mined_on_a_replaced_tip_is_never_announced() {
+        let (mut behaviour, _mined) = node(Config {
+            mining_enabled: false,
+            ..test_util::config()
+        })
+        .await;
+        let (stale, _) = mining_job(&mut behaviour, "mined here");
+        let remote = peer_block(&behaviour);
+
+        // The job is still there when the worker delivers its block, but the chain has moved on without it
+        behaviour
+            .app_mut()
+            .try_add_block(remote.clone())
+            .expect("the remote block is valid");
+        finish_mining(&mut behaviour, Ok((stale.clone(), stats())));
+        assert_not_announced(&mut behaviour, &stale, &remote);
+    }
+
+    #[tokio::test]
+    async fn an_announced_block_is_fetched_from_the_peer_that_announced_it() {
+        let config = Config {
+            mining_enabled: false,
+            ..test_util::config()
+        };
+        let (mut announcer, _) = node(config.clone()).await;
+        let (mut receiver, _) = node(config).await;
+        let (announcer_id, receiver_id) = (PeerId::random(), PeerId::random());
+        let block = peer_block(&announcer);
+        let hash = block.header.hash;
+
+        // A block the peer doesn't have goes unanswered
+        assert!(announcer.requested_block(&hash, &receiver_id).is_none());
+
+        assert!(announcer.receive_block(block.clone(), &PeerId::random()));
+        receiver.handle_announcement(BlockAnnouncement::from_header(&block.header), announcer_id);
+        assert!(receiver.pending_blocks[&hash].asked.contains(&announcer_id));
+
+        let sent = announcer
+            .requested_block(&hash, &receiver_id)
+            .expect("the announcer has the block");
+        receiver.receive_requested_block(sent.clone(), &announcer_id);
+        assert_eq!(receiver.app().tip(), &block);
+        assert!(receiver.pending_blocks.is_empty());
+
+        // The same block sent again once its request has been answered is dropped
+        let blocks_received = receiver.blocks_received;
+        receiver.receive_requested_block(sent, &PeerId::random());
+        assert_eq!(receiver.blocks_received, blocks_received);
+    }
+
+    #[tokio::test]
+    async fn an_announcement_of_the_highest_possible_block_id_is_requested() {
+        let (mut behaviour, _) = node(Config {
+            mining_enabled: false,
+            max_reorg_depth: u64::MAX,
+            ..test_util::config()
+        })
+        .await;
+        let source = PeerId::random();
+        let announcement = BlockAnnouncement {
+            id: u64::MAX,
+            hash: BlockHash::from([7; 32]),
+            previous_hash: BlockHash::from([6; 32]),
+        };
+
+        behaviour.handle_announcement(announcement, source);
+        assert!(behaviour.pending_blocks[&BlockHash::from([7; 32])]
+            .asked
+            .contains(&source));
+    }
+
+    // Peers that have been found but never connected to, or have since disconnected, aren't counted
+    #[tokio::test]
+    async fn stats_count_the_peers_connected_to() {
+        let (mut behaviour, _) = node(test_util::config()).await;
+        let (first, second) = (PeerId::random(), PeerId::random());
+
+        behaviour.dht_peers.insert(PeerId::random());
+        behaviour.connections.established(first, true);
+        behaviour.connections.established(first, false);
+        behaviour.connections.established(second, false);
+        assert_eq!(behaviour.stats().connected_peers, 2);
+
+        behaviour.connections.closed(&second, false);
+        assert_eq!(behaviour.stats().connected_peers, 1);
+    }
+
+    #[tokio::test]
+    async fn an_observer_follows_the_chain_with_the_most_work_without_mining() {
+        let (mut observer, _mined) = node(Config {
+            mining_enabled: false,
+            ..test_util::config()
+        })
+        .await;
+        let (config, genesis) = {
+            let app = observer.app();
+            (app.config.clone(), app.chain().to_vec())
+        };
+        let first = grow(&genesis, &config, 2, "first miner");
+        let second = grow(&genesis, &config, 3, "second miner");
+
+        for block in &first[1..] {
+            assert!(observer.receive_block(block.clone(), &PeerId::random()));
+        }
+        assert_eq!(observer.app().chain(), &first[..]);
+
+        for block in &second[1..] {
+            assert!(observer.receive_block(block.clone(), &PeerId::random()));
+        }
+        assert_eq!(observer.app().chain(), &second[..]);
+
+        observer.start_mining(test_util::entries(&["not mined"]));
+        assert!(observer.mining.is_none());
+    }
+
+    // A message as it arrives from a peer on the block topic
+    fn received(data: Vec<u8>) -> GossipsubMessage {
+        GossipsubMessage {
+            source: Some(PeerId::random()),
+            data,
+            sequence_number: None,
+            topic: Topics::new(DEFAULT_CHAIN_ID).block.hash(),
+        }
+    }
+
+    #[test]
+    fn a_long_chain_round_trips_through_the_binary_encoding() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 1000, "entry");
+        let mut binary_size = 0;
+        let mut json_size = 0;
+
+        for block in &chain {
+            let encoded = Message::Block(block.clone()).encode();
+
+            binary_size += encoded.len();
+            json_size += serde_json::to_vec(block).expect("can encode block").len();
+
+            match Message::decode(&received(encoded)) {
+                Ok(Message::Block(decoded)) => assert_eq!(&decoded, block),
+                other => panic!("expected a block, got {:?}", other),
+            }
+        }
+
+        // Around half the size, as the README says
+        assert!(
+            binary_size * 5 < json_size * 3,
+            "{} against {} bytes of JSON",
+            binary_size,
+            json_size
+        );
+    }
+
+    // Nodes that still send JSON are understood by ones that send binary, whichever kind of message they send
+    #[test]
+    fn legacy_json_messages_are_still_accepted() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 1, "entry");
+        let block = &chain[1];
+        let json = serde_json::to_vec(block).expect("can encode block");
+
+        match Message::decode(&received(json)) {
+            Ok(Message::Block(decoded)) => assert_eq!(&decoded, block),
+            other => panic!("expected a block, got {:?}", other),
+        }
+
+        match Message::decode(&received(br#"{"data":"queued by an older node"}"#.to_vec())) {
+            Ok(Message::PendingData(pending)) => {
+                assert_eq!(pending.data, "queued by an older node")
+            }
+            other => panic!("expected pending data, got {:?}", other),
+        }
+
+        let binary = Message::PendingData(PendingData {
+            data: String::from("queued by a newer node"),
+        })
+        .encode();
+
+        match Message::decode(&received(binary)) {
+            Ok(Message::PendingData(pending)) => assert_eq!(pending.data, "queued by a newer node"),
+            other => panic!("expected pending data, got {:?}", other),
+        }
+
+        assert!(Message::decode(&received(b"not a message".to_vec())).is_err());
+    }
+
+    fn published_by(source: PeerId, data: Vec<u8>) -> GossipsubMessage {
+        GossipsubMessage {
+            source: Some(source),
+            ..received(data)
+        }
+    }
+
+    // An envelope holding `payload` with the signature of the one in `signed`
+    fn with_payload(signed: &[u8], payload: Vec<u8>) -> Vec<u8> {
+        let mut envelope: Envelope =
+            bincode::deserialize(&signed[1..]).expect("can read the envelope");
+        envelope.payload = payload;
+
+        let mut bytes = vec![SIGNED_FORMAT];
+        bincode::serialize_into(&mut bytes, &envelope).expect("can encode the envelope");
+        bytes
+    }
+
+    #[test]
+    fn a_signed_message_is_only_taken_from_its_signer_as_it_was_signed() {
+        let keys = identity::Keypair::generate_ed25519();
+        let signer = PeerId::from(keys.public());
+        let signed = Message::ChainReset.sign(&keys);
+
+        assert!(matches!(
+            Message::decode(&published_by(signer, signed.clone())),
+            Ok(Message::ChainReset)
+        ));
+
+        // Passed off as published by some other peer
+        assert!(matches!(
+            Message::decode(&published_by(PeerId::random(), signed.clone())),
+            Err(DecodeError::BadSignature)
+        ));
+
+        // The signature moved onto a different message
+        let other = Message::PendingData(PendingData {
+            data: String::from("never signed"),
+        });
+        assert!(matches!(
+            Message::decode(&published_by(signer, with_payload(&signed, other.encode()))),
+            Err(DecodeError::BadSignature)
+        ));
+    }
+
+    #[tokio::test]
+    async fn a_message_with_a_bad_signature_is_dropped_and_counted_against_its_publisher() {
+        let (mut behaviour, _mined) = node(Config {
+            mining_enabled: false,
+            ..test_util::config()
+        })
+        .await;
+        let keys = identity::Keypair::generate_ed25519();
+        let publisher = PeerId::from(keys.public());
+        let block = peer_block(&behaviour);
+        let signed = Message::ChainReset.sign(&keys);
+        let forged = with_payload(&signed, Message::Block(block.clone()).encode());
+
+        gossip(&mut behaviour, publisher, forged);
+        assert_eq!(behaviour.bad_signatures, 1);
+        assert_eq!(behaviour.stats().bad_signatures, 1);
+        assert_eq!(behaviour.bans.strikes(&publisher), 1);
+        assert_eq!(behaviour.app().height(), 0);
+
+        let signed = Message::Block(block.clone()).sign(&keys);
+        gossip(&mut behaviour, publisher, signed);
+        assert_eq!(behaviour.app().tip(), &block);
+        assert_eq!(behaviour.bad_signatures, 1);
+    }
+
+    // Delivers a message from `source` the way gossipsub does
+    fn gossip(behaviour: &mut AppBehaviour, source: PeerId, data: Vec<u8>) {
+        let message = GossipsubMessage {
+            source: Some(source),
+            ..received(data)
+        };
+
+        behaviour.inject_event(GossipsubEvent::Message {
+            propagation_source: source,
+            message_id: MessageId::from("test"),
+            message,
+        });
+    }
+
+    #[tokio::test]
+    async fn a_block_with_a_garbage_hash_is_rejected_without_a_panic() {
+        let (mut behaviour, _mined) = node(test_util::config()).await;
+        let block = peer_block(&behaviour);
+        let peer = PeerId::random();
+
+        let mut garbage = serde_json::to_value(&block).expect("can encode block");
+        garbage["hash"] = serde_json::Value::from("zzzz");
+        gossip(&mut behaviour, peer, garbage.to_string().into_bytes());
+
+        assert_eq!(behaviour.app().height(), 0);
+        assert_eq!(behaviour.bans.strikes(&peer), 1);
+
+        // The node is still running, and still takes blocks from the same peer
+        gossip(&mut behaviour, peer, Message::Block(block.clone()).encode());
+        assert_eq!(behaviour.app().tip(), &block);
+    }
+
+    // Every peer that relays a block sends its own copy
+    #[tokio::test]
+    async fn a_block_relayed_by_several_peers_is_only_handled_once() {
+        let (mut behaviour, _mined) = node(Config {
+            mining_enabled: false,
+            ..test_util::config()
+        })
+        .await;
+        let block = peer_block(&behaviour);
+        let data = Message::Block(block.clone()).encode();
+
+        for _ in 0..3 {
+            gossip(&mut behaviour, PeerId::random(), data.clone());
+        }
+        assert_eq!(behaviour.app().tip(), &block);
+        assert_eq!(behaviour.blocks_received, 1);
+        assert_eq!(behaviour.duplicates_dropped, 2);
+
+        // Nor is it fetched when announced afterwards
+        let announcement =
+            Message::BlockAnnouncement(BlockAnnouncement::from_header(&block.header));
+        gossip(&mut behaviour, PeerId::random(), announcement.encode());
+        assert!(behaviour.pending_blocks.is_empty());
+        assert_eq!(behaviour.duplicates_dropped, 3);
+    }
+
+    // Chain ids are normally kept apart by their topics, so these blocks are handed straight to the other node
+    #[tokio::test]
+    async fn nodes_on_different_chains_never_take_each_others_blocks() {
+        let on_chain = |chain_id: &str| Config {
+            chain_id: String::from(chain_id),
+            ..test_util::config()
+        };
+        let (mut a, _mined_a) = node(on_chain("a")).await;
+        let (mut b, _mined_b) = node(on_chain("b")).await;
+        let block_a = peer_block(&a);
+        let block_b = peer_block(&b);
+        let peer = PeerId::random();
+
+        gossip(&mut a, peer, Message::Block(block_b.clone()).encode());
+        gossip(&mut b, peer, Message::Block(block_a.clone()).encode());
+        assert_eq!(a.app().height(), 0);
+        assert_eq!(b.app().height(), 0);
+
+        // Dropped outright, rather than kept until a parent turns up
+        assert!(a.app().orphans.is_empty());
+        assert!(b.app().orphans.is_empty());
+
+        // Nor does a whole chain get through
+        b.receive_chain(
+            ChainResponse {
+                chain_id: String::from("a"),
+                session: 1,
+                sequence: 0,
+                from_height: 0,
+                matched: true,
+                tip_hash: block_a.header.hash,
+                total: 1,
+                chunk_size: 1,
+                blocks: vec![block_a.clone()],
+            },
+            &peer,
+        );
+        assert_eq!(b.app().height(), 0);
+        assert!(b.sync_sessions.is_empty());
+
+        // Each still takes blocks from its own chain
+        gossip(&mut a, peer, Message::Block(block_a.clone()).encode());
+        gossip(&mut b, peer, Message::Block(block_b.clone()).encode());
+        assert_eq!(a.app().tip(), &block_a);
+        assert_eq!(b.app().tip(), &block_b);
+    }
+
+    // Readers on other threads take turns with the command loop while it adds blocks as fast as it can, and must only
+    // ever see a whole chain that never gets shorter.  Once the blocks are all in, each of them sees every one
+    #[tokio::test]
+    async fn readers_always_see_a_consistent_chain_while_blocks_arrive() {
+        let (mut behaviour, _mined) = node(test_util::config()).await;
+        let chain = {
+            let app = behaviour.app();
+            grow(app.chain(), &app.config, 300, "entry")
+        };
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (app, done) = (behaviour.app.clone(), done.clone());
+
+                std::thread::spawn(move || {
+                    let mut height = 0;
+
+                    // Every reader looks at least once, however quickly the blocks are added
+                    loop {
+                        let finished = done.load(Ordering::Relaxed);
+                        let app = app.read().expect("no writer panics");
+                        let chain = app.chain();
+
+                        assert!(app.height() >= height, "the chain got shorter");
+                        assert!(chain.windows(2).all(|pair| {
+                            pair[1].header.previous_hash == pair[0].header.hash
+                                && pair[1].header.id == pair[0].header.id + 1
+                        }));
+                        height = app.height();
+
+                        if finished {
+                            return height;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let peer = PeerId::random();
+        for block in &chain[1..] {
+            gossip(&mut behaviour, peer, Message::Block(block.clone()).encode());
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            assert_eq!(reader.join().expect("the reader never panics"), 300);
+        }
+        assert_eq!(behaviour.app().chain(), &chain[..]);
+    }
+
+    // The request `requester` sends for chunk `sequence` of the session it started last, for the chain above
+    // `from_height`
+    fn chain_request(
+        requester: &AppBehaviour,
+        from_height: u64,
+        sequence: u64,
+        tip_hash: Option<BlockHash>,
+    ) -> ChainRequest {
+        let app = requester.app();
+
+        ChainRequest {
+            chain_id: app.config.chain_id.clone(),
+            session: requester.next_sync_session - 1,
+            sequence,
+            chunk_size: app.config.sync_chunk_size,
+            from_height,
+            from_hash: app.range(from_height..=from_height)[0].header.hash,
+            tip_hash,
+        }
+    }
+
+    // Plays the part of the network between two nodes.  The request that `requester` has just sent for the chain
+    // above `from_height` is answered by `responder`, which is known to it as `responder_id`, and so is every request
+    // for a later chunk of the same session.  Returns the responses, in the order they were delivered
+    fn answer_sync(
+        requester: &mut AppBehaviour,
+        responder: &AppBehaviour,
+        responder_id: PeerId,
+        from_height: u64,
+    ) -> Vec<ChainResponse> {
+        let requester_id = PeerId::random();
+        let mut request = chain_request(requester, from_height, 0, None);
+        let mut responses = vec![];
+
+        loop {
+            let response = responder.chain_chunk(&request, &requester_id);
+            responses.push(response.clone());
+            requester.receive_chain(response, &responder_id);
+
+            match requester.sync_sessions.get(&request.session) {
+                Some(session) => {
+                    request.sequence += 1;
+                    request.chunk_size = session.chunk_size;
+                    request.tip_hash = Some(session.tip_hash);
+                }
+                None => return responses,
+            }
+        }
+    }
+
+    // Each node starts with `chain`, taken on trust so that forks can be set up
+    async fn node_with(chain: &[Block]) -> AppBehaviour {
+        let (behaviour, _mined) = node(test_util::config()).await;
+        assert!(behaviour.app_mut().switch_chain(chain.to_vec()));
+        behaviour
+    }
+
+    #[tokio::test]
+    async fn a_node_that_is_behind_only_fetches_the_blocks_above_its_tip() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 5, "entry");
+        let (mut behind, ahead) = (node_with(&chain[..4]).await, node_with(&chain).await);
+        let peer = PeerId::random();
+
+        behind.request_chain(peer);
+        let responses = answer_sync(&mut behind, &ahead, peer, 3);
+
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].blocks, &chain[4..]);
+        assert_eq!(behind.app().chain(), &chain[..]);
+        assert!(behind.sync_sessions.is_empty());
+    }
+
+    // A peer can only start the session it was asked to, so it can't take over one asked of another peer
+    #[tokio::test]
+    async fn a_chain_for_a_session_the_peer_was_not_asked_for_is_dropped() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 5, "entry");
+        let (mut behind, ahead) = (node_with(&chain[..4]).await, node_with(&chain).await);
+        let (asked, other) = (PeerId::random(), PeerId::random());
+
+        behind.request_chain(asked);
+        let request = chain_request(&behind, 3, 0, None);
+        let mut response = ahead.chain_chunk(&request, &PeerId::random());
+
+        behind.receive_chain(response.clone(), &other);
+        assert_eq!(behind.app().height(), 3);
+        assert_eq!(behind.bans.strikes(&other), 1);
+
+        response.session += 1;
+        behind.receive_chain(response.clone(), &asked);
+        assert_eq!(behind.app().height(), 3);
+        assert_eq!(behind.bans.strikes(&asked), 1);
+
+        // The request it was sent has been given up on, so the answer to it is no longer wanted either
+        response.session -= 1;
+        behind.receive_chain(response, &asked);
+        assert_eq!(behind.app().height(), 3);
+
+        behind.request_chain(asked);
+        answer_sync(&mut behind, &ahead, asked, 3);
+        assert_eq!(behind.app().chain(), &chain[..]);
+    }
+
+    // The nodes share blocks 1 and 2.  Asked for its chain above block 4, the peer has a different block 4, so the
+    // node falls back to asking for the chain above block 2
+    #[tokio::test]
+    async fn a_fork_below_the_height_asked_for_falls_back_to_a_deeper_request() {
+        let config = test_util::config();
+        let shared = grow(&[test_util::genesis(&config)], &config, 2, "shared");
+        let local = grow(&shared, &config, 2, "local");
+        let remote = grow(&shared, &config, 3, "remote");
+        let (mut node, peer_node) = (node_with(&local).await, node_with(&remote).await);
+        let peer = PeerId::random();
+
+        node.request_chain(peer);
+        let responses = answer_sync(&mut node, &peer_node, peer, 4);
+        assert!(!responses[0].matched);
+        assert_eq!(node.app().chain(), &local[..]);
+        assert_eq!(node.chain_requested_from, Some(peer));
+
+        let responses = answer_sync(&mut node, &peer_node, peer, 2);
+        assert!(responses[0].matched);
+        assert_eq!(responses[0].blocks, &remote[3..]);
+        assert_eq!(node.app().chain(), &remote[..]);
+    }
+
+    // The peer's tip is on the local chain, so there is nothing to fetch, and no deeper request
+    #[tokio::test]
+    async fn a_peer_with_a_shorter_chain_has_nothing_to_sync() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 5, "entry");
+        let (mut ahead, behind) = (node_with(&chain).await, node_with(&chain[..3]).await);
+        let peer = PeerId::random();
+
+        ahead.request_chain(peer);
+        let session = ahead.next_sync_session;
+        let responses = answer_sync(&mut ahead, &behind, peer, 5);
+
+        assert_eq!(responses.len(), 1);
+        assert!(!responses[0].matched);
+        assert_eq!(responses[0].tip_hash, chain[2].header.hash);
+        assert_eq!(ahead.app().chain(), &chain[..]);
+        assert_eq!(ahead.chain_requested_from, None);
+        assert_eq!(ahead.next_sync_session, session);
+    }
+
+    // Each peer has a chain of its own, sent in chunks of 2, and the chunks of the two sessions arrive interleaved.
+    // The shorter chain is complete first and adopted, until the longer one replaces it
+    #[tokio::test]
+    async fn chains_from_two_peers_can_be_fetched_at_once() {
+        let config = Config {
+            sync_chunk_size: 2,
+            ..test_util::config()
+        };
+        let genesis = [test_util::genesis(&config)];
+        let chains = [
+            grow(&genesis, &config, 6, "first"),
+            grow(&genesis, &config, 8, "second"),
+        ];
+        let (mut syncing, _mined) = node(config.clone()).await;
+        let mut peers = vec![];
+
+        for chain in &chains {
+            let (peer_node, _mined) = node(config.clone()).await;
+            assert!(peer_node.app_mut().switch_chain(chain.clone()));
+
+            let peer = PeerId::random();
+            syncing.request_chain(peer);
+            peers.push((peer_node, peer, chain_request(&syncing, 0, 0, None)));
+        }
+
+        for sequence in 0..4 {
+            for (peer_node, peer, request) in &mut peers {
+                if sequence > 0 {
+                    let Some(session) = syncing.sync_sessions.get(&request.session) else {
+                        continue;
+                    };
+                    request.sequence = sequence;
+                    request.tip_hash = Some(session.tip_hash);
+                }
+
+                let response = peer_node.chain_chunk(request, &PeerId::random());
+                syncing.receive_chain(response, peer);
+            }
+
+            match sequence {
+                0 => assert_eq!(syncing.sync_sessions.len(), 2),
+                2 => {
+                    assert_eq!(syncing.app().chain(), &chains[0][..]);
+                    assert_eq!(syncing.sync_sessions.len(), 1);
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(syncing.app().chain(), &chains[1][..]);
+        assert!(syncing.sync_sessions.is_empty());
+    }
+
+    // Started as if the node had just come up and --initial-sync-peers were `peers`
+    fn start_initial_sync(behaviour: &mut AppBehaviour, peers: usize) {
+        behaviour.state =
+            NodeState::Syncing(Box::new(InitialSync::new(Duration::from_secs(60), peers)));
+    }
+
+    // A peer on another network has answered even though nothing can be synced from it, so the node doesn't wait on
+    // it until --initial-sync runs out
+    #[tokio::test]
+    async fn a_peer_with_another_genesis_block_counts_as_having_answered_the_initial_sync() {
+        let other = Config {
+            genesis: GenesisSpec {
+                data: test_util::entries(&["another network"]),
+                ..GenesisSpec::default()
+            },
+            ..test_util::config()
+        };
+        let (mut syncing, _mined) = node(test_util::config()).await;
+        let (foreign, _mined) = node(other).await;
+        let peer = PeerId::random();
+
+        start_initial_sync(&mut syncing, 1);
+        syncing.initial_sync_from(peer);
+        answer_sync(&mut syncing, &foreign, peer, 0);
+
+        assert!(!syncing.is_syncing());
+        assert_eq!(syncing.app().height(), 0);
+    }
+
+    // The second peer's chain is the one already being fetched from the first, so it has answered as well
+    #[tokio::test]
+    async fn a_peer_with_the_same_tip_as_another_counts_as_having_answered_the_initial_sync() {
+        let config = Config {
+            sync_chunk_size: 2,
+            ..test_util::config()
+        };
+        let chain = grow(&[test_util::genesis(&config)], &config, 6, "entry");
+        let (mut syncing, _mined) = node(config.clone()).await;
+        let (ahead, _mined) = node(config).await;
+        assert!(ahead.app_mut().switch_chain(chain.clone()));
+        let (first, second) = (PeerId::random(), PeerId::random());
+
+        start_initial_sync(&mut syncing, 2);
+        syncing.initial_sync_from(first);
+        let mut request = chain_request(&syncing, 0, 0, None);
+        syncing.initial_sync_from(second);
+        let duplicate = chain_request(&syncing, 0, 0, None);
+
+        syncing.receive_chain(ahead.chain_chunk(&request, &PeerId::random()), &first);
+        syncing.receive_chain(ahead.chain_chunk(&duplicate, &PeerId::random()), &second);
+        assert_eq!(syncing.sync_sessions.len(), 1);
+        assert!(syncing.is_syncing());
+
+        request.tip_hash = Some(chain[6].header.hash);
+        for sequence in 1..3 {
+            request.sequence = sequence;
+            syncing.receive_chain(ahead.chain_chunk(&request, &PeerId::random()), &first);
+        }
+
+        assert_eq!(syncing.app().chain(), &chain[..]);
+        assert!(!syncing.is_syncing());
+    }
+
+    // A message as gossipsub sends it between peers, signed by this node: a length prefixed protobuf RPC holding one
+    // published message.  The signature is taken over `signed` but the message carries `data`
+    fn signed_rpc(topic: &TopicHash, signed: &[u8], data: &[u8]) -> Vec<u8> {
+        fn field(out: &mut Vec<u8>, number: u8, bytes: &[u8]) {
+            out.push(number << 3 | 2);
+            varint(out, bytes.len());
+            out.extend_from_slice(bytes);
+        }
+        fn varint(out: &mut Vec<u8>, mut n: usize) {
+            while n >= 0x80 {
+                out.push(n as u8 | 0x80);
+                n >>= 7;
+            }
+            out.push(n as u8);
+        }
+        let message = |data: &[u8]| {
+            let mut message = vec![];
+            field(&mut message, 1, &PEER_ID.to_bytes());
+            field(&mut message, 2, data);
+            field(&mut message, 3, &1u64.to_be_bytes());
+            field(&mut message, 4, topic.as_str().as_bytes());
+            message
+        };
+
+        let signature = KEYS
+            .sign(&[&b"libp2p-pubsub:"[..], &message(signed)].concat())
+            .expect("can sign");
+        let mut published = message(data);
+        field(&mut published, 5, &signature);
+
+        let mut rpc = vec![];
+        field(&mut rpc, 2, &published);
+        let mut frame = vec![];
+        varint(&mut frame, rpc.len());
+        frame.extend(rpc);
+        frame
+    }
+
+    // Reads `frame` the way gossipsub reads it from a peer, with the node's own gossipsub settings, and describes what
+    // it made of it.  What gossipsub hands its behaviour isn't public, so the description is its debug output
+    async fn read_rpc(frame: Vec<u8>) -> String {
+        let config = gossipsub_config(MAX_CONTROL_MESSAGE_SIZE);
+        let protocol = ProtocolConfig::new(
+            config.protocol_id_prefix().clone(),
+            config.max_transmit_size(),
+            config.validation_mode().clone(),
+            false,
+        );
+        let id = protocol.protocol_info().remove(0);
+        let (mut framed, _) = protocol
+            .upgrade_inbound(Cursor::new(frame), id)
+            .await
+            .expect("the upgrade always succeeds");
+
+        format!("{:?}", framed.next().await)
+    }
+
+    // A block whose content is changed after it was signed, such as by a peer passing it on.  Gossipsub drops it,
+    // which is why bad signatures can't be counted
+    #[tokio::test]
+    async fn a_message_changed_after_it_was_signed_is_dropped() {
+        let config = test_util::config();
+        let topic = Topics::new(&config.chain_id).block.hash();
+        let block = next_block(&[test_util::genesis(&config)], &config, &["genuine"]);
+        let mut tampered = block.clone();
+        tampered.data = test_util::entries(&["tampered"]);
+        let (genuine, tampered) = (
+            Message::Block(block).encode(),
+            Message::Block(tampered).encode(),
+        );
+
+        let read = read_rpc(signed_rpc(&topic, &genuine, &genuine)).await;
+        assert!(read.contains("invalid_messages: []"), "{}", read);
+
+        let read = read_rpc(signed_rpc(&topic, &genuine, &tampered)).await;
+        assert!(read.contains("InvalidSignature"), "{}", read);
+    }
+
+    fn topic_hashes(topics: &Topics) -> HashSet<TopicHash> {
+        [
+            &topics.chain,
+            &topics.block,
+            &topics.data,
+            &topics.transaction,
+        ]
+        .iter()
+        .map(|topic| topic.hash())
+        .collect()
+    }
+
+    // Nodes on two networks only subscribe to the topics of their own, so gossipsub never passes one network's
+    // messages to the other
+    #[tokio::test]
+    async fn networks_with_different_chain_ids_share_no_topics() {
+        assert_ne!(Topics::new("a").block.hash(), Topics::new("b").block.hash());
+
+        let on_chain = |chain_id: &str| Config {
+            chain_id: String::from(chain_id),
+            ..test_util::config()
+        };
+        let (a, _mined_a) = node(on_chain("a")).await;
+        let (b, _mined_b) = node(on_chain("b")).await;
+        let subscribed =
+            |node: &AppBehaviour| node.gossipsub.topics().cloned().collect::<HashSet<_>>();
+
+        assert_eq!(subscribed(&a), topic_hashes(&a.topics));
+        assert_eq!(subscribed(&b), topic_hashes(&b.topics));
+        assert!(topic_hashes(&a.topics).is_disjoint(&topic_hashes(&b.topics)));
+    }
+
+    // The limit for blocks is --max-data-size and then some, while the chain topic only carries chain resets.  An
+    // oversized message is dropped before it is decoded, and counted against the peer that published it
+    #[tokio::test]
+    async fn oversized_messages_are_dropped_unread() {
+        let config = Config {
+            max_data_size: 1000,
+            ..test_util::config()
+        };
+        let (mut behaviour, _mined) = node(config).await;
+        let peer = PeerId::random();
+        let block = {
+            let app = behaviour.app();
+            let entry = "x".repeat(1000 + MESSAGE_OVERHEAD);
+            next_block(app.chain(), &app.config, &[&entry])
+        };
+
+        gossip(&mut behaviour, peer, Message::Block(block).encode());
+        assert_eq!(behaviour.oversized_dropped, 1);
+        assert_eq!(behaviour.peer_book.oversized_messages(&peer), 1);
+        assert_eq!(behaviour.bans.strikes(&peer), 1);
+        assert_eq!(behaviour.app().height(), 0);
+
+        // Too big for the chain topic, but not for the block topic, where it is read and found to be garbage
+        let garbage = vec![0xff; MAX_CONTROL_MESSAGE_SIZE + 1];
+        let on_chain_topic = GossipsubMessage {
+            source: Some(peer),
+            topic: behaviour.topics.chain.hash(),
+            ..received(garbage.clone())
+        };
+        behaviour.inject_event(GossipsubEvent::Message {
+            propagation_source: peer,
+            message_id: MessageId::from("chain"),
+            message: on_chain_topic,
+        });
+        assert_eq!(behaviour.oversized_dropped, 2);
+
+        gossip(&mut behaviour, peer, garbage);
+        assert_eq!(behaviour.oversized_dropped, 2);
+        assert_eq!(behaviour.peer_book.oversized_messages(&peer), 2);
+        assert_eq!(behaviour.bans.strikes(&peer), 3);
+    }
+
+    // The node asks for chunks of 4, and the peer only sends chunks of 2, so the chain comes in 4 chunks rather than 2
+    #[tokio::test]
+    async fn chunks_are_no_bigger_than_the_sender_allows() {
+        let with_chunks = |size| Config {
+            sync_chunk_size: size,
+            ..test_util::config()
+        };
+        let genesis = [test_util::genesis(&test_util::config())];
+        let chain = grow(&genesis, &test_util::config(), 8, "entry");
+        let (mut syncing, _mined) = node(with_chunks(4)).await;
+        let (peer_node, _peer_mined) = node(with_chunks(2)).await;
+        assert!(peer_node.app_mut().switch_chain(chain.clone()));
+        let peer = PeerId::random();
+
+        syncing.request_chain(peer);
+        let responses = answer_sync(&mut syncing, &peer_node, peer, 0);
+
+        assert_eq!(responses.len(), 4);
+        assert!(responses
+            .iter()
+            .all(|response| response.chunk_size == 2 && response.blocks.len() == 2));
+        assert_eq!(syncing.app().chain(), &chain[..]);
+
+        // A peer that sends bigger chunks than were asked for isn't listened to
+        let (mut syncing, _mined) = node(with_chunks(2)).await;
+        let (peer_node, _peer_mined) = node(with_chunks(4)).await;
+        assert!(peer_node.app_mut().switch_chain(chain.clone()));
+
+        syncing.request_chain(peer);
+        let request = ChainRequest {
+            chunk_size: 4,
+            ..chain_request(&syncing, 0, 0, None)
+        };
+        syncing.receive_chain(peer_node.chain_chunk(&request, &PeerId::random()), &peer);
+        assert!(syncing.sync_sessions.is_empty());
+        assert_eq!(syncing.bans.strikes(&peer), 1);
+        assert_eq!(syncing.app().height(), 0);
     }
 }
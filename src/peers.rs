@@ -0,0 +1,105 @@
+use crate::{connections::ConnectionCounts, latency::PeerLatencyStats};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Addresses remembered for each peer, so that a peer advertising a great many can't use up memory
+pub const MAX_PEER_ADDRS: usize = 8;
+
+// Blocks a peer has sent on the block topic or in answer to a block request, whether or not they were added
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PeerBlocks {
+    pub received: u64,
+    pub rejected: u64,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// What the node has learned about each peer, beyond the connections it holds
+//
+// Addresses come from mDNS, from the addresses the peer listens on according to identify, and from the addresses the
+// node has dialled it at.  These and the block counts are kept after the peer disconnects, so that `ls p` still has
+// something to say about a peer that is only discovered
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Default)]
+pub struct PeerBook {
+    addrs: HashMap<PeerId, Vec<Multiaddr>>,
+    blocks: HashMap<PeerId, PeerBlocks>,
+    oversized: HashMap<PeerId, u64>,
+}
+
+impl PeerBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The peer id on the end of a dialled address is left off, since it is the peer's in any case
+    pub fn add_addr(&mut self, peer: PeerId, mut addr: Multiaddr) {
+        if let Some(Protocol::P2p(_)) = addr.iter().last() {
+            addr.pop();
+        }
+
+        let addrs = self.addrs.entry(peer).or_default();
+
+        if addrs.len() < MAX_PEER_ADDRS && !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    pub fn addrs(&self, peer: &PeerId) -> &[Multiaddr] {
+        self.addrs.get(peer).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn received(&mut self, peer: PeerId) {
+        self.blocks.entry(peer).or_default().received += 1;
+    }
+
+    pub fn rejected(&mut self, peer: PeerId) {
+        self.blocks.entry(peer).or_default().rejected += 1;
+    }
+
+    pub fn blocks(&self, peer: &PeerId) -> PeerBlocks {
+        self.blocks.get(peer).copied().unwrap_or_default()
+    }
+
+    // A message from the peer has been dropped for being too big to read
+    pub fn oversized(&mut self, peer: PeerId) {
+        *self.oversized.entry(peer).or_default() += 1;
+    }
+
+    pub fn oversized_messages(&self, peer: &PeerId) -> u64 {
+        self.oversized.get(peer).copied().unwrap_or_default()
+    }
+}
+
+// One peer as `ls p` shows it
+#[derive(Debug, Serialize)]
+pub struct PeerInfo {
+    pub peer: String,
+    pub connected: bool,
+    pub inbound: u32,
+    pub outbound: u32,
+    pub connected_secs: Option<u64>,
+    pub addrs: Vec<String>,
+    pub blocks: PeerBlocks,
+    pub oversized_messages: u64,
+    pub agent_version: Option<String>,
+    pub protocol_version: Option<String>,
+    pub observed_addr: Option<String>,
+    pub ping: Option<PeerLatencyStats>,
+    pub strikes: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BannedPeer {
+    pub peer: String,
+    // Unset for a ban that lasts until `unban`
+    pub remaining_secs: Option<u64>,
+}
+
+// Everything `ls p --json` prints
+#[derive(Debug, Serialize)]
+pub struct PeerList {
+    pub connections: ConnectionCounts,
+    pub peers: Vec<PeerInfo>,
+    pub banned: Vec<BannedPeer>,
+}
@@ -0,0 +1,85 @@
+use std::{iter::Peekable, str::Chars};
+
+// Longer values are cut short when displayed
+const MAX_DISPLAY_CHARS: usize = 200;
+
+const ESC: char = '\u{1b}';
+// Single character form of ESC [
+const CSI: char = '\u{9b}';
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Makes text received from peers safe to print to a terminal
+//
+// ANSI control sequences (ESC [ ... or CSI ...) are removed, and any other control character, or character that
+// changes the direction of the text, is escaped as it would be in Rust source.  Values longer than MAX_DISPLAY_CHARS
+// are cut short, with a note of their full length in bytes
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub fn sanitize(text: &str) -> String {
+    let mut shown = String::new();
+    let mut chars = text.chars().peekable();
+    let mut count = 0;
+
+    while let Some(c) = chars.next() {
+        if count == MAX_DISPLAY_CHARS {
+            shown.push_str(&format!("… ({} bytes)", text.len()));
+            break;
+        }
+
+        match c {
+            ESC if chars.peek() == Some(&'[') => {
+                chars.next();
+                skip_control_sequence(&mut chars);
+            }
+            CSI => skip_control_sequence(&mut chars),
+            c if c.is_control() || is_bidi_control(c) => shown.extend(c.escape_default()),
+            c => shown.push(c),
+        }
+
+        count += 1;
+    }
+
+    shown
+}
+
+// Parameter and intermediate bytes, up to and including the final byte
+fn skip_control_sequence(chars: &mut Peekable<Chars>) {
+    for c in chars.by_ref() {
+        if !('\u{20}'..='\u{3f}').contains(&c) {
+            break;
+        }
+    }
+}
+
+// These can make text display in a different order from the one it is stored in
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ansi_control_sequences_are_removed() {
+        assert_eq!(sanitize("\u{1b}[31;1mred\u{1b}[0m text"), "red text");
+        assert_eq!(sanitize("\u{9b}2Jcleared"), "cleared");
+    }
+
+    #[test]
+    fn control_and_direction_characters_are_escaped() {
+        assert_eq!(sanitize("two\nlines"), "two\\nlines");
+        assert_eq!(sanitize("abc\u{202e}fed"), "abc\\u{202e}fed");
+    }
+
+    #[test]
+    fn long_values_are_cut_short_with_their_length() {
+        let fits = "a".repeat(MAX_DISPLAY_CHARS);
+        assert_eq!(sanitize(&fits), fits);
+
+        let long = "é".repeat(MAX_DISPLAY_CHARS + 50);
+        assert_eq!(
+            sanitize(&long),
+            format!("{}… ({} bytes)", "é".repeat(MAX_DISPLAY_CHARS), long.len())
+        );
+    }
+}
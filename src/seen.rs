@@ -0,0 +1,95 @@
+use crate::hash::BlockHash;
+use std::collections::{BTreeMap, HashMap};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Hashes of blocks that have already been dealt with
+//
+// Every peer that relays a block sends its own copy, so remembering which blocks have been handled lets the copies be
+// dropped without validating them again.  Once full, the least recently seen hash is forgotten first
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct SeenBlocks {
+    capacity: usize,
+    last_seen: HashMap<BlockHash, u64>,
+    by_age: BTreeMap<u64, BlockHash>,
+    clock: u64,
+}
+
+impl SeenBlocks {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_seen: HashMap::new(),
+            by_age: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    // Returns true if the hash has been seen before, in which case it becomes the most recently seen
+    pub fn check(&mut self, hash: &BlockHash) -> bool {
+        let Some(seen) = self.last_seen.get_mut(hash) else {
+            return false;
+        };
+
+        self.clock += 1;
+        self.by_age.remove(seen);
+        self.by_age.insert(self.clock, *hash);
+        *seen = self.clock;
+        true
+    }
+
+    pub fn insert(&mut self, hash: BlockHash) {
+        if self.capacity == 0 || self.check(&hash) {
+            return;
+        }
+
+        self.clock += 1;
+        self.last_seen.insert(hash, self.clock);
+        self.by_age.insert(self.clock, hash);
+
+        while self.last_seen.len() > self.capacity {
+            let (_, oldest) = self.by_age.pop_first().expect("cache is not empty");
+            self.last_seen.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> BlockHash {
+        BlockHash::from([byte; 32])
+    }
+
+    #[test]
+    fn only_hashes_inserted_have_been_seen() {
+        let mut seen = SeenBlocks::new(2);
+
+        seen.insert(hash(1));
+        assert!(seen.check(&hash(1)));
+        assert!(!seen.check(&hash(2)));
+    }
+
+    #[test]
+    fn the_least_recently_seen_hash_is_forgotten_first() {
+        let mut seen = SeenBlocks::new(2);
+
+        seen.insert(hash(1));
+        seen.insert(hash(2));
+        // Seeing the first again makes the second the oldest
+        assert!(seen.check(&hash(1)));
+        seen.insert(hash(3));
+
+        assert!(seen.check(&hash(1)));
+        assert!(!seen.check(&hash(2)));
+        assert!(seen.check(&hash(3)));
+    }
+
+    #[test]
+    fn nothing_is_remembered_without_room() {
+        let mut seen = SeenBlocks::new(0);
+
+        seen.insert(hash(1));
+        assert!(!seen.check(&hash(1)));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{
+    block::Block,
+    chain::chain_work,
+    hash::BlockHash,
+    ledger::{Account, Ledger},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// State of the stored chain as of its tip, so that a node can start without validating the whole chain again
+//
+// The hash covers every stored block in full along with the other fields, so a snapshot is only trusted for the
+// exact chain it was taken of, and any change made to the file since it was written is noticed.  A snapshot is
+// written by the `snapshot` command, and whenever the node shuts down cleanly
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub height: u64,
+    pub tip_hash: BlockHash,
+    pub chain_work: String,
+    // Balance and nonce of every account that has been used, as of the tip
+    pub accounts: BTreeMap<String, Account>,
+    pub hash: BlockHash,
+}
+
+// Why a snapshot can't be trusted for the stored chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    // Taken of a different tip, which is expected after a crash, or after the stored chain has been replaced
+    WrongTip { height: u64, hash: BlockHash },
+    HashMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::WrongTip { height, hash } => write!(
+                f,
+                "it was taken at block {} {}, which isn't the tip of the stored chain",
+                height, hash
+            ),
+            Self::HashMismatch => write!(
+                f,
+                "its hash doesn't match the stored chain and the balances it records - has it been tampered with?"
+            ),
+        }
+    }
+}
+
+impl Snapshot {
+    // `chain` must be the chain as stored, with the data of every block
+    pub fn take(chain: &[Block], ledger: &Ledger) -> Self {
+        let tip = &chain.last().expect("a stored chain has a block").header;
+        let mut snapshot = Self {
+            height: tip.id,
+            tip_hash: tip.hash,
+            chain_work: chain_work(chain).to_string(),
+            accounts: ledger
+                .accounts()
+                .map(|(name, account)| (name.clone(), *account))
+                .collect(),
+            hash: BlockHash::ZERO,
+        };
+
+        snapshot.hash = snapshot.digest(chain);
+        snapshot
+    }
+
+    // None if no snapshot has been written
+    pub fn read(path: &Path) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Written to a temporary file first, so that a crash part way through leaves the old snapshot in place
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+
+        fs::write(&temp_path, json)?;
+        fs::rename(&temp_path, path)
+    }
+
+    // Checks that the snapshot was taken of exactly `chain`, as stored
+    pub fn verify(&self, chain: &[Block]) -> Result<(), SnapshotError> {
+        let tip = &chain.last().expect("a stored chain has a block").header;
+
+        if (tip.id, tip.hash) != (self.height, self.tip_hash) {
+            return Err(SnapshotError::WrongTip {
+                height: self.height,
+                hash: self.tip_hash,
+            });
+        }
+
+        if self.digest(chain) != self.hash {
+            return Err(SnapshotError::HashMismatch);
+        }
+
+        Ok(())
+    }
+
+    pub fn ledger(&self) -> Ledger {
+        Ledger::from_accounts(self.accounts.clone())
+    }
+
+    fn digest(&self, chain: &[Block]) -> BlockHash {
+        let mut hasher = Sha256::new();
+
+        for block in chain {
+            hasher.update(bincode::serialize(block).expect("can encode block"));
+        }
+
+        let fields = (self.height, &self.tip_hash, &self.chain_work, &self.accounts);
+        hasher.update(bincode::serialize(&fields).expect("can encode snapshot"));
+
+        BlockHash::from(<[u8; 32]>::from(hasher.finalize()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{config, genesis, grow, temp_path, MINER};
+
+    fn snapshot_of(chain: &[Block]) -> Snapshot {
+        let ledger = Ledger::new(&[]).replay(chain).expect("the chain is valid");
+
+        Snapshot::take(chain, &ledger)
+    }
+
+    #[test]
+    fn a_snapshot_read_back_is_trusted_until_its_file_is_changed() {
+        let config = config();
+        let chain = grow(&[genesis(&config)], &config, 3, "entry");
+        let path = temp_path("snapshot.json");
+
+        snapshot_of(&chain)
+            .write(&path)
+            .expect("can write the snapshot");
+        let read = || {
+            Snapshot::read(&path)
+                .expect("can read the snapshot")
+                .expect("the snapshot has been written")
+        };
+
+        let snapshot = read();
+        assert_eq!(snapshot.verify(&chain), Ok(()));
+        assert_eq!(snapshot.ledger().account(MINER).balance, 150);
+
+        // One digit of the miner's balance changed in the file
+        let json = fs::read_to_string(&path).expect("can read the file");
+        fs::write(&path, json.replace("\"balance\": 150", "\"balance\": 159"))
+            .expect("can write the file");
+        let richer = read();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(richer.ledger().account(MINER).balance, 159);
+        assert_eq!(richer.verify(&chain), Err(SnapshotError::HashMismatch));
+    }
+
+    #[test]
+    fn a_snapshot_is_only_trusted_for_the_chain_it_was_taken_of() {
+        let config = config();
+        let chain = grow(&[genesis(&config)], &config, 3, "entry");
+        let snapshot = snapshot_of(&chain);
+
+        let mut tampered = chain.clone();
+        tampered[1].data[0].push('!');
+        assert_eq!(snapshot.verify(&tampered), Err(SnapshotError::HashMismatch));
+
+        let shorter = &chain[..3];
+        assert_eq!(
+            snapshot.verify(shorter),
+            Err(SnapshotError::WrongTip {
+                height: 3,
+                hash: chain[3].header.hash,
+            })
+        );
+    }
+}
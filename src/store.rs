@@ -0,0 +1,182 @@
+use crate::Block;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+// Somewhere the chain can be kept between runs
+pub trait ChainStore: Send + Sync {
+    // The stored blocks in chain order.  Anything after the last block that can be read in full is removed
+    fn load(&mut self) -> io::Result<Vec<Block>>;
+
+    fn append(&mut self, block: &Block) -> io::Result<()>;
+
+    // Replaces the stored blocks from `height` onwards with `blocks` in a single step, so that a crash part way through
+    // leaves the old ones in place.  The blocks below `height` are kept as they were stored, so they never lose data
+    // that has only been pruned from memory
+    fn replace_from(&mut self, height: usize, blocks: &[Block]) -> io::Result<()>;
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Chain stored in an append-only file
+//
+// Each block is stored as a big-endian u32 length, followed by that many bytes of the bincode encoding of the block.
+// A crash while a block is being appended can only leave a partial record at the end of the file, which is cut off
+// the next time the file is loaded.
+//
+// The file is locked for as long as the store is open, so that two nodes can't share it
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    // Held only for its lock
+    _lock: File,
+}
+
+impl FileStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let lock = File::create(with_extension(path, "lock"))?;
+
+        lock.try_lock().map_err(|_| {
+            io::Error::other(
+                "in use by another node - give this one a different --db-path, or run it with --ephemeral",
+            )
+        })?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file: open_for_append(path)?,
+            _lock: lock,
+        })
+    }
+}
+
+impl ChainStore for FileStore {
+    fn load(&mut self) -> io::Result<Vec<Block>> {
+        let bytes = fs::read(&self.path)?;
+        let mut blocks = vec![];
+        let mut offset = 0;
+
+        while let Some(encoded) = encoded_block(&bytes, offset) {
+            let Ok(block) = bincode::deserialize::<Block>(encoded) else {
+                break;
+            };
+
+            blocks.push(block);
+            offset += 4 + encoded.len();
+        }
+
+        if offset < bytes.len() {
+            log::warn!(
+                "removing {} bytes of unreadable or partly written data from the end of {}",
+                bytes.len() - offset,
+                self.path.display()
+            );
+            self.file.set_len(offset as u64)?;
+        }
+
+        Ok(blocks)
+    }
+
+    fn append(&mut self, block: &Block) -> io::Result<()> {
+        self.file.write_all(&record(block)?)?;
+        self.file.sync_data()
+    }
+
+    fn replace_from(&mut self, height: usize, blocks: &[Block]) -> io::Result<()> {
+        let bytes = fs::read(&self.path)?;
+        let mut kept = 0;
+
+        for _ in 0..height {
+            let encoded = encoded_block(&bytes, kept)
+                .ok_or_else(|| io::Error::other(format!("fewer than {} blocks are stored", height)))?;
+            kept += 4 + encoded.len();
+        }
+
+        let temp_path = with_extension(&self.path, "tmp");
+        let mut temp = File::create(&temp_path)?;
+
+        temp.write_all(&bytes[..kept])?;
+
+        for block in blocks {
+            temp.write_all(&record(block)?)?;
+        }
+
+        temp.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+        self.file = open_for_append(&self.path)?;
+        Ok(())
+    }
+}
+
+// The encoded block in the record starting at `offset`, or None if there isn't a whole record there
+fn encoded_block(bytes: &[u8], offset: usize) -> Option<&[u8]> {
+    let prefix = bytes.get(offset..offset + 4)?;
+    let length = u32::from_be_bytes(prefix.try_into().expect("four bytes")) as usize;
+
+    bytes.get(offset + 4..offset + 4 + length)
+}
+
+fn record(block: &Block) -> io::Result<Vec<u8>> {
+    let encoded = bincode::serialize(block).map_err(io::Error::other)?;
+    let mut record = Vec::with_capacity(4 + encoded.len());
+
+    record.extend((encoded.len() as u32).to_be_bytes());
+    record.extend(encoded);
+    Ok(record)
+}
+
+fn open_for_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// chain.db becomes chain.db.lock, rather than chain.lock
+pub fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{config, genesis, grow, temp_path};
+
+    #[test]
+    fn garbage_after_the_last_whole_block_is_cut_off() {
+        let config = config();
+        let chain = grow(&[genesis(&config)], &config, 3, "stored");
+        let path = temp_path("garbage.db");
+        let _ = fs::remove_file(&path);
+
+        let mut store = FileStore::open(&path).expect("can open the store");
+        for block in &chain {
+            store.append(block).expect("can append a block");
+        }
+        let length = fs::metadata(&path).expect("the file exists").len();
+        drop(store);
+
+        // Part of a record, as a crash while appending would leave, followed by bytes that aren't a record at all
+        let mut garbage = record(&chain[1]).expect("can encode a block");
+        garbage.truncate(20);
+        garbage.extend([0xff; 7]);
+        open_for_append(&path)
+            .and_then(|mut file| file.write_all(&garbage))
+            .expect("can append garbage");
+
+        let mut store = FileStore::open(&path).expect("can reopen the store");
+        assert_eq!(store.load().expect("can load the chain"), chain);
+        assert_eq!(fs::metadata(&path).expect("the file exists").len(), length);
+
+        // Blocks appended after the garbage was removed are read back too
+        let next = grow(&chain, &config, 1, "appended");
+        store.append(&next[4]).expect("can append a block");
+        assert_eq!(store.load().expect("can load the chain"), next);
+
+        drop(store);
+        let _ = fs::remove_file(with_extension(&path, "lock"));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,763 @@
+use crate::{block::Block, hash::BlockHash};
+use async_trait::async_trait;
+use libp2p::{
+    core::{
+        upgrade::{read_length_prefixed, write_length_prefixed},
+        ProtocolName,
+    },
+    futures::{AsyncRead, AsyncWrite, AsyncWriteExt},
+};
+use libp2p::{request_response::RequestId, PeerId};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+    time::{Duration, Instant},
+};
+
+// Longest request that is read.  A request only carries a chain id, a few numbers and two hashes
+const MAX_REQUEST_SIZE: usize = 1024;
+
+// A chain being fetched is given up on once no chunk of it has arrived for this long
+pub const SYNC_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Once one peer has answered at startup, the others asked have this much longer to answer too
+pub const INITIAL_SYNC_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct SyncProtocol;
+
+impl ProtocolName for SyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/minimalchain/sync/4"
+    }
+}
+
+// Asks one peer for a chunk of the blocks on its chain above `from_height`, provided its block at that height is
+// `from_hash`.  Asking from height 0 with the hash of the genesis block fetches the whole chain.  Chunk `sequence`
+// holds the `chunk_size` blocks that follow the ones in the chunk before it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainRequest {
+    pub chain_id: String,
+    // Chosen by the node asking, and echoed in the response, so that chunks of different chains don't get mixed up
+    pub session: u64,
+    pub sequence: u64,
+    pub chunk_size: u64,
+    pub from_height: u64,
+    pub from_hash: BlockHash,
+    // Unset for the first chunk.  Every later chunk is taken from the chain ending in the tip named by the response to
+    // the first chunk, so that the chunks all belong to the same chain even if the peer's tip moves on
+    pub tip_hash: Option<BlockHash>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainResponse {
+    // The chain id of the sender, checked before any of the blocks are looked at.  A peer on another chain answers
+    // with its chain id and no blocks
+    pub chain_id: String,
+    // Echoed from the request
+    pub session: u64,
+    pub sequence: u64,
+    pub from_height: u64,
+    // False if the sender's block at `from_height` isn't the one asked about, or it has no block at that height.
+    // Either its chain forks off lower down, or it is behind, and no blocks are sent.  Also false if the tip a later
+    // chunk was asked for from is no longer on the sender's chain
+    pub matched: bool,
+    // The tip the chunks are taken from, so that a sender that is only behind can be told apart from one that has
+    // forked
+    pub tip_hash: BlockHash,
+    // Number of blocks between `from_height` and the tip, across every chunk
+    pub total: u64,
+    // The number of blocks in each chunk but the last.  No more than was asked for, and less if the sender sends
+    // smaller chunks than that, so the chunks are counted by this rather than by the size asked for
+    pub chunk_size: u64,
+    // The blocks of this chunk, oldest first
+    pub blocks: Vec<Block>,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// A chain being fetched from one peer, a chunk at a time
+//
+// Chunks are asked for a few at a time, so they can arrive in any order.  A chunk that arrives before the ones ahead of
+// it waits until they are there, and the chunks are added to `chain` in order, so that they can be validated while the
+// rest are still on their way
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct SyncSession {
+    pub peer: PeerId,
+    pub from_height: u64,
+    pub tip_hash: BlockHash,
+    pub chunk_size: u64,
+    pub total: u64,
+    // Sequence number of the next chunk to ask for
+    pub next_request: u64,
+    // Sequence number of the next chunk to add to `chain`
+    next_chunk: u64,
+    // Chunks that arrived before the ones ahead of them, keyed by sequence number
+    waiting: BTreeMap<u64, Vec<Block>>,
+    // The local blocks up to `from_height`, followed by every chunk added so far
+    pub chain: Vec<Block>,
+    last_received: Instant,
+}
+
+impl SyncSession {
+    // `local` is the local chain up to and including `from_height`
+    pub fn new(peer: PeerId, response: &ChainResponse, local: Vec<Block>) -> Self {
+        Self {
+            peer,
+            from_height: response.from_height,
+            tip_hash: response.tip_hash,
+            chunk_size: response.chunk_size,
+            total: response.total,
+            next_request: 1,
+            next_chunk: 0,
+            waiting: BTreeMap::new(),
+            chain: local,
+            last_received: Instant::now(),
+        }
+    }
+
+    pub fn chunk_count(&self) -> u64 {
+        self.total.div_ceil(self.chunk_size)
+    }
+
+    // Chunks asked for that haven't arrived yet
+    pub fn in_flight(&self) -> u64 {
+        self.next_request - self.next_chunk - self.waiting.len() as u64
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_chunk == self.chunk_count()
+    }
+
+    pub fn has_expired(&self) -> bool {
+        self.last_received.elapsed() >= SYNC_SESSION_TIMEOUT
+    }
+
+    // Returns the index in `chain` of the first block added, if the chunk let any chunks be added.  A chunk that
+    // wasn't asked for, has already arrived, or doesn't hold the number of blocks it should is an error
+    pub fn receive(&mut self, sequence: u64, blocks: Vec<Block>) -> Result<Option<usize>, String> {
+        if sequence >= self.next_request || sequence < self.next_chunk || self.waiting.contains_key(&sequence) {
+            return Err(format!("chunk {} wasn't asked for", sequence));
+        }
+
+        let expected = self.chunk_size.min(self.total - sequence * self.chunk_size);
+
+        if blocks.len() as u64 != expected {
+            return Err(format!(
+                "chunk {} holds {} blocks rather than {}",
+                sequence,
+                blocks.len(),
+                expected
+            ));
+        }
+
+        self.last_received = Instant::now();
+        self.waiting.insert(sequence, blocks);
+
+        let start = self.chain.len();
+
+        while let Some(blocks) = self.waiting.remove(&self.next_chunk) {
+            self.chain.extend(blocks);
+            self.next_chunk += 1;
+        }
+
+        Ok((self.chain.len() > start).then_some(start))
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Chain sync between two peers
+//
+// Rather than being broadcast, a request for a chain goes to one peer, and the part of its chain the peer that asked
+// doesn't have comes straight back to it, a chunk at a time.  Requests and responses are each sent as a length prefixed
+// bincode encoding.  A chunk longer than `max_response_size` is dropped unread, which the peer that asked sees as a
+// failed request
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone)]
+pub struct SyncCodec {
+    pub max_response_size: usize,
+}
+
+#[async_trait]
+impl libp2p::request_response::RequestResponseCodec for SyncCodec {
+    type Protocol = SyncProtocol;
+    type Request = ChainRequest;
+    type Response = ChainResponse;
+
+    async fn read_request<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<ChainRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &SyncProtocol, io: &mut T) -> io::Result<ChainResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, self.max_response_size).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        request: ChainRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        response: ChainResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &response).await
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Fetching an announced block
+//
+// A block that has only been announced is asked for from one peer that announced it, and comes straight back to the
+// peer that asked rather than being published to everyone.  A peer that doesn't have the block drops the request,
+// which the peer that asked sees as a failed request
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone)]
+pub struct BlockProtocol;
+
+impl ProtocolName for BlockProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/minimalchain/block/1"
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockRequest {
+    pub hash: BlockHash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockResponse {
+    pub block: Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockCodec {
+    pub max_response_size: usize,
+}
+
+#[async_trait]
+impl libp2p::request_response::RequestResponseCodec for BlockCodec {
+    type Protocol = BlockProtocol;
+    type Request = BlockRequest;
+    type Response = BlockResponse;
+
+    async fn read_request<T>(&mut self, _: &BlockProtocol, io: &mut T) -> io::Result<BlockRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, MAX_REQUEST_SIZE).await
+    }
+
+    async fn read_response<T>(&mut self, _: &BlockProtocol, io: &mut T) -> io::Result<BlockResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, self.max_response_size).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &BlockProtocol,
+        io: &mut T,
+        request: BlockRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &BlockProtocol,
+        io: &mut T,
+        response: BlockResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, &response).await
+    }
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// The peers asked for their chain when the node starts, before it mines
+//
+// Up to `max_peers` peers are asked, each in a session of its own, and every chain that comes back goes through the
+// usual fork choice, so the node ends up with the heaviest of them.  The node is done syncing once they have all
+// answered, or INITIAL_SYNC_WINDOW after the first one did, or at `until` if none of them has
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Debug, Clone)]
+pub struct InitialSync {
+    pub until: Instant,
+    max_peers: usize,
+    asked: HashSet<PeerId>,
+    answered: HashSet<PeerId>,
+    first_answer: Option<Instant>,
+    // The peer whose chain the node switched to last
+    pub adopted: Option<PeerId>,
+}
+
+impl InitialSync {
+    pub fn new(timeout: Duration, max_peers: usize) -> Self {
+        Self {
+            until: Instant::now() + timeout,
+            max_peers,
+            asked: HashSet::new(),
+            answered: HashSet::new(),
+            first_answer: None,
+            adopted: None,
+        }
+    }
+
+    // Returns true if the peer is to be asked, which it is only once
+    pub fn ask(&mut self, peer: PeerId) -> bool {
+        self.asked.len() < self.max_peers && self.asked.insert(peer)
+    }
+
+    // Makes room for some other peer to be asked instead
+    pub fn failed(&mut self, peer: &PeerId) {
+        if !self.answered.contains(peer) {
+            self.asked.remove(peer);
+        }
+    }
+
+    pub fn answered(&mut self, peer: PeerId) {
+        if self.asked.contains(&peer) && self.answered.insert(peer) {
+            self.first_answer.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn answers(&self) -> usize {
+        self.answered.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.answered.len() >= self.max_peers
+            || self
+                .first_answer
+                .is_some_and(|first| first.elapsed() >= INITIAL_SYNC_WINDOW)
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.first_answer.is_none() && Instant::now() >= self.until
+    }
+}
+
+// Turns a chain of requests from one peer into a burst of this many answered at once, with one more answered every
+// --chain-response-interval after that, so that a peer that has to ask again straight away, when its fork turns out
+// to go back further than it thought, isn't refused
+const CHAIN_RESPONSE_BURST: f64 = 2.0;
+
+// Why a chain request was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    // The peer has asked for too many chains lately
+    TooOften,
+    // --max-chain-responses are already being sent
+    Busy,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct ChainSent {
+    // Unknown, and so 0, until the first response has been worked out
+    chunk_count: u64,
+    served: HashSet<u64>,
+    // When a chunk was last asked for
+    asked: Instant,
+}
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// How often the node sends chains to each peer, and how many it sends at once
+//
+// Every chain a peer asks for takes a token from its bucket, refilled at one every `interval`, and the request is
+// dropped if there is none left, or if `max_in_flight` responses are already being written to peers.  A peer's first
+// request doesn't take a token, so that a node that has just started gets its chain straight away, and nor does the
+// first request of a peer that has asked for nothing for SYNC_SESSION_TIMEOUT.  The later chunks of a chain already
+// being sent to it don't take a token either, but only once each.  None of them is answered while `max_in_flight`
+// responses are being written
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct ResponseLimiter {
+    interval: Duration,
+    max_in_flight: usize,
+    buckets: HashMap<PeerId, Bucket>,
+    // When each peer last asked for a chain
+    answered: HashMap<PeerId, Instant>,
+    // The chains being sent, by peer and the session it chose
+    chains: HashMap<(PeerId, u64), ChainSent>,
+    in_flight: HashSet<RequestId>,
+    pub too_often: u64,
+    pub busy: u64,
+}
+
+impl ResponseLimiter {
+    pub fn new(interval: Duration, max_in_flight: usize) -> Self {
+        Self {
+            interval,
+            max_in_flight,
+            buckets: HashMap::new(),
+            answered: HashMap::new(),
+            chains: HashMap::new(),
+            in_flight: HashSet::new(),
+            too_often: 0,
+            busy: 0,
+        }
+    }
+
+    pub fn allow(&mut self, peer: PeerId, request: &ChainRequest) -> Result<(), Refusal> {
+        let now = Instant::now();
+        self.chains
+            .retain(|_, chain| now.duration_since(chain.asked) < SYNC_SESSION_TIMEOUT);
+        self.answered
+            .retain(|_, asked| now.duration_since(*asked) < SYNC_SESSION_TIMEOUT);
+
+        let busy = self.in_flight.len() >= self.max_in_flight;
+
+        if let Some(chain) = self.chains.get_mut(&(peer, request.session)) {
+            if request.sequence < chain.chunk_count && !chain.served.contains(&request.sequence) {
+                if busy {
+                    self.busy += 1;
+                    return Err(Refusal::Busy);
+                }
+
+                chain.served.insert(request.sequence);
+                chain.asked = now;
+                return Ok(());
+            }
+        }
+
+        if busy {
+            self.busy += 1;
+            return Err(Refusal::Busy);
+        }
+
+        let first = self.answered.insert(peer, now).is_none();
+
+        if !first && !self.take_token(peer, now) {
+            self.too_often += 1;
+            return Err(Refusal::TooOften);
+        }
+
+        self.start_chain(peer, request, now);
+        Ok(())
+    }
+
+    fn start_chain(&mut self, peer: PeerId, request: &ChainRequest, now: Instant) {
+        let chain = self
+            .chains
+            .entry((peer, request.session))
+            .or_insert_with(|| ChainSent {
+                chunk_count: 0,
+                served: HashSet::new(),
+                asked: now,
+            });
+
+        chain.served.insert(request.sequence);
+        chain.asked = now;
+    }
+
+    // The response to a request that was allowed says how many chunks there are to the chain, so how many more of them
+    // the peer can ask for
+    pub fn answering(&mut self, peer: PeerId, response: &ChainResponse) {
+        if let Some(chain) = self.chains.get_mut(&(peer, response.session)) {
+            chain.chunk_count = match response.chunk_size {
+                0 => 0,
+                size => response.total.div_ceil(size),
+            };
+        }
+    }
+
+    fn take_token(&mut self, peer: PeerId, now: Instant) -> bool {
+        if self.interval.is_zero() {
+            return true;
+        }
+
+        let interval = self.interval;
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.updated) < interval.mul_f64(CHAIN_RESPONSE_BURST));
+
+        let bucket = self.buckets.entry(peer).or_insert(Bucket {
+            tokens: CHAIN_RESPONSE_BURST,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / interval.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(CHAIN_RESPONSE_BURST);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    // A response has been handed to the connection to write
+    pub fn sending(&mut self, id: RequestId) {
+        self.in_flight.insert(id);
+    }
+
+    // It has been written, or failed to be
+    pub fn sent(&mut self, id: &RequestId) {
+        self.in_flight.remove(id);
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.chains.retain(|(chain_peer, _), _| chain_peer != peer);
+    }
+}
+
+async fn read<T, M>(io: &mut T, max_size: usize) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let bytes = read_length_prefixed(io, max_size).await?;
+
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let bytes = bincode::serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    write_length_prefixed(io, bytes).await?;
+    io.close().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, grow};
+    use libp2p::futures::io::Cursor;
+
+    #[tokio::test]
+    async fn a_long_chain_round_trips_through_the_sync_codec() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 1000, "entry");
+        let response = ChainResponse {
+            chain_id: config.chain_id.clone(),
+            session: 1,
+            sequence: 0,
+            from_height: 0,
+            matched: true,
+            tip_hash: chain[1000].header.hash,
+            total: 1000,
+            chunk_size: 1000,
+            blocks: chain[1..].to_vec(),
+        };
+        let mut io = Cursor::new(vec![]);
+
+        write(&mut io, &response).await.expect("can write response");
+
+        io.set_position(0);
+        let decoded: ChainResponse = read(&mut io, usize::MAX).await.expect("can read response");
+        assert_eq!(decoded.tip_hash, response.tip_hash);
+        assert_eq!(decoded.total, response.total);
+        assert_eq!(decoded.blocks, response.blocks);
+
+        // A response larger than the most the reader accepts is dropped unread
+        io.set_position(0);
+        assert!(read::<_, ChainResponse>(&mut io, MAX_REQUEST_SIZE)
+            .await
+            .is_err());
+    }
+
+    // A session fetching the 8 blocks of `chain` after its genesis block, in chunks of 2, with every chunk asked for
+    fn session(chain: &[Block]) -> SyncSession {
+        let response = ChainResponse {
+            chain_id: String::from("test"),
+            session: 1,
+            sequence: 0,
+            from_height: 0,
+            matched: true,
+            tip_hash: chain[8].header.hash,
+            total: 8,
+            chunk_size: 2,
+            blocks: vec![],
+        };
+        let mut session = SyncSession::new(PeerId::random(), &response, chain[..1].to_vec());
+        session.next_request = session.chunk_count();
+        session
+    }
+
+    fn chunk(chain: &[Block], sequence: usize) -> Vec<Block> {
+        chain[sequence * 2 + 1..sequence * 2 + 3].to_vec()
+    }
+
+    #[test]
+    fn chunks_that_arrive_out_of_order_wait_for_the_ones_ahead_of_them() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 8, "entry");
+        let mut session = session(&chain);
+
+        assert_eq!(session.receive(2, chunk(&chain, 2)), Ok(None));
+        assert_eq!(session.receive(3, chunk(&chain, 3)), Ok(None));
+        assert_eq!(session.chain.len(), 1);
+        assert_eq!(session.in_flight(), 2);
+
+        // Chunk 0 lets only itself be added, and chunk 1 the rest
+        assert_eq!(session.receive(0, chunk(&chain, 0)), Ok(Some(1)));
+        assert_eq!(session.chain, &chain[..3]);
+        assert_eq!(session.receive(1, chunk(&chain, 1)), Ok(Some(3)));
+        assert_eq!(session.chain, chain);
+        assert!(session.is_complete());
+
+        // Nor can a chunk be sent twice
+        assert!(session.receive(2, chunk(&chain, 2)).is_err());
+    }
+
+    #[test]
+    fn a_session_waiting_on_a_missing_chunk_expires() {
+        let config = test_util::config();
+        let chain = grow(&[test_util::genesis(&config)], &config, 8, "entry");
+        let mut session = session(&chain);
+
+        for sequence in [0, 2, 3] {
+            session
+                .receive(sequence as u64, chunk(&chain, sequence))
+                .expect("the chunk was asked for");
+        }
+        assert!(!session.is_complete());
+        assert!(!session.has_expired());
+
+        session.last_received -= SYNC_SESSION_TIMEOUT;
+        assert!(session.has_expired());
+        assert_eq!(session.chain, &chain[..3]);
+    }
+
+    fn chain_request(sequence: u64) -> ChainRequest {
+        ChainRequest {
+            chain_id: String::from("test"),
+            session: 1,
+            sequence,
+            chunk_size: 2,
+            from_height: 0,
+            from_hash: BlockHash::ZERO,
+            tip_hash: None,
+        }
+    }
+
+    // The first chunk of a chain of 6 blocks, sent 2 at a time
+    fn first_chunk() -> ChainResponse {
+        ChainResponse {
+            chain_id: String::from("test"),
+            session: 1,
+            sequence: 0,
+            from_height: 0,
+            matched: true,
+            tip_hash: BlockHash::ZERO,
+            total: 6,
+            chunk_size: 2,
+            blocks: vec![],
+        }
+    }
+
+    // Only the 2 chunks after the first are free, and only once each.  Anything else asked for in the session takes a
+    // token, and there are only 2 to start with
+    #[test]
+    fn each_later_chunk_of_a_chain_is_answered_once_without_a_token() {
+        let mut limiter = ResponseLimiter::new(Duration::from_secs(10), 4);
+        let peer = PeerId::random();
+
+        assert_eq!(limiter.allow(peer, &chain_request(0)), Ok(()));
+        limiter.answering(peer, &first_chunk());
+
+        for sequence in [1, 2, 1, 3] {
+            assert_eq!(limiter.allow(peer, &chain_request(sequence)), Ok(()));
+        }
+        assert_eq!(limiter.too_often, 0);
+
+        assert_eq!(
+            limiter.allow(peer, &chain_request(1)),
+            Err(Refusal::TooOften)
+        );
+        assert_eq!(
+            limiter.allow(peer, &chain_request(2)),
+            Err(Refusal::TooOften)
+        );
+        assert_eq!(limiter.too_often, 2);
+    }
+
+    // Once the chain is being sent, no more responses are allowed at once, as if others were being written
+    #[test]
+    fn later_chunks_count_against_the_responses_in_flight() {
+        let mut limiter = ResponseLimiter::new(Duration::ZERO, 1);
+        let peer = PeerId::random();
+
+        assert_eq!(limiter.allow(peer, &chain_request(0)), Ok(()));
+        limiter.answering(peer, &first_chunk());
+        limiter.max_in_flight = 0;
+
+        assert_eq!(limiter.allow(peer, &chain_request(1)), Err(Refusal::Busy));
+        assert_eq!(limiter.busy, 1);
+    }
+
+    // The first request doesn't take a token, but still waits its turn, and is still the first when it is answered
+    #[test]
+    fn a_first_request_is_dropped_while_too_many_responses_are_in_flight() {
+        let mut limiter = ResponseLimiter::new(Duration::from_secs(3600), 0);
+        let peer = PeerId::random();
+
+        assert_eq!(limiter.allow(peer, &chain_request(0)), Err(Refusal::Busy));
+        assert_eq!(limiter.busy, 1);
+
+        limiter.max_in_flight = 4;
+        for _ in 0..3 {
+            assert_eq!(limiter.allow(peer, &chain_request(0)), Ok(()));
+        }
+        assert_eq!(
+            limiter.allow(peer, &chain_request(0)),
+            Err(Refusal::TooOften)
+        );
+    }
+
+    // A peer is forgotten once it has asked for nothing for a while, and is answered again as if it had just started
+    #[test]
+    fn a_peer_that_has_been_quiet_is_answered_without_a_token() {
+        let mut limiter = ResponseLimiter::new(Duration::from_secs(3600), 4);
+        let peer = PeerId::random();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.allow(peer, &chain_request(0)), Ok(()));
+        }
+        assert_eq!(
+            limiter.allow(peer, &chain_request(0)),
+            Err(Refusal::TooOften)
+        );
+
+        let asked = limiter.answered.get_mut(&peer).expect("the peer has asked");
+        *asked = asked
+            .checked_sub(SYNC_SESSION_TIMEOUT)
+            .expect("the clock goes back far enough");
+
+        assert_eq!(limiter.allow(peer, &chain_request(0)), Ok(()));
+        assert!(limiter.answered.contains_key(&peer));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::hash::BlockHash;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+mod u256 {
+    // The generated code trips a number of style lints that don't apply to us
+    #![allow(clippy::all)]
+
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+
+pub use u256::U256;
+
+// Easiest possible target, used for a difficulty of 0 bits.  In compact form this is 0x2100ffff
+const MAX_TARGET_COMPACT: u32 = 0x2100_ffff;
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Proof of work target
+//
+// A hash meets the target when, read as a big-endian 256-bit integer, it is below the target.  On the wire and in
+// configuration a target is written in the same compact form as Bitcoin's nBits: the high byte is the length of the
+// target in bytes, and the low three bytes are its most significant digits.
+//
+// Every Target is exactly representable in compact form, so converting to and from compact never loses information
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "u32", try_from = "u32")]
+pub struct Target(U256);
+
+impl Target {
+    pub fn max() -> Self {
+        Self::from_compact(MAX_TARGET_COMPACT).expect("maximum target is valid")
+    }
+
+    // Equivalent to requiring `bits` leading zero bits in the hash
+    pub fn from_leading_zero_bits(bits: u32) -> Self {
+        match bits {
+            0 => Self::max(),
+            1..=255 => Self(U256::one() << (256 - bits) as usize),
+            _ => Self(U256::one()),
+        }
+    }
+
+    // Number of leading zero bits guaranteed in any hash that meets this target
+    pub fn leading_zero_bits(self) -> u32 {
+        (self.0 - U256::one()).leading_zeros()
+    }
+
+    pub fn from_compact(compact: u32) -> Result<Self, String> {
+        let size = compact >> 24;
+        let mantissa = U256::from(compact & 0x007f_ffff);
+
+        if compact & 0x0080_0000 != 0 {
+            return Err(format!("compact target {:#010x} is negative", compact));
+        }
+
+        let target = if size <= 3 {
+            mantissa >> (8 * (3 - size) as usize)
+        } else if mantissa.is_zero() || mantissa.bits() + 8 * (size as usize - 3) <= 256 {
+            mantissa << (8 * (size - 3) as usize)
+        } else {
+            return Err(format!(
+                "compact target {:#010x} overflows 256 bits",
+                compact
+            ));
+        };
+
+        if target.is_zero() {
+            Err(format!("compact target {:#010x} is zero", compact))
+        } else {
+            Ok(Self(target))
+        }
+    }
+
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.0.bits().div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            self.0.low_u32() << (8 * (3 - size))
+        } else {
+            (self.0 >> (8 * (size - 3))).low_u32()
+        };
+
+        // The top bit of the mantissa is a sign bit, so a mantissa that would set it moves up a byte
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        mantissa | (size as u32) << 24
+    }
+
+    // Average number of hashes that have to be tried before one meets this target
+    pub fn expected_attempts(self) -> f64 {
+        let U256(limbs) = self.0;
+        let target = limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64);
+
+        2f64.powi(256) / target
+    }
+
+    // Exact number of hashes expected to be tried before one meets this target, 2^256 / target, for totting up the
+    // work done on a chain
+    pub fn work(self) -> U256 {
+        // 2^256 doesn't fit in a U256, but 2^256 - target does, and 2^256 / target is one more than that over target
+        (!self.0 + U256::one()) / self.0 + U256::one()
+    }
+
+    pub fn is_met_by(&self, hash: &BlockHash) -> bool {
+        U256::from_big_endian(hash.as_bytes()) < self.0
+    }
+
+    // Twice as hard to meet as this target
+    pub fn harder(self) -> Self {
+        Self::normalised((self.0 >> 1).max(U256::one()))
+    }
+
+    // Twice as easy to meet as this target, but never easier than the maximum
+    pub fn easier(self) -> Self {
+        let max = Self::max();
+
+        match self.0.checked_mul(U256::from(2)) {
+            Some(target) if target < max.0 => Self::normalised(target),
+            _ => max,
+        }
+    }
+
+    // Round down to the nearest value that can be represented in compact form
+    fn normalised(target: U256) -> Self {
+        Self::from_compact(Self(target).to_compact()).expect("normalised target is valid")
+    }
+}
+
+impl From<Target> for u32 {
+    fn from(target: Target) -> Self {
+        target.to_compact()
+    }
+}
+
+impl TryFrom<u32> for Target {
+    type Error = String;
+
+    fn try_from(compact: u32) -> Result<Self, Self::Error> {
+        Self::from_compact(compact)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010x}", self.to_compact())
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Target({:#010x}, {} leading zero bits)",
+            self.to_compact(),
+            self.leading_zero_bits()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A hash with exactly `bits` leading zero bits, followed by ones
+    fn hash_with_zero_bits(bits: u32) -> BlockHash {
+        let mut bytes = [0xff; 32];
+
+        for bit in 0..bits.min(256) as usize {
+            bytes[bit / 8] &= !(0x80 >> (bit % 8));
+        }
+
+        if bits < 256 {
+            bytes[bits as usize / 8] |= 0x80 >> (bits % 8);
+        }
+
+        BlockHash::from(bytes)
+    }
+
+    #[test]
+    fn a_hash_meets_a_difficulty_of_exactly_its_leading_zero_bits() {
+        for bits in [1, 2, 7, 8, 9, 16, 31, 64, 255] {
+            let target = Target::from_leading_zero_bits(bits);
+
+            assert!(target.is_met_by(&hash_with_zero_bits(bits)));
+            assert!(target.is_met_by(&hash_with_zero_bits(bits + 1)));
+            assert!(!target.is_met_by(&hash_with_zero_bits(bits - 1)));
+            assert_eq!(target.leading_zero_bits(), bits);
+        }
+    }
+
+    // The maximum target is 0xffff followed by zeros, so all it rules out is a hash starting with 16 one bits
+    #[test]
+    fn a_difficulty_of_zero_bits_is_the_maximum_target() {
+        assert_eq!(Target::from_leading_zero_bits(0), Target::max());
+        assert!(Target::max().is_met_by(&hash_with_zero_bits(1)));
+        assert!(!Target::max().is_met_by(&hash_with_zero_bits(0)));
+    }
+
+    // Nothing but the all zero hash meets a difficulty of more than 255 bits
+    #[test]
+    fn a_difficulty_larger_than_256_bits_is_only_met_by_a_zero_hash() {
+        for bits in [256, 257, 1000, u32::MAX] {
+            let target = Target::from_leading_zero_bits(bits);
+
+            assert!(target.is_met_by(&BlockHash::ZERO));
+            assert!(!target.is_met_by(&hash_with_zero_bits(255)));
+        }
+    }
+
+    fn hash_starting_with(prefix: &[u8]) -> BlockHash {
+        let mut bytes = [0; 32];
+
+        bytes[..prefix.len()].copy_from_slice(prefix);
+        BlockHash::from(bytes)
+    }
+
+    // 0x0060 followed by zeros, which is met by every hash with 10 leading zero bits, but only some of those with 9
+    #[test]
+    fn a_target_between_two_prefix_lengths_splits_hashes_with_the_same_leading_zeros() {
+        let target = Target::from_compact(0x1f60_0000).expect("valid compact target");
+
+        assert_eq!(target.leading_zero_bits(), 9);
+        assert!(target.is_met_by(&hash_starting_with(&[0x00, 0x3f, 0xff])));
+        assert!(target.is_met_by(&hash_starting_with(&[0x00, 0x5f, 0xff])));
+        assert!(!target.is_met_by(&hash_starting_with(&[0x00, 0x60])));
+        assert!(!target.is_met_by(&hash_starting_with(&[0x00, 0x7f])));
+        assert!(Target::from_leading_zero_bits(10) < target);
+        assert!(target < Target::from_leading_zero_bits(9));
+    }
+
+    #[test]
+    fn compact_targets_round_trip() {
+        for compact in [
+            0x1d00_ffff,
+            0x1f60_0000,
+            0x2100_ffff,
+            0x0312_3456,
+            0x0112_0000,
+            0x0201_2300,
+        ] {
+            let target = Target::from_compact(compact).expect("valid compact target");
+
+            assert_eq!(target.to_compact(), compact, "{:#010x}", compact);
+        }
+
+        for bits in 0..=256 {
+            let target = Target::from_leading_zero_bits(bits);
+
+            assert_eq!(Target::from_compact(target.to_compact()), Ok(target));
+        }
+    }
+
+    #[test]
+    fn compact_targets_that_are_negative_zero_or_too_big_are_errors() {
+        assert!(Target::from_compact(0x1d80_0000).is_err());
+        assert!(Target::from_compact(0x1d00_0000).is_err());
+        assert!(Target::from_compact(0x0100_0001).is_err());
+        assert!(Target::from_compact(0x22ff_ffff).is_err());
+        assert!(Target::from_compact(0x2101_0000).is_err());
+    }
+
+    // A target harder or easier than one in compact form has to be one in compact form too
+    #[test]
+    fn harder_and_easier_targets_stay_representable() {
+        let target = Target::from_compact(0x1f60_0000).expect("valid compact target");
+
+        assert_eq!(target.harder().to_compact(), 0x1f30_0000);
+        assert_eq!(target.easier().to_compact(), 0x2000_c000);
+        assert_eq!(target.harder().easier(), target);
+        assert_eq!(Target::max().easier(), Target::max());
+        assert_eq!(
+            Target::from_leading_zero_bits(256).harder(),
+            Target::from_leading_zero_bits(256)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Helpers shared by the unit tests
+//
+// Blocks are sealed here rather than with mine_block, so that they can be given whatever timestamp a test needs, and
+// the nonce search starts from zero so the same chain is built every time.  At the default difficulty of 2 bits that
+// only takes a handful of hashes per block
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+use crate::{
+    block::{calculate_hash, create_genesis, Block, BlockHeader, BLOCK_VERSION},
+    chain::{expected_target, App},
+    config::Config,
+    hash::BlockHash,
+    ledger::Transaction,
+    merkle,
+    mining::{stated_difficulty, DEV_FAST_MINE},
+    target::Target,
+    wallet::Wallet,
+};
+use std::{env, path::PathBuf, process};
+
+pub const MINER: &str = "test-miner";
+
+// The default config, without anything stored on disk
+pub fn config() -> Config {
+    Config {
+        db_path: None,
+        wallet_path: None,
+        key_file: None,
+        mining_threads: 1,
+        ..Config::default()
+    }
+}
+
+pub fn app_with(config: Config) -> App {
+    App::new(config).expect("an app without any storage always starts")
+}
+
+pub fn genesis(config: &Config) -> Block {
+    create_genesis(&config.chain_id, &config.genesis, &config.hashing)
+}
+
+// A file in the temporary directory that no other test, or run of the tests, uses
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("minimalchain-{}-{}", process::id(), name))
+}
+
+pub fn entries(data: &[&str]) -> Vec<String> {
+    data.iter().map(|entry| entry.to_string()).collect()
+}
+
+// The data entry for a transfer from the account of `wallet`, signed by it
+pub fn transfer(wallet: &Wallet, to: &str, amount: u64, nonce: u64) -> String {
+    let mut transaction = Transaction {
+        from: wallet.address(),
+        to: to.to_owned(),
+        amount,
+        nonce,
+        public_key: String::new(),
+        signature: String::new(),
+    };
+
+    wallet.sign(&mut transaction);
+    transaction.encode()
+}
+
+// Finds the first nonce, counting up from the one in the header, whose hash meets the target
+pub fn seal(header: &mut BlockHeader, target: Target, config: &Config) {
+    loop {
+        header.hash = calculate_hash(header, &config.hashing);
+
+        if DEV_FAST_MINE || target.is_met_by(&header.hash) {
+            return;
+        }
+
+        header.nonce += 1;
+    }
+}
+
+// A valid block following the last block of `chain`, dated `timestamp`
+pub fn block_at(chain: &[Block], config: &Config, data: &[&str], timestamp: i64) -> Block {
+    let parent = &chain.last().expect("a chain has a genesis block").header;
+    let target = expected_target(chain, chain.len() as u64, config);
+    let data = entries(data);
+    let mut header = BlockHeader {
+        version: BLOCK_VERSION,
+        chain_id: config.chain_id.clone(),
+        id: parent.id + 1,
+        hash: BlockHash::ZERO,
+        previous_hash: parent.hash,
+        timestamp,
+        merkle_root: merkle::merkle_root(&data),
+        nonce: 0,
+        difficulty: stated_difficulty(target),
+        miner: MINER.to_owned(),
+        reward: config.block_reward,
+    };
+
+    seal(&mut header, target, config);
+    Block { header, data }
+}
+
+// A valid block following the last block of `chain`, dated --block-spacing after it
+pub fn next_block(chain: &[Block], config: &Config, data: &[&str]) -> Block {
+    let parent = chain.last().expect("a chain has a genesis block");
+
+    block_at(
+        chain,
+        config,
+        data,
+        parent.header.timestamp + config.block_spacing,
+    )
+}
+
+// `chain` followed by `count` more blocks, each holding a single entry of `label` and its id
+pub fn grow(chain: &[Block], config: &Config, count: usize, label: &str) -> Vec<Block> {
+    let mut chain = chain.to_vec();
+
+    for _ in 0..count {
+        let entry = format!("{} {}", label, chain.len());
+        let block = next_block(&chain, config, &[&entry]);
+        chain.push(block);
+    }
+
+    chain
+}
+
+// `chain` followed by `count` more blocks, each dated `spacing` seconds after the one before
+pub fn grow_with_spacing(
+    chain: &[Block],
+    config: &Config,
+    count: usize,
+    spacing: i64,
+) -> Vec<Block> {
+    let mut chain = chain.to_vec();
+
+    for _ in 0..count {
+        let timestamp = chain[chain.len() - 1].header.timestamp + spacing;
+        let block = block_at(&chain, config, &[], timestamp);
+        chain.push(block);
+    }
+
+    chain
+}
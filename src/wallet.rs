@@ -0,0 +1,78 @@
+use crate::ledger::Transaction;
+use libp2p::{
+    identity::{ed25519, PublicKey},
+    PeerId,
+};
+use std::{fs, io, path::Path};
+
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Key that signs the transactions sent by this node
+//
+// The secret key is kept in hex in the --wallet file, which is created with a new key the first time the node starts.
+// Without a file, a new key is made for every run
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+pub struct Wallet {
+    keypair: ed25519::Keypair,
+}
+
+impl Wallet {
+    pub fn generate() -> Self {
+        Self {
+            keypair: ed25519::Keypair::generate(),
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let bytes = hex::decode(text.trim()).map_err(io::Error::other)?;
+                let secret = ed25519::SecretKey::from_bytes(bytes).map_err(io::Error::other)?;
+
+                Ok(Self {
+                    keypair: secret.into(),
+                })
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let wallet = Self::generate();
+                let secret = ed25519::SecretKey::from(wallet.keypair.clone());
+
+                write_private(path, &hex::encode(secret))?;
+                log::info!("created a new wallet in {}", path.display());
+                Ok(wallet)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn address(&self) -> String {
+        address(&self.keypair.public())
+    }
+
+    pub fn sign(&self, transaction: &mut Transaction) {
+        transaction.public_key = hex::encode(self.keypair.public().encode());
+        transaction.signature = hex::encode(self.keypair.sign(&transaction.signing_bytes()));
+    }
+}
+
+// Accounts are named after the key that controls them, written the same way as a peer id
+pub fn address(key: &ed25519::PublicKey) -> String {
+    PeerId::from_public_key(PublicKey::Ed25519(key.clone())).to_string()
+}
+
+// Only the owner can read the secret key
+#[cfg(unix)]
+pub(crate) fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    use std::{fs::OpenOptions, io::Write, os::unix::fs::OpenOptionsExt};
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    fs::write(path, contents)
+}
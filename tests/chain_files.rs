@@ -0,0 +1,76 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Chains saved to a file by one node with `save` and loaded by another with `load`
+//
+// The nodes aren't connected, so the only way the chain can get from one to the other is through the file
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use common::Node;
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+// A file of its own for each test, removed when the test is done
+struct ChainFile(PathBuf);
+
+impl ChainFile {
+    fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!(
+            "blockchain-demo-{}-{}.json",
+            std::process::id(),
+            name
+        )))
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().expect("the temporary directory is UTF-8")
+    }
+}
+
+impl Drop for ChainFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+// A node that has mined two blocks, and saved its chain to `file`
+fn saved_chain(file: &ChainFile) -> Node {
+    let mut miner = Node::start(&[]);
+    miner.mine("first");
+    miner.mine("second");
+
+    miner.send(&format!("save {}", file.path()));
+    assert!(miner.wait_for(|node| node.logged("saved 3 blocks to")));
+    miner
+}
+
+#[test]
+fn a_saved_chain_loads_on_another_node() {
+    let file = ChainFile::new("round-trip");
+    let mut miner = saved_chain(&file);
+    let tip = miner.tip();
+
+    let mut node = Node::start(&[]);
+    node.send(&format!("load {}", file.path()));
+
+    assert!(node.wait_for(|node| node.logged("switched to the chain in")));
+    assert_eq!(node.tip(), tip);
+}
+
+#[test]
+fn a_chain_file_with_a_tampered_hash_is_not_loaded() {
+    let file = ChainFile::new("tampered");
+    let _miner = saved_chain(&file);
+
+    let mut chain: Value =
+        serde_json::from_str(&fs::read_to_string(&file.0).expect("can read the chain file"))
+            .expect("the chain file is JSON");
+    chain[1]["hash"] = Value::from("00".repeat(32));
+    fs::write(&file.0, chain.to_string()).expect("can write the chain file");
+
+    let mut node = Node::start(&[]);
+    node.send(&format!("load {}", file.path()));
+
+    assert!(node.wait_for(|node| node.logged("not loading the chain in")));
+    assert!(node.logged("block with id: 1"));
+    assert_eq!(node.height(), 0);
+}
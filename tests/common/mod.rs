@@ -0,0 +1,192 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Nodes for the integration tests
+//
+// Each node is the real binary running in a process of its own, since the node key and peer id are global to a
+// process.  Nodes keep nothing on disk, only find each other through the addresses they are given, and listen on
+// whatever port is free on the loopback interface.  They are driven through standard input like any other node, and
+// read back through the JSON printed by `stats --json` and the lines logged
+//
+// The nodes are started with `--difficulty 0`, under which the first nonce tried always meets the target, so they
+// spend no time on proof of work whichever way they were built.  The dev-fast-mine feature can't be switched on for
+// the integration tests alone: Cargo builds the library, the node binary and the unit tests with the same features
+// in one `cargo test`, so it would also turn off the unit tests that check proof of work.  Built with
+// `--features dev-fast-mine`, the nodes skip proof of work altogether
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// Long enough for a node on a loaded machine to connect and sync, which is usually a matter of a few seconds
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Node {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<String>,
+    log: Arc<Mutex<Vec<String>>>,
+    // Where the node is listening, ending in /p2p/<peer id>
+    pub addr: String,
+}
+
+impl Node {
+    pub fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_blockchain-demo"))
+            .args([
+                "--ephemeral",
+                "--no-mdns",
+                "--initial-sync",
+                "0",
+                "--difficulty",
+                "0",
+                "--listen",
+                "/ip4/127.0.0.1/tcp/0",
+            ])
+            .args(args)
+            .env("RUST_LOG", "info")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("can start the node");
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = lines(child.stdout.take().expect("stdout is piped"));
+        let stderr = lines(child.stderr.take().expect("stderr is piped"));
+        let log = Arc::new(Mutex::new(vec![]));
+
+        // Everything logged is kept, so that a test can look back over it
+        let (listening_sender, listening) = mpsc::channel();
+        let kept = log.clone();
+        thread::spawn(move || {
+            for line in stderr {
+                if let Some((_, addr)) = line.split_once("listening on ") {
+                    listening_sender
+                        .send(addr.trim().to_owned())
+                        .unwrap_or_default();
+                }
+
+                kept.lock().expect("log lock").push(line);
+            }
+        });
+
+        let addr = listening
+            .recv_timeout(TIMEOUT)
+            .expect("the node starts listening");
+
+        Self {
+            child,
+            stdin,
+            stdout,
+            log,
+            addr,
+        }
+    }
+
+    pub fn send(&mut self, command: &str) {
+        writeln!(self.stdin, "{}", command).expect("can write to the node");
+    }
+
+    pub fn stats(&mut self) -> Value {
+        self.send("stats --json");
+
+        loop {
+            let line = self
+                .stdout
+                .recv_timeout(TIMEOUT)
+                .expect("the node prints its stats");
+
+            if let Ok(stats) = serde_json::from_str::<Value>(&line) {
+                if stats.get("tip_hash").is_some() {
+                    return stats;
+                }
+            }
+        }
+    }
+
+    pub fn height(&mut self) -> u64 {
+        self.stats()["height"].as_u64().expect("height is a number")
+    }
+
+    pub fn tip(&mut self) -> String {
+        self.stats()["tip_hash"]
+            .as_str()
+            .expect("tip is a string")
+            .to_owned()
+    }
+
+    // Mines a block holding `data`, and waits for it to be added to the chain
+    pub fn mine(&mut self, data: &str) {
+        let height = self.height();
+
+        self.send(&format!("create b {}", data));
+        assert!(
+            self.wait_for(|node| node.height() > height),
+            "the block was mined"
+        );
+    }
+
+    // Checks `done` every 100ms until it is true, or TIMEOUT has passed.  Returns whether it ended up true
+    pub fn wait_for(&mut self, mut done: impl FnMut(&mut Self) -> bool) -> bool {
+        let started = Instant::now();
+
+        while started.elapsed() < TIMEOUT {
+            if done(self) {
+                return true;
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        false
+    }
+
+    pub fn logged(&self, text: &str) -> bool {
+        self.log
+            .lock()
+            .expect("log lock")
+            .iter()
+            .any(|line| line.contains(text))
+    }
+
+    // The address the node has logged it is listening on that contains `part`, such as "/ws", if it has logged one
+    pub fn listening_on(&self, part: &str) -> Option<String> {
+        self.log
+            .lock()
+            .expect("log lock")
+            .iter()
+            .filter_map(|line| line.split_once("listening on "))
+            .map(|(_, addr)| addr.trim().to_owned())
+            .find(|addr| addr.contains(part))
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.child.kill().unwrap_or_default();
+        self.child.wait().ok();
+    }
+}
+
+// Lines read from a pipe on a thread of their own, so that reading never blocks the test
+fn lines(pipe: impl std::io::Read + Send + 'static) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
@@ -0,0 +1,127 @@
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+// Nodes talking to each other over the loopback interface
+//
+// The nodes mine at difficulty 0, so they find blocks in moments whether or not the dev-fast-mine feature is on
+// - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+mod common;
+
+use common::Node;
+
+#[test]
+fn a_node_syncs_the_chain_of_the_peer_it_connects_to() {
+    let mut miner = Node::start(&[]);
+    miner.mine("first");
+    miner.mine("second");
+
+    let mut node = Node::start(&["--peer", &miner.addr]);
+    let tip = miner.tip();
+
+    assert!(node.wait_for(|node| node.tip() == tip));
+    assert_eq!(node.height(), 2);
+}
+
+// Neither node looks for peers on the LAN, so the only way they can meet is through the bootstrap entry
+#[test]
+fn a_node_without_mdns_syncs_the_chain_of_its_bootstrap_node() {
+    let mut miner = Node::start(&[]);
+    miner.mine("first");
+    miner.mine("second");
+
+    let mut node = Node::start(&["--bootstrap", &miner.addr]);
+    let tip = miner.tip();
+
+    assert!(node.wait_for(|node| node.tip() == tip));
+    assert_eq!(node.stats()["connected_peers"], 1);
+    assert_eq!(node.height(), 2);
+}
+
+// The miner listens for WebSocket connections as well as TCP ones, and the other node, which only listens on TCP,
+// dials it over a WebSocket
+#[test]
+fn a_node_syncs_the_chain_of_a_peer_it_reaches_over_a_websocket() {
+    let mut miner = Node::start(&["--listen", "/ip4/127.0.0.1/tcp/0/ws"]);
+    assert!(miner.wait_for(|node| node.listening_on("/ws/").is_some()));
+    let ws_addr = miner
+        .listening_on("/ws/")
+        .expect("the node is listening on a WebSocket");
+    miner.mine("first");
+    miner.mine("second");
+
+    let mut node = Node::start(&["--peer", &ws_addr]);
+    let tip = miner.tip();
+
+    assert!(node.wait_for(|node| node.tip() == tip));
+    assert_eq!(node.height(), 2);
+}
+
+#[test]
+fn a_block_mined_by_one_node_is_added_by_its_peer() {
+    let mut miner = Node::start(&[]);
+    let mut node = Node::start(&["--peer", &miner.addr]);
+    assert!(node.wait_for(|node| node.stats()["connected_peers"] == 1));
+
+    miner.mine("announced");
+    let tip = miner.tip();
+
+    assert!(node.wait_for(|node| node.tip() == tip));
+}
+
+#[test]
+fn an_observer_follows_the_chain_without_mining_any_of_it() {
+    let mut miner = Node::start(&[]);
+    let mut observer = Node::start(&["--no-mine", "--peer", &miner.addr]);
+    assert!(observer.wait_for(|node| node.stats()["connected_peers"] == 1));
+
+    observer.send("create b refused");
+    assert!(observer.wait_for(|node| node.logged("mining disabled on this node")));
+
+    miner.mine("first");
+    miner.mine("second");
+    let tip = miner.tip();
+
+    assert!(observer.wait_for(|node| node.tip() == tip));
+    assert_eq!(observer.height(), 2);
+}
+
+// The two nodes are only told about the bootstrap node, and find each other through the DHT
+#[test]
+fn nodes_that_share_a_bootstrap_node_find_each_other() {
+    let mut bootstrap = Node::start(&["--dht"]);
+    let mut a = Node::start(&["--bootstrap", &bootstrap.addr]);
+    // The bootstrap node can only tell the other node about this one once it knows where this one listens
+    assert!(bootstrap.wait_for(|node| node.logged("sees this node at")));
+    let mut c = Node::start(&["--bootstrap", &bootstrap.addr]);
+
+    assert!(c.wait_for(|node| node.stats()["connected_peers"] == 2));
+    assert!(a.wait_for(|node| node.stats()["connected_peers"] == 2));
+
+    // With the bootstrap node gone, the block can only reach the other node directly
+    drop(bootstrap);
+    assert!(c.wait_for(|node| node.stats()["connected_peers"] == 1));
+
+    a.mine("found through the DHT");
+    let tip = a.tip();
+
+    assert!(c.wait_for(|node| node.tip() == tip));
+}
+
+// The two nodes only reach each other through the relay.  One listens through it, and the other dials it at its
+// relayed address
+#[test]
+fn nodes_connected_through_a_relay_exchange_blocks() {
+    let relay = Node::start(&["--relay-server"]);
+    let circuit = format!("{}/p2p-circuit", relay.addr);
+    let mut a = Node::start(&["--listen", &circuit]);
+    assert!(a.wait_for(|node| node.logged(&format!("listening on {}", circuit))));
+
+    let a_id = a.addr.rsplit('/').next().expect("the address ends in a peer id");
+    let through_relay = format!("{}/p2p/{}", circuit, a_id);
+    let mut b = Node::start(&["--peer", &through_relay]);
+
+    assert!(b.wait_for(|node| node.stats()["connected_peers"] == 2));
+
+    a.mine("relayed");
+    let tip = a.tip();
+
+    assert!(b.wait_for(|node| node.tip() == tip));
+}